@@ -1,18 +1,20 @@
 //! rekordbox-cli: Lightweight client for Termux
 //!
-//! Communicates with rekordbox-server over TCP socket.
+//! Communicates with rekordbox-server over a TCP or Unix socket.
 //! Designed to be tiny (<500KB) for mobile deployment.
 
 use clap::{Parser, Subcommand};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser, Debug)]
 #[command(name = "rekordbox")]
 #[command(about = "Pioneer DJ export CLI client")]
 struct Args {
-    /// Server address (host:port)
+    /// Server address (host:port, or a Unix socket path such as /tmp/rekordbox.sock)
     #[arg(short, long, default_value = "127.0.0.1:6969")]
     server: String,
 
@@ -24,28 +26,72 @@ struct Args {
 enum Command {
     /// Check server status
     Status,
+
+    /// Show server version, export targets and optional features
+    Info,
     
     /// Analyze music directory
     Analyze {
         /// Optional path override
         #[arg(short, long)]
         path: Option<String>,
+
+        /// Only analyze files modified after this RFC 3339 time
+        #[arg(long)]
+        since: Option<String>,
+    },
+    
+    /// Analyze and cache without exporting (returns only counts)
+    Warm {
+        /// Optional path override
+        #[arg(short, long)]
+        path: Option<String>,
     },
     
     /// Export to USB device
     Export {
         /// Output path (USB mount point)
         output: String,
+
+        /// Target player model (cdj2000nxs2, cdj3000, xdj-xz, cdj900)
+        #[arg(short, long)]
+        target: Option<String>,
     },
     
     /// List analyzed tracks
     List,
+
+    /// Find tracks that sound like the same recording (re-encodes, copies)
+    Duplicates,
     
     /// Show cache statistics
     CacheStats,
     
     /// Clear analysis cache
     CacheClear,
+
+    /// List cached analyses
+    CacheList,
+
+    /// Validate an export.pdb (or a USB root containing one)
+    Validate {
+        /// Path to export.pdb or the USB mount point
+        path: String,
+
+        /// Print the full validation result as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Also print every page's header fields
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Build and validate a synthetic library to check the server build
+    Selftest,
+
+    /// Cancel running analyze/export/list operations
+    Cancel,
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +101,12 @@ struct Request {
     path: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verbose: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,36 +125,110 @@ async fn main() -> anyhow::Result<()> {
             method: "status".into(),
             path: None,
             output: None,
+            target: None,
+            since: None,
+            verbose: None,
+        },
+        Command::Info => Request {
+            method: "info".into(),
+            path: None,
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
         },
-        Command::Analyze { ref path } => Request {
+        Command::Analyze { ref path, ref since } => Request {
             method: "analyze".into(),
             path: path.clone(),
             output: None,
+            target: None,
+            since: since.clone(),
+            verbose: None,
+        },
+        Command::Warm { ref path } => Request {
+            method: "warm".into(),
+            path: path.clone(),
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
         },
-        Command::Export { ref output } => Request {
+        Command::Export { ref output, ref target } => Request {
             method: "export".into(),
             path: None,
             output: Some(output.clone()),
+            target: target.clone(),
+            since: None,
+            verbose: None,
         },
         Command::List => Request {
             method: "list_tracks".into(),
             path: None,
             output: None,
+            target: None,
+            since: None,
+            verbose: None,
+        },
+        Command::Duplicates => Request {
+            method: "find_duplicates".into(),
+            path: None,
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
         },
         Command::CacheStats => Request {
             method: "cache_stats".into(),
             path: None,
             output: None,
+            target: None,
+            since: None,
+            verbose: None,
         },
         Command::CacheClear => Request {
             method: "cache_clear".into(),
             path: None,
             output: None,
+            target: None,
+            since: None,
+            verbose: None,
+        },
+        Command::CacheList => Request {
+            method: "cache_list".into(),
+            path: None,
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
+        },
+        Command::Validate { ref path, verbose, .. } => Request {
+            method: "validate".into(),
+            path: Some(path.clone()),
+            output: None,
+            target: None,
+            since: None,
+            verbose: verbose.then_some(true),
+        },
+        Command::Selftest => Request {
+            method: "self_test".into(),
+            path: None,
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
+        },
+        Command::Cancel => Request {
+            method: "cancel".into(),
+            path: None,
+            output: None,
+            target: None,
+            since: None,
+            verbose: None,
         },
     };
     
     // Connect to server
-    let stream = match TcpStream::connect(&args.server).await {
+    let stream = match connect(&args.server).await {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Failed to connect to server at {}: {}", args.server, e);
@@ -111,7 +237,7 @@ async fn main() -> anyhow::Result<()> {
         }
     };
     
-    let (reader, mut writer) = stream.into_split();
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader);
     
     // Send request
@@ -126,6 +252,18 @@ async fn main() -> anyhow::Result<()> {
     
     let response: Response = serde_json::from_str(&response_line)?;
     
+    // Raw JSON only, so the output can be piped straight into other tools
+    if let Command::Validate { json: true, .. } = args.command {
+        if let Some(ref data) = response.data {
+            println!("{}", serde_json::to_string_pretty(data)?);
+        }
+        let valid = response.success && response.data.as_ref().is_some_and(|d| d["valid"] == true);
+        if !response.success {
+            eprintln!("✗ {}", response.message.unwrap_or_else(|| "Unknown error".into()));
+        }
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
     if response.success {
         if let Some(msg) = response.message {
             println!("✓ {}", msg);
@@ -133,6 +271,12 @@ async fn main() -> anyhow::Result<()> {
         
         if let Some(data) = response.data {
             print_data(&data, &args.command);
+            if matches!(args.command, Command::Validate { .. }) && data["valid"] != true {
+                std::process::exit(1);
+            }
+            if matches!(args.command, Command::Selftest) && data["passed"] != true {
+                std::process::exit(1);
+            }
         }
     } else {
         eprintln!("✗ {}", response.message.unwrap_or_else(|| "Unknown error".into()));
@@ -142,20 +286,74 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// BPM confidence below which `list` marks a tempo as doubtful
+const LOW_BPM_CONFIDENCE: f64 = 0.3;
+
 fn print_data(data: &serde_json::Value, command: &Command) {
     match command {
         Command::List => {
-            if let Some(tracks) = data.as_array() {
-                println!("\n{:<4} {:<30} {:<25} {:<8} {:<6}", "ID", "Title", "Artist", "BPM", "Key");
-                println!("{}", "-".repeat(80));
+            if let Some(tracks) = data.get("tracks").and_then(|t| t.as_array()) {
+                println!(
+                    "\n{:<4} {:<30} {:<25} {:<8} {:<6} {:<7} {:<7}",
+                    "ID", "Title", "Artist", "BPM", "Key", "Clip%", "Cutoff"
+                );
+                println!("{}", "-".repeat(96));
+                let mut uncertain = 0;
                 for track in tracks {
+                    // Older servers don't send a confidence; don't flag those
+                    let confidence = track["bpm_confidence"].as_f64().unwrap_or(1.0);
+                    let flag = if confidence < LOW_BPM_CONFIDENCE {
+                        uncertain += 1;
+                        "?"
+                    } else {
+                        ""
+                    };
+                    let bpm = format!("{:.1}{}", track["bpm"].as_f64().unwrap_or(0.0), flag);
+                    let cutoff = track["spectral_cutoff_hz"].as_u64()
+                        .map(|hz| format!("{:.1}k", hz as f64 / 1000.0))
+                        .unwrap_or_else(|| "-".into());
                     println!(
-                        "{:<4} {:<30} {:<25} {:<8.1} {:<6}",
+                        "{:<4} {:<30} {:<25} {:<8} {:<6} {:<7.2} {:<7}",
                         track["id"].as_u64().unwrap_or(0),
                         truncate(track["title"].as_str().unwrap_or(""), 29),
                         truncate(track["artist"].as_str().unwrap_or(""), 24),
-                        track["bpm"].as_f64().unwrap_or(0.0),
+                        bpm,
                         track["key"].as_str().unwrap_or("-"),
+                        track["clip_ratio"].as_f64().unwrap_or(0.0) * 100.0,
+                        cutoff,
+                    );
+                }
+                if uncertain > 0 {
+                    println!("\n? {} track(s) with an uncertain BPM, check their beat grids", uncertain);
+                }
+            }
+        }
+        Command::Duplicates => {
+            for (i, group) in data.as_array().into_iter().flatten().enumerate() {
+                println!("\nGroup {}:", i + 1);
+                for track in group.as_array().into_iter().flatten() {
+                    println!(
+                        "  {:<4} {} - {} ({}, {} kbps)",
+                        track["id"].as_u64().unwrap_or(0),
+                        track["artist"].as_str().unwrap_or("?"),
+                        track["title"].as_str().unwrap_or("?"),
+                        track["path"].as_str().unwrap_or(""),
+                        track["bitrate"].as_u64().unwrap_or(0),
+                    );
+                }
+            }
+        }
+        Command::CacheList => {
+            if let Some(entries) = data.as_array() {
+                println!("\n{:<16} {:<30} {:<25} {:<10}", "Hash", "Title", "Artist", "Cached");
+                println!("{}", "-".repeat(84));
+                for entry in entries {
+                    println!(
+                        "{:<16} {:<30} {:<25} {:<10}",
+                        entry["hash"].as_str().unwrap_or(""),
+                        truncate(entry["title"].as_str().unwrap_or(""), 29),
+                        truncate(entry["artist"].as_str().unwrap_or(""), 24),
+                        entry["cached_at"].as_u64().unwrap_or(0),
                     );
                 }
             }
@@ -177,6 +375,84 @@ fn print_data(data: &serde_json::Value, command: &Command) {
                 }
             }
         }
+        Command::Validate { .. } => {
+            let stats = &data["stats"];
+            println!("\nStatistics:");
+            for (label, key) in [
+                ("Total pages", "total_pages"),
+                ("Tracks", "track_count"),
+                ("Playlists", "playlist_count"),
+                ("Playlist entries", "playlist_entry_count"),
+            ] {
+                println!("  {}: {}", label, stats[key].as_u64().unwrap_or(0));
+            }
+            if let Some(pages) = data["pages"].as_array() {
+                println!(
+                    "\n{:>5} {:<18} {:>5} {:>5} {:>6} {:>6} {:>10}",
+                    "Page", "Table", "Flags", "Rows", "Free", "Used", "Next"
+                );
+                println!("{}", "-".repeat(61));
+                for page in pages {
+                    let table = page["table"].as_str().unwrap_or("-");
+                    let kind = if page["is_index"] == true { " (index)" } else { "" };
+                    let next = page["next_page"].as_u64()
+                        .map(|n| format!("{:#x}", n))
+                        .unwrap_or_else(|| "-".into());
+                    println!(
+                        "{:>5} {:<18} {:>#5x} {:>5} {:>6} {:>6} {:>10}",
+                        page["index"].as_u64().unwrap_or(0),
+                        format!("{}{}", table, kind),
+                        page["flags"].as_u64().unwrap_or(0),
+                        page["num_rows"].as_u64().unwrap_or(0),
+                        page["free_size"].as_u64().unwrap_or(0),
+                        page["used_size"].as_u64().unwrap_or(0),
+                        next,
+                    );
+                }
+            }
+            for (label, key) in [("Errors", "errors"), ("Warnings", "warnings")] {
+                if let Some(items) = data[key].as_array().filter(|a| !a.is_empty()) {
+                    println!("\n{}:", label);
+                    for item in items {
+                        println!("  - {}", item.as_str().unwrap_or(""));
+                    }
+                }
+            }
+        }
+        Command::Selftest => {
+            if let Some(checks) = data["checks"].as_array() {
+                println!();
+                for check in checks {
+                    let mark = if check["passed"] == true { "✓" } else { "✗" };
+                    println!(
+                        "  {} {}: {}",
+                        mark,
+                        check["name"].as_str().unwrap_or("?"),
+                        check["detail"].as_str().unwrap_or(""),
+                    );
+                }
+            }
+        }
+        Command::Info => {
+            let yes_no = |value: &serde_json::Value| if value == true { "yes" } else { "no" };
+            println!("\nNavidrome: {}", yes_no(&data["navidrome"]));
+            println!("Unix socket: {}", yes_no(&data["unix_socket"]));
+            if let Some(formats) = data["export_formats"].as_array() {
+                let formats: Vec<_> = formats.iter().filter_map(|f| f.as_str()).collect();
+                println!("Export formats: {}", formats.join(", "));
+            }
+            if let Some(targets) = data["targets"].as_array() {
+                println!("\nTargets:");
+                for target in targets {
+                    let name = target["name"].as_str().unwrap_or("?");
+                    let default = if data["default_target"] == *name { " (default)" } else { "" };
+                    let files: Vec<_> = target["anlz_files"].as_array()
+                        .map(|f| f.iter().filter_map(|f| f.as_str()).collect())
+                        .unwrap_or_default();
+                    println!("  {:<12} {}{}", name, files.join("/"), default);
+                }
+            }
+        }
         Command::CacheStats => {
             println!("\nCache statistics:");
             println!("  Entries: {}", data["entries"].as_u64().unwrap_or(0));
@@ -191,6 +467,19 @@ fn print_data(data: &serde_json::Value, command: &Command) {
     }
 }
 
+/// Either kind of server connection
+trait Stream: AsyncRead + AsyncWrite + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for T {}
+
+/// Connect over a Unix socket when the address is a path, TCP otherwise
+async fn connect(server: &str) -> std::io::Result<Box<dyn Stream>> {
+    #[cfg(unix)]
+    if server.contains('/') {
+        return Ok(Box::new(UnixStream::connect(server).await?));
+    }
+    Ok(Box::new(TcpStream::connect(server).await?))
+}
+
 fn truncate(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         s.to_string()
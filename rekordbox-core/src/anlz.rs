@@ -3,23 +3,32 @@
 //! ANLZ files are **big-endian** and contain tagged sections:
 //! - PMAI: File header
 //! - PQTZ: Beat grid
+//! - PQT2: Extended beat grid (.EXT/.2EX only)
 //! - PWAV: Preview waveform (monochrome)
 //! - PWV5: Detail waveform (color)
+//! - PWV6/PWV7: Three-band preview and detail waveforms (.2EX only)
 //! - PPTH: File path
 //!
 //! Reference: https://djl-analysis.deepsymmetry.org/rekordbox-export-analysis/anlz.html
 
-use crate::error::Result;
-use crate::track::{BeatGrid, Waveform, WaveformPreview, WaveformDetail, WaveformColorPreview,
-                   CuePoint, CueType, HotCueColor};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::track::{Beat, BeatGrid, Waveform, WaveformPreview, WaveformDetail, WaveformColorPreview,
+                   WaveformColorEntry, CuePoint, CueType, HotCueColor};
 
 /// Section tags (4 bytes each)
 const PMAI_TAG: &[u8; 4] = b"PMAI";
 const PQTZ_TAG: &[u8; 4] = b"PQTZ";
+const PQT2_TAG: &[u8; 4] = b"PQT2"; // Extended beat grid
 const PWAV_TAG: &[u8; 4] = b"PWAV";
 const PWV3_TAG: &[u8; 4] = b"PWV3"; // 3-band waveform for NXS compatibility
 const PWV4_TAG: &[u8; 4] = b"PWV4"; // Color preview waveform (1200×6 bytes)
 const PWV5_TAG: &[u8; 4] = b"PWV5";
+const PWV6_TAG: &[u8; 4] = b"PWV6"; // 3-band preview (CDJ-3000)
+const PWV7_TAG: &[u8; 4] = b"PWV7"; // 3-band detail (CDJ-3000)
 const PPTH_TAG: &[u8; 4] = b"PPTH";
 const PCOB_TAG: &[u8; 4] = b"PCOB"; // Cue/loop points (basic)
 const PCO2_TAG: &[u8; 4] = b"PCO2"; // Extended cue points with colors (Nexus 2+)
@@ -95,12 +104,67 @@ fn generate_pqtz_section(beat_grid: &BeatGrid) -> Vec<u8> {
         // Tempo as BPM × 100
         buffer.extend_from_slice(&beat.tempo_100.to_be_bytes());
         // Time in milliseconds as u32
-        buffer.extend_from_slice(&(beat.time_ms as u32).to_be_bytes());
+        buffer.extend_from_slice(&beat_time_ms(beat).to_be_bytes());
     }
     
     buffer
 }
 
+/// Generate PQT2 (extended beat grid) section
+///
+/// Layout per the Kaitai `rekordbox_anlz.ksy` extended beat grid tag:
+/// ```text
+/// 0x0C  u32  0
+/// 0x10  u32  0x01000002
+/// 0x14  u32  0
+/// 0x18  2 x (u16 beat_number, u16 tempo_100, u32 time_ms)   first / last beat
+/// 0x28  u32  entry count
+/// 0x2C  3 x u32 0
+/// 0x38  per beat: u16
+/// ```
+/// The spec only sizes the per-beat entries; we write each beat's number
+/// within its bar there, as PQTZ does.
+fn generate_pqt2_section(beat_grid: &BeatGrid) -> Vec<u8> {
+    const HEADER_LEN: usize = 0x38;
+    const ENTRY_LEN: usize = 2;
+
+    let section_len = HEADER_LEN + beat_grid.beats.len() * ENTRY_LEN;
+    let mut buffer = Vec::with_capacity(section_len);
+
+    buffer.extend_from_slice(PQT2_TAG);
+    buffer.extend_from_slice(&(HEADER_LEN as u32 - 4).to_be_bytes());
+    buffer.extend_from_slice(&(section_len as u32).to_be_bytes());
+    buffer.extend_from_slice(&0u32.to_be_bytes());
+    buffer.extend_from_slice(&0x0100_0002u32.to_be_bytes());
+    buffer.extend_from_slice(&0u32.to_be_bytes());
+
+    // First and last beat, in PQTZ's 8-byte beat layout
+    let empty = Beat { beat_number: 1, time_ms: 0.0, tempo_100: 0 };
+    for beat in [beat_grid.beats.first(), beat_grid.beats.last()] {
+        let beat = beat.unwrap_or(&empty);
+        buffer.extend_from_slice(&(beat.beat_number as u16).to_be_bytes());
+        buffer.extend_from_slice(&beat.tempo_100.to_be_bytes());
+        buffer.extend_from_slice(&beat_time_ms(beat).to_be_bytes());
+    }
+
+    buffer.extend_from_slice(&(beat_grid.beats.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(&[0u8; 12]);
+
+    for beat in &beat_grid.beats {
+        buffer.extend_from_slice(&(beat.beat_number as u16).to_be_bytes());
+    }
+
+    buffer
+}
+
+/// Beat time as stored in PQTZ/PQT2, rounded to the nearest millisecond
+///
+/// Beats of a changing tempo fall between milliseconds; truncating them
+/// would pull every one up to 1 ms early.
+fn beat_time_ms(beat: &Beat) -> u32 {
+    beat.time_ms.round().max(0.0) as u32
+}
+
 /// Generate PWAV (preview waveform) section - exactly 400 bytes of waveform data
 fn generate_pwav_section(preview: &WaveformPreview) -> Vec<u8> {
     let mut buffer = Vec::new();
@@ -170,28 +234,34 @@ fn generate_ppth_section(file_path: &str) -> Vec<u8> {
     // Tag
     buffer.extend_from_slice(PPTH_TAG);
     
-    // Encode path as UTF-16BE
-    let path_utf16: Vec<u16> = file_path.encode_utf16().collect();
-    let path_bytes_len = path_utf16.len() * 2;
+    let path = encode_utf16_be(file_path);
     
     // Header: 4 (tag) + 4 (header_len) + 4 (section_len) + 4 (path_len) = 16 bytes
     let header_len = 16u32 - 4;
-    let section_len = 16 + path_bytes_len;
+    let section_len = 16 + path.len();
     
     buffer.extend_from_slice(&header_len.to_be_bytes());
     buffer.extend_from_slice(&(section_len as u32).to_be_bytes());
     
-    // Path length in characters
-    buffer.extend_from_slice(&(path_utf16.len() as u32).to_be_bytes());
-    
-    // Path data (UTF-16BE)
-    for ch in path_utf16 {
-        buffer.extend_from_slice(&ch.to_be_bytes());
-    }
+    // Path length in bytes, like PCP2 comments
+    buffer.extend_from_slice(&(path.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(&path);
     
     buffer
 }
 
+/// UTF-16BE text with a null terminator, as PPTH paths and PCP2 comments
+/// store it
+///
+/// Both are preceded by their length in bytes, so characters outside the
+/// BMP (emoji in file names) count as the two code units they take.
+fn encode_utf16_be(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
 /// Generate PWV3 (3-band waveform) section for NXS compatibility
 /// PWV3 uses 1 byte per entry (simpler than PWV5's 2-byte encoding)
 fn generate_pwv3_section(detail: &WaveformDetail) -> Vec<u8> {
@@ -264,6 +334,78 @@ fn generate_pwv4_section(color_preview: &WaveformColorPreview) -> Vec<u8> {
     buffer
 }
 
+/// Columns in a PWV6 preview, like PWV4
+const PWV6_COLUMNS: usize = 1200;
+
+/// Generate PWV6 (3-band preview waveform) section
+/// 1200 fixed columns of 3 bytes, each the loudest detail entry it covers
+fn generate_pwv6_section(detail: &WaveformDetail) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    // Tag
+    buffer.extend_from_slice(PWV6_TAG);
+
+    // Header: 4 (tag) + 4 (header_len) + 4 (section_len) + 4 (entry_bytes) + 4 (entry_count) = 20 bytes
+    let header_len = 20u32 - 4;
+    let section_len = 20 + PWV6_COLUMNS * 3;
+
+    buffer.extend_from_slice(&header_len.to_be_bytes());
+    buffer.extend_from_slice(&(section_len as u32).to_be_bytes());
+    buffer.extend_from_slice(&3u32.to_be_bytes());
+    buffer.extend_from_slice(&(PWV6_COLUMNS as u32).to_be_bytes());
+
+    let entries = &detail.entries;
+    for column in 0..PWV6_COLUMNS {
+        let start = column * entries.len() / PWV6_COLUMNS;
+        let end = ((column + 1) * entries.len() / PWV6_COLUMNS).max(start + 1).min(entries.len());
+        let mut bands = [0u8; 3];
+        for entry in entries.get(start..end).unwrap_or_default() {
+            for (band, value) in bands.iter_mut().zip(three_band_bytes(entry)) {
+                *band = (*band).max(value);
+            }
+        }
+        buffer.extend_from_slice(&bands);
+    }
+
+    buffer
+}
+
+/// Generate PWV7 (3-band detail waveform) section
+/// One 3-byte entry per detail entry (150/second)
+fn generate_pwv7_section(detail: &WaveformDetail) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    // Tag
+    buffer.extend_from_slice(PWV7_TAG);
+
+    // Header: 4 (tag) + 4 (header_len) + 4 (section_len) + 4 (entry_bytes) + 4 (entry_count) + 4 (unknown) = 24 bytes
+    let header_len = 24u32 - 4;
+    let section_len = 24 + detail.entries.len() * 3;
+
+    buffer.extend_from_slice(&header_len.to_be_bytes());
+    buffer.extend_from_slice(&(section_len as u32).to_be_bytes());
+    buffer.extend_from_slice(&3u32.to_be_bytes());
+    buffer.extend_from_slice(&(detail.entries.len() as u32).to_be_bytes());
+
+    // Unknown, always 0x00960000 in rekordbox exports
+    buffer.extend_from_slice(&0x0096_0000u32.to_be_bytes());
+
+    for entry in &detail.entries {
+        buffer.extend_from_slice(&three_band_bytes(entry));
+    }
+
+    buffer
+}
+
+/// Mid, high and low band heights (0-255) of a detail entry, in PWV6/PWV7
+/// byte order
+///
+/// Each band's 3-bit level scales the entry's 5-bit height.
+fn three_band_bytes(entry: &WaveformColorEntry) -> [u8; 3] {
+    let band = |level: u8| ((level.min(7) as u32 * entry.height.min(31) as u32 * 255) / (7 * 31)) as u8;
+    [band(entry.green), band(entry.blue), band(entry.red)]
+}
+
 /// Generate PCO2 (extended cue points with colors) section
 /// Used by CDJ-2000NXS2 and later for hot cue colors
 fn generate_pco2_section(cue_points: &[CuePoint]) -> Vec<u8> {
@@ -302,10 +444,7 @@ fn generate_pco2_entries(cues: &[&CuePoint], is_hot_cue: bool) -> Vec<u8> {
     // Calculate entry sizes
     // Each extended entry is at least 56 bytes for hot cues (with color)
     let base_entry_size = if is_hot_cue { 56usize } else { 40usize };
-    let entries_size: usize = cues.iter().map(|cue| {
-        let comment_len = cue.comment.as_ref().map(|c| c.len() + 4).unwrap_or(0);
-        base_entry_size + comment_len
-    }).sum();
+    let entries_size: usize = cues.iter().map(|cue| base_entry_size + pcp2_comment_len(cue)).sum();
 
     // Header: 4 (tag) + 4 (header_len) + 4 (section_len) + 4 (type) + 2 (unknown) + 2 (count) = 20 bytes
     let header_len = 20u32 - 4;
@@ -323,12 +462,13 @@ fn generate_pco2_entries(cues: &[&CuePoint], is_hot_cue: bool) -> Vec<u8> {
 
     // Write cue entries
     for cue in cues {
+        let entry_start = buffer.len();
+
         // Entry tag "PCP2"
         buffer.extend_from_slice(b"PCP2");
 
         // Calculate entry length
-        let comment_len = cue.comment.as_ref().map(|c| c.len() + 4).unwrap_or(0);
-        let entry_len = if is_hot_cue { 56 + comment_len } else { 40 + comment_len };
+        let entry_len = base_entry_size + pcp2_comment_len(cue);
         buffer.extend_from_slice(&((entry_len - 4) as u32).to_be_bytes());
 
         // Hot cue number (0 for memory, 1-8 for hot cue A-H)
@@ -354,18 +494,20 @@ fn generate_pco2_entries(cues: &[&CuePoint], is_hot_cue: bool) -> Vec<u8> {
             buffer.extend_from_slice(&0xFFFFFFFFu32.to_be_bytes());
         }
 
-        // Color ID for memory cues (4 bytes) - default to 0
-        buffer.extend_from_slice(&0u32.to_be_bytes());
+        // Color ID for memory cues (4 bytes) - 0 = no color, hot cues carry
+        // their color in the trailing block instead
+        let color_id = if is_hot_cue { 0 } else { cue.memory_color() };
+        buffer.extend_from_slice(&(color_id as u32).to_be_bytes());
 
         // Unknown bytes (8 bytes padding)
         buffer.extend_from_slice(&[0u8; 8]);
 
         // Comment (if present)
         if let Some(ref comment) = cue.comment {
-            // Comment length including null terminator
-            buffer.extend_from_slice(&((comment.len() + 1) as u32).to_be_bytes());
-            buffer.extend_from_slice(comment.as_bytes());
-            buffer.push(0); // Null terminator
+            // Comment length in bytes, including the null terminator
+            let comment = encode_utf16_be(comment);
+            buffer.extend_from_slice(&(comment.len() as u32).to_be_bytes());
+            buffer.extend_from_slice(&comment);
         }
 
         // Hot cue color data (for hot cues only)
@@ -379,15 +521,21 @@ fn generate_pco2_entries(cues: &[&CuePoint], is_hot_cue: bool) -> Vec<u8> {
             buffer.push(color.red);
             buffer.push(color.green);
             buffer.push(color.blue);
-
-            // Padding to align
-            buffer.extend_from_slice(&[0u8; 4]);
         }
+
+        // Zero-fill to the declared length, so the section length (and
+        // every section after it) lines up with what was written
+        buffer.resize(entry_start + entry_len, 0);
     }
 
     buffer
 }
 
+/// Bytes a PCP2 entry's comment takes: length, text and null terminator
+fn pcp2_comment_len(cue: &CuePoint) -> usize {
+    cue.comment.as_ref().map(|c| 4 + (c.encode_utf16().count() + 1) * 2).unwrap_or(0)
+}
+
 /// Generate PCOB (cue/loop points) section
 fn generate_pcob_section(cue_points: &[CuePoint]) -> Vec<u8> {
     let mut buffer = Vec::new();
@@ -417,7 +565,7 @@ fn generate_pcob_section(cue_points: &[CuePoint]) -> Vec<u8> {
     buffer.extend_from_slice(&(cue_points.len() as u16).to_be_bytes());
 
     // Write cue entries
-    for (i, cue) in cue_points.iter().enumerate() {
+    for cue in cue_points {
         // Entry header (4 bytes): "PCP1" for cue entry or similar marker
         buffer.extend_from_slice(b"PCP\x01");
 
@@ -465,6 +613,80 @@ pub fn generate_anlz_full_path(usb_root: &str, track_id: u32) -> String {
     format!("{}/{}", usb_root.trim_end_matches('/'), generate_anlz_path(track_id))
 }
 
+/// Order of the sections in .EXT/.2EX files
+///
+/// Firmwares differ in which order they expect (beat grid before or after
+/// the waveforms, ...). Sections a layout leaves out are written after the
+/// listed ones in the default order, so a layout never drops data.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct AnlzLayout {
+    tags: Vec<[u8; 4]>,
+}
+
+/// Sections of an .EXT file, in the order they are written by default
+const EXT_SECTION_ORDER: [&[u8; 4]; 9] = [
+    PPTH_TAG, // Path first
+    PQTZ_TAG, // Beat grid
+    PQT2_TAG, // Extended beat grid
+    PWAV_TAG, // Preview waveform (monochrome)
+    PWV3_TAG, // 3-band waveform (NXS compat)
+    PWV4_TAG, // Color preview (NXS2/3000)
+    PWV5_TAG, // Color detail (NXS2/3000)
+    PCOB_TAG, // Basic cue points
+    PCO2_TAG, // Extended cue points with colors
+];
+
+impl Default for AnlzLayout {
+    fn default() -> Self {
+        Self { tags: EXT_SECTION_ORDER.iter().map(|tag| **tag).collect() }
+    }
+}
+
+impl AnlzLayout {
+    /// Layout from section tags ("PQTZ", "PWV5", ...), first written first
+    pub fn new<S: AsRef<str>>(tags: &[S]) -> Result<Self> {
+        let mut layout = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let tag = tag.as_ref().trim();
+            let known = EXT_SECTION_ORDER.iter()
+                .find(|known| known.as_slice() == tag.as_bytes())
+                .ok_or_else(|| Error::Validation(format!("Unknown ANLZ section tag {:?}", tag)))?;
+            if layout.contains(*known) {
+                return Err(Error::Validation(format!("ANLZ section tag {:?} listed twice", tag)));
+            }
+            layout.push(**known);
+        }
+        Ok(Self { tags: layout })
+    }
+
+    /// Every section tag, in write order
+    pub fn order(&self) -> Vec<[u8; 4]> {
+        let rest = EXT_SECTION_ORDER.iter()
+            .map(|tag| **tag)
+            .filter(|tag| !self.tags.contains(tag));
+        self.tags.iter().copied().chain(rest).collect()
+    }
+}
+
+impl FromStr for AnlzLayout {
+    type Err = Error;
+
+    /// Comma-separated tags, e.g. `PPTH,PQTZ,PCO2,PWV5`
+    fn from_str(s: &str) -> Result<Self> {
+        let tags: Vec<&str> = s.split(',').filter(|tag| !tag.trim().is_empty()).collect();
+        Self::new(&tags)
+    }
+}
+
+impl TryFrom<Vec<String>> for AnlzLayout {
+    type Error = Error;
+
+    fn try_from(tags: Vec<String>) -> Result<Self> {
+        Self::new(&tags)
+    }
+}
+
 /// Generate .EXT file (extended analysis for Nexus+ players)
 /// Includes additional sections not present in .DAT:
 /// - PWV3: 3-band waveform for NXS compatibility
@@ -476,38 +698,54 @@ pub fn generate_ext_file(
     file_path: &str,
     cue_points: &[CuePoint],
 ) -> Result<Vec<u8>> {
-    let mut buffer = Vec::with_capacity(128 * 1024);
+    generate_ext_file_with_layout(beat_grid, waveform, file_path, cue_points, &AnlzLayout::default())
+}
+
+/// Generate .EXT file with its sections in `layout` order
+pub fn generate_ext_file_with_layout(
+    beat_grid: &BeatGrid,
+    waveform: &Waveform,
+    file_path: &str,
+    cue_points: &[CuePoint],
+    layout: &AnlzLayout,
+) -> Result<Vec<u8>> {
+    let sections: Vec<Vec<u8>> = layout.order().iter()
+        .map(|tag| ext_section(tag, beat_grid, waveform, file_path, cue_points))
+        .collect();
+    Ok(anlz_file(&sections))
+}
 
-    // Build sections first to calculate sizes
-    let ppth_section = generate_ppth_section(file_path);
-    let pqtz_section = generate_pqtz_section(beat_grid);
-    let pwav_section = generate_pwav_section(&waveform.preview);
-    let pwv3_section = generate_pwv3_section(&waveform.detail);
-    let pwv4_section = generate_pwv4_section(&waveform.color_preview);
-    let pwv5_section = generate_pwv5_section(&waveform.detail);
-    let pcob_section = if !cue_points.is_empty() {
-        generate_pcob_section(cue_points)
-    } else {
-        Vec::new()
-    };
-    let pco2_section = if !cue_points.is_empty() {
-        generate_pco2_section(cue_points)
-    } else {
-        Vec::new()
-    };
+/// One .EXT section; cue sections are left out when there are no cues
+fn ext_section(
+    tag: &[u8; 4],
+    beat_grid: &BeatGrid,
+    waveform: &Waveform,
+    file_path: &str,
+    cue_points: &[CuePoint],
+) -> Vec<u8> {
+    match tag {
+        PPTH_TAG => generate_ppth_section(file_path),
+        PQTZ_TAG => generate_pqtz_section(beat_grid),
+        PQT2_TAG => generate_pqt2_section(beat_grid),
+        PWAV_TAG => generate_pwav_section(&waveform.preview),
+        PWV3_TAG => generate_pwv3_section(&waveform.detail),
+        PWV4_TAG => generate_pwv4_section(&waveform.color_preview),
+        PWV5_TAG => generate_pwv5_section(&waveform.detail),
+        PCOB_TAG if !cue_points.is_empty() => generate_pcob_section(cue_points),
+        PCO2_TAG => generate_pco2_section(cue_points),
+        _ => Vec::new(),
+    }
+}
 
+/// PMAI header followed by the sections
+fn anlz_file(sections: &[Vec<u8>]) -> Vec<u8> {
     // Calculate total file size
-    let sections_size = ppth_section.len()
-        + pqtz_section.len()
-        + pwav_section.len()
-        + pwv3_section.len()
-        + pwv4_section.len()
-        + pwv5_section.len()
-        + pcob_section.len()
-        + pco2_section.len();
+    let sections_size: usize = sections.iter().map(Vec::len).sum();
     let header_size = 28; // PMAI header
     let total_size = header_size + sections_size;
 
+    let mut buffer = Vec::with_capacity(total_size);
+
     // Write PMAI header
     buffer.extend_from_slice(PMAI_TAG);
     buffer.extend_from_slice(&(header_size as u32 - 4).to_be_bytes()); // Header length after tag
@@ -520,20 +758,11 @@ pub fn generate_ext_file(
     buffer.extend_from_slice(&0u32.to_be_bytes()); // Unknown
 
     // Write sections (order matters for some players)
-    buffer.extend_from_slice(&ppth_section); // Path first
-    buffer.extend_from_slice(&pqtz_section); // Beat grid
-    buffer.extend_from_slice(&pwav_section); // Preview waveform (monochrome)
-    buffer.extend_from_slice(&pwv3_section); // 3-band waveform (NXS compat)
-    buffer.extend_from_slice(&pwv4_section); // Color preview (NXS2/3000)
-    buffer.extend_from_slice(&pwv5_section); // Color detail (NXS2/3000)
-    if !pcob_section.is_empty() {
-        buffer.extend_from_slice(&pcob_section); // Basic cue points
-    }
-    if !pco2_section.is_empty() {
-        buffer.extend_from_slice(&pco2_section); // Extended cue points with colors
+    for section in sections {
+        buffer.extend_from_slice(section);
     }
 
-    Ok(buffer)
+    buffer
 }
 
 /// Generate .2EX file (second extended analysis for CDJ-3000)
@@ -544,15 +773,40 @@ pub fn generate_2ex_file(
     file_path: &str,
     cue_points: &[CuePoint],
 ) -> Result<Vec<u8>> {
-    // .2EX files have the same structure as .EXT but may include additional tags
-    // For now, generate the same content as EXT with extended color support
-    generate_ext_file(beat_grid, waveform, file_path, cue_points)
+    generate_2ex_file_with_layout(beat_grid, waveform, file_path, cue_points, &AnlzLayout::default())
+}
+
+/// Generate .2EX file with its sections in `layout` order
+///
+/// Carries the same path, beat grid and cue sections as the .EXT, but the
+/// waveforms are the three-band PWV7 detail and PWV6 preview (written where
+/// `layout` puts PWV5) instead of the .EXT's PWAV/PWV3/PWV4/PWV5.
+pub fn generate_2ex_file_with_layout(
+    beat_grid: &BeatGrid,
+    waveform: &Waveform,
+    file_path: &str,
+    cue_points: &[CuePoint],
+    layout: &AnlzLayout,
+) -> Result<Vec<u8>> {
+    let mut sections = Vec::new();
+    for tag in layout.order() {
+        match &tag {
+            PWAV_TAG | PWV3_TAG | PWV4_TAG => {}
+            PWV5_TAG => {
+                sections.push(generate_pwv7_section(&waveform.detail));
+                sections.push(generate_pwv6_section(&waveform.detail));
+            }
+            tag => sections.push(ext_section(tag, beat_grid, waveform, file_path, cue_points)),
+        }
+    }
+    Ok(anlz_file(&sections))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::track::{Beat, WaveformColumn, WaveformColorEntry};
+    use std::collections::HashMap;
+    use crate::track::{Beat, MemoryCueColor, WaveformColumn, WaveformColorEntry};
     
     #[test]
     fn test_anlz_path_generation() {
@@ -579,6 +833,7 @@ mod tests {
                 Beat { beat_number: 1, time_ms: 100.0, tempo_100: 12800 },
                 Beat { beat_number: 2, time_ms: 568.75, tempo_100: 12800 },
             ],
+            time_signature: (4, 4),
         };
         
         let section = generate_pqtz_section(&grid);
@@ -617,9 +872,48 @@ mod tests {
         // Check tag
         assert_eq!(&section[0..4], b"PPTH");
         
-        // Path length should be 18 characters
+        // Path length in bytes: 18 UTF-16 code units and a null terminator
         let path_len = u32::from_be_bytes([section[12], section[13], section[14], section[15]]);
-        assert_eq!(path_len, 18);
+        assert_eq!(path_len, 38);
+    }
+
+    #[test]
+    fn test_astral_path_and_comment_round_trip() {
+        let path = "/Contents/\u{1F525} Fire.mp3";
+        let section = generate_ppth_section(path);
+
+        // The emoji is a surrogate pair: two code units, four bytes
+        let path_len = u32::from_be_bytes(section[12..16].try_into().unwrap()) as usize;
+        assert_eq!(path_len, (path.encode_utf16().count() + 1) * 2);
+        assert_eq!(path_len, section.len() - 16);
+        let units: Vec<u16> = section[16..16 + path_len - 2]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(String::from_utf16(&units).unwrap(), path);
+
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
+        let dat = generate_dat_file(&grid, &Waveform::default(), path).unwrap();
+        assert_eq!(crate::validate::validate_anlz(&dat).path.as_deref(), Some(path));
+
+        // PCP2 comments use the same encoding and length
+        let comment = "Drop \u{1F680}";
+        let cues = vec![CuePoint {
+            hot_cue: 0,
+            cue_type: CueType::Cue,
+            time_ms: 2000.0,
+            loop_ms: 0.0,
+            comment: Some(comment.to_string()),
+            color: None,
+            memory_color_id: None,
+        }];
+        let section = generate_pco2_section(&cues);
+        let section_len = u32::from_be_bytes(section[8..12].try_into().unwrap());
+        assert_eq!(section_len as usize, section.len());
+        let encoded = encode_utf16_be(comment);
+        let at = section.windows(encoded.len()).position(|w| w == encoded.as_slice()).unwrap();
+        let comment_len = u32::from_be_bytes(section[at - 4..at].try_into().unwrap());
+        assert_eq!(comment_len as usize, encoded.len());
     }
     
     #[test]
@@ -668,6 +962,8 @@ mod tests {
                 time_ms: 5000.0,
                 loop_ms: 0.0,
                 comment: None,
+                color: None,
+                memory_color_id: None,
             },
             CuePoint {
                 hot_cue: 2,
@@ -675,6 +971,8 @@ mod tests {
                 time_ms: 10000.0,
                 loop_ms: 4000.0,
                 comment: None,
+                color: None,
+                memory_color_id: None,
             },
         ];
 
@@ -688,6 +986,58 @@ mod tests {
         assert_eq!(count, 2);
     }
 
+    #[test]
+    fn test_pco2_memory_cue_color() {
+        let cues = vec![CuePoint {
+            hot_cue: 0,
+            cue_type: CueType::Cue,
+            time_ms: 2000.0,
+            loop_ms: 0.0,
+            comment: None,
+            color: Some(HotCueColor::RED),
+            memory_color_id: None,
+        }];
+
+        let section = generate_pco2_section(&cues);
+        assert_eq!(&section[0..4], b"PCO2");
+
+        // Memory cue section type
+        let section_type = u32::from_be_bytes([section[12], section[13], section[14], section[15]]);
+        assert_eq!(section_type, 0);
+
+        // Color ID follows PCP2 tag, length, hot cue, type, time and loop end
+        let color_offset = 20 + 24;
+        let color_id = u32::from_be_bytes(section[color_offset..color_offset + 4].try_into().unwrap());
+        assert_ne!(color_id, 0);
+        assert_eq!(color_id, 2);
+
+        // Declared section length covers exactly the bytes written
+        let section_len = u32::from_be_bytes(section[8..12].try_into().unwrap());
+        assert_eq!(section_len as usize, section.len());
+    }
+
+    #[test]
+    fn test_pco2_memory_color_id_split_from_hot_cues() {
+        let cues = vec![
+            CuePoint { hot_cue: 1, time_ms: 1000.0, memory_color_id: Some(5), ..Default::default() },
+            CuePoint { hot_cue: 0, time_ms: 2000.0, memory_color_id: Some(MemoryCueColor::Orange.id()), ..Default::default() },
+            CuePoint { hot_cue: 0, time_ms: 3000.0, ..Default::default() },
+        ];
+        let pco2 = generate_pco2_section(&cues);
+
+        // Hot cues first (type 1), their color_id field left at 0
+        let be = |pos: usize| u32::from_be_bytes(pco2[pos..pos + 4].try_into().unwrap());
+        assert_eq!(be(12), 1);
+        assert_eq!(be(20 + 24), 0);
+        let memory = be(8) as usize;
+        assert_eq!(&pco2[memory..memory + 4], b"PCO2");
+        assert_eq!(be(memory + 12), 0);
+        assert_eq!(u16::from_be_bytes([pco2[memory + 18], pco2[memory + 19]]), 2);
+        // Memory cues: the picked color, then none
+        assert_eq!(be(memory + 20 + 24), 3);
+        assert_eq!(be(memory + 20 + 40 + 24), 0);
+    }
+
     #[test]
     fn test_ext_file_differs_from_dat() {
         let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
@@ -705,6 +1055,70 @@ mod tests {
         assert_eq!(&ext_data[0..4], b"PMAI");
     }
 
+    #[test]
+    fn test_pqt2_section_bytes() {
+        // 3/4 at 120 BPM from 250 ms: six beats over 3 s
+        let grid = BeatGrid::constant_tempo_with_signature(120.0, 250.0, 3000.0, (3, 4));
+        assert_eq!(grid.beats.len(), 6);
+        let section = generate_pqt2_section(&grid);
+
+        let expected: Vec<u8> = [
+            &b"PQT2"[..],
+            &[0, 0, 0, 0x34], // header length after the tag
+            &[0, 0, 0, 0x44], // 0x38 + 6 x 2
+            &[0, 0, 0, 0],
+            &[1, 0, 0, 2],
+            &[0, 0, 0, 0],
+            &[0, 1, 0x2E, 0xE0, 0, 0, 0x00, 0xFA], // first beat: 1, 120.00, 250 ms
+            &[0, 3, 0x2E, 0xE0, 0, 0, 0x0A, 0xBE], // last beat: 3, 120.00, 2750 ms
+            &[0, 0, 0, 6],
+            &[0; 12],
+            &[0, 1, 0, 2, 0, 3, 0, 1, 0, 2, 0, 3], // beat within the bar
+        ].concat();
+        assert_eq!(section, expected);
+    }
+
+    #[test]
+    fn test_variable_tempo_beat_grids() {
+        // 120 BPM, then 126 from 2 s
+        let grid = BeatGrid::variable_tempo(&[(0.0, 120.0), (2000.0, 126.0)], 4000.0, (4, 4));
+        let pqtz = generate_pqtz_section(&grid);
+        let pqt2 = generate_pqt2_section(&grid);
+
+        let count = u32::from_be_bytes(pqtz[20..24].try_into().unwrap()) as usize;
+        assert_eq!(count, grid.beats.len());
+        assert_eq!(pqtz.len(), 24 + count * 8);
+        let pqtz_beat = |i: usize| &pqtz[24 + i * 8..24 + (i + 1) * 8];
+        assert_eq!(u16::from_be_bytes([pqtz_beat(3)[2], pqtz_beat(3)[3]]), 12000);
+        assert_eq!(u16::from_be_bytes([pqtz_beat(4)[2], pqtz_beat(4)[3]]), 12600);
+        // 2000 + 476.19 ms, rounded
+        assert_eq!(u32::from_be_bytes(pqtz_beat(5)[4..8].try_into().unwrap()), 2476);
+        // Beat numbers restart at each downbeat across the tempo change
+        assert_eq!(u16::from_be_bytes([pqtz_beat(4)[0], pqtz_beat(4)[1]]), 1);
+
+        assert_eq!(u32::from_be_bytes(pqt2[0x28..0x2C].try_into().unwrap()) as usize, count);
+        assert_eq!(pqt2.len(), 0x38 + count * 2);
+        // The last beat has the last tempo and a rounded time, as in PQTZ
+        assert_eq!(pqt2[0x20..0x28], *pqtz_beat(count - 1));
+        assert_eq!(u16::from_be_bytes([pqt2[0x22], pqt2[0x23]]), 12600);
+        assert_eq!(pqt2[0x38 + 4 * 2..0x38 + 5 * 2], [0, 1]);
+    }
+
+    #[test]
+    fn test_dat_and_ext_share_the_beat_grid() {
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
+        let waveform = Waveform::default();
+
+        let ext_data = generate_ext_file(&grid, &waveform, "/Contents/test.mp3", &[]).unwrap();
+        let has_tag = |data: &[u8], tag: &[u8]| data.windows(4).any(|w| w == tag);
+        assert!(has_tag(&ext_data, b"PQTZ"));
+        assert!(has_tag(&ext_data, b"PQT2"));
+
+        let dat_data = generate_dat_file(&grid, &waveform, "/Contents/test.mp3").unwrap();
+        assert!(has_tag(&dat_data, b"PQTZ"));
+        assert!(!has_tag(&dat_data, b"PQT2"));
+    }
+
     #[test]
     fn test_ext_file_with_cues() {
         let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
@@ -716,6 +1130,8 @@ mod tests {
                 time_ms: 1000.0,
                 loop_ms: 0.0,
                 comment: None,
+                color: None,
+                memory_color_id: None,
             },
         ];
 
@@ -725,4 +1141,118 @@ mod tests {
         let ext_str = String::from_utf8_lossy(&ext_data);
         assert!(ext_str.contains("PCOB"));
     }
+
+    #[test]
+    fn test_coincident_cues_and_loops_all_written() {
+        let cues = vec![
+            CuePoint::hot_cue(1, 4_000.0, HotCueColor::RED).unwrap(),
+            CuePoint::hot_cue(2, 4_000.0, HotCueColor::CYAN).unwrap(),
+            CuePoint::loop_beats(4_000.0, 8.0, 128.0).unwrap(),
+            CuePoint::loop_beats(5_000.0, 4.0, 128.0).unwrap(),
+            CuePoint { time_ms: 4_000.0, ..Default::default() },
+        ];
+
+        let pcob = generate_pcob_section(&cues);
+        assert_eq!(u16::from_be_bytes([pcob[18], pcob[19]]), 5);
+
+        let pco2 = generate_pco2_section(&cues);
+        let count = |pos: usize| u16::from_be_bytes([pco2[pos + 18], pco2[pos + 19]]);
+        let memory = u32::from_be_bytes(pco2[8..12].try_into().unwrap()) as usize;
+        assert_eq!((count(0), count(memory)), (2, 3));
+        // The overlapping loops keep their own start and end
+        let be = |pos: usize| u32::from_be_bytes(pco2[pos..pos + 4].try_into().unwrap());
+        let first_loop = memory + 20;
+        assert_eq!((be(first_loop + 16), be(first_loop + 20)), (4_000, 7_750));
+        assert_eq!((be(first_loop + 40 + 16), be(first_loop + 40 + 20)), (5_000, 6_875));
+    }
+
+    /// Offsets of each section tag in an ANLZ file
+    fn section_offsets(data: &[u8]) -> Vec<(String, usize)> {
+        let mut pos = 28;
+        let mut sections = Vec::new();
+        while pos + 12 <= data.len() {
+            let len = u32::from_be_bytes(data[pos + 8..pos + 12].try_into().unwrap()) as usize;
+            sections.push((String::from_utf8_lossy(&data[pos..pos + 4]).into_owned(), pos));
+            pos += len;
+        }
+        sections
+    }
+
+    #[test]
+    fn test_2ex_has_three_band_waveforms() {
+        let detail = WaveformDetail {
+            entries: (0..3000)
+                .map(|i| WaveformColorEntry { red: 7, green: (i % 8) as u8, blue: 0, height: 31 })
+                .collect(),
+        };
+        let waveform = Waveform { detail, ..Waveform::default() };
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, 20_000.0);
+        let two_ex = generate_2ex_file(&grid, &waveform, "/Contents/test.mp3", &[]).unwrap();
+        let ext = generate_ext_file(&grid, &waveform, "/Contents/test.mp3", &[]).unwrap();
+
+        let tags: Vec<String> = section_offsets(&two_ex).into_iter().map(|(tag, _)| tag).collect();
+        assert_eq!(tags, ["PPTH", "PQTZ", "PQT2", "PWV7", "PWV6"]);
+        assert_ne!(two_ex, ext);
+
+        let be = |data: &[u8], pos: usize| u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap());
+        let offsets: HashMap<String, usize> = section_offsets(&two_ex).into_iter().collect();
+        let pwv7 = offsets["PWV7"];
+        assert_eq!(be(&two_ex, pwv7 + 8), 24 + 3000 * 3);
+        assert_eq!(be(&two_ex, pwv7 + 12), 3);
+        assert_eq!(be(&two_ex, pwv7 + 16), 3000);
+        // Entries are mid, high, low: entry 3 has green 3 of 7
+        assert_eq!(two_ex[pwv7 + 24 + 9..pwv7 + 24 + 12], [109, 0, 255]);
+
+        let pwv6 = offsets["PWV6"];
+        assert_eq!(be(&two_ex, pwv6 + 8), 20 + 1200 * 3);
+        assert_eq!(be(&two_ex, pwv6 + 16), 1200);
+        // 2.5 detail entries per column: the loudest mid band is kept
+        assert_eq!(two_ex[pwv6 + 20 + 3 * 3..pwv6 + 20 + 3 * 4], [255, 0, 255]);
+
+        // No detail: PWV7 is empty, PWV6 is still 1200 silent columns
+        let empty = generate_2ex_file(&grid, &Waveform::default(), "/Contents/test.mp3", &[]).unwrap();
+        let offsets: HashMap<String, usize> = section_offsets(&empty).into_iter().collect();
+        assert_eq!(be(&empty, offsets["PWV7"] + 16), 0);
+        assert_eq!(be(&empty, offsets["PWV6"] + 8), 20 + 1200 * 3);
+    }
+
+    #[test]
+    fn test_custom_layout_orders_sections() {
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
+        let waveform = Waveform::default();
+        let cues = vec![CuePoint {
+            hot_cue: 1,
+            cue_type: CueType::Cue,
+            time_ms: 1000.0,
+            loop_ms: 0.0,
+            comment: None,
+            color: None,
+            memory_color_id: None,
+        }];
+        // Walk the section headers: tag at +0, section length at +8
+        let tags = |data: &[u8]| {
+            let mut tags = Vec::new();
+            let mut offset = 28;
+            while offset + 12 <= data.len() {
+                tags.push(String::from_utf8_lossy(&data[offset..offset + 4]).into_owned());
+                offset += u32::from_be_bytes(data[offset + 8..offset + 12].try_into().unwrap()) as usize;
+            }
+            assert_eq!(offset, data.len());
+            tags
+        };
+
+        let default = tags(&generate_ext_file(&grid, &waveform, "/Contents/test.mp3", &cues).unwrap());
+        assert_eq!(default, ["PPTH", "PQTZ", "PQT2", "PWAV", "PWV3", "PWV4", "PWV5", "PCOB", "PCO2"]);
+
+        let layout: AnlzLayout = "PPTH, PCO2,PWV5".parse().unwrap();
+        let data = generate_ext_file_with_layout(&grid, &waveform, "/Contents/test.mp3", &cues, &layout).unwrap();
+        let custom = tags(&data);
+        assert_eq!(custom, ["PPTH", "PCO2", "PWV5", "PQTZ", "PQT2", "PWAV", "PWV3", "PWV4", "PCOB"]);
+        let position = |tag| custom.iter().position(|t| t == tag).unwrap();
+        assert!(position("PCO2") < position("PWV5"));
+        assert_eq!(u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize, data.len());
+
+        assert!("PQTZ,PXXX".parse::<AnlzLayout>().is_err());
+        assert!("PQTZ,PQTZ".parse::<AnlzLayout>().is_err());
+    }
 }
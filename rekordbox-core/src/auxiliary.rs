@@ -4,9 +4,14 @@
 //! - DEVSETTING.DAT: Device settings file
 //! - djprofile.nxs: DJ profile information
 //! - Artwork: Album art thumbnails and full images
+//! - masterPlaylists6.xml: playlist UUIDs and order for rekordbox 6 import
+//! - DeviceLibBackup info: device UUID and backup date
 
-use std::io::Write;
-use crate::error::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+
+use crate::pdb::PlaylistInfo;
 
 /// rekordbox version string for DEVSETTING.DAT
 const REKORDBOX_VERSION: &str = "6.8.4";
@@ -105,6 +110,104 @@ pub fn artwork_full_name(artwork_id: u32) -> String {
     format!("a{}_m.jpg", artwork_id)
 }
 
+/// Highest artwork id handed out, so folder numbers stay within 5 digits
+pub const MAX_ARTWORK_ID: u32 = 999_999;
+
+/// Artwork id derived from the image (or its path), stable across exports
+///
+/// Decks cache art by file name, so the same image must keep its `a{id}.jpg`
+/// name no matter which tracks are exported or in what order. Ids are in
+/// 1..=MAX_ARTWORK_ID; on a collision the caller probes with
+/// `id % MAX_ARTWORK_ID + 1`.
+pub fn stable_artwork_id(key: &[u8]) -> u32 {
+    (xxh3_64(key) % MAX_ARTWORK_ID as u64) as u32 + 1
+}
+
+/// Read the artwork id back out of a thumbnail path (`.../a{id}.jpg`)
+pub fn parse_artwork_id(path: &str) -> Option<u32> {
+    let name = path.rsplit('/').next()?;
+    name.strip_prefix('a')?.strip_suffix(".jpg")?.parse().ok()
+}
+
+/// Where an export's dates and device UUID come from
+///
+/// `Fixed` pins both, so exporting the same library twice gives identical
+/// files (for byte-for-byte checks in CI).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportClock {
+    /// The current time
+    #[default]
+    System,
+    /// This many seconds after the Unix epoch
+    Fixed(u64),
+}
+
+impl ExportClock {
+    /// Fixed at 1970-01-01 00:00:00 UTC
+    pub const EPOCH: ExportClock = ExportClock::Fixed(0);
+
+    /// Nanoseconds since the Unix epoch
+    fn unix_nanos(&self) -> u128 {
+        match self {
+            ExportClock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+            ExportClock::Fixed(secs) => *secs as u128 * 1_000_000_000,
+        }
+    }
+
+    fn unix_secs(&self) -> u64 {
+        (self.unix_nanos() / 1_000_000_000) as u64
+    }
+
+    /// UUID-like device id (32 hex chars) from the current time
+    pub fn uuid(&self) -> String {
+        format!("{:032x}", self.unix_nanos())
+    }
+
+    /// UTC date as YYYY-MM-DD
+    pub fn iso_date(&self) -> String {
+        let (year, month, day) = civil_from_days((self.unix_secs() / 86_400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// UTC date and time as YYYY/MM/DD HH:MM:SS
+    pub fn date_time(&self) -> String {
+        let secs = self.unix_secs();
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let time_secs = secs % 86_400;
+        format!("{}/{:02}/{:02} {:02}:{:02}:{:02}",
+                year, month, day, time_secs / 3600, (time_secs % 3600) / 60, time_secs % 60)
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date
+/// (Howard Hinnant's days_from_civil inverse)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 for a civil date (Howard Hinnant's days_from_civil)
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
 /// DeviceLibBackup info JSON structure
 #[derive(Debug, Clone)]
 pub struct DeviceBackupInfo {
@@ -117,19 +220,18 @@ pub struct DeviceBackupInfo {
 impl DeviceBackupInfo {
     /// Generate a new UUID for the device
     pub fn new_uuid() -> String {
-        // Generate a simple UUID-like string (32 hex chars)
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos();
-        format!("{:032x}", timestamp)
+        ExportClock::System.uuid()
     }
 }
 
-/// Generate rbDevLibBaInfo JSON content
-pub fn generate_device_backup_info(info: &DeviceBackupInfo, pc_id: u32) -> String {
-    let now = chrono_lite_format();
+/// File name of the DeviceLibBackup info JSON for a backup PC id
+pub fn device_backup_info_name(pc_id: u32) -> String {
+    format!("rbDevLibBaInfo_{}.json", pc_id)
+}
+
+/// Generate rbDevLibBaInfo JSON content, dated by `clock`
+pub fn generate_device_backup_info(info: &DeviceBackupInfo, pc_id: u32, clock: &ExportClock) -> String {
+    let now = clock.date_time();
     
     format!(r#"{{
   "uuid": "{}",
@@ -161,34 +263,96 @@ pub fn generate_device_backup_info(info: &DeviceBackupInfo, pc_id: u32) -> Strin
     )
 }
 
-/// Simple date/time formatter (YYYY/MM/DD HH:MM:SS)
-fn chrono_lite_format() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let secs = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    
-    // Simple UTC conversion (not accurate for all timezones but sufficient)
-    let days = secs / 86400;
-    let time_secs = secs % 86400;
-    let hours = time_secs / 3600;
-    let minutes = (time_secs % 3600) / 60;
-    let seconds = time_secs % 60;
-    
-    // Approximate date calculation (good enough for backup timestamp)
-    let year = 1970 + (days / 365);
-    let day_of_year = days % 365;
-    let month = (day_of_year / 30) + 1;
-    let day = (day_of_year % 30) + 1;
-    
-    format!("{}/{:02}/{:02} {:02}:{:02}:{:02}",
-            year, month.min(12), day.min(28), hours, minutes, seconds)
+/// Stable UUID for a playlist, derived from its id, whether it's a folder
+/// and its name path (folders included) so re-exports of the same tree keep
+/// the same ids while a folder and a playlist of the same name don't clash
+pub fn playlist_uuid(id: u32, is_folder: bool, name_path: &[&str]) -> String {
+    let mut key = id.to_le_bytes().to_vec();
+    key.push(is_folder as u8);
+    key.extend_from_slice(name_path.join("\u{0}").as_bytes());
+    let hash = xxh3_128(&key);
+    let hex = format!("{:032x}", hash);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Generate masterPlaylists6.xml for the playlist tree
+///
+/// One NODE per playlist or folder, in the given (sort) order, with
+/// `ParentId` pointing at the parent folder's UUID or "root". Without it
+/// rekordbox 6 sorts imported playlists alphabetically.
+pub fn generate_master_playlists_xml(playlists: &[PlaylistInfo]) -> String {
+    let uuid_of = |id: u32| {
+        playlists.iter()
+            .find(|p| p.is_folder && p.id == id)
+            .map(|folder| playlist_uuid(folder.id, true, &name_path(folder, playlists)))
+            .unwrap_or_else(|| "root".to_string())
+    };
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<MASTER_PLAYLIST Version=\"1.0.0\" AutomaticSync=\"0\">\n");
+    xml.push_str(&format!(
+        "  <PRODUCT Name=\"rekordbox\" Version=\"{}\" Company=\"AlphaTheta\"/>\n",
+        REKORDBOX_VERSION
+    ));
+    xml.push_str("  <PLAYLISTS>\n");
+    for playlist in playlists {
+        xml.push_str(&format!(
+            "    <NODE Id=\"{}\" ParentId=\"{}\" Attribute=\"{}\" Timestamp=\"0\" Lib_Type=\"0\" CheckType=\"0\" Seq=\"{}\" Name=\"{}\"/>\n",
+            playlist_uuid(playlist.id, playlist.is_folder, &name_path(playlist, playlists)),
+            uuid_of(playlist.parent_id),
+            playlist.is_folder as u8,
+            playlist.sort_order,
+            escape_xml(&playlist.name),
+        ));
+    }
+    xml.push_str("  </PLAYLISTS>\n</MASTER_PLAYLIST>\n");
+    xml
+}
+
+/// Names from the top-level folder down to `playlist`
+fn name_path<'a>(playlist: &'a PlaylistInfo, playlists: &'a [PlaylistInfo]) -> Vec<&'a str> {
+    let mut path = vec![playlist.name.as_str()];
+    let mut parent = playlist.parent_id;
+    // Bounded walk, so a parent cycle can't hang the export
+    for _ in 0..playlists.len() {
+        let Some(folder) = playlists.iter().find(|p| p.is_folder && p.id == parent) else { break };
+        path.push(folder.name.as_str());
+        parent = folder.parent_id;
+    }
+    path.reverse();
+    path
+}
+
+/// Escape text for an XML attribute value
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_civil_date_conversions() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_889), (2024, 6, 15));
+        assert_eq!(days_from_civil(2024, 6, 15), 19_889);
+        for days in [-719_468, -1, 0, 59, 11_016, 19_889, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
     
     #[test]
     fn test_devsetting_generation() {
@@ -212,6 +376,15 @@ mod tests {
         assert_eq!(u32::from_le_bytes([data[0x68], data[0x69], data[0x6A], data[0x6B]]), 0x12345678);
     }
     
+    #[test]
+    fn test_fixed_clock() {
+        let clock = ExportClock::Fixed(1_718_454_645); // 2024-06-15 12:30:45 UTC
+        assert_eq!(clock.iso_date(), "2024-06-15");
+        assert_eq!(clock.date_time(), "2024/06/15 12:30:45");
+        assert_eq!(clock.uuid(), ExportClock::Fixed(1_718_454_645).uuid());
+        assert_ne!(clock.uuid(), ExportClock::EPOCH.uuid());
+    }
+
     #[test]
     fn test_djprofile_generation() {
         let data = generate_djprofile("Test DJ");
@@ -225,6 +398,53 @@ mod tests {
         assert_eq!(data[0x27], 0);
     }
     
+    #[test]
+    fn test_master_playlists_xml_order_and_uuids() {
+        let playlist = |id, parent_id, name: &str, is_folder, sort_order| PlaylistInfo {
+            id,
+            parent_id,
+            name: name.to_string(),
+            is_folder,
+            sort_order,
+            track_ids: Vec::new(),
+        };
+        let playlists = vec![
+            playlist(1, 0, "Zeta", false, 0),
+            playlist(2, 0, "Gigs", true, 1),
+            playlist(3, 2, "Friday & Co", false, 2),
+            playlist(4, 0, "Alpha", false, 3),
+        ];
+
+        let xml = generate_master_playlists_xml(&playlists);
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let nodes: Vec<_> = doc.descendants().filter(|n| n.has_tag_name("NODE")).collect();
+
+        // User order, not alphabetical
+        let names: Vec<_> = nodes.iter().map(|n| n.attribute("Name").unwrap()).collect();
+        assert_eq!(names, vec!["Zeta", "Gigs", "Friday & Co", "Alpha"]);
+
+        for node in &nodes {
+            let id = node.attribute("Id").unwrap();
+            assert_eq!(id.len(), 36);
+            assert_eq!(id.matches('-').count(), 4);
+        }
+        assert_eq!(nodes[0].attribute("ParentId"), Some("root"));
+        assert_eq!(nodes[1].attribute("Attribute"), Some("1"));
+        assert_eq!(nodes[2].attribute("ParentId"), nodes[1].attribute("Id"));
+
+        // Same tree, same ids
+        assert_eq!(generate_master_playlists_xml(&playlists), xml);
+        assert_eq!(nodes[2].attribute("Id").unwrap(), playlist_uuid(3, false, &["Gigs", "Friday & Co"]));
+
+        // A folder and a playlist sharing a name get different ids
+        let twins = vec![playlist(1, 0, "Gigs", true, 0), playlist(2, 0, "Gigs", false, 1)];
+        let xml = generate_master_playlists_xml(&twins);
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let ids: Vec<_> = doc.descendants().filter_map(|n| n.attribute("Id")).collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
     #[test]
     fn test_artwork_paths() {
         assert_eq!(artwork_folder_path(1), "PIONEER/Artwork/00001");
@@ -234,5 +454,14 @@ mod tests {
         
         assert_eq!(artwork_thumbnail_name(42), "a42.jpg");
         assert_eq!(artwork_full_name(42), "a42_m.jpg");
+        assert_eq!(artwork_folder_path(MAX_ARTWORK_ID), "PIONEER/Artwork/10000");
+
+        assert_eq!(parse_artwork_id("/PIONEER/Artwork/00001/a42.jpg"), Some(42));
+        assert_eq!(parse_artwork_id("/PIONEER/Artwork/00001/a42_m.jpg"), None);
+        assert_eq!(parse_artwork_id("/PIONEER/Artwork/00001/cover.jpg"), None);
+
+        let id = stable_artwork_id(b"image bytes");
+        assert_eq!(id, stable_artwork_id(b"image bytes"));
+        assert!((1..=MAX_ARTWORK_ID).contains(&id));
     }
 }
@@ -3,16 +3,23 @@
 //! Stores analysis results on disk keyed by file hash.
 //! This is critical for memory-constrained environments.
 
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{self, File};
 use std::io::{BufReader, BufWriter, Read};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use serde::{Deserialize, Serialize};
 use xxhash_rust::xxh3::xxh3_64;
 
 use crate::error::{Error, Result};
 use crate::track::TrackAnalysis;
 
 /// File-based cache for track analysis results
+///
+/// Holds only the cache directory, so clones are cheap and can be handed to
+/// long-running tasks.
+#[derive(Debug, Clone)]
 pub struct AnalysisCache {
     cache_dir: PathBuf,
 }
@@ -29,9 +36,30 @@ impl AnalysisCache {
     fn cache_key(file_hash: u64) -> String {
         format!("{:016x}.json", file_hash)
     }
+
+    /// The hash a cache entry file is stored under, or None for other files
+    fn entry_hash(path: &Path) -> Option<u64> {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_suffix(".json"))
+            .filter(|h| h.len() == 16)
+            .and_then(|h| u64::from_str_radix(h, 16).ok())
+    }
     
-    /// Get cached analysis if it exists and is valid
-    pub fn get(&self, file_hash: u64) -> Option<TrackAnalysis> {
+    /// Get cached analysis if it exists, is valid and was made with
+    /// `settings`
+    ///
+    /// `settings` fingerprints whatever analysis options shape the result
+    /// (the caller decides); an entry made with other settings, or in an
+    /// older `CACHE_FORMAT`, is a miss.
+    pub fn get(&self, file_hash: u64, settings: u64) -> Option<TrackAnalysis> {
+        self.read_entry(file_hash)
+            .filter(|entry| entry.format == CACHE_FORMAT && entry.settings == settings)
+            .map(|entry| entry.analysis)
+    }
+
+    /// Read a cache entry whatever settings it was made with
+    fn read_entry(&self, file_hash: u64) -> Option<CacheEntry<TrackAnalysis>> {
         let key = Self::cache_key(file_hash);
         let path = self.cache_dir.join(&key);
         
@@ -44,14 +72,14 @@ impl AnalysisCache {
         serde_json::from_reader(reader).ok()
     }
     
-    /// Store analysis result in cache
-    pub fn put(&self, analysis: &TrackAnalysis) -> Result<()> {
+    /// Store analysis result in cache, made with `settings` (see `get`)
+    pub fn put(&self, analysis: &TrackAnalysis, settings: u64) -> Result<()> {
         let key = Self::cache_key(analysis.file_hash);
         let path = self.cache_dir.join(&key);
         
         let file = File::create(&path)?;
         let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, analysis)?;
+        serde_json::to_writer(writer, &CacheEntry { format: CACHE_FORMAT, settings, analysis })?;
         
         Ok(())
     }
@@ -68,10 +96,13 @@ impl AnalysisCache {
     }
     
     /// Clear entire cache
+    ///
+    /// Track ids are kept, so a re-analyzed library keeps the ids already
+    /// on its sticks.
     pub fn clear(&self) -> Result<()> {
         for entry in fs::read_dir(&self.cache_dir)? {
             let entry = entry?;
-            if entry.path().extension().map(|e| e == "json").unwrap_or(false) {
+            if Self::entry_hash(&entry.path()).is_some() {
                 fs::remove_file(entry.path())?;
             }
         }
@@ -85,7 +116,7 @@ impl AnalysisCache {
         
         for entry in fs::read_dir(&self.cache_dir)? {
             let entry = entry?;
-            if entry.path().extension().map(|e| e == "json").unwrap_or(false) {
+            if Self::entry_hash(&entry.path()).is_some() {
                 count += 1;
                 total_size += entry.metadata()?.len();
             }
@@ -96,6 +127,155 @@ impl AnalysisCache {
             total_size_bytes: total_size,
         })
     }
+
+    /// List cached hashes with a summary of each stored analysis
+    ///
+    /// Sorted by hash. Files that aren't cache entries or no longer parse are
+    /// left out.
+    pub fn entries(&self) -> Result<Vec<(u64, CacheEntryMeta)>> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(&self.cache_dir)? {
+            let entry = entry?;
+            let Some(hash) = Self::entry_hash(&entry.path()) else {
+                continue;
+            };
+            let Some(CacheEntry { analysis, .. }) = self.read_entry(hash) else {
+                continue;
+            };
+
+            entries.push((hash, CacheEntryMeta {
+                title: analysis.title,
+                artist: analysis.artist,
+                file_path: analysis.file_path,
+                modified: entry.metadata()?.modified()?,
+            }));
+        }
+
+        entries.sort_by_key(|(hash, _)| *hash);
+        Ok(entries)
+    }
+
+    /// Track ids handed out by earlier runs (empty on first use)
+    ///
+    /// A file that exists but doesn't parse is an error and is left as it
+    /// is: numbering from scratch would give tracks already on a stick new
+    /// ids.
+    pub fn track_ids(&self) -> Result<TrackIds> {
+        let path = self.cache_dir.join(TRACK_IDS_FILE);
+        if !path.exists() {
+            return Ok(TrackIds::default());
+        }
+        let mut ids: TrackIds = serde_json::from_reader(BufReader::new(File::open(&path)?))
+            .map_err(|e| Error::Cache(format!(
+                "{:?} is unreadable ({}); restore it, or remove it to number tracks from scratch",
+                path, e
+            )))?;
+        ids.highest = ids.assigned.values().flatten().map(|a| a.id).max().unwrap_or(0);
+        Ok(ids)
+    }
+
+    /// Persist track ids for the next run
+    ///
+    /// Written to a temporary file and renamed over the old one, so an
+    /// interrupted save leaves the previous ids in place.
+    pub fn save_track_ids(&self, ids: &TrackIds) -> Result<()> {
+        let path = self.cache_dir.join(TRACK_IDS_FILE);
+        let temp = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut writer, ids)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp, &path)?;
+        Ok(())
+    }
+}
+
+/// Version of what analysis writes into cache entries
+///
+/// Bump it when the analyzer starts filling in a field older entries lack
+/// (they would otherwise keep its default until re-analyzed), so existing
+/// entries miss and get analyzed again.
+const CACHE_FORMAT: u32 = 1;
+
+/// A cache entry file: the analysis plus the format and settings it was
+/// made with
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<A> {
+    /// Entries written before the format was recorded read as 0
+    #[serde(default)]
+    format: u32,
+    /// Entries written before settings were recorded read as 0
+    #[serde(default)]
+    settings: u64,
+    #[serde(flatten)]
+    analysis: A,
+}
+
+/// File in the cache directory holding `TrackIds`
+const TRACK_IDS_FILE: &str = "track_ids.json";
+
+/// Stable track ids, keyed by file hash
+///
+/// A file keeps its id from run to run (and across moves and renames), so
+/// re-exporting doesn't reshuffle the ids hot cues and playlists on a stick
+/// refer to. Files whose sampled hashes collide are told apart by path and
+/// each get an id of their own. Ids of removed files are never reused.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackIds {
+    assigned: BTreeMap<u64, Vec<AssignedId>>,
+    /// Ids handed out since loading
+    #[serde(skip)]
+    claimed: HashSet<u32>,
+    #[serde(skip)]
+    highest: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AssignedId {
+    id: u32,
+    path: String,
+}
+
+impl TrackIds {
+    /// Id for the file at `path`, reusing the one its hash had before
+    ///
+    /// New files are numbered from `first_id` or after the highest id ever
+    /// assigned, whichever is larger.
+    pub fn assign(&mut self, file_hash: u64, path: &str, first_id: u32) -> u32 {
+        let assigned = self.assigned.entry(file_hash).or_default();
+        let previous = assigned.iter().position(|a| a.path == path)
+            .or_else(|| assigned.iter().position(|a| !self.claimed.contains(&a.id)));
+        let id = match previous {
+            Some(index) => {
+                assigned[index].path = path.to_string();
+                assigned[index].id
+            }
+            None => {
+                let id = first_id.max(self.highest + 1);
+                self.highest = id;
+                assigned.push(AssignedId { id, path: path.to_string() });
+                id
+            }
+        };
+        self.claimed.insert(id);
+        id
+    }
+
+    /// Whether another file has taken an id under this hash since loading
+    pub fn claimed(&self, file_hash: u64) -> bool {
+        self.assigned.get(&file_hash)
+            .is_some_and(|assigned| assigned.iter().any(|a| self.claimed.contains(&a.id)))
+    }
+}
+
+/// Summary of one cached analysis
+#[derive(Debug, Clone)]
+pub struct CacheEntryMeta {
+    pub title: String,
+    pub artist: String,
+    pub file_path: String,
+    /// When the entry was written
+    pub modified: SystemTime,
 }
 
 /// Cache statistics
@@ -105,6 +285,9 @@ pub struct CacheStats {
     pub total_size_bytes: u64,
 }
 
+/// Bytes from the start of a file that go into its hash
+pub const HASH_SAMPLE_SIZE: usize = 1024 * 1024;
+
 /// Compute file hash for cache invalidation
 /// Uses XXH3 on a sample of the file (first 1MB + file size) for speed
 pub fn compute_file_hash<P: AsRef<Path>>(path: P) -> Result<u64> {
@@ -112,16 +295,29 @@ pub fn compute_file_hash<P: AsRef<Path>>(path: P) -> Result<u64> {
     let file_size = metadata.len();
     
     // Read first 1MB (or entire file if smaller)
-    let sample_size = std::cmp::min(file_size as usize, 1024 * 1024);
-    let mut sample = vec![0u8; sample_size + 8];
+    let sample_size = std::cmp::min(file_size as usize, HASH_SAMPLE_SIZE);
+    let mut sample = vec![0u8; sample_size];
     
     let mut file = File::open(&path)?;
-    file.read_exact(&mut sample[..sample_size])?;
+    file.read_exact(&mut sample)?;
     
+    Ok(file_hash_from_prefix(&sample, file_size))
+}
+
+/// The `compute_file_hash` of a file, from its first bytes (only the first
+/// `HASH_SAMPLE_SIZE` are used) and its size
+///
+/// For callers that have already read the start of the file for another
+/// purpose, such as probing its format.
+pub fn file_hash_from_prefix(prefix: &[u8], file_size: u64) -> u64 {
+    let sample_size = prefix.len().min(HASH_SAMPLE_SIZE);
+    let mut sample = Vec::with_capacity(sample_size + 8);
+    sample.extend_from_slice(&prefix[..sample_size]);
+
     // Append file size to sample for uniqueness
-    sample[sample_size..].copy_from_slice(&file_size.to_le_bytes());
-    
-    Ok(xxh3_64(&sample))
+    sample.extend_from_slice(&file_size.to_le_bytes());
+
+    xxh3_64(&sample)
 }
 
 #[cfg(test)]
@@ -136,23 +332,16 @@ mod tests {
             file_path: "test.mp3".into(),
             title: "Test Track".into(),
             artist: "Test Artist".into(),
-            album: None,
-            genre: None,
             duration_secs: 180.0,
             sample_rate: 44100,
             bit_depth: 16,
+            channels: 2,
             bitrate: 320,
             bpm: 128.0,
-            key: None,
-            beat_grid: BeatGrid::default(),
-            waveform: Waveform::default(),
-            cue_points: Vec::new(),
             file_size: 5_000_000,
             file_hash: 0x12345678ABCDEF00,
-            year: None,
-            comment: None,
-            track_number: None,
             file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
         }
     }
     
@@ -164,8 +353,8 @@ mod tests {
         let analysis = make_test_analysis();
         
         // Store and retrieve
-        cache.put(&analysis).unwrap();
-        let retrieved = cache.get(analysis.file_hash).unwrap();
+        cache.put(&analysis, 0).unwrap();
+        let retrieved = cache.get(analysis.file_hash, 0).unwrap();
         
         assert_eq!(retrieved.id, analysis.id);
         assert_eq!(retrieved.title, analysis.title);
@@ -177,9 +366,38 @@ mod tests {
         let tmp = TempDir::new().unwrap();
         let cache = AnalysisCache::new(tmp.path()).unwrap();
         
-        let result = cache.get(0xDEADBEEF);
+        let result = cache.get(0xDEADBEEF, 0);
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_cache_miss_on_other_settings() {
+        let tmp = TempDir::new().unwrap();
+        let cache = AnalysisCache::new(tmp.path()).unwrap();
+        let analysis = make_test_analysis();
+
+        cache.put(&analysis, 7).unwrap();
+        assert!(cache.get(analysis.file_hash, 7).is_some());
+        assert!(cache.get(analysis.file_hash, 8).is_none());
+        // Listing doesn't care what the entry was made with
+        assert_eq!(cache.entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cache_miss_on_older_format() {
+        let tmp = TempDir::new().unwrap();
+        let cache = AnalysisCache::new(tmp.path()).unwrap();
+        let analysis = make_test_analysis();
+
+        // An entry from before the format was recorded
+        let entry = CacheEntry { format: 0, settings: 7, analysis: &analysis };
+        let path = tmp.path().join(AnalysisCache::cache_key(analysis.file_hash));
+        fs::write(&path, serde_json::to_vec(&entry).unwrap()).unwrap();
+        assert!(cache.get(analysis.file_hash, 7).is_none());
+
+        cache.put(&analysis, 7).unwrap();
+        assert!(cache.get(analysis.file_hash, 7).is_some());
+    }
     
     #[test]
     fn test_cache_stats() {
@@ -187,23 +405,93 @@ mod tests {
         let cache = AnalysisCache::new(tmp.path()).unwrap();
         
         let mut analysis = make_test_analysis();
-        cache.put(&analysis).unwrap();
+        cache.put(&analysis, 0).unwrap();
         
         analysis.file_hash = 0x9999;
-        cache.put(&analysis).unwrap();
+        cache.put(&analysis, 0).unwrap();
         
         let stats = cache.stats().unwrap();
         assert_eq!(stats.entry_count, 2);
         assert!(stats.total_size_bytes > 0);
     }
     
+    #[test]
+    fn test_cache_entries() {
+        let tmp = TempDir::new().unwrap();
+        let cache = AnalysisCache::new(tmp.path()).unwrap();
+        
+        let mut analysis = make_test_analysis();
+        cache.put(&analysis, 0).unwrap();
+        
+        analysis.file_hash = 0x9999;
+        analysis.title = "Second Track".into();
+        cache.put(&analysis, 0).unwrap();
+        fs::write(tmp.path().join("notes.txt"), "not an entry").unwrap();
+        
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 0x9999);
+        assert_eq!(entries[0].1.title, "Second Track");
+        assert_eq!(entries[1].0, 0x12345678ABCDEF00);
+        assert_eq!(entries[1].1.title, "Test Track");
+        assert_eq!(entries[1].1.artist, "Test Artist");
+        assert!(entries[1].1.modified <= SystemTime::now());
+    }
+    
+    #[test]
+    fn test_track_ids_stable_across_runs() {
+        let tmp = TempDir::new().unwrap();
+        let cache = AnalysisCache::new(tmp.path()).unwrap();
+
+        let mut ids = cache.track_ids().unwrap();
+        assert_eq!(ids.assign(0xA, "/music/a.mp3", 1), 1);
+        assert_eq!(ids.assign(0xB, "/music/b.mp3", 1), 2);
+        // Same sampled hash, different file: a fresh id
+        assert!(ids.claimed(0xA));
+        assert_eq!(ids.assign(0xA, "/music/copy of a.mp3", 1), 3);
+        cache.save_track_ids(&ids).unwrap();
+
+        // Walked in another order, with a rename and a new file
+        let mut ids = cache.track_ids().unwrap();
+        assert!(!ids.claimed(0xA));
+        assert_eq!(ids.assign(0xC, "/music/c.mp3", 1), 4);
+        assert_eq!(ids.assign(0xA, "/music/copy of a.mp3", 1), 3);
+        assert_eq!(ids.assign(0xB, "/music/renamed.mp3", 1), 2);
+        assert_eq!(ids.assign(0xA, "/music/a.mp3", 1), 1);
+        assert_eq!(ids.assign(0xD, "/music/d.mp3", 100), 100);
+
+        // Not a cache entry
+        cache.save_track_ids(&ids).unwrap();
+        cache.clear().unwrap();
+        assert_eq!(cache.stats().unwrap().entry_count, 0);
+        assert_eq!(cache.track_ids().unwrap().assign(0xD, "/music/d.mp3", 1), 100);
+    }
+
+    #[test]
+    fn test_unreadable_track_ids_kept() {
+        let tmp = TempDir::new().unwrap();
+        let cache = AnalysisCache::new(tmp.path()).unwrap();
+        let mut ids = cache.track_ids().unwrap();
+        ids.assign(0xA, "/music/a.mp3", 1);
+        cache.save_track_ids(&ids).unwrap();
+        let files: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(files, [TRACK_IDS_FILE]);
+
+        // A truncated file is an error, not a fresh start, and stays put
+        let path = tmp.path().join(TRACK_IDS_FILE);
+        let truncated = &fs::read(&path).unwrap()[..10];
+        fs::write(&path, truncated).unwrap();
+        assert!(matches!(cache.track_ids(), Err(Error::Cache(_))));
+        assert_eq!(fs::read(&path).unwrap(), truncated);
+    }
+
     #[test]
     fn test_cache_clear() {
         let tmp = TempDir::new().unwrap();
         let cache = AnalysisCache::new(tmp.path()).unwrap();
         
         let analysis = make_test_analysis();
-        cache.put(&analysis).unwrap();
+        cache.put(&analysis, 0).unwrap();
         
         cache.clear().unwrap();
         
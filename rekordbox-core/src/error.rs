@@ -36,6 +36,9 @@ pub enum Error {
 
     #[error("Validation error: {0}")]
     Validation(String),
+
+    #[error("XML error: {0}")]
+    Xml(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -51,3 +54,9 @@ impl From<serde_json::Error> for Error {
         Error::Cache(e.to_string())
     }
 }
+
+impl From<roxmltree::Error> for Error {
+    fn from(e: roxmltree::Error) -> Self {
+        Error::Xml(e.to_string())
+    }
+}
@@ -11,21 +11,32 @@ pub mod error;
 pub mod string;
 pub mod page;
 pub mod pdb;
+pub mod reader;
 pub mod anlz;
 pub mod track;
 pub mod cache;
 pub mod validate;
 pub mod auxiliary;
+pub mod xml;
 
 // Re-exports for convenience
 pub use error::{Error, Result};
 pub use track::{TrackAnalysis, BeatGrid, Beat, Waveform, WaveformPreview, WaveformDetail,
                 WaveformColumn, WaveformColorEntry, WaveformColorPreview, WaveformColorPreviewColumn,
-                Key, FileType, CuePoint, CueType, HotCueColor};
-pub use pdb::PdbBuilder;
-pub use anlz::{generate_dat_file, generate_ext_file, generate_2ex_file, generate_anlz_path};
-pub use cache::{AnalysisCache, CacheStats, compute_file_hash};
-pub use validate::{validate_pdb, validate_and_print, ValidationResult, PdbStats};
+                Key, FileType, CuePoint, CueType, HotCueColor, MemoryCueColor, derive_sort_name, round_bpm,
+                assign_cue_slots, quantize_cues, MAX_HOT_CUES};
+pub use pdb::{PdbBuilder, VARIOUS_ARTISTS, EnergyField, PlaylistInfo, HistoryPlaylistInfo, PageFill, PdbLayout, SkippedTrack, update_track_in_pdb, update_or_rebuild_track, read_track_marker,
+              read_sequence, DEFAULT_COLORS, DEFAULT_SEQUENCE, DEFAULT_TOOL_MARKER};
+pub use reader::{PdbReader, Rows, TrackRow, TrackRows, ArtistRow, AlbumRow, NamedRow, PlaylistRow, PlaylistEntryRow};
+pub use anlz::{generate_dat_file, generate_ext_file, generate_2ex_file, generate_ext_file_with_layout,
+               generate_2ex_file_with_layout, generate_anlz_path, AnlzLayout};
+pub use cache::{AnalysisCache, CacheEntryMeta, CacheStats, TrackIds, compute_file_hash, file_hash_from_prefix,
+                HASH_SAMPLE_SIZE};
+pub use validate::{validate_pdb, validate_anlz, AnlzValidation, validate_and_print, validate_to_json, validate_verbose, ValidationResult,
+                   PdbStats, PageReport};
 pub use auxiliary::{generate_devsetting, generate_djprofile, artwork_folder_path,
                     artwork_thumbnail_name, artwork_full_name, ARTWORK_THUMBNAIL_SIZE,
-                    ARTWORK_FULL_SIZE};
+                    ARTWORK_FULL_SIZE, generate_master_playlists_xml, playlist_uuid,
+                    stable_artwork_id, parse_artwork_id, MAX_ARTWORK_ID, ExportClock,
+                    DeviceBackupInfo, generate_device_backup_info, device_backup_info_name};
+pub use xml::import_collection_xml;
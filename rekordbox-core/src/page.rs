@@ -44,7 +44,7 @@ pub const EMPTY_TABLE_MARKER: u32 = 0x03FFFFFF;
 /// All 20 tables (types 0-19) must be present for rekordbox PC compatibility
 /// Values from Kaitai struct spec: rekordbox_pdb.ksy
 #[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PageType {
     Tracks = 0,
     Genres = 1,
@@ -119,7 +119,12 @@ impl IndexPageBuilder {
             page_type,
         }
     }
-    
+
+    /// Get the table type this index page belongs to
+    pub fn page_type(&self) -> PageType {
+        self.page_type
+    }
+
     /// Finalize the index page
     /// - data_page_index: the data page that follows (or EMPTY_TABLE_MARKER if empty)
     /// - has_data: whether there's actual data in the data page
@@ -253,14 +258,13 @@ impl PageBuilder {
         let index_size = num_groups * ROW_GROUP_SIZE;
         let index_start = PAGE_SIZE - index_size;
         
-        if self.heap_pos >= index_start {
-            0
-        } else {
-            index_start - self.heap_pos
-        }
+        index_start.saturating_sub(self.heap_pos)
     }
     
     /// Check if adding data of given size would overflow
+    ///
+    /// Reserves the padding `write_row` adds to realign the heap, so a row
+    /// that fits never has its padding run into the row index.
     pub fn would_overflow(&self, data_size: usize) -> bool {
         // Account for potential new row group if we're at a boundary
         let new_row_count = self.row_count + 1;
@@ -268,7 +272,12 @@ impl PageBuilder {
         let index_size = num_groups * ROW_GROUP_SIZE;
         let index_start = PAGE_SIZE - index_size;
         
-        self.heap_pos + data_size > index_start
+        self.heap_pos + data_size + self.padding_after(data_size) > index_start
+    }
+
+    /// Bytes needed after `data_size` more heap bytes to reach 4-byte alignment
+    fn padding_after(&self, data_size: usize) -> usize {
+        (4 - (self.heap_pos - HEAP_START + data_size) % 4) % 4
     }
     
     /// Write raw bytes to the heap, returns offset relative to HEAP_START
@@ -300,15 +309,11 @@ impl PageBuilder {
     /// Write row data and add to index in one step
     /// Rows are padded to 4-byte alignment
     pub fn write_row(&mut self, data: &[u8]) -> Result<u16> {
+        // write_heap's overflow check already reserved the padding
+        let padding = self.padding_after(data.len());
         let offset = self.write_heap(data)?;
         self.add_row(offset)?;
-        
-        // Pad to 4-byte alignment
-        let current_pos = self.heap_pos - HEAP_START;
-        let padding = (4 - (current_pos % 4)) % 4;
-        if padding > 0 && !self.would_overflow(padding) {
-            self.heap_pos += padding;  // Skip padding bytes (already zero)
-        }
+        self.heap_pos += padding;  // Skip padding bytes (already zero)
         
         Ok(offset)
     }
@@ -417,7 +422,7 @@ impl PageBuilder {
         let num_groups = if self.row_offsets.is_empty() {
             1
         } else {
-            (self.row_offsets.len() + ROWS_PER_GROUP - 1) / ROWS_PER_GROUP
+            self.row_offsets.len().div_ceil(ROWS_PER_GROUP)
         };
         
         for group_idx in 0..num_groups {
@@ -475,57 +480,67 @@ impl PageBuilder {
     }
 }
 
+/// Offset of the transaction sequence in the file header
+pub const HEADER_SEQUENCE: usize = 0x14;
+
+/// Offset of the first table pointer in the file header
+pub const HEADER_TABLE_POINTERS: usize = 0x1C;
+
 /// Table pointer in file header
-/// Format from rekordbox export.pdb: (first, empty, last, table_type)
-/// - first: transaction/allocation counter (not a page number)
-/// - empty: INDEX page number for this table
-/// - last: DATA page number (or same as INDEX if empty table)
+/// Format: (table_type, empty_candidate, first_page, last_page)
 /// - table_type: table type (0-19)
+/// - empty_candidate: page the table would grow into next
+/// - first_page: INDEX page number for this table
+/// - last_page: last DATA page number (or same as first_page if empty table)
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TablePointer {
-    pub first: u32,       // Transaction/allocation counter
-    pub empty: u32,       // INDEX page number
-    pub last: u32,        // DATA page number (or same as empty if no data)
-    pub table_type: u32,  // Table type (0-19)
+    pub table_type: u32,       // Table type (0-19)
+    pub empty_candidate: u32,  // Next page to allocate
+    pub first_page: u32,       // INDEX page number
+    pub last_page: u32,        // DATA page number (or same as first_page if no data)
 }
 
 impl TablePointer {
     /// Create a new table pointer
     /// - table_type: the table type (0-19)
-    /// - first: transaction counter (can use next_unused_page as approximation)
+    /// - empty_candidate: next unused page (the header's next_unused_page)
     /// - index_page: the INDEX page number for this table
     /// - data_page: the last DATA page number (or same as index_page if no data)
-    pub fn new(table_type: PageType, first: u32, index_page: u32, data_page: u32) -> Self {
+    pub fn new(table_type: PageType, empty_candidate: u32, index_page: u32, data_page: u32) -> Self {
         Self {
-            first,
-            empty: index_page,
-            last: data_page,
             table_type: table_type as u32,
+            empty_candidate,
+            first_page: index_page,
+            last_page: data_page,
         }
     }
     
-    /// Serialize to bytes - format: (first, empty, last, table_type)
+    /// Serialize to bytes - format: (table_type, empty_candidate, first_page, last_page)
     pub fn to_bytes(&self) -> [u8; 16] {
         let mut bytes = [0u8; 16];
-        bytes[0..4].copy_from_slice(&self.first.to_le_bytes());
-        bytes[4..8].copy_from_slice(&self.empty.to_le_bytes());
-        bytes[8..12].copy_from_slice(&self.last.to_le_bytes());
-        bytes[12..16].copy_from_slice(&self.table_type.to_le_bytes());
+        bytes[0..4].copy_from_slice(&self.table_type.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.empty_candidate.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.first_page.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.last_page.to_le_bytes());
         bytes
     }
 }
 
 /// File header builder
-/// Format verified from rekordbox export.pdb:
+/// Format of rekordbox export.pdb:
 /// - 0x00-0x03: zero padding
 /// - 0x04-0x07: page_size
 /// - 0x08-0x0B: num_tables
 /// - 0x0C-0x0F: next_unused_page
-/// - 0x10+: table pointers (20 entries × 16 bytes)
+/// - 0x10-0x13: unknown (left zero)
+/// - 0x14-0x17: sequence, bumped by every write
+/// - 0x18-0x1B: zero
+/// - 0x1C+: table pointers (20 entries × 16 bytes)
 pub struct FileHeader {
     pub page_size: u32,
     pub num_tables: u32,
     pub next_unused_page: u32,
+    pub sequence: u32,
     pub tables: Vec<TablePointer>,
 }
 
@@ -535,6 +550,7 @@ impl FileHeader {
             page_size: PAGE_SIZE as u32,
             num_tables: 0,
             next_unused_page: 1,
+            sequence: 0,
             tables: Vec::new(),
         }
     }
@@ -557,8 +573,11 @@ impl FileHeader {
         // Bytes 12-15: next_unused_page
         page[12..16].copy_from_slice(&self.next_unused_page.to_le_bytes());
         
-        // Table pointers start at byte 0x10 (16)
-        let mut offset = 0x10;
+        // Bytes 20-23: sequence
+        page[HEADER_SEQUENCE..HEADER_SEQUENCE + 4].copy_from_slice(&self.sequence.to_le_bytes());
+        
+        // Table pointers start at byte 0x1C (28)
+        let mut offset = HEADER_TABLE_POINTERS;
         for table in &self.tables {
             page[offset..offset + 16].copy_from_slice(&table.to_bytes());
             offset += 16;
@@ -636,4 +655,31 @@ mod tests {
         ]);
         assert_eq!(offset_0, 0); // Row 0 at heap offset 0
     }
+
+    #[test]
+    fn test_padding_never_reaches_row_index() {
+        // 14 aligned rows, then a 15th ending 1 byte short of the row index:
+        // its padding must still be written and stop exactly at the index
+        let mut page = PageBuilder::new(1, PageType::Artists);
+        for i in 0..14u8 {
+            page.write_row(&[i; 4]).unwrap();
+        }
+        let index_start = PAGE_SIZE - ROW_GROUP_SIZE;
+        let row = vec![0xBB; index_start - page.heap_position() - 1];
+        assert!(!page.would_overflow(row.len()));
+        assert!(page.would_overflow(row.len() + 2));
+        page.write_row(&row).unwrap();
+        assert_eq!(page.heap_position(), index_start);
+        assert!(page.would_overflow(1));
+
+        let finalized = page.finalize(0xFFFFFFFF);
+        let group = &finalized[index_start..];
+        assert_eq!(u16::from_le_bytes([group[32], group[33]]), 0x7FFF);
+        assert_eq!(u16::from_le_bytes([group[34], group[35]]), 0x7FFF);
+        // Row 14 sits at array position 1, after the 14 four-byte rows
+        assert_eq!(u16::from_le_bytes([group[2], group[3]]), 56);
+        assert_eq!(u16::from_le_bytes([group[30], group[31]]), 0);
+        assert_eq!(finalized[index_start - 2], 0xBB);
+        assert_eq!(finalized[index_start - 1], 0);
+    }
 }
@@ -5,19 +5,95 @@
 //!
 //! Reference: https://djl-analysis.deepsymmetry.org/rekordbox-export-analysis/exports.html
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
-use crate::error::Result;
-use crate::page::{PageBuilder, IndexPageBuilder, PageType, TablePointer, FileHeader, PAGE_SIZE};
-use crate::string::{encode_string, encode_isrc};
-use crate::track::TrackAnalysis;
+use crate::anlz::generate_anlz_path;
+use crate::auxiliary::{parse_artwork_id, stable_artwork_id, ExportClock, MAX_ARTWORK_ID};
+use crate::error::{Error, Result};
+use crate::page::{PageBuilder, IndexPageBuilder, PageType, TablePointer, FileHeader,
+                  PAGE_SIZE, HEAP_START, ROW_GROUP_SIZE, ROWS_PER_GROUP,
+                  HEADER_SEQUENCE, HEADER_TABLE_POINTERS};
+use crate::reader::{AlbumRow, PdbReader, TrackRow};
+use crate::string::{encode_string, encode_isrc, encoded_length, decode_string};
+use crate::track::{FileType, Key, TrackAnalysis};
 
 /// Row subtypes for offset size determination
-const SUBTYPE_NEAR: u16 = 0x0060; // 1-byte offsets (artist, album short)
-const SUBTYPE_FAR: u16 = 0x0064;  // 2-byte offsets (artist, album long)
+pub(crate) const SUBTYPE_NEAR: u16 = 0x0060; // 1-byte offsets (artist, album short)
+pub(crate) const SUBTYPE_FAR: u16 = 0x0064;  // 2-byte offsets (artist, album long)
 const SUBTYPE_TRACK: u16 = 0x0024; // Track rows always use 2-byte offsets
 
+/// Playlist name length past which a warning is given
+///
+/// A heuristic: no format reference documents a limit, and the names are
+/// stored as DeviceSQL strings of any length. Longer names may be cut off
+/// on the narrower player screens.
+pub const MAX_PLAYLIST_NAME_LEN: usize = 64;
+
+/// Folder nesting past which a warning is given
+///
+/// Also a heuristic; the format itself doesn't limit the parent chain.
+pub const MAX_PLAYLIST_DEPTH: usize = 8;
+
+/// Longest string (in chars) written into genre/label/key/artwork/color
+/// rows; even as UTF-16 with surrogate pairs such a row stays inside one page
+const MAX_ROW_STRING_CHARS: usize = 512;
+
+/// Album artist of compilations that have no album artist tag
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Transaction sequence of a fresh database when no previous one is known
+pub const DEFAULT_SEQUENCE: u32 = 60;
+
+/// Track color rows rekordbox writes: (color_id, name)
+pub const DEFAULT_COLORS: [(u8, &str); 8] = [
+    (1, "Pink"),
+    (2, "Red"),
+    (3, "Orange"),
+    (4, "Yellow"),
+    (5, "Green"),
+    (6, "Aqua"),
+    (7, "Blue"),
+    (8, "Purple"),
+];
+
+/// Marker identifying rows written by this tool (track string slot 5)
+pub const DEFAULT_TOOL_MARKER: &str = concat!("rekord-export ", env!("CARGO_PKG_VERSION"));
+
+/// Track string written with a track's energy rating ("Energy 7")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnergyField {
+    /// In front of the comment, as Mixed In Key does; players can show and
+    /// sort by it
+    Comment,
+    /// In the otherwise unused mix name slot (12), leaving comments alone
+    MixName,
+}
+
+/// Result of building one table: (index_page, data_pages, index_page_idx, last_data_page_idx)
+type TablePages = (Vec<u8>, Vec<Vec<u8>>, u32, u32);
+
+/// A built file: header page plus each table's pages (index page first)
+type BuiltPages = (Vec<u8>, Vec<(PageType, Vec<Vec<u8>>)>);
+
+/// Page fill for one page: (table, used bytes, usable capacity)
+pub type PageFill = (PageType, usize, usize);
+
+/// A track left out of a build: (track id, reason)
+pub type SkippedTrack = (u32, String);
+
+/// Page layout of a built file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdbLayout {
+    /// Table -> (first data page, last data page), as file page indices.
+    /// The table's index page sits just before its first data page.
+    pub table_pages: HashMap<PageType, (u32, u32)>,
+    /// Pages in the file, header included
+    pub total_pages: u32,
+}
+
 /// High-level database builder
+#[derive(Clone)]
 pub struct PdbBuilder {
     tracks: Vec<TrackInfo>,
     artists: HashMap<String, u32>,
@@ -27,15 +103,32 @@ pub struct PdbBuilder {
     keys: HashMap<u8, u32>, // rekordbox_key_id -> row_id
     playlists: Vec<PlaylistInfo>,
     artworks: HashMap<String, u32>, // artwork_path -> artwork_id
+    artwork_ids: HashSet<u32>,
     next_artist_id: u32,
     next_album_id: u32,
     next_genre_id: u32,
     next_label_id: u32,
     next_key_id: u32,
-    next_artwork_id: u32,
+    truncate_playlist_names: bool,
+    tool_marker: Option<String>,
+    sequence: u32,
+    colors: Vec<(u8, String)>,
+    history_playlists: Vec<HistoryPlaylistInfo>,
+    energy_field: Option<EnergyField>,
+    clock: ExportClock,
+    warnings: Vec<String>,
+}
+
+/// Play history session (HistoryPlaylists + HistoryEntries tables)
+#[derive(Debug, Clone)]
+pub struct HistoryPlaylistInfo {
+    pub id: u32,
+    pub name: String,
+    pub track_ids: Vec<u32>,
 }
 
 /// Internal track representation
+#[derive(Clone)]
 struct TrackInfo {
     analysis: TrackAnalysis,
     artist_id: u32,
@@ -44,6 +137,7 @@ struct TrackInfo {
     label_id: u32,
     key_id: u32,
     artwork_id: u32,
+    color_id: u8,
     analyze_path: String,
 }
 
@@ -54,6 +148,7 @@ pub struct ArtworkInfo {
 }
 
 /// Playlist information
+#[derive(Debug, Clone)]
 pub struct PlaylistInfo {
     pub id: u32,
     pub parent_id: u32,
@@ -74,13 +169,233 @@ impl PdbBuilder {
             keys: HashMap::new(),
             playlists: Vec::new(),
             artworks: HashMap::new(),
+            artwork_ids: HashSet::new(),
             next_artist_id: 1,
             next_album_id: 1,
             next_genre_id: 1,
             next_label_id: 1,
             next_key_id: 1,
-            next_artwork_id: 1,
+            truncate_playlist_names: false,
+            tool_marker: None,
+            sequence: DEFAULT_SEQUENCE,
+            colors: DEFAULT_COLORS.iter().map(|&(id, name)| (id, name.to_string())).collect(),
+            history_playlists: Vec::new(),
+            energy_field: None,
+            clock: ExportClock::System,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Add a play history session
+    ///
+    /// When none are added, a single empty "HISTORY yyyy-mm-dd" session for
+    /// today is written so the History tables are never completely blank.
+    pub fn add_history_playlist(&mut self, id: u32, name: &str, track_ids: Vec<u32>) {
+        self.history_playlists.push(HistoryPlaylistInfo {
+            id,
+            name: name.to_string(),
+            track_ids,
+        });
+    }
+
+    /// Write a tool/version marker into each track's message string (slot 5)
+    ///
+    /// Lets a later merge recognise rows this tool owns. Off by default.
+    pub fn set_tool_marker(&mut self, marker: Option<&str>) {
+        self.tool_marker = marker.map(|m| m.to_string());
+    }
+
+    /// Write each track's energy rating into this string (None, the
+    /// default, leaves it out)
+    pub fn set_energy_field(&mut self, field: Option<EnergyField>) {
+        self.energy_field = field;
+    }
+
+    /// Date the default history session and each track's analyze_date by
+    /// this clock instead of the current time
+    pub fn set_clock(&mut self, clock: ExportClock) {
+        self.clock = clock;
+    }
+
+    /// Set the database transaction sequence
+    ///
+    /// rekordbox bumps this on every write, so players and the desktop app can
+    /// tell a stick changed. When re-exporting over an existing database, pass
+    /// its `read_sequence` + 1. The value goes in the header's sequence field.
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.sequence = sequence;
+    }
+
+    /// Replace the Colors table rows (defaults to `DEFAULT_COLORS`), e.g. to
+    /// localize names or add custom entries
+    pub fn set_colors(&mut self, colors: &[(u8, &str)]) {
+        self.colors = colors.iter().map(|&(id, name)| (id, name.to_string())).collect();
+    }
+
+    /// Tag an added track with a color_id from the palette (0 = no color)
+    ///
+    /// Set the palette first: ids it doesn't contain are flagged as warnings.
+    pub fn set_track_color(&mut self, track_id: u32, color_id: u8) {
+        if color_id != 0 && !self.colors.iter().any(|(id, _)| *id == color_id) {
+            self.warnings.push(format!(
+                "Track {} uses color {} which is not in the color table", track_id, color_id
+            ));
+        }
+        for track in self.tracks.iter_mut().filter(|t| t.analysis.id == track_id) {
+            track.color_id = color_id;
+        }
+    }
+
+    /// Truncate playlist/folder names longer than MAX_PLAYLIST_NAME_LEN
+    /// (they are only flagged otherwise)
+    pub fn set_truncate_playlist_names(&mut self, truncate: bool) {
+        self.truncate_playlist_names = truncate;
+    }
+
+    /// Reuse an existing library's artist id when merging into it
+    ///
+    /// Tracks added later with this artist name get `id`, and new artists are
+    /// numbered after the highest id seen. Preloaded rows are written to the
+    /// Artists table like any other. The same goes for the album, genre and
+    /// label variants below.
+    pub fn preload_artist(&mut self, name: &str, id: u32) {
+        self.artists.insert(name.to_string(), id);
+        self.next_artist_id = self.next_artist_id.max(id + 1);
+    }
+
+    /// Reuse an existing album id (albums are keyed by name and artist id)
+    pub fn preload_album(&mut self, name: &str, artist_id: u32, id: u32) {
+        self.albums.insert((name.to_string(), artist_id), id);
+        self.next_album_id = self.next_album_id.max(id + 1);
+    }
+
+    /// Reuse an existing genre id
+    pub fn preload_genre(&mut self, name: &str, id: u32) {
+        self.genres.insert(name.to_string(), id);
+        self.next_genre_id = self.next_genre_id.max(id + 1);
+    }
+
+    /// Reuse an existing label id
+    pub fn preload_label(&mut self, name: &str, id: u32) {
+        self.labels.insert(name.to_string(), id);
+        self.next_label_id = self.next_label_id.max(id + 1);
+    }
+
+    /// Pick up an existing export.pdb, to merge new tracks and playlists
+    /// into a stick instead of regenerating it
+    ///
+    /// Tracks, artists, albums, genres, labels, keys, artwork and playlists
+    /// come back with their ids (new rows are numbered after them), and the
+    /// sequence continues from the file's. Beat grids, waveforms and cues
+    /// live in the ANLZ files, so restored tracks have none; play history,
+    /// colors and the tool marker start from the defaults.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = PdbReader::new(std::io::Cursor::new(data))?;
+        let mut builder = Self::new();
+        builder.set_sequence(read_sequence(data)?.saturating_add(1));
+
+        let artists: HashMap<u32, String> = reader.artists()
+            .map(|row| row.map(|artist| (artist.id, artist.name)))
+            .collect::<Result<_>>()?;
+        for (&id, name) in &artists {
+            builder.preload_artist(name, id);
+        }
+        let albums: HashMap<u32, AlbumRow> = reader.albums()
+            .map(|row| row.map(|album| (album.id, album)))
+            .collect::<Result<_>>()?;
+        for album in albums.values() {
+            builder.preload_album(&album.name, album.artist_id, album.id);
+        }
+        let genres: HashMap<u32, String> = reader.genres()
+            .map(|row| row.map(|genre| (genre.id, genre.name)))
+            .collect::<Result<_>>()?;
+        for (&id, name) in &genres {
+            builder.preload_genre(name, id);
+        }
+        let labels: HashMap<u32, String> = reader.labels()
+            .map(|row| row.map(|label| (label.id, label.name)))
+            .collect::<Result<_>>()?;
+        for (&id, name) in &labels {
+            builder.preload_label(name, id);
+        }
+
+        // Key rows only carry the name; map it back to the rekordbox key id
+        let mut keys: HashMap<u32, Key> = HashMap::new();
+        for row in reader.keys() {
+            let row = row?;
+            let Some(rekordbox_id) = (1..=24).find(|&id| Key::from_rekordbox_id(id).name() == row.name) else {
+                builder.warnings.push(format!("Key {} has unknown name '{}'", row.id, row.name));
+                continue;
+            };
+            builder.keys.insert(rekordbox_id, row.id);
+            builder.next_key_id = builder.next_key_id.max(row.id + 1);
+            keys.insert(row.id, Key::from_rekordbox_id(rekordbox_id));
+        }
+        for row in reader.artworks() {
+            let row = row?;
+            builder.artwork_ids.insert(row.id);
+            builder.artworks.insert(row.name, row.id);
+        }
+
+        for row in reader.tracks() {
+            let row = row?;
+            let album = albums.get(&row.album_id);
+            let name = |names: &HashMap<u32, String>, id: u32| names.get(&id).cloned();
+            let artist = name(&artists, row.artist_id).unwrap_or_default();
+            let album_artist = album.and_then(|a| name(&artists, a.artist_id)).filter(|a| *a != artist);
+            let analysis = restored_track(&row, TrackAnalysis {
+                artist,
+                album: album.map(|a| a.name.clone()),
+                album_artist,
+                genre: name(&genres, row.genre_id),
+                label: name(&labels, row.label_id),
+                key: keys.get(&row.key_id).copied(),
+                // Tempos already in a library are taken as settled
+                bpm_confidence: 1.0,
+                ..TrackAnalysis::default()
+            });
+            builder.tracks.push(TrackInfo {
+                analysis,
+                artist_id: row.artist_id,
+                album_id: row.album_id,
+                genre_id: row.genre_id,
+                label_id: row.label_id,
+                key_id: row.key_id,
+                artwork_id: row.artwork_id,
+                color_id: row.color_id,
+                analyze_path: row.analyze_path,
+            });
+        }
+
+        // Rows stay in file order; sort_order only ranks siblings
+        let playlists = reader.playlists().collect::<Result<Vec<_>>>()?;
+        let mut entries = reader.playlist_entries().collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.entry_index);
+        for playlist in playlists {
+            builder.playlists.push(PlaylistInfo {
+                id: playlist.id,
+                parent_id: playlist.parent_id,
+                name: playlist.name,
+                is_folder: playlist.is_folder,
+                sort_order: playlist.sort_order,
+                track_ids: entries.iter()
+                    .filter(|e| e.playlist_id == playlist.id)
+                    .map(|e| e.track_id)
+                    .collect(),
+            });
         }
+
+        Ok(builder)
+    }
+
+    /// Playlists and folders in the order they were added
+    pub fn playlists(&self) -> &[PlaylistInfo] {
+        &self.playlists
+    }
+
+    /// Warnings collected while adding rows (long names, deep folders)
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
     }
     
     /// Add a track and return its ID
@@ -88,6 +403,12 @@ impl PdbBuilder {
         self.add_track_with_artwork(analysis, analyze_path, None)
     }
 
+    /// Add a track under a pinned id instead of `analysis.id`, and return it
+    pub fn add_track_with_id(&mut self, track_id: u32, analysis: &TrackAnalysis, analyze_path: &str) -> u32 {
+        let analysis = TrackAnalysis { id: track_id, ..analysis.clone() };
+        self.add_track(&analysis, analyze_path)
+    }
+
     /// Add a track with optional artwork path and return its ID
     pub fn add_track_with_artwork(&mut self, analysis: &TrackAnalysis, analyze_path: &str, artwork_path: Option<&str>) -> u32 {
         let track_id = analysis.id;
@@ -95,10 +416,8 @@ impl PdbBuilder {
         // Get or create artist ID
         let artist_id = self.get_or_create_artist(&analysis.artist);
         
-        // Get or create album ID (associated with artist)
-        let album_id = analysis.album.as_ref()
-            .map(|a| self.get_or_create_album(a, artist_id))
-            .unwrap_or(0);
+        // Get or create album ID (associated with the album artist)
+        let album_id = self.get_or_create_track_album(analysis);
         
         // Get or create genre ID  
         let genre_id = analysis.genre.as_ref()
@@ -128,35 +447,166 @@ impl PdbBuilder {
             label_id,
             key_id,
             artwork_id,
+            color_id: 0,
             analyze_path: analyze_path.to_string(),
         });
         
         track_id
     }
-    
+
+    /// Remove an added track and its playlist and history entries
+    ///
+    /// Returns false if no track has this id. Artist/album/genre/label/key
+    /// rows it interned are kept, so ids handed out to other tracks (and any
+    /// preloaded ones) stay stable.
+    pub fn remove_track(&mut self, track_id: u32) -> bool {
+        let before = self.tracks.len();
+        self.tracks.retain(|t| t.analysis.id != track_id);
+        if self.tracks.len() == before {
+            return false;
+        }
+        for playlist in &mut self.playlists {
+            playlist.track_ids.retain(|&id| id != track_id);
+        }
+        for history in &mut self.history_playlists {
+            history.track_ids.retain(|&id| id != track_id);
+        }
+        true
+    }
+
+    /// Swap in new metadata for an added track, keeping its id, position,
+    /// ANLZ path, artwork, color and playlist entries
+    ///
+    /// Returns false if no track has this id. The analysis' own id is ignored.
+    pub fn replace_track(&mut self, track_id: u32, analysis: &TrackAnalysis) -> bool {
+        let Some(index) = self.tracks.iter().position(|t| t.analysis.id == track_id) else {
+            return false;
+        };
+        let artist_id = self.get_or_create_artist(&analysis.artist);
+        let album_id = self.get_or_create_track_album(analysis);
+        let genre_id = analysis.genre.as_ref()
+            .map(|g| self.get_or_create_genre(g))
+            .unwrap_or(0);
+        let label_id = analysis.label.as_ref()
+            .map(|l| self.get_or_create_label(l))
+            .unwrap_or(0);
+        let key_id = analysis.key
+            .map(|k| self.get_or_create_key(k.to_rekordbox_id(), &k.name()))
+            .unwrap_or(0);
+
+        let track = &mut self.tracks[index];
+        track.analysis = analysis.clone();
+        track.analysis.id = track_id;
+        track.artist_id = artist_id;
+        track.album_id = album_id;
+        track.genre_id = genre_id;
+        track.label_id = label_id;
+        track.key_id = key_id;
+        true
+    }
+
     /// Add a playlist
     pub fn add_playlist(&mut self, id: u32, parent_id: u32, name: &str, track_ids: Vec<u32>) {
+        let name = self.check_playlist_entry(parent_id, name);
         self.playlists.push(PlaylistInfo {
             id,
             parent_id,
-            name: name.to_string(),
+            name,
             is_folder: false,
-            sort_order: self.playlists.len() as u32,
+            sort_order: self.sibling_count(parent_id),
             track_ids,
         });
     }
     
+    /// Put an added playlist's tracks in a new manual order
+    ///
+    /// `track_ids` must hold exactly the tracks already in the playlist
+    /// (duplicates included), only rearranged; entry indices follow the new
+    /// order.
+    pub fn set_playlist_order(&mut self, playlist_id: u32, track_ids: Vec<u32>) -> Result<()> {
+        let playlist = self.playlists.iter_mut()
+            .find(|p| p.id == playlist_id && !p.is_folder)
+            .ok_or_else(|| Error::Database(format!("no playlist with id {}", playlist_id)))?;
+
+        let mut current = playlist.track_ids.clone();
+        let mut reordered = track_ids.clone();
+        current.sort_unstable();
+        reordered.sort_unstable();
+        if current != reordered {
+            return Err(Error::Validation(format!(
+                "new order for playlist {} does not hold the same tracks", playlist_id
+            )));
+        }
+
+        playlist.track_ids = track_ids;
+        Ok(())
+    }
+
     /// Add a playlist folder
     pub fn add_folder(&mut self, id: u32, parent_id: u32, name: &str) {
+        let name = self.check_playlist_entry(parent_id, name);
         self.playlists.push(PlaylistInfo {
             id,
             parent_id,
-            name: name.to_string(),
+            name,
             is_folder: true,
-            sort_order: self.playlists.len() as u32,
+            sort_order: self.sibling_count(parent_id),
             track_ids: Vec::new(),
         });
     }
+
+    /// Number of entries already added under `parent_id`, the next one's sort order
+    fn sibling_count(&self, parent_id: u32) -> u32 {
+        self.playlists.iter().filter(|p| p.parent_id == parent_id).count() as u32
+    }
+
+    /// Flag names and nesting the CDJ can't display, returning the name to store
+    fn check_playlist_entry(&mut self, parent_id: u32, name: &str) -> String {
+        let depth = self.playlist_depth(parent_id) + 1;
+        if depth > MAX_PLAYLIST_DEPTH {
+            self.warnings.push(format!(
+                "Playlist '{}' is nested {} levels deep (more than {}, which may be awkward to browse on a player)",
+                name, depth, MAX_PLAYLIST_DEPTH
+            ));
+        }
+
+        let char_count = name.chars().count();
+        if char_count <= MAX_PLAYLIST_NAME_LEN {
+            return name.to_string();
+        }
+
+        if self.truncate_playlist_names {
+            let truncated: String = name.chars().take(MAX_PLAYLIST_NAME_LEN).collect();
+            self.warnings.push(format!(
+                "Playlist name truncated from {} to {} characters: '{}'",
+                char_count, MAX_PLAYLIST_NAME_LEN, truncated
+            ));
+            truncated
+        } else {
+            self.warnings.push(format!(
+                "Playlist name is {} characters (more than {}; player screens may cut it off): '{}'",
+                char_count, MAX_PLAYLIST_NAME_LEN, name
+            ));
+            name.to_string()
+        }
+    }
+
+    /// Nesting depth of a playlist/folder id (0 for the root)
+    fn playlist_depth(&self, id: u32) -> usize {
+        let mut depth = 0;
+        let mut current = id;
+        // Bounded by the number of playlists to survive parent cycles
+        while current != 0 && depth <= self.playlists.len() {
+            match self.playlists.iter().find(|p| p.id == current) {
+                Some(p) => {
+                    depth += 1;
+                    current = p.parent_id;
+                }
+                None => break,
+            }
+        }
+        depth
+    }
     
     fn get_or_create_artist(&mut self, name: &str) -> u32 {
         if name.is_empty() {
@@ -171,6 +621,19 @@ impl PdbBuilder {
         id
     }
     
+    /// Album of a track, keyed by its album artist: the album artist tag,
+    /// `VARIOUS_ARTISTS` for compilations without one, else the track artist
+    fn get_or_create_track_album(&mut self, analysis: &TrackAnalysis) -> u32 {
+        let Some(album) = analysis.album.as_deref() else {
+            return 0;
+        };
+        let album_artist = analysis.album_artist.as_deref()
+            .filter(|a| !a.trim().is_empty())
+            .unwrap_or(if analysis.compilation { VARIOUS_ARTISTS } else { analysis.artist.as_str() });
+        let artist_id = self.get_or_create_artist(album_artist);
+        self.get_or_create_album(album, artist_id)
+    }
+
     fn get_or_create_album(&mut self, name: &str, artist_id: u32) -> u32 {
         if name.is_empty() {
             return 0;
@@ -228,8 +691,14 @@ impl PdbBuilder {
         if let Some(&id) = self.artworks.get(path) {
             return id;
         }
-        let id = self.next_artwork_id;
-        self.next_artwork_id += 1;
+        // Same id as the written a{id}.jpg where possible, so re-exports
+        // don't renumber art a deck has cached
+        let mut id = parse_artwork_id(path)
+            .filter(|id| (1..=MAX_ARTWORK_ID).contains(id))
+            .unwrap_or_else(|| stable_artwork_id(path.as_bytes()));
+        while !self.artwork_ids.insert(id) {
+            id = id % MAX_ARTWORK_ID + 1;
+        }
         self.artworks.insert(path.to_string(), id);
         id
     }
@@ -239,54 +708,161 @@ impl PdbBuilder {
     /// This creates a PDB file with all 20 required tables, each with:
     /// 1. An INDEX page (flags 0x64)
     /// 2. One or more DATA pages (flags 0x24/0x34)
+    ///
+    /// Tracks whose row can't be encoded are left out; use
+    /// `build_with_skipped` to learn which.
     pub fn build(&self) -> Result<Vec<u8>> {
-        let mut all_pages: Vec<Vec<u8>> = Vec::new();
+        self.build_with_skipped().map(|(data, _)| data)
+    }
+
+    /// Build the PDB, dropping tracks whose row fails to encode (e.g. a row
+    /// too large for a page) instead of failing the whole database
+    ///
+    /// Skipped tracks are also removed from playlists and history sessions
+    /// so no entry points at a missing row.
+    pub fn build_with_skipped(&self) -> Result<(Vec<u8>, Vec<SkippedTrack>)> {
+        let (builder, skipped) = self.pruned();
+        Ok((builder.build_file()?, skipped))
+    }
+
+    /// Where each table ended up in the file `build()` produces
+    pub fn layout(&self) -> Result<PdbLayout> {
+        let (builder, _) = self.pruned();
+        let (_, tables) = builder.build_pages()?;
+
+        // Page 0 is the file header; tables follow back to back
+        let mut next_page = 1u32;
+        let mut table_pages = HashMap::new();
+        for (page_type, pages) in &tables {
+            let index_page = next_page;
+            let last_page = index_page + pages.len() as u32 - 1;
+            let first_data = (index_page + 1).min(last_page);
+            table_pages.insert(*page_type, (first_data, last_page));
+            next_page = last_page + 1;
+        }
+
+        Ok(PdbLayout { table_pages, total_pages: next_page })
+    }
+
+    /// This builder minus tracks that can't be encoded, plus what was dropped
+    fn pruned(&self) -> (Cow<'_, Self>, Vec<SkippedTrack>) {
+        let skipped = self.unbuildable_tracks();
+        if skipped.is_empty() {
+            return (Cow::Borrowed(self), skipped);
+        }
+
+        let ids: HashSet<u32> = skipped.iter().map(|(id, _)| *id).collect();
+        let mut pruned = self.clone();
+        pruned.tracks.retain(|t| !ids.contains(&t.analysis.id));
+        for playlist in &mut pruned.playlists {
+            playlist.track_ids.retain(|id| !ids.contains(id));
+        }
+        for history in &mut pruned.history_playlists {
+            history.track_ids.retain(|id| !ids.contains(id));
+        }
+        (Cow::Owned(pruned), skipped)
+    }
+
+    /// Tracks whose row fails to build or can't fit on an empty page
+    fn unbuildable_tracks(&self) -> Vec<SkippedTrack> {
+        self.tracks.iter()
+            .filter_map(|track| {
+                let reason = match self.build_track_row(track) {
+                    Ok(row) if PageBuilder::new(0, PageType::Tracks).would_overflow(row.len()) => {
+                        format!("row of {} bytes does not fit in a page", row.len())
+                    }
+                    Ok(_) => return None,
+                    Err(e) => e.to_string(),
+                };
+                Some((track.analysis.id, reason))
+            })
+            .collect()
+    }
+
+    fn build_file(&self) -> Result<Vec<u8>> {
+        let (header, tables) = self.build_pages()?;
+
+        // Flatten to single buffer
+        let page_count = 1 + tables.iter().map(|(_, pages)| pages.len()).sum::<usize>();
+        let mut output = Vec::with_capacity(page_count * PAGE_SIZE);
+        output.extend_from_slice(&header);
+        for (_, pages) in tables {
+            for page in pages {
+                output.extend_from_slice(&page);
+            }
+        }
+        
+        Ok(output)
+    }
+
+    /// How full every page of a dry build ends up
+    ///
+    /// One entry per page after the file header, in file order. `used` counts
+    /// heap bytes plus the row index; index pages and empty placeholder pages
+    /// report 0. Capacity is the page minus its header.
+    pub fn page_fill_report(&self) -> Result<Vec<PageFill>> {
+        let (_, tables) = self.build_pages()?;
+        let capacity = PAGE_SIZE - HEAP_START;
+
+        let mut report = Vec::new();
+        for (page_type, pages) in tables {
+            // First page of each table is its index page
+            report.push((page_type, 0, capacity));
+            for page in &pages[1..] {
+                let used_heap = u16::from_le_bytes([page[0x1E], page[0x1F]]) as usize;
+                let packed = u32::from_le_bytes([page[0x18], page[0x19], page[0x1A], 0]);
+                // num_rows; num_row_offsets is written as 4x the row count
+                let num_rows = (packed & 0x7FF) as usize;
+                let used = if num_rows > 0 {
+                    let groups = num_rows.div_ceil(ROWS_PER_GROUP);
+                    used_heap + groups * ROW_GROUP_SIZE
+                } else {
+                    used_heap
+                };
+                report.push((page_type, used, capacity));
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Build the header page and each table's pages (index page first)
+    fn build_pages(&self) -> Result<BuiltPages> {
+        let mut tables = Vec::new();
         let mut header = FileHeader::new();
         
-        // Reserve page 0 for header
-        all_pages.push(vec![0u8; PAGE_SIZE]);
+        // Page 0 is the header
         let mut next_page_index = 1u32;
         
         // We'll collect table pointers and build all pages
-        // Table pointer format: (first=counter, empty=INDEX_page, last=DATA_page, type)
-        
-        // Transaction counter - starts high and we'll decrement
-        let mut transaction_counter = 60u32;  // Arbitrary starting value
+        // Table pointer format: (type, empty_candidate, first=INDEX_page, last=DATA_page)
+        header.sequence = self.sequence;
         
         // Build all 20 tables in order
         for page_type in PageType::all_types() {
             let (index_page, data_pages, index_page_idx, last_data_page) = 
                 self.build_table(*page_type, &mut next_page_index)?;
             
-            // Add table pointer with correct field order:
-            // - first: transaction counter
-            // - empty: INDEX page number  
-            // - last: DATA page number (or INDEX if no data)
-            // - type: table type
-            header.add_table(TablePointer::new(*page_type, transaction_counter, index_page_idx, last_data_page));
-            transaction_counter = transaction_counter.wrapping_sub(1);
+            // empty_candidate is filled in once the page count is known
+            header.add_table(TablePointer::new(*page_type, 0, index_page_idx, last_data_page));
             
             // Add pages
-            all_pages.push(index_page);
-            all_pages.extend(data_pages);
+            let mut pages = vec![index_page];
+            pages.extend(data_pages);
+            tables.push((*page_type, pages));
         }
         
         // Update header with final page count
         header.next_unused_page = next_page_index;
-        all_pages[0] = header.to_page();
-        
-        // Flatten to single buffer
-        let mut output = Vec::with_capacity(all_pages.len() * PAGE_SIZE);
-        for page in all_pages {
-            output.extend_from_slice(&page);
+        for table in &mut header.tables {
+            table.empty_candidate = next_page_index;
         }
-        
-        Ok(output)
+        Ok((header.to_page(), tables))
     }
     
     /// Build a single table (index page + data pages)
     /// Returns: (index_page, data_pages, index_page_idx, last_data_page_idx)
-    fn build_table(&self, page_type: PageType, next_idx: &mut u32) -> Result<(Vec<u8>, Vec<Vec<u8>>, u32, u32)> {
+    fn build_table(&self, page_type: PageType, next_idx: &mut u32) -> Result<TablePages> {
         let index_page_idx = *next_idx;
         *next_idx += 1;
         
@@ -304,6 +880,7 @@ impl PdbBuilder {
             PageType::PlaylistTree => self.build_playlist_tree_data_pages(next_idx)?,
             PageType::PlaylistEntries => self.build_playlist_entry_data_pages(next_idx)?,
             PageType::HistoryPlaylists => self.build_history_playlist_data_pages(next_idx)?,
+            PageType::HistoryEntries => self.build_history_entry_data_pages(next_idx)?,
             PageType::Artwork => self.build_artwork_data_pages(next_idx)?,
             PageType::Columns => self.build_columns_data_pages(next_idx)?,
             PageType::Unknown17 => self.build_unknown17_data_pages(next_idx)?,
@@ -354,7 +931,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Tracks);
         *next_idx += 1;
@@ -382,7 +958,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Genres);
         *next_idx += 1;
@@ -413,7 +988,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Artists);
         *next_idx += 1;
@@ -444,7 +1018,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Albums);
         *next_idx += 1;
@@ -475,7 +1048,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Labels);
         *next_idx += 1;
@@ -506,7 +1078,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Keys);
         *next_idx += 1;
@@ -534,25 +1105,20 @@ impl PdbBuilder {
     
     /// Build color data pages (always includes 8 default colors)
     fn build_color_data_pages(&self, next_idx: &mut u32) -> Result<(Vec<Vec<u8>>, bool)> {
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Colors);
         *next_idx += 1;
         
-        // Default colors from rekordbox (same as rex project)
-        let colors = [
-            (1, "Pink"),
-            (2, "Red"),
-            (3, "Orange"),
-            (4, "Yellow"),
-            (5, "Green"),
-            (6, "Aqua"),
-            (7, "Blue"),
-            (8, "Purple"),
-        ];
-        
-        for (id, name) in colors {
-            let row_data = self.build_color_row(id, name);
+        for (id, name) in &self.colors {
+            let row_data = self.build_color_row(*id as u32, name);
+
+            if current_page.would_overflow(row_data.len()) {
+                let next = *next_idx;
+                pages.push(current_page.finalize(next));
+                current_page = PageBuilder::new(next, PageType::Colors);
+                *next_idx += 1;
+            }
+
             current_page.write_row(&row_data)?;
         }
         
@@ -566,7 +1132,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::PlaylistTree);
         *next_idx += 1;
@@ -589,8 +1154,11 @@ impl PdbBuilder {
     }
     
     /// Build playlist entry data pages
+    ///
+    /// Entries are sorted by (playlist_id, entry_index) so the output does not
+    /// depend on the order playlists were added.
     fn build_playlist_entry_data_pages(&self, next_idx: &mut u32) -> Result<(Vec<Vec<u8>>, bool)> {
-        let entries: Vec<_> = self.playlists.iter()
+        let mut entries: Vec<_> = self.playlists.iter()
             .filter(|p| !p.is_folder)
             .flat_map(|p| {
                 p.track_ids.iter().enumerate().map(move |(idx, &track_id)| {
@@ -598,12 +1166,12 @@ impl PdbBuilder {
                 })
             })
             .collect();
+        entries.sort_by_key(|&(entry_index, _, playlist_id)| (playlist_id, entry_index));
         
         if entries.is_empty() {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::PlaylistEntries);
         *next_idx += 1;
@@ -625,10 +1193,86 @@ impl PdbBuilder {
         Ok((pages, true))
     }
     
-    /// Build history playlist data pages
+    /// History sessions to write, falling back to one empty session for
+    /// today (by the builder's clock)
+    fn history_playlists(&self) -> Vec<HistoryPlaylistInfo> {
+        if !self.history_playlists.is_empty() {
+            return self.history_playlists.clone();
+        }
+
+        vec![HistoryPlaylistInfo {
+            id: 1,
+            name: format!("HISTORY {}", self.clock.iso_date()),
+            track_ids: Vec::new(),
+        }]
+    }
+
+    /// Build history playlist data pages (type 11)
+    /// Kaitai spec: id(u4) + name(DeviceSQL string)
     fn build_history_playlist_data_pages(&self, next_idx: &mut u32) -> Result<(Vec<Vec<u8>>, bool)> {
-        // For now, just create empty table
-        self.build_empty_data_pages(next_idx)
+        let mut pages: Vec<Vec<u8>> = Vec::new();
+        let mut current_page = PageBuilder::new(*next_idx, PageType::HistoryPlaylists);
+        *next_idx += 1;
+
+        let mut history = self.history_playlists();
+        history.sort_by_key(|h| h.id);
+
+        for playlist in &history {
+            let mut row_data = Vec::new();
+            row_data.extend_from_slice(&playlist.id.to_le_bytes());
+            row_data.extend_from_slice(&encode_string(&playlist.name));
+
+            if current_page.would_overflow(row_data.len()) {
+                let next = *next_idx;
+                pages.push(current_page.finalize(next));
+                current_page = PageBuilder::new(next, PageType::HistoryPlaylists);
+                *next_idx += 1;
+            }
+
+            current_page.write_row(&row_data)?;
+        }
+
+        pages.push(current_page.finalize(0xFFFFFFFF));
+        Ok((pages, true))
+    }
+
+    /// Build history entry data pages (type 12)
+    /// Kaitai spec: track_id(u4) + playlist_id(u4) + entry_index(u4)
+    fn build_history_entry_data_pages(&self, next_idx: &mut u32) -> Result<(Vec<Vec<u8>>, bool)> {
+        let mut entries: Vec<(u32, u32, u32)> = self.history_playlists()
+            .iter()
+            .flat_map(|h| {
+                h.track_ids.iter().enumerate()
+                    .map(move |(idx, &track_id)| (track_id, h.id, idx as u32))
+            })
+            .collect();
+        if entries.is_empty() {
+            return self.build_empty_data_pages(next_idx);
+        }
+        entries.sort_by_key(|&(_, playlist_id, entry_index)| (playlist_id, entry_index));
+
+        let mut pages: Vec<Vec<u8>> = Vec::new();
+        let mut current_page = PageBuilder::new(*next_idx, PageType::HistoryEntries);
+        *next_idx += 1;
+
+        for (track_id, playlist_id, entry_index) in entries {
+            let mut row_data = Vec::with_capacity(12);
+            row_data.extend_from_slice(&track_id.to_le_bytes());
+            row_data.extend_from_slice(&playlist_id.to_le_bytes());
+            row_data.extend_from_slice(&entry_index.to_le_bytes());
+
+            if current_page.would_overflow(row_data.len()) {
+                let next = *next_idx;
+                pages.push(current_page.finalize(next));
+                current_page = PageBuilder::new(next, PageType::HistoryEntries);
+                *next_idx += 1;
+            }
+
+            current_page.write_row(&row_data)?;
+        }
+
+        pages.push(current_page.finalize(0xFFFFFFFF));
+        Ok((pages, true))
     }
     
     /// Build artwork data pages
@@ -637,7 +1281,6 @@ impl PdbBuilder {
             return self.build_empty_data_pages(next_idx);
         }
         
-        let first_page = *next_idx;
         let mut pages: Vec<Vec<u8>> = Vec::new();
         let mut current_page = PageBuilder::new(*next_idx, PageType::Artwork);
         *next_idx += 1;
@@ -835,47 +1478,82 @@ impl PdbBuilder {
         Ok((pages, true))
     }
     
+    /// Encoded size of the row `analysis` would get, without building it
+    ///
+    /// Uses the ANLZ path of the added track with the same id, or the
+    /// default `generate_anlz_path` one for a track not added yet.
+    pub fn track_row_size(&self, analysis: &TrackAnalysis) -> usize {
+        let analyze_path = self.tracks.iter()
+            .find(|t| t.analysis.id == analysis.id)
+            .map(|t| t.analyze_path.clone())
+            .unwrap_or_else(|| generate_anlz_path(analysis.id));
+        let strings = self.track_strings(analysis, &analyze_path);
+        TRACK_STRING_OFFSETS + TRACK_STRING_COUNT * 2
+            + strings.iter().map(|s| encoded_length(s)).sum::<usize>()
+    }
+
+    /// The 21 strings of a track row, in slot order
+    ///
+    /// Slot 0 (ISRC) is always empty, which encodes the same as a plain
+    /// empty string.
+    fn track_strings(&self, analysis: &TrackAnalysis, analyze_path: &str) -> [String; TRACK_STRING_COUNT] {
+        // Prefer the full tagged date, fall back to January 1st of the year
+        let release_date = analysis.release_date.clone()
+            .or_else(|| analysis.year.map(|y| format!("{}-01-01", y)))
+            .unwrap_or_default();
+
+        let energy = analysis.energy.map(|e| format!("Energy {}", e));
+        let (mix_name, comment) = match (self.energy_field, energy) {
+            (Some(EnergyField::MixName), Some(energy)) => (energy, analysis.comment.clone().unwrap_or_default()),
+            (Some(EnergyField::Comment), Some(energy)) => match analysis.comment.as_deref() {
+                Some(comment) if !comment.is_empty() => (String::new(), format!("{} - {}", energy, comment)),
+                _ => (String::new(), energy),
+            },
+            _ => (String::new(), analysis.comment.clone().unwrap_or_default()),
+        };
+
+        [
+            String::new(), // 0: ISRC
+            String::new(), // 1: lyricist
+            String::new(), // 2: unknown (version?)
+            String::new(), // 3: unknown
+            String::new(), // 4: unknown
+            self.tool_marker.clone().unwrap_or_default(), // 5: message (tool marker)
+            String::new(), // 6: publish_track_info
+            String::new(), // 7: autoload_hotcues
+            String::new(), // 8: unknown
+            String::new(), // 9: unknown
+            String::new(), // 10: date_added
+            release_date, // 11: release_date
+            mix_name, // 12: mix_name
+            String::new(), // 13: unknown
+            format!("/{}", analyze_path.trim_start_matches('/')), // 14: analyze_path (must have leading /)
+            self.clock.iso_date(), // 15: analyze_date
+            comment, // 16: comment
+            analysis.title.clone(), // 17: title
+            String::new(), // 18: unknown
+            analysis.file_path.split('/').next_back().unwrap_or(&analysis.file_path).to_string(), // 19: filename
+            analysis.file_path.clone(), // 20: file_path
+        ]
+    }
+
     /// Build a single track row
     fn build_track_row(&self, track: &TrackInfo) -> Result<Vec<u8>> {
         let analysis = &track.analysis;
         
-        // Track row has fixed fields + 21 string offsets
-        // We need to calculate the total size first to determine string offsets
-        
         // Fixed part: 0x5E bytes (94 bytes) before string offsets
         // Then 21 × 2-byte offsets = 42 bytes
         // Total fixed header: 136 bytes
-        const FIXED_SIZE: usize = 0x5E;
-        const STRING_COUNT: usize = 21;
-        const HEADER_SIZE: usize = FIXED_SIZE + STRING_COUNT * 2;
-        
-        // Build all strings
-        let strings: Vec<Vec<u8>> = vec![
-            encode_isrc(""), // 0: ISRC
-            encode_string(""), // 1: lyricist
-            encode_string(""), // 2: unknown (version?)
-            encode_string(""), // 3: unknown
-            encode_string(""), // 4: unknown
-            encode_string(""), // 5: message
-            encode_string(""), // 6: publish_track_info
-            encode_string(""), // 7: autoload_hotcues
-            encode_string(""), // 8: unknown
-            encode_string(""), // 9: unknown
-            encode_string(""), // 10: date_added
-            encode_string(analysis.year.map(|y| format!("{}-01-01", y)).as_deref().unwrap_or("")), // 11: release_date
-            encode_string(""), // 12: mix_name
-            encode_string(""), // 13: unknown
-            encode_string(&format!("/{}", track.analyze_path.trim_start_matches('/'))), // 14: analyze_path (must have leading /)
-            encode_string(""), // 15: analyze_date
-            encode_string(analysis.comment.as_deref().unwrap_or("")), // 16: comment
-            encode_string(&analysis.title), // 17: title
-            encode_string(""), // 18: unknown
-            encode_string(&analysis.file_path.split('/').last().unwrap_or(&analysis.file_path)), // 19: filename
-            encode_string(&analysis.file_path), // 20: file_path
-        ];
+        const HEADER_SIZE: usize = TRACK_STRING_OFFSETS + TRACK_STRING_COUNT * 2;
+        
+        let strings: Vec<Vec<u8>> = self.track_strings(analysis, &track.analyze_path)
+            .iter()
+            .enumerate()
+            .map(|(slot, s)| if slot == 0 { encode_isrc(s) } else { encode_string(s) })
+            .collect();
         
         // Calculate offsets (relative to row start)
-        let mut string_offsets = Vec::with_capacity(STRING_COUNT);
+        let mut string_offsets = Vec::with_capacity(TRACK_STRING_COUNT);
         let mut current_offset = HEADER_SIZE;
         for s in &strings {
             string_offsets.push(current_offset as u16);
@@ -936,7 +1614,7 @@ impl PdbBuilder {
         row.extend_from_slice(&analysis.track_number.unwrap_or(0).to_le_bytes());
         
         // 0x38-0x3B: tempo (BPM × 100)
-        let tempo = (analysis.bpm * 100.0) as u32;
+        let tempo = (analysis.bpm * 100.0).round() as u32;
         row.extend_from_slice(&tempo.to_le_bytes());
         
         // 0x3C-0x3F: genre_id
@@ -963,14 +1641,16 @@ impl PdbBuilder {
         // 0x52-0x53: sample_depth
         row.extend_from_slice(&analysis.bit_depth.to_le_bytes());
         
-        // 0x54-0x55: duration (seconds)
-        row.extend_from_slice(&(analysis.duration_secs as u16).to_le_bytes());
+        // 0x54-0x55: duration (seconds), pinned at the field's maximum
+        // (~18.2 hours) rather than wrapping for longer recordings
+        let duration = analysis.duration_secs.round().clamp(0.0, u16::MAX as f64) as u16;
+        row.extend_from_slice(&duration.to_le_bytes());
         
         // 0x56-0x57: unknown - Kaitai says "always 41?"
         row.extend_from_slice(&41u16.to_le_bytes());
         
         // 0x58: color_id
-        row.push(0);
+        row.push(track.color_id);
         
         // 0x59: rating
         row.push(0);
@@ -1042,6 +1722,8 @@ impl PdbBuilder {
     /// Kaitai spec: magic(u2) + index_shift(u2) + unknown(u4) + artist_id(u4) + 
     ///              id(u4) + unknown(u4) + 0x03(u1) + ofs_name(u1)
     /// Note: Kaitai only defines near format (0x80), far format (0x84) follows artist pattern
+    /// `artist_id` is the album artist. The spec has no compilation flag, so
+    /// compilations are told apart by their album artist alone.
     fn build_album_row(&self, id: u32, artist_id: u32, name: &str) -> Vec<u8> {
         let name_encoded = encode_string(name);
         let name_len = name_encoded.len();
@@ -1098,7 +1780,7 @@ impl PdbBuilder {
     fn build_genre_row(&self, id: u32, name: &str) -> Vec<u8> {
         let mut row = Vec::new();
         row.extend_from_slice(&id.to_le_bytes());
-        row.extend_from_slice(&encode_string(name));
+        row.extend_from_slice(&encode_row_string(name));
         row
     }
     
@@ -1108,16 +1790,19 @@ impl PdbBuilder {
         let mut row = Vec::new();
         row.extend_from_slice(&id.to_le_bytes());
         row.extend_from_slice(&id.to_le_bytes()); // id2 is same as id
-        row.extend_from_slice(&encode_string(name));
+        row.extend_from_slice(&encode_row_string(name));
         row
     }
     
     /// Build a single label row
     /// Labels use the same format as genres: id (4 bytes) + name (DeviceSQL string)
+    /// Kaitai spec `label_row` has no subtype or near/far name offset (unlike
+    /// artist/album rows); the string's own long-ASCII/UTF-16 header carries
+    /// lengths past 126 chars
     fn build_label_row(&self, id: u32, name: &str) -> Vec<u8> {
         let mut row = Vec::new();
         row.extend_from_slice(&id.to_le_bytes());
-        row.extend_from_slice(&encode_string(name));
+        row.extend_from_slice(&encode_row_string(name));
         row
     }
     
@@ -1139,7 +1824,7 @@ impl PdbBuilder {
         row.push(id as u8);                 // byte 4: u2 = id
         row.push(id as u8);                 // byte 5: id
         row.extend_from_slice(&[0u8; 2]);  // 2 zeros
-        row.extend_from_slice(&encode_string(name));
+        row.extend_from_slice(&encode_row_string(name));
         row
     }
     
@@ -1184,17 +1869,228 @@ impl PdbBuilder {
     fn build_artwork_row(&self, id: u32, path: &str) -> Vec<u8> {
         let mut row = Vec::new();
         row.extend_from_slice(&id.to_le_bytes());
-        row.extend_from_slice(&encode_string(path));
+        row.extend_from_slice(&encode_row_string(path));
         row
     }
 }
 
+/// Encode a row's string field, clamped to MAX_ROW_STRING_CHARS
+///
+/// Long names need no offset handling in these rows (the string directly
+/// follows the fixed fields and carries its own length), so the only limit
+/// is fitting the row on a page.
+fn encode_row_string(s: &str) -> Vec<u8> {
+    match s.char_indices().nth(MAX_ROW_STRING_CHARS) {
+        Some((end, _)) => encode_string(&s[..end]),
+        None => encode_string(s),
+    }
+}
+
 impl Default for PdbBuilder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Track row field offsets used when reading or editing an existing database
+pub(crate) const TRACK_SAMPLE_RATE: usize = 0x08;
+pub(crate) const TRACK_FILE_SIZE: usize = 0x10;
+pub(crate) const TRACK_ARTWORK_ID: usize = 0x1C;
+pub(crate) const TRACK_KEY_ID: usize = 0x20;
+pub(crate) const TRACK_LABEL_ID: usize = 0x28;
+pub(crate) const TRACK_BITRATE: usize = 0x30;
+pub(crate) const TRACK_NUMBER: usize = 0x34;
+pub(crate) const TRACK_TEMPO: usize = 0x38;
+pub(crate) const TRACK_GENRE_ID: usize = 0x3C;
+pub(crate) const TRACK_ALBUM_ID: usize = 0x40;
+pub(crate) const TRACK_ARTIST_ID: usize = 0x44;
+pub(crate) const TRACK_ID: usize = 0x48;
+pub(crate) const TRACK_YEAR: usize = 0x50;
+pub(crate) const TRACK_BIT_DEPTH: usize = 0x52;
+pub(crate) const TRACK_DURATION: usize = 0x54;
+pub(crate) const TRACK_COLOR_ID: usize = 0x58;
+pub(crate) const TRACK_STRING_OFFSETS: usize = 0x5E;
+pub(crate) const TRACK_STRING_COUNT: usize = 21;
+pub(crate) const TRACK_SLOT_MESSAGE: usize = 5;
+pub(crate) const TRACK_SLOT_RELEASE_DATE: usize = 11;
+pub(crate) const TRACK_SLOT_ANALYZE_PATH: usize = 14;
+pub(crate) const TRACK_SLOT_ANALYZE_DATE: usize = 15;
+pub(crate) const TRACK_SLOT_COMMENT: usize = 16;
+pub(crate) const TRACK_SLOT_TITLE: usize = 17;
+pub(crate) const TRACK_SLOT_FILE_PATH: usize = 20;
+
+/// Rewrite a single track row of an existing export.pdb in place
+///
+/// The row is located by `analysis.id` and re-encoded from `analysis`, keeping
+/// the artist/album/genre/label/key/artwork ids and analyze path already stored
+/// in the row. Nothing else in the file changes. If the new row is longer than
+/// the space the old one occupied, `Error::PageOverflow` is returned and the
+/// caller should rebuild the whole database (see `update_or_rebuild_track`).
+pub fn update_track_in_pdb(pdb: &[u8], analysis: &TrackAnalysis) -> Result<Vec<u8>> {
+    let row_start = find_track_row(pdb, analysis.id)?
+        .ok_or_else(|| Error::Database(format!("track {} not found", analysis.id)))?;
+    // find_track_row only returns rows whose fixed part lies within the page
+    let row = &pdb[row_start..(row_start / PAGE_SIZE + 1) * PAGE_SIZE];
+
+    let (last_offset, _, last_len) = track_row_string(row, TRACK_STRING_COUNT - 1)?;
+    let old_len = last_offset + last_len;
+    // write_row pads each row to 4 bytes, so that space belongs to the row too
+    let capacity = old_len.div_ceil(4) * 4;
+
+    let (_, analyze_path, _) = track_row_string(row, TRACK_SLOT_ANALYZE_PATH)?;
+    let (_, marker, _) = track_row_string(row, TRACK_SLOT_MESSAGE)?;
+    let info = TrackInfo {
+        analysis: analysis.clone(),
+        artist_id: read_u32(row, TRACK_ARTIST_ID),
+        album_id: read_u32(row, TRACK_ALBUM_ID),
+        genre_id: read_u32(row, TRACK_GENRE_ID),
+        label_id: read_u32(row, TRACK_LABEL_ID),
+        key_id: read_u32(row, TRACK_KEY_ID),
+        artwork_id: read_u32(row, TRACK_ARTWORK_ID),
+        color_id: row[TRACK_COLOR_ID],
+        analyze_path,
+    };
+    let mut builder = PdbBuilder::new();
+    builder.set_tool_marker((!marker.is_empty()).then_some(marker.as_str()));
+    let new_row = builder.build_track_row(&info)?;
+
+    if new_row.len() > capacity || capacity > row.len() {
+        return Err(Error::PageOverflow(format!(
+            "track {} row grew from {} to {} bytes, full rebuild required",
+            analysis.id, old_len, new_row.len()
+        )));
+    }
+
+    let mut output = pdb.to_vec();
+    output[row_start..row_start + new_row.len()].copy_from_slice(&new_row);
+    output[row_start + new_row.len()..row_start + capacity].fill(0);
+    Ok(output)
+}
+
+/// Rewrite a single track of an existing export.pdb, in place when the new
+/// row fits and by rebuilding the database from its own rows otherwise
+///
+/// The rebuild goes through `PdbBuilder::from_bytes`, so it resets what that
+/// leaves out (track colors and play history); the tool marker is kept.
+pub fn update_or_rebuild_track(pdb: &[u8], analysis: &TrackAnalysis) -> Result<Vec<u8>> {
+    match update_track_in_pdb(pdb, analysis) {
+        Err(Error::PageOverflow(_)) => {
+            let marker = read_track_marker(pdb, analysis.id)?;
+            let mut builder = PdbBuilder::from_bytes(pdb)?;
+            builder.set_tool_marker(marker.as_deref());
+            builder.replace_track(analysis.id, analysis);
+            builder.build()
+        }
+        result => result,
+    }
+}
+
+/// A track as its PDB row describes it, with the names from `names`
+fn restored_track(row: &TrackRow, names: TrackAnalysis) -> TrackAnalysis {
+    let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+    TrackAnalysis {
+        id: row.id,
+        file_path: row.file_path.clone(),
+        title: row.title.clone(),
+        duration_secs: row.duration_secs as f64,
+        sample_rate: row.sample_rate,
+        bit_depth: row.bit_depth,
+        bitrate: row.bitrate,
+        bpm: row.bpm,
+        file_size: row.file_size as u64,
+        year: Some(row.year).filter(|&y| y > 0),
+        release_date: non_empty(&row.release_date),
+        comment: non_empty(&row.comment),
+        track_number: Some(row.track_number).filter(|&n| n > 0),
+        file_type: row.file_path
+            .rsplit_once('.')
+            .map(|(_, ext)| FileType::from_extension(ext))
+            .unwrap_or_default(),
+        ..names
+    }
+}
+
+/// Read the transaction sequence written by `PdbBuilder::set_sequence`
+pub fn read_sequence(pdb: &[u8]) -> Result<u32> {
+    if pdb.len() < PAGE_SIZE {
+        return Err(Error::Database("file shorter than the header page".into()));
+    }
+    Ok(read_u32(pdb, HEADER_SEQUENCE))
+}
+
+/// Read the tool marker (message slot) of a track row, if one was written
+pub fn read_track_marker(pdb: &[u8], track_id: u32) -> Result<Option<String>> {
+    let row_start = find_track_row(pdb, track_id)?
+        .ok_or_else(|| Error::Database(format!("track {} not found", track_id)))?;
+    let row = &pdb[row_start..(row_start / PAGE_SIZE + 1) * PAGE_SIZE];
+    let (_, marker, _) = track_row_string(row, TRACK_SLOT_MESSAGE)?;
+    Ok(Some(marker).filter(|m| !m.is_empty()))
+}
+
+/// Decode string `slot` of a track row running to the end of its page
+///
+/// Returns the string's offset in the row, its value and its encoded length.
+fn track_row_string(row: &[u8], slot: usize) -> Result<(usize, String, usize)> {
+    let pos = TRACK_STRING_OFFSETS + slot * 2;
+    let offset = u16::from_le_bytes([row[pos], row[pos + 1]]) as usize;
+    let data = row.get(offset..)
+        .ok_or_else(|| Error::Database(format!("track string {} at {:#x} lies past its page", slot, offset)))?;
+    let (value, len) = decode_string(data)?;
+    Ok((offset, value, len))
+}
+
+/// Find the absolute offset of a track row by id, walking the Tracks data pages
+///
+/// Rows are only returned when their fixed-size part (up to the string
+/// offsets) lies within their page.
+fn find_track_row(pdb: &[u8], track_id: u32) -> Result<Option<usize>> {
+    if pdb.len() < PAGE_SIZE || !pdb.len().is_multiple_of(PAGE_SIZE) {
+        return Err(Error::Database(format!("invalid PDB size {}", pdb.len())));
+    }
+    let num_pages = (pdb.len() / PAGE_SIZE) as u32;
+    let pointer = HEADER_TABLE_POINTERS + PageType::Tracks as usize * 16;
+    let index_page = read_u32(pdb, pointer + 8);
+    if index_page >= num_pages {
+        return Err(Error::Database(format!("tracks index page {} out of range", index_page)));
+    }
+    // Row groups grow down from the page end and must not reach the heap
+    let max_rows = (PAGE_SIZE - HEAP_START) / ROW_GROUP_SIZE * ROWS_PER_GROUP;
+
+    // The index page points at the first data page
+    let mut page_idx = read_u32(pdb, index_page as usize * PAGE_SIZE + 0x2C);
+    let mut visited = 0;
+    while page_idx < num_pages && visited < num_pages {
+        let page_start = page_idx as usize * PAGE_SIZE;
+        let page = &pdb[page_start..page_start + PAGE_SIZE];
+        let num_rows = (u32::from_le_bytes([page[0x18], page[0x19], page[0x1A], 0]) & 0x7FF) as usize;
+        if num_rows > max_rows {
+            return Err(Error::Database(format!("page {} claims {} rows", page_idx, num_rows)));
+        }
+
+        for row in 0..num_rows {
+            let group_start = PAGE_SIZE - (row / ROWS_PER_GROUP + 1) * ROW_GROUP_SIZE;
+            let pos = group_start + (ROWS_PER_GROUP - 1 - row % ROWS_PER_GROUP) * 2;
+            let offset = u16::from_le_bytes([page[pos], page[pos + 1]]) as usize;
+            let row_start = HEAP_START + offset;
+
+            if row_start + TRACK_STRING_OFFSETS + TRACK_STRING_COUNT * 2 <= PAGE_SIZE
+                && read_u32(page, row_start + TRACK_ID) == track_id
+            {
+                return Ok(Some(page_start + row_start));
+            }
+        }
+
+        page_idx = read_u32(page, 0x08);
+        visited += 1;
+    }
+
+    Ok(None)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1208,22 +2104,19 @@ mod tests {
             artist: artist.to_string(),
             album: Some("Test Album".to_string()),
             genre: Some("Electronic".to_string()),
-            label: None,
             duration_secs: 180.0,
             sample_rate: 44100,
             bit_depth: 16,
+            channels: 2,
             bitrate: 320,
             bpm: 128.0,
             key: Some(Key::new(9, false)), // Am
-            beat_grid: BeatGrid::default(),
-            waveform: Waveform::default(),
-            cue_points: Vec::new(),
             file_size: 5_000_000,
             file_hash: 0x12345678,
             year: Some(2024),
-            comment: None,
             track_number: Some(1),
             file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
         }
     }
     
@@ -1260,4 +2153,762 @@ mod tests {
         let data = builder.build().unwrap();
         assert!(data.len() >= PAGE_SIZE * 2);
     }
+
+    /// Read (entry_index, track_id, playlist_id) rows from the first PlaylistEntries data page
+    fn read_playlist_entries(data: &[u8]) -> Vec<(u32, u32, u32)> {
+        let ptr = HEADER_TABLE_POINTERS + PageType::PlaylistEntries as usize * 16;
+        let data_page = u32::from_le_bytes(data[ptr + 12..ptr + 16].try_into().unwrap()) as usize;
+        let page = &data[data_page * PAGE_SIZE..(data_page + 1) * PAGE_SIZE];
+        let num_rows = u32::from_le_bytes([page[0x18], page[0x19], page[0x1A], 0]) & 0x7FF;
+        (0..num_rows as usize)
+            .map(|i| {
+                let row = &page[0x28 + i * 12..0x28 + (i + 1) * 12];
+                (
+                    u32::from_le_bytes(row[0..4].try_into().unwrap()),
+                    u32::from_le_bytes(row[4..8].try_into().unwrap()),
+                    u32::from_le_bytes(row[8..12].try_into().unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    fn build_sample_library() -> Vec<u8> {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=4 {
+            let track = make_test_track(id, &format!("Track {}", id), &format!("Artist {}", id % 2));
+            builder.add_track(&track, &format!("PIONEER/USBANLZ/P000/{:08X}/ANLZ0000.DAT", id));
+        }
+        builder.add_playlist(2, 0, "Second", vec![4, 3]);
+        builder.add_playlist(1, 0, "First", vec![1, 2, 3]);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_long_playlist_name_flagged() {
+        let long_name = "x".repeat(200);
+
+        let mut builder = PdbBuilder::new();
+        builder.add_playlist(1, 0, &long_name, vec![]);
+        assert_eq!(builder.warnings().len(), 1);
+        assert_eq!(builder.playlists[0].name.len(), 200);
+
+        let mut builder = PdbBuilder::new();
+        builder.set_truncate_playlist_names(true);
+        builder.add_playlist(1, 0, &long_name, vec![]);
+        assert_eq!(builder.warnings().len(), 1);
+        assert_eq!(builder.playlists[0].name.chars().count(), MAX_PLAYLIST_NAME_LEN);
+    }
+
+    #[test]
+    fn test_deep_folder_nesting_flagged() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=MAX_PLAYLIST_DEPTH as u32 {
+            builder.add_folder(id, id - 1, "Folder");
+        }
+        assert!(builder.warnings().is_empty());
+
+        builder.add_playlist(100, MAX_PLAYLIST_DEPTH as u32, "Too deep", vec![]);
+        assert_eq!(builder.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_sort_order_counts_siblings() {
+        let mut builder = PdbBuilder::new();
+        builder.add_folder(1, 0, "Sets");
+        builder.add_playlist(2, 1, "Friday", vec![]);
+        builder.add_playlist(3, 1, "Saturday", vec![]);
+        builder.add_playlist(4, 0, "Openers", vec![]);
+
+        let order: Vec<_> = builder.playlists.iter().map(|p| (p.id, p.sort_order)).collect();
+        assert_eq!(order, vec![(1, 0), (2, 0), (3, 1), (4, 1)]);
+
+        let rebuilt = PdbBuilder::from_bytes(&builder.build().unwrap()).unwrap();
+        let ids: Vec<_> = rebuilt.playlists.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_update_track_in_place() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=3 {
+            let track = make_test_track(id, &format!("Track {}", id), "Artist");
+            builder.add_track(&track, &generate_anlz_path(id));
+        }
+        let original = builder.build().unwrap();
+
+        let mut updated_track = make_test_track(2, "Track 2", "Artist");
+        updated_track.bpm = 130.0;
+        let updated = update_track_in_pdb(&original, &updated_track).unwrap();
+
+        assert_eq!(updated.len(), original.len());
+        let row_start = find_track_row(&original, 2).unwrap().unwrap();
+        let changed: Vec<usize> = (0..original.len())
+            .filter(|&i| original[i] != updated[i])
+            .collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|&i| i >= row_start + 0x38 && i < row_start + 0x3C));
+        assert_eq!(read_u32(&updated, row_start + 0x38), 13000);
+    }
+
+    #[test]
+    fn test_high_precision_bpm_reaches_tempo_fields() {
+        // Detected 128.3312 BPM, kept at 0.01 precision
+        let bpm = round_bpm(128.3312, 0.01);
+        let mut track = make_test_track(1, "Precise", "Artist");
+        track.bpm = bpm;
+        track.beat_grid = BeatGrid::constant_tempo(bpm, 0.0, 10_000.0);
+        assert_eq!(track.beat_grid.beats[0].tempo_100, 12833);
+
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&track, &generate_anlz_path(1));
+        let pdb = builder.build().unwrap();
+        let row_start = find_track_row(&pdb, 1).unwrap().unwrap();
+        assert_eq!(read_u32(&pdb, row_start + 0x38), 12833);
+
+        // The default half-BPM step still snaps
+        assert_eq!(round_bpm(128.3312, 0.5), 128.5);
+    }
+
+    /// Decode a string slot of the track row with the given id
+    fn read_track_string(data: &[u8], track_id: u32, slot: usize) -> String {
+        let row_start = find_track_row(data, track_id).unwrap().unwrap();
+        let pos = row_start + TRACK_STRING_OFFSETS + slot * 2;
+        let offset = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        decode_string(&data[row_start + offset..]).unwrap().0
+    }
+
+    #[test]
+    fn test_analyze_date_and_long_duration() {
+        let mut track = make_test_track(1, "All Nighter", "Artist");
+        track.duration_secs = 19.0 * 3600.0; // 68400 s, past the u16 field
+        let mut builder = PdbBuilder::new();
+        builder.set_clock(ExportClock::Fixed(1_718_454_645));
+        builder.add_track(&track, &generate_anlz_path(1));
+        let pdb = builder.build().unwrap();
+
+        let row_start = find_track_row(&pdb, 1).unwrap().unwrap();
+        let duration = u16::from_le_bytes([pdb[row_start + TRACK_DURATION], pdb[row_start + TRACK_DURATION + 1]]);
+        assert_eq!(duration, u16::MAX);
+        assert_eq!(read_track_string(&pdb, 1, TRACK_SLOT_ANALYZE_DATE), "2024-06-15");
+    }
+
+    #[test]
+    fn test_release_date_slot() {
+        let mut builder = PdbBuilder::new();
+        let mut dated = make_test_track(1, "Dated", "Artist");
+        dated.release_date = Some("2024-06-15".to_string());
+        builder.add_track(&dated, &generate_anlz_path(1));
+        builder.add_track(&make_test_track(2, "Year Only", "Artist"), &generate_anlz_path(2));
+
+        let data = builder.build().unwrap();
+        assert_eq!(read_track_string(&data, 1, 11), "2024-06-15");
+        assert_eq!(read_track_string(&data, 2, 11), "2024-01-01");
+    }
+
+    #[test]
+    fn test_energy_field() {
+        let mut rated = make_test_track(1, "Rated", "Artist");
+        rated.energy = Some(7);
+        rated.comment = Some("Peak time".to_string());
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&rated, &generate_anlz_path(1));
+        builder.add_track(&make_test_track(2, "Unrated", "Artist"), &generate_anlz_path(2));
+
+        let data = builder.build().unwrap();
+        assert_eq!(read_track_string(&data, 1, 12), "");
+        assert_eq!(read_track_string(&data, 1, 16), "Peak time");
+
+        builder.set_energy_field(Some(EnergyField::Comment));
+        let data = builder.build().unwrap();
+        assert_eq!(read_track_string(&data, 1, 16), "Energy 7 - Peak time");
+        assert_eq!(read_track_string(&data, 2, 16), "");
+
+        builder.set_energy_field(Some(EnergyField::MixName));
+        let data = builder.build().unwrap();
+        assert_eq!(read_track_string(&data, 1, 12), "Energy 7");
+        assert_eq!(read_track_string(&data, 1, 16), "Peak time");
+        assert_eq!(read_track_string(&data, 2, 12), "");
+    }
+
+    /// First data page of a table, located through the file header
+    fn table_data_page(data: &[u8], page_type: PageType) -> &[u8] {
+        let ptr = HEADER_TABLE_POINTERS + page_type as usize * 16;
+        let index_page = read_u32(data, ptr + 8) as usize;
+        let first_data = read_u32(data, index_page * PAGE_SIZE + 0x2C) as usize;
+        &data[first_data * PAGE_SIZE..(first_data + 1) * PAGE_SIZE]
+    }
+
+    fn row_count(page: &[u8]) -> u32 {
+        u32::from_le_bytes([page[0x18], page[0x19], page[0x1A], 0]) & 0x7FF
+    }
+
+    #[test]
+    fn test_default_history_tables() {
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&make_test_track(1, "Track", "Artist"), &generate_anlz_path(1));
+        let data = builder.build().unwrap();
+
+        let page = table_data_page(&data, PageType::HistoryPlaylists);
+        assert_eq!(page[0x1B], 0x24);
+        assert_eq!(row_count(page), 1);
+        assert_eq!(read_u32(page, HEAP_START), 1);
+        let (name, _) = decode_string(&page[HEAP_START + 4..]).unwrap();
+        assert!(name.starts_with("HISTORY "));
+        assert_eq!(name.len(), "HISTORY yyyy-mm-dd".len());
+
+        // No plays yet: entries table stays empty and inactive
+        let ptr = HEADER_TABLE_POINTERS + PageType::HistoryEntries as usize * 16;
+        assert_eq!(read_u32(&data, ptr + 8), read_u32(&data, ptr + 12));
+    }
+
+    #[test]
+    fn test_history_entries_rows() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=2 {
+            builder.add_track(&make_test_track(id, &format!("Track {}", id), "Artist"), &generate_anlz_path(id));
+        }
+        builder.add_history_playlist(1, "HISTORY 2024-06-15", vec![2, 1]);
+        let data = builder.build().unwrap();
+
+        let page = table_data_page(&data, PageType::HistoryEntries);
+        assert_eq!(row_count(page), 2);
+        // track_id, playlist_id, entry_index
+        assert_eq!(read_u32(page, HEAP_START), 2);
+        assert_eq!(read_u32(page, HEAP_START + 4), 1);
+        assert_eq!(read_u32(page, HEAP_START + 8), 0);
+        assert_eq!(read_u32(page, HEAP_START + 12), 1);
+        assert_eq!(read_u32(page, HEAP_START + 20), 1);
+    }
+
+    #[test]
+    fn test_custom_color_palette() {
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&make_test_track(1, "Colored", "Artist"), &generate_anlz_path(1));
+        let default = builder.build().unwrap();
+        assert!(default.windows(4).any(|w| w == b"Pink"));
+
+        let mut palette = DEFAULT_COLORS.to_vec();
+        palette[0].1 = "Rosa";
+        palette.push((9, "Festival"));
+        builder.set_colors(&palette);
+        builder.set_track_color(1, 9);
+        assert!(builder.warnings().is_empty());
+
+        let custom = builder.build().unwrap();
+        assert!(custom.windows(4).any(|w| w == b"Rosa"));
+        assert!(!custom.windows(4).any(|w| w == b"Pink"));
+        assert!(custom.windows(8).any(|w| w == b"Festival"));
+
+        let row = find_track_row(&custom, 1).unwrap().unwrap();
+        assert_eq!(custom[row + TRACK_COLOR_ID], 9);
+
+        builder.set_track_color(1, 42);
+        assert_eq!(builder.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_tool_marker_round_trip() {
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&make_test_track(1, "Marked", "Artist"), &generate_anlz_path(1));
+        let unmarked = builder.build().unwrap();
+        assert_eq!(read_track_marker(&unmarked, 1).unwrap(), None);
+
+        builder.set_tool_marker(Some(DEFAULT_TOOL_MARKER));
+        let marked = builder.build().unwrap();
+        assert_eq!(read_track_marker(&marked, 1).unwrap().as_deref(), Some(DEFAULT_TOOL_MARKER));
+
+        // In-place updates keep the marker
+        let mut track = make_test_track(1, "Marked", "Artist");
+        track.bpm = 126.0;
+        let updated = update_track_in_pdb(&marked, &track).unwrap();
+        assert_eq!(read_track_marker(&updated, 1).unwrap().as_deref(), Some(DEFAULT_TOOL_MARKER));
+    }
+
+    #[test]
+    fn test_update_track_too_large_needs_rebuild() {
+        let mut builder = PdbBuilder::new();
+        let track = make_test_track(1, "Short", "Artist");
+        builder.add_track(&track, &generate_anlz_path(1));
+        let original = builder.build().unwrap();
+
+        let longer = make_test_track(1, &"Much longer title ".repeat(10), "Artist");
+        assert!(matches!(
+            update_track_in_pdb(&original, &longer),
+            Err(Error::PageOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_or_rebuild_track_falls_back() {
+        let mut builder = PdbBuilder::new();
+        builder.set_tool_marker(Some(DEFAULT_TOOL_MARKER));
+        builder.add_track(&make_test_track(1, "Short", "Artist"), &generate_anlz_path(1));
+        builder.add_track(&make_test_track(2, "Other", "Artist"), &generate_anlz_path(2));
+        builder.add_playlist(1, 0, "Set", vec![2, 1]);
+        let original = builder.build().unwrap();
+
+        let longer = make_test_track(1, &"Much longer title ".repeat(10), "Artist");
+        let updated = update_or_rebuild_track(&original, &longer).unwrap();
+
+        let mut reader = PdbReader::new(std::io::Cursor::new(&updated)).unwrap();
+        let titles: Vec<String> = reader.tracks().map(|t| t.unwrap().title).collect();
+        assert_eq!(titles, vec![longer.title.clone(), "Other".to_string()]);
+        assert_eq!(read_track_marker(&updated, 1).unwrap().as_deref(), Some(DEFAULT_TOOL_MARKER));
+        let rebuilt = PdbBuilder::from_bytes(&updated).unwrap();
+        assert_eq!(rebuilt.playlists()[0].track_ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_update_track_rejects_corrupt_rows() {
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&make_test_track(1, "Track", "Artist"), &generate_anlz_path(1));
+        let original = builder.build().unwrap();
+        let row_start = find_track_row(&original, 1).unwrap().unwrap();
+        let track = make_test_track(1, "Track", "Artist");
+
+        // A string offset pointing past the page
+        let mut corrupt = original.clone();
+        let pos = row_start + TRACK_STRING_OFFSETS + TRACK_SLOT_MESSAGE * 2;
+        corrupt[pos..pos + 2].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        assert!(matches!(update_track_in_pdb(&corrupt, &track), Err(Error::Database(_))));
+        assert!(read_track_marker(&corrupt, 1).is_err());
+
+        // A row count larger than the page can hold
+        let mut corrupt = original.clone();
+        let page_start = row_start / PAGE_SIZE * PAGE_SIZE;
+        corrupt[page_start + 0x18] = 0xFF;
+        corrupt[page_start + 0x19] |= 0x07;
+        assert!(matches!(update_track_in_pdb(&corrupt, &track), Err(Error::Database(_))));
+
+        assert!(update_track_in_pdb(&original[..100], &track).is_err());
+    }
+
+    #[test]
+    fn test_page_fill_report() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=60 {
+            let track = make_test_track(id, &format!("Track {}", id), "Artist");
+            builder.add_track(&track, &format!("PIONEER/USBANLZ/P000/{:08X}/ANLZ0000.DAT", id));
+        }
+
+        let data = builder.build().unwrap();
+        let report = builder.page_fill_report().unwrap();
+
+        // Every page but the file header
+        assert_eq!(report.len() + 1, data.len() / PAGE_SIZE);
+        assert!(report.iter().all(|&(_, used, capacity)| used <= capacity));
+
+        let track_pages: Vec<_> = report.iter()
+            .filter(|(page_type, used, _)| *page_type == PageType::Tracks && *used > 0)
+            .collect();
+        assert!(track_pages.len() > 1, "60 tracks should span several pages");
+        assert!(track_pages.iter().all(|&&(_, used, capacity)| used * 2 > capacity));
+
+        // Many small rows on one page: the row index grows with the row count
+        builder.add_playlist(1, 0, "Long", (1..=60).collect());
+        let report = builder.page_fill_report().unwrap();
+        let &(_, used, _) = report.iter()
+            .find(|(page_type, used, _)| *page_type == PageType::PlaylistEntries && *used > 0)
+            .unwrap();
+        assert_eq!(used, 60 * 12 + 60usize.div_ceil(ROWS_PER_GROUP) * ROW_GROUP_SIZE);
+    }
+
+    #[test]
+    fn test_layout_matches_built_file() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=60 {
+            builder.add_track(&make_test_track(id, &format!("Track {}", id), "Artist"), &generate_anlz_path(id));
+        }
+
+        let data = builder.build().unwrap();
+        let layout = builder.layout().unwrap();
+        assert_eq!(layout.total_pages as usize, data.len() / PAGE_SIZE);
+        assert_eq!(layout.table_pages.len(), PageType::all_types().len());
+
+        let (first, last) = layout.table_pages[&PageType::Tracks];
+        assert!(first < last && last < layout.total_pages);
+        assert_eq!(table_data_page(&data, PageType::Tracks).as_ptr(), data[first as usize * PAGE_SIZE..].as_ptr());
+
+        // The header's table pointer agrees on the last data page
+        let ptr = HEADER_TABLE_POINTERS + PageType::Tracks as usize * 16;
+        assert_eq!(read_u32(&data, ptr + 12), last);
+        let page = &data[last as usize * PAGE_SIZE..(last as usize + 1) * PAGE_SIZE];
+        assert!(row_count(page) > 0);
+    }
+
+    #[test]
+    fn test_build_is_deterministic() {
+        assert_eq!(build_sample_library(), build_sample_library());
+    }
+
+    #[test]
+    fn test_sequence_increases_between_builds() {
+        let first = build_sample_library();
+        assert_eq!(read_sequence(&first).unwrap(), DEFAULT_SEQUENCE);
+
+        let mut builder = PdbBuilder::new();
+        builder.add_track(&make_test_track(1, "Track", "Artist"), &generate_anlz_path(1));
+        builder.set_sequence(read_sequence(&first).unwrap() + 1);
+        let second = builder.build().unwrap();
+        assert_eq!(read_sequence(&second).unwrap(), DEFAULT_SEQUENCE + 1);
+        assert!(crate::validate::validate_pdb(&second).valid);
+
+        // The sequence has its own header field; table pointers follow it
+        builder.set_sequence(3);
+        let low = builder.build().unwrap();
+        assert_eq!(read_sequence(&low).unwrap(), 3);
+        for (i, page_type) in PageType::all_types().iter().enumerate() {
+            assert_eq!(read_u32(&low, HEADER_TABLE_POINTERS + i * 16), *page_type as u32);
+        }
+    }
+
+    #[test]
+    fn test_playlist_entries_sorted_by_playlist_then_index() {
+        let data = build_sample_library();
+        let entries = read_playlist_entries(&data);
+
+        assert_eq!(entries, vec![
+            (0, 1, 1),
+            (1, 2, 1),
+            (2, 3, 1),
+            (0, 4, 2),
+            (1, 3, 2),
+        ]);
+    }
+
+    #[test]
+    fn test_compilation_album_artist() {
+        let mut builder = PdbBuilder::new();
+        for (id, artist) in [(1, "Artist A"), (2, "Artist B")] {
+            let mut track = make_test_track(id, "Track", artist);
+            track.album = Some("Summer Hits".to_string());
+            track.compilation = true;
+            builder.add_track(&track, &generate_anlz_path(id));
+        }
+        let mut single = make_test_track(3, "Track", "Artist C");
+        single.album = Some("Solo".to_string());
+        builder.add_track(&single, &generate_anlz_path(3));
+
+        // Both compilation tracks share one album, owned by Various Artists
+        assert_eq!(builder.tracks[0].album_id, builder.tracks[1].album_id);
+        assert_ne!(builder.tracks[0].album_id, builder.tracks[2].album_id);
+        let various = builder.artists[VARIOUS_ARTISTS];
+        let artist_c = builder.artists["Artist C"];
+
+        let data = builder.build().unwrap();
+        let result = crate::validate::validate_pdb(&data);
+        assert!(result.valid, "Errors: {:?}", result.errors);
+
+        let albums = table_data_page(&data, PageType::Albums);
+        assert_eq!(row_count(albums), 2);
+        // Rows: artist_id at +8, id at +12; the unknown at +16 stays zero
+        let compilation = HEAP_START;
+        assert_eq!(read_u32(albums, compilation + 8), various);
+        assert_eq!(read_u32(albums, compilation + 12), builder.tracks[0].album_id);
+        assert_eq!(read_u32(albums, compilation + 16), 0);
+        let solo = HEAP_START + (22 + encode_string("Summer Hits").len()).next_multiple_of(4);
+        assert_eq!(read_u32(albums, solo + 8), artist_c);
+        assert_eq!(read_u32(albums, solo + 16), 0);
+
+        // An album artist tag wins over Various Artists
+        let mut tagged = make_test_track(4, "Track", "Artist D");
+        tagged.album = Some("Mixed".to_string());
+        tagged.album_artist = Some("DJ E".to_string());
+        tagged.compilation = true;
+        builder.add_track(&tagged, &generate_anlz_path(4));
+        let album_id = builder.tracks[3].album_id;
+        assert_eq!(builder.albums[&("Mixed".to_string(), builder.artists["DJ E"])], album_id);
+    }
+
+    #[test]
+    fn test_long_label_and_artwork_rows() {
+        let label = "L".repeat(300);
+        let artwork_path = format!("/PIONEER/Artwork/00001/{}.jpg", "a".repeat(400));
+
+        let mut builder = PdbBuilder::new();
+        let mut track = make_test_track(1, "Track", "Artist");
+        track.label = Some(label.clone());
+        builder.add_track_with_artwork(&track, &generate_anlz_path(1), Some(&artwork_path));
+        let mut unicode = make_test_track(2, "Track", "Artist");
+        unicode.label = Some("é".repeat(300));
+        builder.add_track(&unicode, &generate_anlz_path(2));
+
+        let data = builder.build().unwrap();
+        let result = crate::validate::validate_pdb(&data);
+        assert!(result.valid, "Errors: {:?}", result.errors);
+
+        let labels = table_data_page(&data, PageType::Labels);
+        assert_eq!(row_count(labels), 2);
+        let (name, used) = decode_string(&labels[HEAP_START + 4..]).unwrap();
+        assert_eq!(name, label);
+        // Second row follows the first, 4-byte aligned
+        let second = HEAP_START + (4 + used).next_multiple_of(4);
+        assert_eq!(read_u32(labels, second), 2);
+        assert_eq!(decode_string(&labels[second + 4..]).unwrap().0, "é".repeat(300));
+
+        let artwork = table_data_page(&data, PageType::Artwork);
+        assert_eq!(read_u32(artwork, HEAP_START), stable_artwork_id(artwork_path.as_bytes()));
+        assert_eq!(decode_string(&artwork[HEAP_START + 4..]).unwrap().0, artwork_path);
+    }
+
+    #[test]
+    fn test_artwork_ids_stable_across_builds() {
+        let paths = ["/PIONEER/Artwork/00001/cover.jpg", "/PIONEER/Artwork/00002/a123.jpg", "/art/other.jpg"];
+        let build = |order: &[usize]| {
+            let mut builder = PdbBuilder::new();
+            for &i in order {
+                let track = make_test_track(i as u32 + 1, "Track", "Artist");
+                builder.add_track_with_artwork(&track, &generate_anlz_path(track.id), Some(paths[i]));
+            }
+            builder.artworks.clone()
+        };
+
+        // Track order no longer decides the ids
+        let first = build(&[0, 1, 2]);
+        assert_eq!(first, build(&[2, 0, 1]));
+        assert_eq!(first[paths[0]], stable_artwork_id(paths[0].as_bytes()));
+        // Exported a{id}.jpg names keep their id
+        assert_eq!(first[paths[1]], 123);
+
+        // A colliding path probes to the next free id
+        let mut builder = PdbBuilder::new();
+        assert_eq!(builder.get_or_create_artwork(paths[1]), 123);
+        assert_eq!(builder.get_or_create_artwork("/x/a123.jpg"), 124);
+        assert_eq!(builder.get_or_create_artwork(paths[1]), 123);
+    }
+
+    #[test]
+    fn test_label_row_long_string_formats() {
+        let builder = PdbBuilder::new();
+
+        // Either side of the short ASCII limit, and a UTF-16 name
+        for name in ["L".repeat(126), "L".repeat(127), "Ç".repeat(150)] {
+            let row = builder.build_label_row(7, &name);
+            assert_eq!(read_u32(&row, 0), 7);
+            let (decoded, used) = decode_string(&row[4..]).unwrap();
+            assert_eq!(decoded, name);
+            assert_eq!(4 + used, row.len(), "no offset fields around the name");
+        }
+        // Long ASCII header (0x40) rather than the 1-byte short form
+        assert_eq!(builder.build_label_row(1, &"L".repeat(127))[4], 0x40);
+    }
+
+    #[test]
+    fn test_preloaded_ids_are_reused() {
+        let mut builder = PdbBuilder::new();
+        builder.preload_artist("Artist A", 5);
+        builder.preload_genre("Techno", 3);
+
+        let mut a = make_test_track(1, "One", "Artist A");
+        a.genre = Some("Techno".into());
+        builder.add_track(&a, &generate_anlz_path(1));
+        builder.add_track(&make_test_track(2, "Two", "Artist B"), &generate_anlz_path(2));
+        builder.add_track(&make_test_track(3, "Three", "Artist C"), &generate_anlz_path(3));
+
+        assert_eq!(builder.tracks[0].artist_id, 5);
+        assert_eq!(builder.tracks[1].artist_id, 6);
+        assert_eq!(builder.tracks[2].artist_id, 7);
+        assert_eq!(builder.tracks[0].genre_id, 3);
+        assert_eq!(builder.tracks[1].genre_id, 4);
+
+        // Preloading a lower id never moves the counter back
+        builder.preload_artist("Artist Z", 2);
+        builder.add_track(&make_test_track(4, "Four", "Artist D"), &generate_anlz_path(4));
+        assert_eq!(builder.tracks[3].artist_id, 8);
+
+        let data = builder.build().unwrap();
+        assert!(crate::validate::validate_pdb(&data).valid);
+    }
+
+    #[test]
+    fn test_unbuildable_track_skipped() {
+        let mut builder = PdbBuilder::new();
+        let mut huge = make_test_track(2, "Huge", "Artist");
+        huge.comment = Some("x".repeat(5000));
+        builder.add_track(&make_test_track(1, "One", "Artist"), &generate_anlz_path(1));
+        builder.add_track(&huge, &generate_anlz_path(2));
+        builder.add_track(&make_test_track(3, "Three", "Artist"), &generate_anlz_path(3));
+        builder.add_playlist(1, 0, "Set", vec![1, 2, 3]);
+
+        let (data, skipped) = builder.build_with_skipped().unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, 2);
+
+        assert!(find_track_row(&data, 1).unwrap().is_some());
+        assert!(find_track_row(&data, 2).unwrap().is_none());
+        assert!(find_track_row(&data, 3).unwrap().is_some());
+        let entries: Vec<u32> = read_playlist_entries(&data).iter().map(|e| e.1).collect();
+        assert_eq!(entries, vec![1, 3]);
+
+        assert_eq!(builder.build().unwrap(), data);
+    }
+
+    #[test]
+    fn test_add_track_with_pinned_id() {
+        let mut builder = PdbBuilder::new();
+        let track = make_test_track(1, "Pinned", "Artist");
+        assert_eq!(builder.add_track_with_id(4021, &track, &generate_anlz_path(4021)), 4021);
+        assert!(find_track_row(&builder.build().unwrap(), 4021).unwrap().is_some());
+        assert_eq!(builder.tracks[0].analysis.id, 4021);
+    }
+
+    #[test]
+    fn test_remove_and_replace_track() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=3 {
+            builder.add_track(&make_test_track(id, &format!("Track {}", id), "Artist"), &generate_anlz_path(id));
+        }
+        builder.add_playlist(1, 0, "Set", vec![3, 2, 1]);
+        builder.add_history_playlist(1, "HISTORY 2024-01-01", vec![2, 3]);
+
+        assert!(builder.remove_track(2));
+        assert!(!builder.remove_track(2));
+        assert_eq!(builder.playlists()[0].track_ids, vec![3, 1]);
+        assert_eq!(builder.history_playlists[0].track_ids, vec![3]);
+
+        let data = builder.build().unwrap();
+        assert!(crate::validate::validate_pdb(&data).valid);
+        assert!(find_track_row(&data, 1).unwrap().is_some());
+        assert!(find_track_row(&data, 2).unwrap().is_none());
+        assert!(find_track_row(&data, 3).unwrap().is_some());
+        let entries: Vec<(u32, u32)> = read_playlist_entries(&data).iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(entries, vec![(0, 3), (1, 1)]);
+
+        // Replacing keeps the id and position but re-interns the artist
+        let mut retagged = make_test_track(99, "Track 3 (Edit)", "Other Artist");
+        retagged.genre = Some("House".into());
+        assert!(builder.replace_track(3, &retagged));
+        assert!(!builder.replace_track(2, &retagged));
+        assert_eq!(builder.tracks[1].analysis.id, 3);
+        assert_eq!(builder.tracks[1].analysis.title, "Track 3 (Edit)");
+        assert_ne!(builder.tracks[1].artist_id, builder.tracks[0].artist_id);
+        assert_eq!(builder.tracks[1].analyze_path, generate_anlz_path(3));
+
+        let data = builder.build().unwrap();
+        assert!(find_track_row(&data, 3).unwrap().is_some());
+        assert!(find_track_row(&data, 99).unwrap().is_none());
+        assert!(data.windows(14).any(|w| w == b"Track 3 (Edit)"));
+    }
+
+    #[test]
+    fn test_set_playlist_order() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=3 {
+            builder.add_track(&make_test_track(id, &format!("Track {}", id), "Artist"), &generate_anlz_path(id));
+        }
+        builder.add_folder(1, 0, "Folder");
+        builder.add_playlist(2, 1, "Set", vec![1, 2, 3]);
+
+        builder.set_playlist_order(2, vec![3, 1, 2]).unwrap();
+        let data = builder.build().unwrap();
+        let entries: Vec<(u32, u32)> = read_playlist_entries(&data).iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(entries, vec![(0, 3), (1, 1), (2, 2)]);
+
+        // Dropping, adding or swapping tracks is not a reorder
+        assert!(matches!(builder.set_playlist_order(2, vec![3, 1]), Err(Error::Validation(_))));
+        assert!(matches!(builder.set_playlist_order(2, vec![3, 1, 1]), Err(Error::Validation(_))));
+        assert!(matches!(builder.set_playlist_order(2, vec![3, 1, 4]), Err(Error::Validation(_))));
+        assert!(matches!(builder.set_playlist_order(1, vec![]), Err(Error::Database(_))));
+        assert!(matches!(builder.set_playlist_order(9, vec![]), Err(Error::Database(_))));
+        assert_eq!(builder.playlists()[1].track_ids, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_track_row_size_matches_built_row() {
+        let mut builder = PdbBuilder::new();
+        builder.set_tool_marker(Some("rekord-export 0.1"));
+
+        let plain = make_test_track(1, "Plain", "Artist");
+        let mut tagged = make_test_track(2, "日本語のタイトル", "Artist");
+        tagged.comment = Some("x".repeat(200));
+        tagged.year = Some(1999);
+        let mut dated = make_test_track(3, "Dated", "Artist");
+        dated.release_date = Some("2024-06-15".into());
+        dated.file_path = "/Contents/Ünïcödé/track.flac".into();
+
+        builder.add_track(&plain, &generate_anlz_path(1));
+        builder.add_track(&tagged, "PIONEER/USBANLZ/custom/ANLZ0000.DAT");
+        for (track, analysis) in builder.tracks.iter().zip([&plain, &tagged]) {
+            assert_eq!(builder.track_row_size(analysis), builder.build_track_row(track).unwrap().len());
+        }
+
+        // Not added yet: sized with the default ANLZ path it will get
+        let size = builder.track_row_size(&dated);
+        builder.add_track(&dated, &generate_anlz_path(3));
+        assert_eq!(size, builder.build_track_row(&builder.tracks[2]).unwrap().len());
+    }
+
+    #[test]
+    fn test_oversized_row_string_clamped() {
+        let mut builder = PdbBuilder::new();
+        let mut track = make_test_track(1, "Track", "Artist");
+        track.genre = Some("\u{1F3B5}".repeat(3000));
+        builder.add_track(&track, &generate_anlz_path(1));
+
+        let data = builder.build().unwrap();
+        let genres = table_data_page(&data, PageType::Genres);
+        let (name, _) = decode_string(&genres[HEAP_START + 4..]).unwrap();
+        assert_eq!(name.chars().count(), MAX_ROW_STRING_CHARS);
+    }
+
+    #[test]
+    fn test_from_bytes_round_trip() {
+        let long_artist = "A".repeat(300); // far artist row
+        let mut builder = PdbBuilder::new();
+        builder.set_clock(ExportClock::Fixed(1_718_454_645));
+        let mut first = make_test_track(1, "First", &long_artist);
+        first.label = Some("Label".to_string());
+        first.comment = Some("Warm-up".to_string());
+        builder.add_track_with_artwork(&first, &generate_anlz_path(1), Some("/PIONEER/Artwork/00001/a1.jpg"));
+        for (id, artist) in [(2, "Artist B"), (3, "Artist C")] {
+            let mut track = make_test_track(id, "Various", artist);
+            track.album = Some("Summer Hits".to_string());
+            track.compilation = true;
+            track.key = None;
+            builder.add_track(&track, &generate_anlz_path(id));
+        }
+        builder.add_folder(10, 0, "Sets");
+        builder.add_playlist(11, 10, "Friday", vec![3, 1, 2]);
+        builder.add_playlist(12, 0, "Openers", vec![1]);
+        let data = builder.build().unwrap();
+
+        let mut restored = PdbBuilder::from_bytes(&data).unwrap();
+        assert!(restored.warnings().is_empty(), "{:?}", restored.warnings());
+        assert_eq!(read_sequence(&data).unwrap() + 1, restored.sequence);
+        assert_eq!(restored.tracks.len(), 3);
+        let track = &restored.tracks[0].analysis;
+        assert_eq!(track.artist, long_artist);
+        assert_eq!(track.label.as_deref(), Some("Label"));
+        assert_eq!(track.key, Some(Key::new(9, false)));
+        assert_eq!(track.comment.as_deref(), Some("Warm-up"));
+        assert_eq!(track.file_type, FileType::Mp3);
+        assert_eq!(restored.tracks[1].analysis.album_artist.as_deref(), Some(VARIOUS_ARTISTS));
+        assert_eq!(restored.playlists()[1].track_ids, [3, 1, 2]);
+        assert!(restored.playlists()[0].is_folder);
+
+        // Nothing added: the same file comes back out
+        restored.set_sequence(read_sequence(&data).unwrap());
+        restored.set_clock(ExportClock::Fixed(1_718_454_645));
+        assert_eq!(restored.build().unwrap(), data);
+
+        // New tracks reuse the ids already on the stick
+        let artist_id = restored.tracks[0].artist_id;
+        restored.add_track(&make_test_track(4, "Fourth", &long_artist), &generate_anlz_path(4));
+        assert_eq!(restored.tracks[3].artist_id, artist_id);
+        assert_eq!(restored.tracks[3].genre_id, restored.tracks[0].genre_id);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_corrupt_pages() {
+        let data = build_sample_library();
+        assert!(PdbBuilder::from_bytes(&data[..PAGE_SIZE + 100]).is_err());
+
+        // A row count the page can't hold
+        let mut corrupt = data.clone();
+        let ptr = HEADER_TABLE_POINTERS + PageType::Artists as usize * 16;
+        let page = u32::from_le_bytes(corrupt[ptr + 12..ptr + 16].try_into().unwrap()) as usize * PAGE_SIZE;
+        corrupt[page + 0x18] = 0xFF;
+        corrupt[page + 0x19] |= 0x07;
+        assert!(PdbBuilder::from_bytes(&corrupt).is_err());
+    }
 }
@@ -0,0 +1,514 @@
+//! Streaming reader for export.pdb
+//!
+//! Reads one 4096-byte page at a time from any `Read + Seek` source and
+//! decodes rows lazily, so memory stays at a page regardless of library
+//! size. Tables are walked like the players do: from the table pointer's
+//! index page to its first data page, then along the data pages' next_page
+//! links until the table's last page.
+//!
+//! Besides Tracks, the tables a library is rebuilt from (artists, albums,
+//! genres, labels, keys, artwork and playlists) can be read the same way;
+//! `PdbBuilder::from_bytes` uses them to pick up an existing stick.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::page::{PageType, HEADER_TABLE_POINTERS, HEAP_START, PAGE_SIZE, ROW_GROUP_SIZE, ROWS_PER_GROUP};
+use crate::pdb::{
+    SUBTYPE_FAR, SUBTYPE_NEAR, TRACK_ALBUM_ID, TRACK_ARTIST_ID, TRACK_ARTWORK_ID, TRACK_BIT_DEPTH,
+    TRACK_BITRATE, TRACK_COLOR_ID, TRACK_DURATION,
+    TRACK_FILE_SIZE, TRACK_GENRE_ID, TRACK_ID, TRACK_KEY_ID, TRACK_LABEL_ID, TRACK_NUMBER,
+    TRACK_SAMPLE_RATE, TRACK_SLOT_ANALYZE_DATE, TRACK_SLOT_ANALYZE_PATH, TRACK_SLOT_COMMENT,
+    TRACK_SLOT_FILE_PATH, TRACK_SLOT_RELEASE_DATE, TRACK_SLOT_TITLE, TRACK_STRING_COUNT,
+    TRACK_STRING_OFFSETS, TRACK_TEMPO, TRACK_YEAR,
+};
+use crate::string::decode_string;
+
+/// One decoded Tracks row
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackRow {
+    pub id: u32,
+    pub title: String,
+    /// USB-relative path of the audio file
+    pub file_path: String,
+    /// USB-relative path of the ANLZ .DAT file
+    pub analyze_path: String,
+    /// YYYY-MM-DD the export wrote the analysis
+    pub analyze_date: String,
+    pub comment: String,
+    /// YYYY-MM-DD, or empty
+    pub release_date: String,
+    pub artist_id: u32,
+    pub album_id: u32,
+    pub genre_id: u32,
+    pub label_id: u32,
+    pub key_id: u32,
+    pub artwork_id: u32,
+    pub color_id: u8,
+    pub bpm: f64,
+    pub duration_secs: u16,
+    /// 0 when unknown
+    pub year: u16,
+    pub track_number: u32,
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    /// kbps
+    pub bitrate: u32,
+    pub file_size: u32,
+}
+
+/// One decoded Artists row (near or far name offset)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtistRow {
+    pub id: u32,
+    pub name: String,
+}
+
+/// One decoded Albums row
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbumRow {
+    pub id: u32,
+    /// Album artist
+    pub artist_id: u32,
+    pub name: String,
+}
+
+/// An id and its string: a Genres, Labels or Keys name, or an Artwork path
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedRow {
+    pub id: u32,
+    pub name: String,
+}
+
+/// One decoded PlaylistTree row (a playlist or folder)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistRow {
+    pub id: u32,
+    /// 0 for the root
+    pub parent_id: u32,
+    pub sort_order: u32,
+    pub is_folder: bool,
+    pub name: String,
+}
+
+/// One decoded PlaylistEntries row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaylistEntryRow {
+    /// 1-based position in the playlist
+    pub entry_index: u32,
+    pub track_id: u32,
+    pub playlist_id: u32,
+}
+
+/// Page-at-a-time reader over an export.pdb
+pub struct PdbReader<R> {
+    inner: R,
+    num_pages: u32,
+    /// (table type, index page, last data page) from the header
+    tables: Vec<(u32, u32, u32)>,
+}
+
+impl PdbReader<BufReader<File>> {
+    /// Open a database file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> PdbReader<R> {
+    /// Read the header page; rows are only read when iterated
+    pub fn new(mut inner: R) -> Result<Self> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        if len < PAGE_SIZE as u64 || len % PAGE_SIZE as u64 != 0 {
+            return Err(Error::Database(format!("invalid PDB size {}", len)));
+        }
+
+        let mut header = vec![0u8; PAGE_SIZE];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header)?;
+
+        // Table pointers at 0x1C: (type, empty candidate, index page, last data page)
+        let num_tables = (read_u32(&header, 8) as usize).min((PAGE_SIZE - HEADER_TABLE_POINTERS) / 16);
+        let tables = (0..num_tables)
+            .map(|table| {
+                let ptr = HEADER_TABLE_POINTERS + table * 16;
+                (read_u32(&header, ptr), read_u32(&header, ptr + 8), read_u32(&header, ptr + 12))
+            })
+            .collect();
+
+        Ok(Self { inner, num_pages: (len / PAGE_SIZE as u64) as u32, tables })
+    }
+
+    /// Pages in the file, header included
+    pub fn num_pages(&self) -> u32 {
+        self.num_pages
+    }
+
+    /// Decode Tracks rows one at a time, in page order
+    ///
+    /// Rows whose presence bit is cleared (deleted by rekordbox) are
+    /// skipped. A page that can't be read or a row that doesn't fit its
+    /// page yields an error; after a read error the iterator ends.
+    pub fn tracks(&mut self) -> TrackRows<'_, R> {
+        Rows { rows: self.rows(PageType::Tracks), decode: decode_track_row }
+    }
+
+    /// Decode Artists rows, like `tracks`
+    pub fn artists(&mut self) -> Rows<'_, R, ArtistRow> {
+        Rows { rows: self.rows(PageType::Artists), decode: decode_artist_row }
+    }
+
+    /// Decode Albums rows, like `tracks`
+    pub fn albums(&mut self) -> Rows<'_, R, AlbumRow> {
+        Rows { rows: self.rows(PageType::Albums), decode: decode_album_row }
+    }
+
+    /// Decode Genres rows, like `tracks`
+    pub fn genres(&mut self) -> Rows<'_, R, NamedRow> {
+        Rows { rows: self.rows(PageType::Genres), decode: |page, row| decode_named_row(page, row, 4) }
+    }
+
+    /// Decode Labels rows, like `tracks`
+    pub fn labels(&mut self) -> Rows<'_, R, NamedRow> {
+        Rows { rows: self.rows(PageType::Labels), decode: |page, row| decode_named_row(page, row, 4) }
+    }
+
+    /// Decode Keys rows (the name is the key's, e.g. "Am"), like `tracks`
+    pub fn keys(&mut self) -> Rows<'_, R, NamedRow> {
+        // id, id2, name
+        Rows { rows: self.rows(PageType::Keys), decode: |page, row| decode_named_row(page, row, 8) }
+    }
+
+    /// Decode Artwork rows (the name is the image path), like `tracks`
+    pub fn artworks(&mut self) -> Rows<'_, R, NamedRow> {
+        Rows { rows: self.rows(PageType::Artwork), decode: |page, row| decode_named_row(page, row, 4) }
+    }
+
+    /// Decode PlaylistTree rows, like `tracks`
+    pub fn playlists(&mut self) -> Rows<'_, R, PlaylistRow> {
+        Rows { rows: self.rows(PageType::PlaylistTree), decode: decode_playlist_row }
+    }
+
+    /// Decode PlaylistEntries rows, like `tracks`
+    pub fn playlist_entries(&mut self) -> Rows<'_, R, PlaylistEntryRow> {
+        Rows { rows: self.rows(PageType::PlaylistEntries), decode: decode_playlist_entry_row }
+    }
+
+    /// Walk a table's data pages, yielding each present row's page offset
+    fn rows(&mut self, table: PageType) -> TableRows<'_, R> {
+        let pointer = self.tables.iter().find(|&&(t, _, _)| t == table as u32).copied();
+        let mut rows = TableRows {
+            page: vec![0u8; PAGE_SIZE],
+            index: 0,
+            next_page: None,
+            last_page: 0,
+            row: 0,
+            num_rows: 0,
+            visited: 0,
+            reader: self,
+        };
+        // The index page points at the first data page
+        if let Some((_, index_page, last_page)) = pointer {
+            rows.last_page = last_page;
+            if rows.load(index_page).is_ok() {
+                rows.next_page = Some(read_u32(&rows.page, 0x2C));
+            }
+        }
+        rows
+    }
+
+    fn read_page(&mut self, index: u32, page: &mut [u8]) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(index as u64 * PAGE_SIZE as u64))?;
+        self.inner.read_exact(page)?;
+        Ok(())
+    }
+}
+
+/// Row offsets of one table, following its page chain
+struct TableRows<'a, R> {
+    reader: &'a mut PdbReader<R>,
+    /// Current page
+    page: Vec<u8>,
+    /// Its page number
+    index: u32,
+    next_page: Option<u32>,
+    last_page: u32,
+    row: usize,
+    num_rows: usize,
+    /// Pages loaded so far; a corrupt chain can't loop forever
+    visited: u32,
+}
+
+impl<R: Read + Seek> TableRows<'_, R> {
+    fn load(&mut self, index: u32) -> Result<()> {
+        if index == 0 || index >= self.reader.num_pages || self.visited >= self.reader.num_pages {
+            return Err(Error::Database(format!("page {} out of range", index)));
+        }
+        self.visited += 1;
+        self.index = index;
+        self.reader.read_page(index, &mut self.page)
+    }
+}
+
+impl<R: Read + Seek> Iterator for TableRows<'_, R> {
+    /// Offset of the row within `page`
+    type Item = Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.row < self.num_rows {
+                let row = self.row;
+                self.row += 1;
+
+                // A corrupt row count would run the index into the header
+                let index_len = (row / ROWS_PER_GROUP + 1) * ROW_GROUP_SIZE;
+                if index_len > PAGE_SIZE - HEAP_START {
+                    self.row = self.num_rows;
+                    return Some(Err(Error::Database(format!(
+                        "page {}: row index of {} rows does not fit the page", self.index, self.num_rows
+                    ))));
+                }
+                let group_start = PAGE_SIZE - index_len;
+                let presence = u16::from_le_bytes([self.page[group_start + 32], self.page[group_start + 33]]);
+                if presence & (1 << (row % ROWS_PER_GROUP)) == 0 {
+                    continue;
+                }
+                let pos = group_start + (ROWS_PER_GROUP - 1 - row % ROWS_PER_GROUP) * 2;
+                let offset = u16::from_le_bytes([self.page[pos], self.page[pos + 1]]) as usize;
+                return Some(Ok(HEAP_START + offset));
+            }
+
+            // Empty tables point their index page at an out-of-range marker
+            let index = self.next_page.take().filter(|&p| p > 0 && p < self.reader.num_pages)?;
+            if let Err(e) = self.load(index) {
+                return Some(Err(e));
+            }
+            self.row = 0;
+            self.num_rows = (read_u32(&self.page, 0x18) & 0x7FF) as usize;
+            if index != self.last_page {
+                self.next_page = Some(read_u32(&self.page, 0x08));
+            }
+        }
+    }
+}
+
+/// Iterator over one table's decoded rows (see `PdbReader::tracks`)
+pub struct Rows<'a, R, T> {
+    rows: TableRows<'a, R>,
+    decode: fn(&[u8], usize) -> Result<T>,
+}
+
+/// Iterator over decoded Tracks rows
+pub type TrackRows<'a, R> = Rows<'a, R, TrackRow>;
+
+impl<R: Read + Seek, T> Iterator for Rows<'_, R, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row_start = match self.rows.next()? {
+            Ok(row_start) => row_start,
+            Err(e) => return Some(Err(e)),
+        };
+        Some((self.decode)(&self.rows.page, row_start))
+    }
+}
+
+/// Decode the track row starting at `row_start` within `page`
+fn decode_track_row(page: &[u8], row_start: usize) -> Result<TrackRow> {
+    if row_start + TRACK_STRING_OFFSETS + TRACK_STRING_COUNT * 2 > PAGE_SIZE {
+        return Err(Error::Database(format!("track row at 0x{:X} runs past its page", row_start)));
+    }
+    let u32_at = |field: usize| read_u32(page, row_start + field);
+    let u16_at = |field: usize| u16::from_le_bytes([page[row_start + field], page[row_start + field + 1]]);
+    let string = |slot: usize| -> Result<String> {
+        let offset = u16_at(TRACK_STRING_OFFSETS + slot * 2) as usize;
+        let start = row_start + offset;
+        if start >= PAGE_SIZE {
+            return Err(Error::Database(format!("track string {} at 0x{:X} runs past its page", slot, start)));
+        }
+        Ok(decode_string(&page[start..])?.0)
+    };
+
+    Ok(TrackRow {
+        id: u32_at(TRACK_ID),
+        title: string(TRACK_SLOT_TITLE)?,
+        file_path: string(TRACK_SLOT_FILE_PATH)?,
+        analyze_path: string(TRACK_SLOT_ANALYZE_PATH)?,
+        analyze_date: string(TRACK_SLOT_ANALYZE_DATE)?,
+        comment: string(TRACK_SLOT_COMMENT)?,
+        release_date: string(TRACK_SLOT_RELEASE_DATE)?,
+        artist_id: u32_at(TRACK_ARTIST_ID),
+        album_id: u32_at(TRACK_ALBUM_ID),
+        genre_id: u32_at(TRACK_GENRE_ID),
+        label_id: u32_at(TRACK_LABEL_ID),
+        key_id: u32_at(TRACK_KEY_ID),
+        artwork_id: u32_at(TRACK_ARTWORK_ID),
+        color_id: page[row_start + TRACK_COLOR_ID],
+        bpm: u32_at(TRACK_TEMPO) as f64 / 100.0,
+        duration_secs: u16_at(TRACK_DURATION),
+        year: u16_at(TRACK_YEAR),
+        track_number: u32_at(TRACK_NUMBER),
+        sample_rate: u32_at(TRACK_SAMPLE_RATE),
+        bit_depth: u16_at(TRACK_BIT_DEPTH),
+        bitrate: u32_at(TRACK_BITRATE),
+        file_size: u32_at(TRACK_FILE_SIZE),
+    })
+}
+
+/// Decode an artist row, whose name offset is a byte (subtype 0x60) or,
+/// for long names, a u16 at 0x0A (subtype 0x64)
+fn decode_artist_row(page: &[u8], row_start: usize) -> Result<ArtistRow> {
+    let name_offset = match checked_u16(page, row_start, "artist row")? {
+        SUBTYPE_NEAR => checked_bytes(page, row_start + 9, 1, "artist row")?[0] as usize,
+        SUBTYPE_FAR => checked_u16(page, row_start + 0x0A, "artist row")? as usize,
+        subtype => return Err(Error::Database(format!("artist row at 0x{:X} has subtype 0x{:04X}", row_start, subtype))),
+    };
+    Ok(ArtistRow {
+        id: checked_u32(page, row_start + 4, "artist row")?,
+        name: checked_string(page, row_start + name_offset, "artist name")?,
+    })
+}
+
+/// Decode an album row: near (0x80) or far (0x84), like artist rows with
+/// the name offset at 0x15 or 0x16
+fn decode_album_row(page: &[u8], row_start: usize) -> Result<AlbumRow> {
+    let name_offset = match checked_u16(page, row_start, "album row")? {
+        0x0080 => checked_bytes(page, row_start + 0x15, 1, "album row")?[0] as usize,
+        0x0084 => checked_u16(page, row_start + 0x16, "album row")? as usize,
+        subtype => return Err(Error::Database(format!("album row at 0x{:X} has subtype 0x{:04X}", row_start, subtype))),
+    };
+    Ok(AlbumRow {
+        id: checked_u32(page, row_start + 12, "album row")?,
+        artist_id: checked_u32(page, row_start + 8, "album row")?,
+        name: checked_string(page, row_start + name_offset, "album name")?,
+    })
+}
+
+/// Decode an id followed, `name_at` bytes into the row, by its string
+fn decode_named_row(page: &[u8], row_start: usize, name_at: usize) -> Result<NamedRow> {
+    Ok(NamedRow {
+        id: checked_u32(page, row_start, "row")?,
+        name: checked_string(page, row_start + name_at, "row name")?,
+    })
+}
+
+fn decode_playlist_row(page: &[u8], row_start: usize) -> Result<PlaylistRow> {
+    let field = |pos: usize| checked_u32(page, row_start + pos, "playlist row");
+    Ok(PlaylistRow {
+        parent_id: field(0)?,
+        sort_order: field(8)?,
+        id: field(12)?,
+        is_folder: field(16)? != 0,
+        name: checked_string(page, row_start + 20, "playlist name")?,
+    })
+}
+
+fn decode_playlist_entry_row(page: &[u8], row_start: usize) -> Result<PlaylistEntryRow> {
+    let field = |pos: usize| checked_u32(page, row_start + pos, "playlist entry");
+    Ok(PlaylistEntryRow {
+        entry_index: field(0)?,
+        track_id: field(4)?,
+        playlist_id: field(8)?,
+    })
+}
+
+/// `len` bytes at `pos`, or an error if they run past the page
+fn checked_bytes<'a>(page: &'a [u8], pos: usize, len: usize, what: &str) -> Result<&'a [u8]> {
+    page.get(pos..pos + len)
+        .ok_or_else(|| Error::Database(format!("{} at 0x{:X} runs past its page", what, pos)))
+}
+
+fn checked_u16(page: &[u8], pos: usize, what: &str) -> Result<u16> {
+    let bytes = checked_bytes(page, pos, 2, what)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn checked_u32(page: &[u8], pos: usize, what: &str) -> Result<u32> {
+    Ok(read_u32(checked_bytes(page, pos, 4, what)?, 0))
+}
+
+fn checked_string(page: &[u8], pos: usize, what: &str) -> Result<String> {
+    let data = page.get(pos..)
+        .filter(|data| !data.is_empty())
+        .ok_or_else(|| Error::Database(format!("{} at 0x{:X} runs past its page", what, pos)))?;
+    Ok(decode_string(data)?.0)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::anlz::generate_anlz_path;
+    use crate::pdb::PdbBuilder;
+    use crate::track::{BeatGrid, FileType, TrackAnalysis};
+
+    fn track(id: u32) -> TrackAnalysis {
+        TrackAnalysis {
+            id,
+            file_path: format!("/Contents/Track {}.mp3", id),
+            title: format!("Track {}", id),
+            artist: format!("Artist {}", id % 7),
+            duration_secs: 200.0 + id as f64,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 320,
+            bpm: 124.5,
+            beat_grid: BeatGrid::constant_tempo(124.5, 0.0, 1000.0),
+            file_size: 5_000_000,
+            year: Some(2020),
+            file_type: FileType::Mp3,
+            bpm_confidence: 1.0,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    #[test]
+    fn test_tracks_streamed_across_pages() {
+        let mut builder = PdbBuilder::new();
+        for id in 1..=300 {
+            builder.add_track(&track(id), &generate_anlz_path(id));
+        }
+        let data = builder.build().unwrap();
+        let track_pages = builder.layout().unwrap().table_pages[&PageType::Tracks];
+        assert!(track_pages.1 > track_pages.0, "expected several Tracks pages");
+
+        let mut reader = PdbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.tracks().count(), 300);
+
+        let mut expected_id = 0;
+        for row in reader.tracks() {
+            let row = row.unwrap();
+            expected_id += 1;
+            assert_eq!(row.id, expected_id);
+        }
+        assert_eq!(expected_id, 300);
+
+        let first = reader.tracks().next().unwrap().unwrap();
+        assert_eq!(first.title, "Track 1");
+        assert_eq!(first.file_path, "/Contents/Track 1.mp3");
+        assert_eq!(first.analyze_path, format!("/{}", generate_anlz_path(1)));
+        assert_eq!(first.release_date, "2020-01-01");
+        assert_eq!(first.analyze_date.len(), "2020-01-01".len());
+        assert_eq!(first.bpm, 124.5);
+        assert_eq!(first.duration_secs, 201);
+        assert_eq!(first.year, 2020);
+        assert_eq!(first.sample_rate, 44100);
+    }
+
+    #[test]
+    fn test_empty_and_invalid() {
+        let data = PdbBuilder::new().build().unwrap();
+        let mut reader = PdbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.tracks().count(), 0);
+
+        assert!(PdbReader::new(Cursor::new(&data[..100])).is_err());
+    }
+}
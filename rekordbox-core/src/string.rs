@@ -7,7 +7,10 @@
 //!
 //! Reference: https://djl-analysis.deepsymmetry.org/rekordbox-export-analysis/exports.html
 
-/// Maximum length for short ASCII strings
+use crate::error::{Error, Result};
+
+/// Maximum length for short ASCII strings: the header byte holds the total
+/// length (data + header) in 7 bits, so 126 chars + 1 = 127 is the limit
 const MAX_SHORT_ASCII_LEN: usize = 126;
 
 /// Flag byte values
@@ -41,6 +44,7 @@ pub fn encode_string(s: &str) -> Vec<u8> {
 /// Header byte: ((length + 1) << 1) | 1
 fn encode_short_ascii(s: &str) -> Vec<u8> {
     let total_len = s.len() + 1; // +1 for header byte
+    assert!(total_len <= 0x7F, "short ASCII string of {} chars does not fit the header", s.len());
     let header = ((total_len as u8) << 1) | 1;
     
     let mut result = Vec::with_capacity(total_len);
@@ -107,6 +111,42 @@ pub fn encode_isrc(isrc: &str) -> Vec<u8> {
     result
 }
 
+/// Decode a DeviceSQL string, returning it with its encoded length in bytes
+pub fn decode_string(data: &[u8]) -> Result<(String, usize)> {
+    let flag = *data.first()
+        .ok_or_else(|| Error::StringEncoding("empty string data".into()))?;
+
+    if flag & 1 == 1 {
+        // Short ASCII: length (including header) in the upper 7 bits
+        let total_len = (flag >> 1) as usize;
+        let bytes = data.get(1..total_len)
+            .ok_or_else(|| Error::StringEncoding(format!("short string of {} bytes truncated", total_len)))?;
+        return Ok((String::from_utf8_lossy(bytes).into_owned(), total_len));
+    }
+
+    let total_len = data.get(1..3)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+        .ok_or_else(|| Error::StringEncoding("string header truncated".into()))?;
+    let body = data.get(4..total_len)
+        .ok_or_else(|| Error::StringEncoding(format!("string of {} bytes truncated", total_len)))?;
+
+    match flag {
+        FLAG_LONG_ASCII => Ok((String::from_utf8_lossy(body).into_owned(), total_len)),
+        FLAG_UTF16LE if body.first() == Some(&0x03) && body.last() == Some(&0x00) => {
+            // ISRC: 0x03 marker + ASCII + null terminator
+            let isrc = &body[1..body.len() - 1];
+            Ok((String::from_utf8_lossy(isrc).into_owned(), total_len))
+        }
+        FLAG_UTF16LE => {
+            let units: Vec<u16> = body.chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            Ok((String::from_utf16_lossy(&units), total_len))
+        }
+        _ => Err(Error::StringEncoding(format!("unknown string flag 0x{:02X}", flag))),
+    }
+}
+
 /// Get the encoded length of a string without actually encoding it
 pub fn encoded_length(s: &str) -> usize {
     if s.is_empty() {
@@ -151,6 +191,33 @@ mod tests {
         assert_eq!(encoded[1], b'A');
     }
     
+    #[test]
+    fn test_short_ascii_boundary() {
+        for len in [125, 126] {
+            let s = "x".repeat(len);
+            let encoded = encode_string(&s);
+            assert_eq!(encoded.len(), len + 1);
+            assert_eq!(encoded_length(&s), len + 1);
+            assert_eq!(encoded[0], (((len + 1) as u8) << 1) | 1);
+            assert_eq!(decode_string(&encoded).unwrap(), (s, len + 1));
+        }
+        assert_eq!(encode_string(&"x".repeat(126))[0], 0xFF);
+
+        // One past the limit switches to the long form
+        let s = "x".repeat(127);
+        let encoded = encode_string(&s);
+        assert_eq!(encoded[0], FLAG_LONG_ASCII);
+        assert_eq!(encoded.len(), 4 + 127);
+        assert_eq!(encoded_length(&s), 4 + 127);
+        assert_eq!(decode_string(&encoded).unwrap(), (s, 4 + 127));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_short_ascii_overflow_rejected() {
+        encode_short_ascii(&"x".repeat(127));
+    }
+
     #[test]
     fn test_long_ascii() {
         let long_str = "a".repeat(200);
@@ -172,6 +239,18 @@ mod tests {
         assert_eq!(len, 10);
     }
     
+    #[test]
+    fn test_decode_round_trip() {
+        let long_ascii = "a".repeat(200);
+        for s in ["", "foo", long_ascii.as_str(), "日本語"] {
+            let encoded = encode_string(s);
+            assert_eq!(decode_string(&encoded).unwrap(), (s.to_string(), encoded.len()));
+        }
+
+        let isrc = encode_isrc("GBAYE0000351");
+        assert_eq!(decode_string(&isrc).unwrap().0, "GBAYE0000351");
+    }
+
     #[test]
     fn test_encoded_length() {
         assert_eq!(encoded_length(""), 1);
@@ -4,8 +4,10 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+
 /// Complete analysis results for a single track
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TrackAnalysis {
     /// Unique track ID (generated, starts at 1)
     pub id: u32,
@@ -15,6 +17,10 @@ pub struct TrackAnalysis {
     pub title: String,
     /// Artist name
     pub artist: String,
+    /// Artist name for sorting ("Chemical Brothers, The"). The PDB artist
+    /// row has no sort slot, so exports list it in their manifest instead
+    #[serde(default)]
+    pub artist_sort: Option<String>,
     /// Album name
     pub album: Option<String>,
     /// Genre
@@ -27,6 +33,10 @@ pub struct TrackAnalysis {
     pub sample_rate: u32,
     /// Bit depth
     pub bit_depth: u16,
+    /// Source channel count (1 = mono, 2 = stereo, 0 = unknown); the PDB
+    /// track row has no field for it, so it lives in the analysis cache
+    #[serde(default)]
+    pub channels: u8,
     /// Bitrate in kbps
     pub bitrate: u32,
     /// BPM (beats per minute)
@@ -45,12 +55,47 @@ pub struct TrackAnalysis {
     pub file_hash: u64,
     /// Year of release
     pub year: Option<u16>,
+    /// Full release date (ISO YYYY-MM-DD) when known
+    #[serde(default)]
+    pub release_date: Option<String>,
     /// Track comment
     pub comment: Option<String>,
     /// Track number in album
     pub track_number: Option<u32>,
     /// File type (MP3, FLAC, etc.)
     pub file_type: FileType,
+    /// Fraction of analyzed samples at full scale (heavy limiting / clipping)
+    #[serde(default)]
+    pub clip_ratio: f32,
+    /// Frequency where the spectrum drops away; far below Nyquist on a
+    /// lossless file suggests a lossy transcode
+    #[serde(default)]
+    pub spectral_cutoff_hz: Option<u32>,
+    /// How clearly the detected tempo stood out (0 = guess, 1 = unambiguous);
+    /// tempos from tags or sidecars count as 1
+    #[serde(default)]
+    pub bpm_confidence: f32,
+    /// Coarse acoustic fingerprint of the decoded audio; re-encodes of the
+    /// same recording land within a few bits of each other
+    #[serde(default)]
+    pub acoustic_id: Option<u64>,
+    /// Coarse 1-10 energy rating from loudness, brightness and onset
+    /// density, for sorting a set by intensity
+    #[serde(default)]
+    pub energy: Option<u8>,
+    /// Album artist tag, when set (ALBUMARTIST / TPE2)
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    /// Part of a compilation (COMPILATION / TCMP / cpil); without an album
+    /// artist tag the album is filed under Various Artists. Not stored in
+    /// the PDB, which has no field for it.
+    #[serde(default)]
+    pub compilation: bool,
+    /// Where the audio first rises above the silence threshold, in ms from
+    /// track start (None when not measured). Informational: waveforms, grid
+    /// and cues all stay on the file's own timeline.
+    #[serde(default)]
+    pub audio_start_ms: Option<f64>,
 }
 
 /// Audio file type
@@ -64,6 +109,11 @@ pub enum FileType {
     Flac = 0x05,
     Wav = 0x0B,
     Aiff = 0x0C,
+    /// Apple Lossless in an MP4 container (same extension as AAC)
+    ///
+    /// Internal only, to tell ALAC from AAC: rekordbox has no ALAC file
+    /// type and files both as M4a, so this discriminant means nothing to it.
+    Alac,
 }
 
 impl FileType {
@@ -77,6 +127,11 @@ impl FileType {
             _ => FileType::Unknown,
         }
     }
+
+    /// True for formats that decode to the original PCM
+    pub fn is_lossless(self) -> bool {
+        matches!(self, FileType::Flac | FileType::Wav | FileType::Aiff | FileType::Alac)
+    }
 }
 
 /// Musical key in Open Key / Camelot notation
@@ -113,6 +168,25 @@ impl Key {
         format!("{}{}", pos, suffix)
     }
     
+    /// Parse Camelot wheel notation (e.g. "8A", "12b")
+    /// Inverse of to_camelot()
+    pub fn from_camelot(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let letter = s.chars().last()?;
+        let num = &s[..s.len() - letter.len_utf8()];
+        let pos: u8 = num.parse().ok()?;
+        let is_major = match letter {
+            'A' | 'a' => false,
+            'B' | 'b' => true,
+            _ => return None,
+        };
+
+        let camelot = format!("{}{}", pos, letter.to_ascii_uppercase());
+        (0..12)
+            .map(|pc| Key::new(pc, is_major))
+            .find(|k| k.to_camelot() == camelot)
+    }
+
     /// Convert to Open Key notation (1m-12d)
     pub fn to_open_key(&self) -> String {
         // Open Key maps differently
@@ -178,14 +252,35 @@ impl Key {
 }
 
 /// Beat grid containing all beat positions
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+///
+/// Each beat carries its own tempo, so a grid can follow tempo changes
+/// (see `BeatGrid::variable_tempo`); `bpm` is the tempo it starts at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BeatGrid {
-    /// Tempo in BPM
+    /// Tempo in BPM (of the first beat when the tempo changes)
     pub bpm: f64,
     /// First beat position in milliseconds from track start
     pub first_beat_ms: f64,
     /// Beat positions
     pub beats: Vec<Beat>,
+    /// (beats per bar, beat unit); the numerator sets the beat_number cycle
+    #[serde(default = "default_time_signature")]
+    pub time_signature: (u8, u8),
+}
+
+impl Default for BeatGrid {
+    fn default() -> Self {
+        Self {
+            bpm: 0.0,
+            first_beat_ms: 0.0,
+            beats: Vec::new(),
+            time_signature: default_time_signature(),
+        }
+    }
+}
+
+fn default_time_signature() -> (u8, u8) {
+    (4, 4)
 }
 
 /// Single beat in the grid
@@ -199,9 +294,102 @@ pub struct Beat {
     pub tempo_100: u16,
 }
 
+/// Leading articles moved to the end by `derive_sort_name`
+const SORT_ARTICLES: [&str; 3] = ["The", "A", "An"];
+
+/// Derive a sort name by moving a leading article to the end
+/// ("The Chemical Brothers" -> "Chemical Brothers, The")
+pub fn derive_sort_name(name: &str) -> String {
+    let name = name.trim();
+    for article in SORT_ARTICLES {
+        let Some(rest) = name.get(article.len()..) else { continue };
+        if name[..article.len()].eq_ignore_ascii_case(article) && rest.starts_with(' ') {
+            let rest = rest.trim_start();
+            if !rest.is_empty() {
+                return format!("{}, {}", rest, &name[..article.len()]);
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Round a BPM value to a multiple of `step` (0.5 for a tidy display, 0.01
+/// for the full precision the PQTZ/PDB tempo fields can hold)
+///
+/// A non-positive step leaves the value untouched.
+pub fn round_bpm(bpm: f64, step: f64) -> f64 {
+    if step <= 0.0 {
+        return bpm;
+    }
+    // Snap to the 0.01 grid afterwards so 128.33 doesn't become 128.329999
+    ((bpm / step).round() * step * 100.0).round() / 100.0
+}
+
+/// Hot cue slots on the players (A-H)
+pub const MAX_HOT_CUES: u8 = 8;
+
+/// Re-slot imported cues: the first `hot_cues` (at most 8) become hot cues
+/// A, B, ... in import order and the rest become memory cues
+///
+/// Cue types (cue vs loop) and everything else are kept as imported.
+pub fn assign_cue_slots(cues: &[CuePoint], hot_cues: u8) -> Vec<CuePoint> {
+    let hot_cues = hot_cues.min(MAX_HOT_CUES) as usize;
+    cues.iter()
+        .enumerate()
+        .map(|(i, cue)| CuePoint {
+            hot_cue: if i < hot_cues { i as u8 + 1 } else { 0 },
+            ..cue.clone()
+        })
+        .collect()
+}
+
+/// Snap cues lying within `tolerance_ms` of a beat onto that beat
+///
+/// Cues further from the grid are left alone (they are probably meant to
+/// be off-beat). A snapped loop keeps its length in beats, so its end
+/// moves with the grid rather than staying at its imported time.
+pub fn quantize_cues(cues: &[CuePoint], grid: &BeatGrid, tolerance_ms: f64) -> Vec<CuePoint> {
+    cues.iter()
+        .map(|cue| {
+            let Some(beat) = grid.nearest_beat(cue.time_ms) else {
+                return cue.clone();
+            };
+            let snapped_ms = grid.beats[beat].time_ms;
+            if (snapped_ms - cue.time_ms).abs() > tolerance_ms || snapped_ms == cue.time_ms {
+                return cue.clone();
+            }
+
+            let mut loop_ms = cue.loop_ms;
+            if cue.loop_ms > 0.0 {
+                let start = grid.beat_position(cue.time_ms);
+                let end = grid.beat_position(cue.time_ms + cue.loop_ms);
+                if let (Some(start), Some(end)) = (start, end) {
+                    if let Some(end_ms) = grid.time_at_position(beat as f64 + (end - start)) {
+                        loop_ms = end_ms - snapped_ms;
+                    }
+                }
+            }
+
+            CuePoint { time_ms: snapped_ms, loop_ms, ..cue.clone() }
+        })
+        .collect()
+}
+
 impl BeatGrid {
-    /// Generate a constant-tempo beat grid
+    /// Generate a constant-tempo 4/4 beat grid
     pub fn constant_tempo(bpm: f64, first_beat_ms: f64, duration_ms: f64) -> Self {
+        Self::constant_tempo_with_signature(bpm, first_beat_ms, duration_ms, default_time_signature())
+    }
+
+    /// Generate a constant-tempo beat grid whose bars hold
+    /// `time_signature.0` beats (3/4 cycles 1-2-3, 6/8 cycles 1-6)
+    pub fn constant_tempo_with_signature(
+        bpm: f64,
+        first_beat_ms: f64,
+        duration_ms: f64,
+        time_signature: (u8, u8),
+    ) -> Self {
+        let beats_per_bar = time_signature.0.max(1);
         let beat_duration_ms = 60_000.0 / bpm;
         let tempo_100 = (bpm * 100.0).round() as u16;
 
@@ -217,14 +405,155 @@ impl BeatGrid {
             });
 
             time += beat_duration_ms;
-            beat_in_bar = if beat_in_bar == 4 { 1 } else { beat_in_bar + 1 };
+            beat_in_bar = if beat_in_bar >= beats_per_bar { 1 } else { beat_in_bar + 1 };
         }
 
         Self {
             bpm,
             first_beat_ms,
             beats,
+            time_signature,
+        }
+    }
+
+    /// Generate a beat grid whose tempo changes at given times
+    ///
+    /// `changes` holds (start_ms, bpm) pairs in time order; the first one
+    /// places the first beat. A change takes effect from the first beat at
+    /// or after its start, and bars run on across changes.
+    pub fn variable_tempo(changes: &[(f64, f64)], duration_ms: f64, time_signature: (u8, u8)) -> Self {
+        let Some(&(first_beat_ms, first_bpm)) = changes.first() else {
+            return Self { time_signature, ..Self::default() };
+        };
+        let beats_per_bar = time_signature.0.max(1);
+
+        let mut beats = Vec::new();
+        let mut time = first_beat_ms;
+        let mut beat_in_bar = 1u8;
+        let mut segment = 0;
+        while time < duration_ms {
+            while changes.get(segment + 1).is_some_and(|&(start, _)| start <= time) {
+                segment += 1;
+            }
+            let bpm = changes[segment].1;
+            if bpm <= 0.0 {
+                break;
+            }
+            beats.push(Beat {
+                beat_number: beat_in_bar,
+                time_ms: time,
+                tempo_100: (bpm * 100.0).round() as u16,
+            });
+
+            time += 60_000.0 / bpm;
+            beat_in_bar = if beat_in_bar >= beats_per_bar { 1 } else { beat_in_bar + 1 };
+        }
+
+        Self {
+            bpm: first_bpm,
+            first_beat_ms,
+            beats,
+            time_signature,
+        }
+    }
+
+    /// Whether every beat has the same tempo
+    pub fn is_constant_tempo(&self) -> bool {
+        self.beats.windows(2).all(|pair| pair[0].tempo_100 == pair[1].tempo_100)
+    }
+
+    /// (start_ms, bpm) of the first beat and of every beat the tempo
+    /// changes at, as taken by `variable_tempo`
+    pub fn tempo_changes(&self) -> Vec<(f64, f64)> {
+        let mut changes: Vec<(f64, f64)> = Vec::new();
+        for (i, beat) in self.beats.iter().enumerate() {
+            if i == 0 || beat.tempo_100 != self.beats[i - 1].tempo_100 {
+                let bpm = if i == 0 { self.bpm } else { beat.tempo_100 as f64 / 100.0 };
+                changes.push((beat.time_ms, bpm));
+            }
+        }
+        changes
+    }
+
+    /// Move every beat by `delta_ms` (positive = later)
+    ///
+    /// Beats pushed before the start of the track are dropped; the remaining
+    /// beats keep their bar positions.
+    pub fn shift_ms(&mut self, delta_ms: f64) {
+        self.first_beat_ms += delta_ms;
+        for beat in &mut self.beats {
+            beat.time_ms += delta_ms;
         }
+        self.beats.retain(|b| b.time_ms >= 0.0);
+    }
+
+    /// Multiply the tempo by `factor`, keeping the first beat in place
+    ///
+    /// The grid is regenerated over the same span, so a factor of 2 doubles
+    /// tempo_100 and halves the beat spacing. Tempo changes stay where they
+    /// were.
+    pub fn scale_tempo(&mut self, factor: f64) {
+        if factor <= 0.0 || self.bpm <= 0.0 {
+            return;
+        }
+
+        let constant = self.is_constant_tempo();
+        let last_bpm = match self.beats.last() {
+            Some(beat) if !constant => beat.tempo_100 as f64 / 100.0,
+            _ => self.bpm,
+        };
+        let end_ms = self.beats.last()
+            .map(|b| b.time_ms + 60_000.0 / last_bpm)
+            .unwrap_or(self.first_beat_ms);
+
+        *self = if constant {
+            Self::constant_tempo_with_signature(self.bpm * factor, self.first_beat_ms, end_ms, self.time_signature)
+        } else {
+            let changes: Vec<(f64, f64)> = self.tempo_changes().into_iter()
+                .map(|(start, bpm)| (start, bpm * factor))
+                .collect();
+            Self::variable_tempo(&changes, end_ms, self.time_signature)
+        };
+    }
+
+    /// Move the grid so the first beat lands at `first_beat_ms`
+    pub fn set_first_beat(&mut self, first_beat_ms: f64) {
+        self.shift_ms(first_beat_ms - self.first_beat_ms);
+    }
+
+    /// Index of the beat closest to `time_ms`
+    fn nearest_beat(&self, time_ms: f64) -> Option<usize> {
+        let after = self.beats.partition_point(|b| b.time_ms < time_ms);
+        [after.checked_sub(1), Some(after)].into_iter()
+            .flatten()
+            .filter(|&i| i < self.beats.len())
+            .min_by(|&a, &b| {
+                let distance = |i: usize| (self.beats[i].time_ms - time_ms).abs();
+                distance(a).total_cmp(&distance(b))
+            })
+    }
+
+    /// Fractional beat index of `time_ms` (the spacing of the first or last
+    /// pair of beats extends the grid past either end)
+    fn beat_position(&self, time_ms: f64) -> Option<f64> {
+        if self.beats.len() < 2 {
+            return None;
+        }
+        let last = self.beats.len() - 1;
+        let i = self.beats.partition_point(|b| b.time_ms <= time_ms).clamp(1, last) - 1;
+        let (a, b) = (self.beats[i].time_ms, self.beats[i + 1].time_ms);
+        Some(i as f64 + (time_ms - a) / (b - a))
+    }
+
+    /// Inverse of `beat_position`
+    fn time_at_position(&self, position: f64) -> Option<f64> {
+        if self.beats.len() < 2 {
+            return None;
+        }
+        let last = self.beats.len() - 1;
+        let i = (position.floor().max(0.0) as usize).min(last - 1);
+        let (a, b) = (self.beats[i].time_ms, self.beats[i + 1].time_ms);
+        Some(a + (position - i as f64) * (b - a))
     }
 
     /// Number of beats
@@ -238,10 +567,11 @@ impl BeatGrid {
 }
 
 /// Cue point type
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
 #[repr(u8)]
 pub enum CueType {
     /// Regular cue point
+    #[default]
     Cue = 1,
     /// Fade-in point
     FadeIn = 2,
@@ -253,12 +583,6 @@ pub enum CueType {
     Loop = 5,
 }
 
-impl Default for CueType {
-    fn default() -> Self {
-        CueType::Cue
-    }
-}
-
 /// Hot cue color palette (63 colors supported by CDJs)
 /// Common colors: Green=0x00, Cyan=0x09, Orange=0x22, Red=0x2A, Purple=0x3E
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -282,6 +606,65 @@ impl HotCueColor {
     pub const ORANGE: HotCueColor = HotCueColor { palette_index: 0x22, red: 0xFF, green: 0xA0, blue: 0x00 };
     pub const YELLOW: HotCueColor = HotCueColor { palette_index: 0x32, red: 0xFF, green: 0xFF, blue: 0x00 };
 
+    /// Memory cue colors in PCP2 color_id order (1=pink ... 8=purple)
+    pub const MEMORY_CUE_COLORS: [HotCueColor; 8] = [
+        Self::PINK, Self::RED, Self::ORANGE, Self::YELLOW,
+        Self::GREEN, Self::CYAN, Self::BLUE, Self::PURPLE,
+    ];
+
+    /// Named colors accepted by from_name()
+    const NAMED: [(&'static str, HotCueColor); 8] = [
+        ("green", Self::GREEN), ("cyan", Self::CYAN), ("blue", Self::BLUE),
+        ("purple", Self::PURPLE), ("pink", Self::PINK), ("red", Self::RED),
+        ("orange", Self::ORANGE), ("yellow", Self::YELLOW),
+    ];
+
+    /// Look up a standard color by name (case-insensitive, e.g. "red", "Cyan")
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        Self::NAMED.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, c)| *c)
+    }
+
+    /// Parse `#RRGGBB` hex (the `#` is optional) into the nearest standard color
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Self::nearest(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// Nearest standard color to an arbitrary RGB value
+    pub fn nearest(red: u8, green: u8, blue: u8) -> Self {
+        let rgb = HotCueColor { palette_index: 0, red, green, blue };
+        Self::NAMED.iter()
+            .map(|(_, c)| *c)
+            .min_by_key(|c| rgb.distance_sq(c))
+            .unwrap_or_default()
+    }
+
+    /// Nearest memory cue color_id (1-8) by RGB distance
+    pub fn memory_color_id(&self) -> u8 {
+        Self::MEMORY_CUE_COLORS.iter()
+            .enumerate()
+            .min_by_key(|(_, c)| self.distance_sq(c))
+            .map(|(i, _)| i as u8 + 1)
+            .unwrap_or(0)
+    }
+
+    /// Squared RGB distance to another color
+    fn distance_sq(&self, other: &HotCueColor) -> i32 {
+        let dr = self.red as i32 - other.red as i32;
+        let dg = self.green as i32 - other.green as i32;
+        let db = self.blue as i32 - other.blue as i32;
+        dr * dr + dg * dg + db * db
+    }
+
     /// Get default color for a hot cue slot (A-H)
     pub fn default_for_slot(slot: u8) -> Self {
         match slot {
@@ -298,6 +681,43 @@ impl HotCueColor {
     }
 }
 
+/// The eight memory cue colors rekordbox offers, valued by PCP2 color_id
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryCueColor {
+    Pink = 1,
+    Red = 2,
+    Orange = 3,
+    Yellow = 4,
+    Green = 5,
+    Cyan = 6,
+    Blue = 7,
+    Purple = 8,
+}
+
+impl MemoryCueColor {
+    /// All colors in color_id order
+    pub const ALL: [MemoryCueColor; 8] = [
+        Self::Pink, Self::Red, Self::Orange, Self::Yellow,
+        Self::Green, Self::Cyan, Self::Blue, Self::Purple,
+    ];
+
+    /// PCP2 color_id (1-8)
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Color for a PCP2 color_id, None for 0 (no color) or out of range
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(id.checked_sub(1)? as usize).copied()
+    }
+
+    /// RGB of the color band rekordbox draws
+    pub fn color(self) -> HotCueColor {
+        HotCueColor::MEMORY_CUE_COLORS[self as usize - 1]
+    }
+}
+
 /// Cue point for PCOB/PCO2 section
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct CuePoint {
@@ -313,6 +733,65 @@ pub struct CuePoint {
     pub comment: Option<String>,
     /// Hot cue color (for PCO2 extended format)
     pub color: Option<HotCueColor>,
+    /// Memory cue color_id (1-8, see `MemoryCueColor`); when unset, memory
+    /// cues take the palette color nearest to `color`
+    #[serde(default)]
+    pub memory_color_id: Option<u8>,
+}
+
+impl CuePoint {
+    /// Hot cue on pad `slot` (1-8 for A-H) at `time_ms`
+    ///
+    /// Cues are written as given: several may share a time position, and
+    /// loops may overlap them.
+    pub fn hot_cue(slot: u8, time_ms: f64, color: HotCueColor) -> Result<Self> {
+        if !(1..=8).contains(&slot) {
+            return Err(Error::Validation(format!("Hot cue slot {} is outside 1-8", slot)));
+        }
+        Ok(Self {
+            hot_cue: slot,
+            time_ms: check_cue_time(time_ms)?,
+            color: Some(color),
+            ..Default::default()
+        })
+    }
+
+    /// Memory loop of `beats` beats at `bpm`, starting at `start_ms`
+    ///
+    /// Fractional beats make the short loops (e.g. 0.5 for a half-beat
+    /// roll). Set `hot_cue` afterwards to put the loop on a pad.
+    pub fn loop_beats(start_ms: f64, beats: f64, bpm: f64) -> Result<Self> {
+        let loop_ms = beats * 60_000.0 / bpm;
+        if !(loop_ms.is_finite() && loop_ms > 0.0) {
+            return Err(Error::Validation(format!(
+                "Loop of {} beats at {} BPM has no positive length", beats, bpm
+            )));
+        }
+        Ok(Self {
+            cue_type: CueType::Loop,
+            time_ms: check_cue_time(start_ms)?,
+            loop_ms,
+            ..Default::default()
+        })
+    }
+
+    /// PCP2 color_id written for this cue as a memory cue (0 = no color)
+    pub fn memory_color(&self) -> u8 {
+        self.memory_color_id
+            .and_then(MemoryCueColor::from_id)
+            .map(MemoryCueColor::id)
+            .or_else(|| self.color.map(|c| c.memory_color_id()))
+            .unwrap_or(0)
+    }
+}
+
+/// A cue position the ANLZ u32 millisecond fields can hold
+fn check_cue_time(time_ms: f64) -> Result<f64> {
+    if time_ms.is_finite() && (0.0..=u32::MAX as f64).contains(&time_ms) {
+        Ok(time_ms)
+    } else {
+        Err(Error::Validation(format!("Cue time {} ms is out of range", time_ms)))
+    }
 }
 
 /// Waveform data for both preview and detail displays
@@ -352,6 +831,26 @@ pub struct WaveformColorPreviewColumn {
 }
 
 impl WaveformColorPreviewColumn {
+    /// Build a column from normalized (0.0-1.0) height and band energies
+    ///
+    /// Bands follow the detail waveform: red = bass, green = mids, blue =
+    /// highs. Luminance is the strongest band, and blue2 sits between the
+    /// blue band and that luminance. Out-of-range inputs clamp to 7 bits.
+    pub fn from_energy(height: f32, rgb: (f32, f32, f32)) -> Self {
+        let scale = |v: f32| (v * 127.0).round().clamp(0.0, 127.0) as u8;
+        let (red, green, blue) = rgb;
+        let luminance = red.max(green).max(blue);
+
+        Self {
+            height: scale(height),
+            luminance: scale(luminance),
+            blue: scale(blue),
+            red: scale(red),
+            green: scale(green),
+            blue2: scale((blue + luminance) / 2.0),
+        }
+    }
+
     /// Encode to PWV4 6-byte format
     pub fn to_bytes(&self) -> [u8; 6] {
         [
@@ -389,7 +888,7 @@ pub struct WaveformPreview {
 pub struct WaveformColumn {
     /// Height 0-31 (5 bits)
     pub height: u8,
-    /// Whiteness 0-7 (3 bits) - higher = more white/louder
+    /// Whiteness 0-7 (3 bits) - quieter band relative to peak, higher = more sustained
     pub whiteness: u8,
 }
 
@@ -472,6 +971,25 @@ mod tests {
         assert_eq!(cm.to_camelot(), "5A");
     }
     
+    #[test]
+    fn test_key_from_camelot() {
+        assert_eq!(Key::from_camelot("8A"), Some(Key::new(9, false)));
+        assert_eq!(Key::from_camelot("8b"), Some(Key::new(0, true)));
+        assert_eq!(Key::from_camelot("13A"), None);
+        assert_eq!(Key::from_camelot("Am"), None);
+        assert_eq!(Key::from_camelot("12é"), None);
+        assert_eq!(Key::from_camelot("8♭"), None);
+        assert_eq!(Key::from_camelot("é"), None);
+        assert_eq!(Key::from_camelot(""), None);
+
+        for pc in 0..12 {
+            for is_major in [false, true] {
+                let key = Key::new(pc, is_major);
+                assert_eq!(Key::from_camelot(&key.to_camelot()), Some(key));
+            }
+        }
+    }
+
     #[test]
     fn test_key_rekordbox_id() {
         // C minor should be 1
@@ -508,12 +1026,235 @@ mod tests {
         assert_eq!(grid.beats[0].beat_number, 1);
         assert_eq!(grid.beats[0].tempo_100, 12800);
     }
+
+    #[test]
+    fn test_color_preview_column_from_energy() {
+        let full = WaveformColorPreviewColumn::from_energy(1.0, (1.0, 1.0, 1.0));
+        assert!(full.to_bytes().iter().all(|&b| b >= 126), "{:?}", full);
+
+        let decoded = WaveformColorPreviewColumn::from_bytes(full.to_bytes());
+        assert_eq!(decoded.to_bytes(), full.to_bytes());
+
+        // Bass-heavy column: luminance follows the loudest band, overdrive clamps
+        let bass = WaveformColorPreviewColumn::from_energy(2.0, (0.8, 0.2, -1.0));
+        assert_eq!(bass.height, 127);
+        assert_eq!(bass.red, 102);
+        assert_eq!(bass.luminance, 102);
+        assert_eq!(bass.blue, 0);
+        assert_eq!(bass.blue2, 0);
+        assert_eq!(WaveformColorPreviewColumn::from_bytes(bass.to_bytes()).green, 25);
+    }
+
+    #[test]
+    fn test_derive_sort_name() {
+        assert_eq!(derive_sort_name("The X"), "X, The");
+        assert_eq!(derive_sort_name("The Chemical Brothers"), "Chemical Brothers, The");
+        assert_eq!(derive_sort_name("a Tribe Called Quest"), "Tribe Called Quest, a");
+        assert_eq!(derive_sort_name("Theo Parrish"), "Theo Parrish");
+        assert_eq!(derive_sort_name("The"), "The");
+        assert_eq!(derive_sort_name("Über"), "Über");
+    }
+
+    #[test]
+    fn test_assign_cue_slots() {
+        let cues: Vec<CuePoint> = (0..10)
+            .map(|i| CuePoint { hot_cue: 0, time_ms: i as f64 * 1000.0, ..Default::default() })
+            .collect();
+
+        let slotted = assign_cue_slots(&cues, 8);
+        assert_eq!(slotted.len(), 10);
+        let hot: Vec<u8> = slotted.iter().map(|c| c.hot_cue).collect();
+        assert_eq!(hot, [1, 2, 3, 4, 5, 6, 7, 8, 0, 0]);
+        assert_eq!(slotted[7].time_ms, 7000.0);
+        assert_eq!(slotted[9].time_ms, 9000.0);
+
+        // More slots than the players have is capped at H
+        assert_eq!(assign_cue_slots(&cues, 12).iter().filter(|c| c.hot_cue > 0).count(), 8);
+        assert!(assign_cue_slots(&cues, 0).iter().all(|c| c.hot_cue == 0));
+    }
+
+    #[test]
+    fn test_quantize_cues() {
+        // 60 BPM: a beat every second, from 100 ms
+        let grid = BeatGrid::constant_tempo(60.0, 100.0, 60_000.0);
+        let cues = vec![
+            CuePoint { hot_cue: 1, time_ms: 2_112.0, ..Default::default() },
+            CuePoint { hot_cue: 2, time_ms: 5_400.0, ..Default::default() },
+            // Loop starting 12 ms early, a little over four beats long
+            CuePoint { cue_type: CueType::Loop, time_ms: 8_088.0, loop_ms: 4_060.0, ..Default::default() },
+        ];
+
+        let quantized = quantize_cues(&cues, &grid, 50.0);
+        assert_eq!(quantized[0].time_ms, 2_100.0, "12 ms off snaps");
+        assert_eq!(quantized[0].hot_cue, 1);
+        assert_eq!(quantized[1].time_ms, 5_400.0, "300 ms off stays");
+        assert_eq!(quantized[2].time_ms, 8_100.0);
+        assert!((quantized[2].loop_ms - 4_060.0).abs() < 1e-6, "{}", quantized[2].loop_ms);
+
+        // Slowing to 50 BPM after the loop starts: the start moves 12 ms,
+        // 0.012 of a 1000 ms beat, and the end 0.012 of a 1200 ms beat
+        let mut grid = grid;
+        for (i, beat) in grid.beats.iter_mut().enumerate().skip(9) {
+            beat.time_ms = 8_100.0 + (i - 8) as f64 * 1_200.0;
+        }
+        let quantized = quantize_cues(&cues[2..], &grid, 50.0);
+        assert_eq!(quantized[0].time_ms, 8_100.0);
+        let end_ms = quantized[0].time_ms + quantized[0].loop_ms;
+        assert!((end_ms - (12_148.0 + 14.4)).abs() < 1e-6, "{}", end_ms);
+
+        assert_eq!(quantize_cues(&cues, &BeatGrid::default(), 50.0)[0].time_ms, 2_112.0);
+    }
+
+    #[test]
+    fn test_three_four_grid_cycles() {
+        let grid = BeatGrid::constant_tempo_with_signature(120.0, 0.0, 3_000.0, (3, 4));
+        let numbers: Vec<u8> = grid.beats.iter().map(|b| b.beat_number).collect();
+        assert_eq!(numbers, vec![1, 2, 3, 1, 2, 3]);
+
+        // Rescaling keeps the signature
+        let mut grid = grid;
+        grid.scale_tempo(2.0);
+        assert_eq!(grid.time_signature, (3, 4));
+        assert_eq!(grid.beats[3].beat_number, 1);
+
+        // Grids cached before the field existed deserialize as 4/4
+        let old: BeatGrid = serde_json::from_str(r#"{"bpm":120.0,"first_beat_ms":0.0,"beats":[]}"#).unwrap();
+        assert_eq!(old.time_signature, (4, 4));
+    }
     
+    #[test]
+    fn test_memory_color_id() {
+        assert_eq!(HotCueColor::PINK.memory_color_id(), 1);
+        assert_eq!(HotCueColor::RED.memory_color_id(), 2);
+        assert_eq!(HotCueColor::PURPLE.memory_color_id(), 8);
+
+        let near_red = HotCueColor { palette_index: 0, red: 0xF0, green: 0x20, blue: 0x10 };
+        assert_eq!(near_red.memory_color_id(), 2);
+    }
+
+    #[test]
+    fn test_hot_cue_color_from_name_and_hex() {
+        assert_eq!(HotCueColor::from_name("red"), Some(HotCueColor::RED));
+        assert_eq!(HotCueColor::from_name(" Cyan "), Some(HotCueColor::CYAN));
+        assert_eq!(HotCueColor::from_name("mauve"), None);
+
+        assert_eq!(
+            HotCueColor::from_hex("#00E0FF").map(|c| c.palette_index),
+            Some(HotCueColor::CYAN.palette_index)
+        );
+        assert_eq!(HotCueColor::from_hex("f02010"), Some(HotCueColor::RED));
+        assert_eq!(HotCueColor::from_hex("#00E0F"), None);
+        assert_eq!(HotCueColor::from_hex("#GG0000"), None);
+    }
+
+    #[test]
+    fn test_beat_grid_shift() {
+        let mut grid = BeatGrid::constant_tempo(120.0, 100.0, 10_000.0);
+        let before: Vec<f64> = grid.beats.iter().map(|b| b.time_ms).collect();
+
+        grid.shift_ms(50.0);
+
+        assert_eq!(grid.first_beat_ms, 150.0);
+        assert_eq!(grid.beats.len(), before.len());
+        for (beat, old) in grid.beats.iter().zip(&before) {
+            assert_eq!(beat.time_ms, old + 50.0);
+        }
+
+        grid.set_first_beat(0.0);
+        assert_eq!(grid.beats[0].time_ms, 0.0);
+    }
+
+    #[test]
+    fn test_beat_grid_scale_tempo() {
+        let mut grid = BeatGrid::constant_tempo(64.0, 0.0, 10_000.0);
+        let old_spacing = grid.beats[1].time_ms - grid.beats[0].time_ms;
+
+        grid.scale_tempo(2.0);
+
+        assert_eq!(grid.bpm, 128.0);
+        assert_eq!(grid.beats[0].tempo_100, 12800);
+        let new_spacing = grid.beats[1].time_ms - grid.beats[0].time_ms;
+        assert!((new_spacing - old_spacing / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_variable_tempo_grid() {
+        let grid = BeatGrid::variable_tempo(&[(100.0, 120.0), (2100.0, 60.0), (2200.0, 128.0)], 6000.0, (4, 4));
+        assert_eq!(grid.bpm, 120.0);
+        assert_eq!(grid.first_beat_ms, 100.0);
+        assert!(!grid.is_constant_tempo());
+
+        // Four beats at 500 ms, then 60 BPM from the beat at 2100 ms; the
+        // change at 2200 ms waits for the next beat (3100 ms)
+        let times: Vec<f64> = grid.beats.iter().take(7).map(|b| b.time_ms).collect();
+        assert_eq!(times, [100.0, 600.0, 1100.0, 1600.0, 2100.0, 3100.0, 3568.75]);
+        assert_eq!(grid.beats[4].tempo_100, 6000);
+        assert_eq!(grid.beats[5].tempo_100, 12800);
+        // Bars run on across changes
+        assert_eq!(grid.beats[4].beat_number, 1);
+        assert_eq!(grid.beats[5].beat_number, 2);
+        assert_eq!(grid.tempo_changes(), [(100.0, 120.0), (2100.0, 60.0), (3100.0, 128.0)]);
+
+        // Scaling keeps the changes in place
+        let mut doubled = grid.clone();
+        doubled.scale_tempo(2.0);
+        assert_eq!(doubled.tempo_changes(), [(100.0, 240.0), (2100.0, 120.0), (3100.0, 256.0)]);
+        assert_eq!(doubled.beats[1].time_ms, 350.0);
+
+        assert!(BeatGrid::constant_tempo(128.0, 0.0, 5000.0).is_constant_tempo());
+        assert!(BeatGrid::variable_tempo(&[], 5000.0, (4, 4)).beats.is_empty());
+    }
+
+    #[test]
+    fn test_memory_cue_color_ids() {
+        assert_eq!(MemoryCueColor::Pink.id(), 1);
+        assert_eq!(MemoryCueColor::Purple.id(), 8);
+        assert_eq!(MemoryCueColor::from_id(3), Some(MemoryCueColor::Orange));
+        assert_eq!(MemoryCueColor::from_id(0), None);
+        assert_eq!(MemoryCueColor::from_id(9), None);
+        for color in MemoryCueColor::ALL {
+            assert_eq!(color.color().memory_color_id(), color.id());
+        }
+
+        // An explicit id wins over the hot cue color; bad ids fall back to it
+        let mut cue = CuePoint { color: Some(HotCueColor::RED), ..Default::default() };
+        assert_eq!(cue.memory_color(), 2);
+        cue.memory_color_id = Some(MemoryCueColor::Blue.id());
+        assert_eq!(cue.memory_color(), 7);
+        cue.memory_color_id = Some(12);
+        assert_eq!(cue.memory_color(), 2);
+        assert_eq!(CuePoint::default().memory_color(), 0);
+    }
+
+    #[test]
+    fn test_cue_constructors() {
+        let cue = CuePoint::hot_cue(3, 1_500.0, HotCueColor::BLUE).unwrap();
+        assert_eq!((cue.hot_cue, cue.cue_type, cue.time_ms, cue.loop_ms), (3, CueType::Cue, 1_500.0, 0.0));
+        assert_eq!(cue.color, Some(HotCueColor::BLUE));
+        assert!(CuePoint::hot_cue(1, 0.0, HotCueColor::GREEN).is_ok());
+        assert!(CuePoint::hot_cue(8, 0.0, HotCueColor::GREEN).is_ok());
+        for slot in [0, 9] {
+            assert!(matches!(CuePoint::hot_cue(slot, 0.0, HotCueColor::GREEN), Err(Error::Validation(_))));
+        }
+        assert!(CuePoint::hot_cue(1, -1.0, HotCueColor::GREEN).is_err());
+        assert!(CuePoint::hot_cue(1, f64::NAN, HotCueColor::GREEN).is_err());
+
+        // 4 beats at 120 BPM is 2 s; half a beat is 250 ms
+        let four = CuePoint::loop_beats(8_000.0, 4.0, 120.0).unwrap();
+        assert_eq!((four.hot_cue, four.cue_type, four.time_ms, four.loop_ms), (0, CueType::Loop, 8_000.0, 2_000.0));
+        assert_eq!(CuePoint::loop_beats(0.0, 0.5, 120.0).unwrap().loop_ms, 250.0);
+        for (beats, bpm) in [(0.0, 120.0), (-4.0, 120.0), (4.0, 0.0), (4.0, -120.0)] {
+            assert!(matches!(CuePoint::loop_beats(0.0, beats, bpm), Err(Error::Validation(_))), "{} beats at {}", beats, bpm);
+        }
+    }
+
     #[test]
     fn test_file_type_from_extension() {
         assert_eq!(FileType::from_extension("mp3"), FileType::Mp3);
         assert_eq!(FileType::from_extension("MP3"), FileType::Mp3);
         assert_eq!(FileType::from_extension("flac"), FileType::Flac);
         assert_eq!(FileType::from_extension("unknown"), FileType::Unknown);
+        assert!(FileType::Alac.is_lossless());
+        assert!(!FileType::M4a.is_lossless());
     }
 }
@@ -2,6 +2,7 @@
 //!
 //! Validates Pioneer DeviceSQL (export.pdb) files against the binary structure
 //! documented at https://djl-analysis.deepsymmetry.org/rekordbox-export-analysis/
+//! (`validate_anlz` does the same for ANLZ .DAT/.EXT/.2EX files)
 //!
 //! File Header (page 0):
 //! - Bytes 4-7: page_size (must be 4096)
@@ -17,11 +18,13 @@
 //! - Bytes 24-26: packed row counts
 //! - Byte 27: page_flags
 
+use serde::Serialize;
+
 use crate::error::{Error, Result};
-use crate::page::{PAGE_SIZE, HEAP_START};
+use crate::page::{PageType, PAGE_SIZE, HEAP_START, HEADER_TABLE_POINTERS};
 
 /// Statistics about a PDB file
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct PdbStats {
     pub total_pages: u32,
     pub track_count: u32,
@@ -34,7 +37,7 @@ pub struct PdbStats {
 }
 
 /// Result of validating a PDB file
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ValidationResult {
     pub valid: bool,
     pub stats: PdbStats,
@@ -62,6 +65,108 @@ impl ValidationResult {
     }
 }
 
+/// Header fields of one page, for walking a file page by page
+#[derive(Debug, Clone, Serialize)]
+pub struct PageReport {
+    /// Position in the file
+    pub index: u32,
+    /// page_index stored in the page header (should equal `index`)
+    pub stored_index: u32,
+    /// Table type of the pointer chain the page is on (None for the file
+    /// header and for pages no table reaches)
+    pub page_type: Option<u32>,
+    /// "Header", the table name, or None for unreachable pages
+    pub table: Option<String>,
+    /// Index page (page_flags & 0x40) rather than a data page
+    pub is_index: bool,
+    pub flags: u8,
+    pub num_rows: u32,
+    pub num_row_offsets: u32,
+    pub free_size: u16,
+    pub used_size: u16,
+    /// Next page in the table's chain (index pages point at their first
+    /// data page); None for the file header
+    pub next_page: Option<u32>,
+}
+
+/// Dump every page's header fields, in file order
+///
+/// Pages are assigned to tables by following each table pointer from its
+/// index page through the data pages' next_page links. No checks are made
+/// beyond slicing whole pages (a trailing partial page is ignored); pair
+/// with `validate_pdb` to see which pages are at fault.
+pub fn validate_verbose(data: &[u8]) -> Vec<PageReport> {
+    let pages: Vec<&[u8]> = data.chunks_exact(PAGE_SIZE).collect();
+    let u32_at = |page: &[u8], offset: usize| {
+        u32::from_le_bytes([page[offset], page[offset + 1], page[offset + 2], page[offset + 3]])
+    };
+    let is_index = |page: &[u8]| page[27] & 0x40 != 0;
+    // Index pages name their first data page (or the empty table marker)
+    // at 0x2C; data pages chain through next_page at 0x08
+    let next_page = |page: &[u8]| if is_index(page) { u32_at(page, 0x2C) } else { u32_at(page, 0x08) };
+
+    // Page -> table type, from the header's table pointers
+    let mut owners: Vec<Option<u32>> = vec![None; pages.len()];
+    if let Some(header) = pages.first() {
+        let num_tables = u32_at(header, 8) as usize;
+        // Table pointers at 0x1C: (type, empty candidate, index page, last data page)
+        for table in 0..num_tables.min((PAGE_SIZE - HEADER_TABLE_POINTERS) / 16) {
+            let ptr = HEADER_TABLE_POINTERS + table * 16;
+            let table_type = u32_at(header, ptr);
+            let mut current = u32_at(header, ptr + 8) as usize;
+            let last = u32_at(header, ptr + 12) as usize;
+            while current != 0 && current < pages.len() && owners[current].is_none() {
+                owners[current] = Some(table_type);
+                if current == last && !is_index(pages[current]) {
+                    break;
+                }
+                current = next_page(pages[current]) as usize;
+            }
+        }
+    }
+
+    pages.iter()
+        .enumerate()
+        .map(|(index, page)| {
+            if index == 0 {
+                return PageReport {
+                    index: 0,
+                    stored_index: 0,
+                    page_type: None,
+                    table: Some("Header".into()),
+                    is_index: false,
+                    flags: 0,
+                    num_rows: 0,
+                    num_row_offsets: 0,
+                    free_size: 0,
+                    used_size: 0,
+                    next_page: None,
+                };
+            }
+
+            // Packed row counts: num_rows in the low 11 bits, num_row_offsets above
+            let packed = (page[24] as u32) | ((page[25] as u32) << 8) | ((page[26] as u32) << 16);
+            let page_type = owners[index];
+            PageReport {
+                index: index as u32,
+                stored_index: u32_at(page, 4),
+                page_type,
+                table: page_type.map(|t| match PageType::all_types().get(t as usize) {
+                    Some(known) => format!("{:?}", known),
+                    None => format!("Type{}", t),
+                }),
+                is_index: is_index(page),
+                flags: page[27],
+                num_rows: packed & 0x7FF,
+                num_row_offsets: packed >> 11,
+                free_size: u16::from_le_bytes([page[28], page[29]]),
+                used_size: u16::from_le_bytes([page[30], page[31]]),
+                next_page: Some(next_page(page)),
+            }
+        })
+        .collect()
+}
+
 /// Validate a PDB file and return detailed results
 ///
 /// Checks:
@@ -85,7 +190,7 @@ pub fn validate_pdb(data: &[u8]) -> ValidationResult {
     }
 
     // Check page alignment
-    if data.len() % PAGE_SIZE != 0 {
+    if !data.len().is_multiple_of(PAGE_SIZE) {
         result.add_error(format!(
             "File size {} is not a multiple of page size {}",
             data.len(),
@@ -149,7 +254,7 @@ pub fn validate_pdb(data: &[u8]) -> ValidationResult {
     //   Bytes 8-11: first_page
     //   Bytes 12-15: last_page
     for i in 0..num_tables {
-        let ptr_offset = 28 + (i as usize) * 16;
+        let ptr_offset = HEADER_TABLE_POINTERS + (i as usize) * 16;
 
         if ptr_offset + 16 > PAGE_SIZE {
             result.add_error(format!(
@@ -311,6 +416,89 @@ fn validate_data_page(page: &[u8], expected_idx: u32) -> Result<()> {
     Ok(())
 }
 
+/// Result of validating an ANLZ file
+#[derive(Debug, Serialize)]
+pub struct AnlzValidation {
+    pub valid: bool,
+    /// Section tags in file order
+    pub sections: Vec<String>,
+    /// Audio file path from the PPTH section
+    pub path: Option<String>,
+    pub errors: Vec<String>,
+}
+
+/// Length of the PMAI file header
+const ANLZ_HEADER_LEN: usize = 28;
+
+/// Validate the section structure of an ANLZ file
+///
+/// Checks:
+/// - PMAI header, with a file length matching the data
+/// - Sections (tag, header length, section length) tile the rest of the
+///   file exactly, with 4-letter "P..." tags
+/// - A PPTH path section and a PQTZ beat grid are present
+pub fn validate_anlz(data: &[u8]) -> AnlzValidation {
+    let mut result = AnlzValidation { valid: true, sections: Vec::new(), path: None, errors: Vec::new() };
+    let u32_at = |pos: usize| u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+
+    if data.len() < ANLZ_HEADER_LEN || &data[0..4] != b"PMAI" {
+        result.valid = false;
+        result.errors.push("Missing PMAI header".into());
+        return result;
+    }
+    if u32_at(8) != data.len() {
+        result.errors.push(format!("PMAI file length {} but file is {} bytes", u32_at(8), data.len()));
+    }
+
+    let mut pos = ANLZ_HEADER_LEN;
+    while pos < data.len() {
+        if pos + 12 > data.len() {
+            result.errors.push(format!("Truncated section header at 0x{:X}", pos));
+            break;
+        }
+        let tag = &data[pos..pos + 4];
+        let (header_len, section_len) = (u32_at(pos + 4), u32_at(pos + 8));
+        if tag[0] != b'P' || !tag.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+            result.errors.push(format!("Bad section tag {:02X?} at 0x{:X}", tag, pos));
+            break;
+        }
+        let tag = String::from_utf8_lossy(tag).into_owned();
+        if section_len < 12 || header_len > section_len || pos + section_len > data.len() {
+            result.errors.push(format!(
+                "{} at 0x{:X}: header length {} / section length {} don't fit the file",
+                tag, pos, header_len, section_len
+            ));
+            break;
+        }
+
+        if tag == "PPTH" && section_len >= 16 {
+            let units: Vec<u16> = data[pos + 16..pos + section_len]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            result.path = Some(String::from_utf16_lossy(&units));
+        }
+        result.sections.push(tag);
+        pos += section_len;
+    }
+
+    for required in ["PPTH", "PQTZ"] {
+        if !result.sections.iter().any(|s| s == required) {
+            result.errors.push(format!("No {} section", required));
+        }
+    }
+    result.valid = result.errors.is_empty();
+    result
+}
+
+/// Validate a PDB file and return the full result as JSON
+///
+/// Machine-readable counterpart of validate_and_print() for scripts and CI.
+pub fn validate_to_json(data: &[u8]) -> serde_json::Value {
+    serde_json::to_value(validate_pdb(data)).unwrap_or_default()
+}
+
 /// Validate a PDB file and print results to stdout
 pub fn validate_and_print(data: &[u8]) -> bool {
     let result = validate_pdb(data);
@@ -399,4 +587,68 @@ mod tests {
         // Should be valid with just header
         assert!(result.valid, "Errors: {:?}", result.errors);
     }
+
+    #[test]
+    fn test_validate_verbose_one_entry_per_page() {
+        use crate::pdb::{PdbBuilder, DEFAULT_COLORS};
+
+        let data = PdbBuilder::new().build().unwrap();
+        let pages = validate_verbose(&data);
+
+        assert_eq!(pages.len(), data.len() / PAGE_SIZE);
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(page.index, i as u32);
+        }
+        assert_eq!(pages[0].table.as_deref(), Some("Header"));
+
+        // The Colors index page leads to the data page holding the palette
+        let index = pages.iter().find(|p| p.is_index && p.page_type == Some(6)).unwrap();
+        assert_eq!(index.table.as_deref(), Some("Colors"));
+        let colors = &pages[index.next_page.unwrap() as usize];
+        assert!(!colors.is_index);
+        assert_eq!(colors.stored_index, colors.index);
+        assert_eq!(colors.table.as_deref(), Some("Colors"));
+        assert_eq!(colors.num_rows as usize, DEFAULT_COLORS.len());
+        assert!(colors.used_size > 0);
+
+        // The index page's link is its 0x2C next page, not the unknown at 0x0C
+        let mut data = data;
+        let offset = index.index as usize * PAGE_SIZE + 0x0C;
+        data[offset..offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        let pages = validate_verbose(&data);
+        assert_eq!(pages[index.index as usize].next_page, Some(colors.index));
+        assert_eq!(pages[colors.index as usize].table.as_deref(), Some("Colors"));
+    }
+
+    #[test]
+    fn test_validate_anlz() {
+        use crate::anlz::{generate_2ex_file, generate_dat_file, generate_ext_file};
+        use crate::track::{BeatGrid, Waveform};
+
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, 5000.0);
+        let waveform = Waveform::default();
+        let dat = generate_dat_file(&grid, &waveform, "/Contents/test.mp3").unwrap();
+        let ext = generate_ext_file(&grid, &waveform, "/Contents/test.mp3", &[]).unwrap();
+        let two_ex = generate_2ex_file(&grid, &waveform, "/Contents/test.mp3", &[]).unwrap();
+        for data in [&dat, &ext, &two_ex] {
+            let result = validate_anlz(data);
+            assert!(result.valid, "{:?}", result.errors);
+            assert_eq!(result.path.as_deref(), Some("/Contents/test.mp3"));
+        }
+        assert_eq!(&validate_anlz(&dat).sections[..2], ["PPTH", "PQTZ"]);
+
+        let truncated = validate_anlz(&dat[..dat.len() - 10]);
+        assert!(!truncated.valid);
+        assert!(!validate_anlz(b"nope").valid);
+    }
+
+    #[test]
+    fn test_validate_to_json() {
+        let json = validate_to_json(&[0u8; 100]);
+
+        assert_eq!(json["valid"], false);
+        assert!(json["stats"]["total_pages"].is_u64());
+        assert!(!json["errors"].as_array().unwrap().is_empty());
+        assert!(json["warnings"].is_array());
+    }
 }
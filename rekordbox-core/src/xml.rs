@@ -0,0 +1,385 @@
+//! rekordbox DJ-XML collection import
+//!
+//! Parses the XML exported by desktop rekordbox (File > Export Collection in
+//! xml format) into tracks and playlists, so a desktop-managed library can be
+//! written to a USB stick without re-analysis.
+//!
+//! Layout:
+//! ```text
+//! DJ_PLAYLISTS
+//!   COLLECTION
+//!     TRACK (TrackID, Name, Artist, AverageBpm, Tonality, Location, ...)
+//!       TEMPO (Inizio, Bpm), one per tempo change
+//!       POSITION_MARK (Type, Start, End, Num)
+//!   PLAYLISTS
+//!     NODE Type=0 Name=ROOT
+//!       NODE Type=0 (folder) / Type=1 (playlist)
+//!         TRACK Key=<TrackID or Location>
+//! ```
+
+use std::collections::HashMap;
+
+use roxmltree::{Document, Node};
+
+use crate::error::{Error, Result};
+use crate::pdb::PlaylistInfo;
+use crate::track::{derive_sort_name, BeatGrid, CuePoint, CueType, FileType, Key, TrackAnalysis, Waveform};
+
+/// NODE Type attribute for folders
+const NODE_TYPE_FOLDER: &str = "0";
+
+/// POSITION_MARK Type attribute for loops
+const MARK_TYPE_LOOP: &str = "4";
+
+/// Parse a rekordbox DJ-XML document into tracks and playlists
+///
+/// Tracks get sequential IDs starting at 1 in collection order; playlist
+/// entries are remapped to those IDs. Waveforms are left empty since the XML
+/// does not carry them.
+pub fn import_collection_xml(xml: &str) -> Result<(Vec<TrackAnalysis>, Vec<PlaylistInfo>)> {
+    let doc = Document::parse(xml)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "DJ_PLAYLISTS" {
+        return Err(Error::Xml(format!(
+            "expected DJ_PLAYLISTS root, found {}",
+            root.tag_name().name()
+        )));
+    }
+
+    let mut tracks = Vec::new();
+    // XML TrackID / Location -> imported track id
+    let mut ids_by_key: HashMap<&str, u32> = HashMap::new();
+    let mut ids_by_location: HashMap<&str, u32> = HashMap::new();
+
+    if let Some(collection) = child(root, "COLLECTION") {
+        for node in children(collection, "TRACK") {
+            let id = tracks.len() as u32 + 1;
+            if let Some(key) = node.attribute("TrackID") {
+                ids_by_key.insert(key, id);
+            }
+            if let Some(location) = node.attribute("Location") {
+                ids_by_location.insert(location, id);
+            }
+            tracks.push(parse_track(node, id));
+        }
+    }
+
+    let mut playlists = Vec::new();
+    if let Some(root_node) = child(root, "PLAYLISTS").and_then(|p| child(p, "NODE")) {
+        let mut next_id = 1;
+        for (sort_order, node) in children(root_node, "NODE").enumerate() {
+            parse_playlist_node(
+                node,
+                0,
+                sort_order as u32,
+                &mut next_id,
+                &ids_by_key,
+                &ids_by_location,
+                &mut playlists,
+            );
+        }
+    }
+
+    Ok((tracks, playlists))
+}
+
+/// Convert a COLLECTION/TRACK element
+fn parse_track(node: Node, id: u32) -> TrackAnalysis {
+    let location = node.attribute("Location").unwrap_or("");
+    let file_path = location_to_path(location);
+    let file_type = file_path
+        .rsplit_once('.')
+        .map(|(_, ext)| FileType::from_extension(ext))
+        .unwrap_or_default();
+
+    let duration_secs: f64 = attr_parse(node, "TotalTime").unwrap_or(0.0);
+    let bpm: f64 = attr_parse(node, "AverageBpm").unwrap_or(0.0);
+
+    let tempo = child(node, "TEMPO");
+    let first_beat_ms = tempo
+        .and_then(|t| attr_parse::<f64>(t, "Inizio"))
+        .map(|s| s * 1000.0)
+        .unwrap_or(0.0);
+    let time_signature = tempo
+        .and_then(|t| t.attribute("Metro"))
+        .and_then(parse_metro)
+        .unwrap_or((4, 4));
+    let tempos: Vec<(f64, f64)> = children(node, "TEMPO")
+        .filter_map(|t| Some((attr_parse::<f64>(t, "Inizio")? * 1000.0, attr_parse::<f64>(t, "Bpm")?)))
+        .filter(|&(_, bpm)| bpm > 0.0)
+        .collect();
+    let beat_grid = if tempos.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+        // Inizio is rounded to the millisecond; start each change a little
+        // early so the rounding can't push it onto the following beat
+        let changes: Vec<(f64, f64)> = tempos.iter()
+            .enumerate()
+            .map(|(i, &(start_ms, bpm))| (if i == 0 { start_ms } else { start_ms - 1.0 }, bpm))
+            .collect();
+        BeatGrid::variable_tempo(&changes, duration_secs * 1000.0, time_signature)
+    } else if bpm > 0.0 {
+        BeatGrid::constant_tempo_with_signature(bpm, first_beat_ms, duration_secs * 1000.0, time_signature)
+    } else {
+        BeatGrid::default()
+    };
+
+    let cue_points = children(node, "POSITION_MARK").filter_map(parse_position_mark).collect();
+    let artist = attr_string(node, "Artist").unwrap_or_default();
+    let artist_sort = Some(derive_sort_name(&artist));
+
+    TrackAnalysis {
+        id,
+        file_path,
+        title: attr_string(node, "Name").unwrap_or_default(),
+        artist,
+        artist_sort,
+        album: attr_string(node, "Album"),
+        genre: attr_string(node, "Genre"),
+        label: attr_string(node, "Label"),
+        duration_secs,
+        sample_rate: attr_parse(node, "SampleRate").unwrap_or(44100),
+        bit_depth: 16,
+        channels: 0,
+        bitrate: attr_parse(node, "BitRate").unwrap_or(0),
+        bpm,
+        key: node.attribute("Tonality").and_then(Key::from_camelot),
+        beat_grid,
+        waveform: Waveform::default(),
+        cue_points,
+        file_size: attr_parse(node, "Size").unwrap_or(0),
+        file_hash: 0,
+        year: attr_parse(node, "Year").filter(|&y| y > 0),
+        release_date: None,
+        comment: attr_string(node, "Comments"),
+        track_number: attr_parse(node, "TrackNumber").filter(|&n| n > 0),
+        file_type,
+        clip_ratio: 0.0,
+        spectral_cutoff_hz: None,
+        bpm_confidence: 1.0,
+        acoustic_id: None,
+        energy: None,
+        album_artist: None,
+        compilation: false,
+        audio_start_ms: None,
+    }
+}
+
+/// Parse a TEMPO Metro attribute such as "3/4"
+fn parse_metro(metro: &str) -> Option<(u8, u8)> {
+    let (beats, unit) = metro.split_once('/')?;
+    let beats: u8 = beats.trim().parse().ok().filter(|&b| b > 0)?;
+    Some((beats, unit.trim().parse().ok()?))
+}
+
+/// Convert a POSITION_MARK element (Start/End are in seconds, Num -1 = memory cue)
+fn parse_position_mark(node: Node) -> Option<CuePoint> {
+    let start: f64 = attr_parse(node, "Start")?;
+    let num: i32 = attr_parse(node, "Num").unwrap_or(-1);
+    let is_loop = node.attribute("Type") == Some(MARK_TYPE_LOOP);
+    let loop_ms = if is_loop {
+        attr_parse::<f64>(node, "End").map(|end| (end - start) * 1000.0).unwrap_or(0.0)
+    } else {
+        0.0
+    };
+
+    Some(CuePoint {
+        hot_cue: if num >= 0 { (num + 1) as u8 } else { 0 },
+        cue_type: if is_loop { CueType::Loop } else { CueType::Cue },
+        time_ms: start * 1000.0,
+        loop_ms,
+        comment: attr_string(node, "Name"),
+        color: None,
+        memory_color_id: None,
+    })
+}
+
+/// Recursively convert a PLAYLISTS NODE into folders and playlists
+fn parse_playlist_node(
+    node: Node,
+    parent_id: u32,
+    sort_order: u32,
+    next_id: &mut u32,
+    ids_by_key: &HashMap<&str, u32>,
+    ids_by_location: &HashMap<&str, u32>,
+    out: &mut Vec<PlaylistInfo>,
+) {
+    let id = *next_id;
+    *next_id += 1;
+    let name = node.attribute("Name").unwrap_or("").to_string();
+
+    if node.attribute("Type") == Some(NODE_TYPE_FOLDER) {
+        out.push(PlaylistInfo {
+            id,
+            parent_id,
+            name,
+            is_folder: true,
+            sort_order,
+            track_ids: Vec::new(),
+        });
+
+        for (i, sub) in children(node, "NODE").enumerate() {
+            parse_playlist_node(sub, id, i as u32, next_id, ids_by_key, ids_by_location, out);
+        }
+    } else {
+        // KeyType 0 references TrackID, KeyType 1 references Location
+        let by_location = node.attribute("KeyType") == Some("1");
+        let track_ids = children(node, "TRACK")
+            .filter_map(|t| t.attribute("Key"))
+            .filter_map(|key| {
+                if by_location {
+                    ids_by_location.get(key).copied()
+                } else {
+                    ids_by_key.get(key).copied()
+                }
+            })
+            .collect();
+
+        out.push(PlaylistInfo {
+            id,
+            parent_id,
+            name,
+            is_folder: false,
+            sort_order,
+            track_ids,
+        });
+    }
+}
+
+/// Convert a `file://localhost/...` URI to a plain, percent-decoded path
+fn location_to_path(location: &str) -> String {
+    let path = location
+        .strip_prefix("file://localhost")
+        .or_else(|| location.strip_prefix("file://"))
+        .unwrap_or(location);
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+/// Non-empty string attribute
+fn attr_string(node: Node, name: &str) -> Option<String> {
+    node.attribute(name)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+fn attr_parse<T: std::str::FromStr>(node: Node, name: &str) -> Option<T> {
+    node.attribute(name).and_then(|s| s.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DJ_PLAYLISTS Version="1.0.0">
+  <PRODUCT Name="rekordbox" Version="6.8.5" Company="AlphaTheta"/>
+  <COLLECTION Entries="1">
+    <TRACK TrackID="93811" Name="Night Drive" Artist="Example Artist" Album="Nights"
+           Genre="Techno" Kind="MP3 File" Size="9876543" TotalTime="300" Year="2021"
+           AverageBpm="128.00" BitRate="320" SampleRate="44100" Comments=""
+           Tonality="8A" Label="Example Label"
+           Location="file://localhost/Users/dj/Music/Night%20Drive.mp3">
+      <TEMPO Inizio="0.125" Bpm="128.00" Metro="4/4" Battito="1"/>
+      <POSITION_MARK Name="Drop" Type="0" Start="60.000" Num="0"/>
+      <POSITION_MARK Name="" Type="4" Start="90.000" End="91.875" Num="-1"/>
+    </TRACK>
+  </COLLECTION>
+  <PLAYLISTS>
+    <NODE Type="0" Name="ROOT" Count="1">
+      <NODE Name="Friday" Type="1" KeyType="0" Entries="1">
+        <TRACK Key="93811"/>
+      </NODE>
+    </NODE>
+  </PLAYLISTS>
+</DJ_PLAYLISTS>"#;
+
+    #[test]
+    fn test_import_minimal_collection() {
+        let (tracks, playlists) = import_collection_xml(MINIMAL_XML).unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        let track = &tracks[0];
+        assert_eq!(track.id, 1);
+        assert_eq!(track.title, "Night Drive");
+        assert_eq!(track.artist, "Example Artist");
+        assert_eq!(track.label.as_deref(), Some("Example Label"));
+        assert_eq!(track.file_path, "/Users/dj/Music/Night Drive.mp3");
+        assert_eq!(track.file_type, FileType::Mp3);
+        assert_eq!(track.bpm, 128.0);
+        assert_eq!(track.key, Some(Key::new(9, false)));
+        assert_eq!(track.year, Some(2021));
+        assert_eq!(track.comment, None);
+        assert_eq!(track.beat_grid.first_beat_ms, 125.0);
+        assert_eq!(track.beat_grid.time_signature, (4, 4));
+        assert_eq!(parse_metro("3/4"), Some((3, 4)));
+        assert_eq!(parse_metro("0/4"), None);
+
+        assert_eq!(track.cue_points.len(), 2);
+        assert_eq!(track.cue_points[0].hot_cue, 1);
+        assert_eq!(track.cue_points[0].time_ms, 60_000.0);
+        assert_eq!(track.cue_points[1].hot_cue, 0);
+        assert_eq!(track.cue_points[1].cue_type, CueType::Loop);
+        assert_eq!(track.cue_points[1].loop_ms, 1875.0);
+
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].name, "Friday");
+        assert_eq!(playlists[0].parent_id, 0);
+        assert!(!playlists[0].is_folder);
+        assert_eq!(playlists[0].track_ids, vec![1]);
+    }
+
+    #[test]
+    fn test_import_tempo_changes() {
+        // 16 beats at 120 BPM from 0.1 s, then 130 BPM
+        let xml = MINIMAL_XML.replace(
+            r#"<TEMPO Inizio="0.125" Bpm="128.00" Metro="4/4" Battito="1"/>"#,
+            r#"<TEMPO Inizio="0.100" Bpm="120.00" Metro="4/4" Battito="1"/>
+      <TEMPO Inizio="8.100" Bpm="130.00" Metro="4/4" Battito="1"/>"#,
+        );
+        let (tracks, _) = import_collection_xml(&xml).unwrap();
+        let grid = &tracks[0].beat_grid;
+
+        assert!(!grid.is_constant_tempo());
+        assert_eq!(grid.bpm, 120.0);
+        assert_eq!(grid.first_beat_ms, 100.0);
+        assert_eq!(grid.beats[15].tempo_100, 12000);
+        assert_eq!(grid.beats[16].tempo_100, 13000);
+        assert!((grid.beats[16].time_ms - 8100.0).abs() < 1e-6);
+        assert_eq!(grid.beats[16].beat_number, 1);
+        let changes = grid.tempo_changes();
+        assert_eq!(changes.len(), 2);
+        assert!((changes[1].0 - 8100.0).abs() < 1e-6 && changes[1].1 == 130.0);
+    }
+
+    #[test]
+    fn test_import_rejects_other_documents() {
+        assert!(import_collection_xml("<NML/>").is_err());
+        assert!(import_collection_xml("not xml").is_err());
+    }
+}
@@ -5,36 +5,94 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use serde::Serialize;
 use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::codecs::{
+    CodecType, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3,
+};
 use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn, debug};
 use walkdir::WalkDir;
+use xxhash_rust::xxh3::xxh3_64;
 
 use rekordbox_core::{
-    AnalysisCache, compute_file_hash,
-    TrackAnalysis, BeatGrid, FileType,
+    AnalysisCache, file_hash_from_prefix, HASH_SAMPLE_SIZE,
+    TrackAnalysis, BeatGrid, CuePoint, FileType, Waveform, derive_sort_name, round_bpm,
 };
+use rekordbox_core::auxiliary::days_from_civil;
+use crate::autocue::auto_cues as detect_auto_cues;
 use crate::config::Config;
-use crate::navidrome::{NavidromeClient, build_path_to_playlist_map};
-use crate::waveform::WaveformGenerator;
+use crate::cue_import::{add_cues, serato_cues, XmlCues};
+use crate::energy::energy_rating;
+use crate::key::detect_key;
+use crate::fingerprint::acoustic_id;
+use crate::gapless::{apply_encoder_delay, lame_encoder_delay, parse_itunsmpb};
+use crate::navidrome::{
+    NavidromeClient, NavidromeError, build_path_to_playlist_map, find_playlist_for_path,
+};
+use crate::quality::{audio_start_ms, clip_ratio, spectral_cutoff_hz};
+use crate::playlist_import::import_playlists;
+use crate::serato::{parse_vorbis_markers2, VORBIS_MARKERS2_KEY};
+use crate::sidecar::load_sidecar;
+use crate::transcode::{ensure_transcoded, needs_transcode, transcode_dir, transcoded_path};
+use crate::waveform::{WaveformConfig, WaveformGenerator};
 
 /// Result of directory analysis
 pub struct AnalysisResult {
     /// Analyzed tracks
     pub tracks: Vec<TrackAnalysis>,
-    /// Playlist name -> track IDs
-    pub playlists: HashMap<String, Vec<u32>>,
+    /// Playlist path (the folders holding it, outermost first, then its
+    /// name) -> track IDs
+    pub playlists: HashMap<Vec<String>, Vec<u32>>,
+    /// How each audio file was handled
+    pub counts: AnalyzeCounts,
+}
+
+/// Per-file outcome tallies of a directory walk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AnalyzeCounts {
+    /// Decoded and analyzed (now cached)
+    pub analyzed: usize,
+    /// Served from the cache
+    pub cache_hits: usize,
+    /// Older than `since`, unreadable or failed to analyze
+    pub skipped: usize,
+    /// Not in the cache, and left out because the walk was `cached_only`
+    /// (only export plans walk that way; server responses never carry it)
+    #[serde(skip)]
+    pub uncached: usize,
+}
+
+/// Options controlling a directory walk
+#[derive(Debug, Clone, Default)]
+pub struct AnalyzeOptions {
+    /// Skip files last modified before this time (not hashed, not returned),
+    /// so incremental runs only pick up new additions
+    pub since: Option<SystemTime>,
+    /// Checked between files; tracks finished before cancellation stay cached
+    pub cancel: CancellationToken,
+    /// Number of audio files visited so far
+    pub progress: Arc<AtomicUsize>,
+    /// Only return tracks already in the cache, writing nothing: files not
+    /// analyzed yet (or whose AIFF copy isn't made yet) are counted in
+    /// `uncached` and left out, and no track ids are saved
+    pub cached_only: bool,
 }
 
 /// Analyze all audio files in a directory
 pub async fn analyze_directory(
     config: &Config,
     cache: &AnalysisCache,
+    options: &AnalyzeOptions,
 ) -> anyhow::Result<AnalysisResult> {
     // Try to fetch playlists from Navidrome if configured
     let navidrome_playlists = if let Some(ref nav_config) = config.navidrome {
@@ -52,15 +110,34 @@ pub async fn analyze_directory(
         None
     };
 
+    // Cues from a rekordbox collection, applied to every track walked
+    let xml_cues = config.rekordbox_xml.as_deref().and_then(|path| match XmlCues::load(path) {
+        Ok(cues) => Some(cues),
+        Err(e) => {
+            warn!("{:#}; keeping the cues found in the files", e);
+            None
+        }
+    });
+
     // Build path-to-playlist map from Navidrome data
+    let path_prefix = config.navidrome.as_ref().and_then(|n| n.path_prefix.as_deref());
     let path_to_playlist: HashMap<String, String> = navidrome_playlists
         .as_ref()
-        .map(|p| build_path_to_playlist_map(p))
+        .map(|playlists| build_path_to_playlist_map(playlists, path_prefix))
         .unwrap_or_default();
 
     let mut results = Vec::new();
-    let mut playlists: HashMap<String, Vec<u32>> = HashMap::new();
-    let mut track_id = 1u32;
+    let mut playlists: HashMap<Vec<String>, Vec<u32>> = HashMap::new();
+    let mut counts = AnalyzeCounts::default();
+    // Walked path -> track id, for resolving M3U entries
+    let mut track_paths: HashMap<PathBuf, u32> = HashMap::new();
+    // A file keeps the id it had in earlier runs; new ones are numbered
+    // from the configured base (0 is reserved as "no track")
+    let first_id = config.starting_track_id.max(1);
+    let track_options = TrackOptions::from_config(config);
+    let settings = track_options.cache_settings();
+    // Unreadable ids stop the run rather than renumber tracks on sticks
+    let mut track_ids = cache.track_ids()?;
 
     // Scan music directory
     for entry in WalkDir::new(&config.music_dir)
@@ -70,62 +147,164 @@ pub async fn analyze_directory(
     {
         let path = entry.path();
 
-        // Check if audio file
-        if !is_audio_file(path) {
+        // Check if audio file (or one to re-encode into a playable format)
+        let transcode = config.transcode_incompatible && path.is_file() && needs_transcode(path);
+        if !is_audio_file(path) && !transcode {
             continue;
         }
 
-        // Determine playlist name
-        let playlist_name = determine_playlist_name(
+        if options.cancel.is_cancelled() {
+            info!("Analysis cancelled after {} tracks", results.len());
+            anyhow::bail!("Analysis cancelled");
+        }
+
+        // Decoding is blocking; give other clients (status pings, cancel
+        // requests) a turn on the single-threaded runtime between files
+        tokio::task::yield_now().await;
+        options.progress.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(since) = options.since {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            if modified.is_some_and(|m| m < since) {
+                debug!("Skipping {:?} (not modified since cutoff)", path);
+                counts.skipped += 1;
+                continue;
+            }
+        }
+
+        // Determine playlist
+        let playlist_path = determine_playlist_path(
             path,
             &config.music_dir,
             &path_to_playlist,
         );
+        let walked_path = path.to_path_buf();
 
-        // Compute file hash for cache lookup
-        let file_hash = match compute_file_hash(path) {
-            Ok(h) => h,
+        // Unplayable formats are analyzed (and later exported) as their
+        // AIFF copy; ffmpeg runs on the blocking pool
+        let transcoded;
+        let path = if transcode && options.cached_only {
+            match transcoded_path(path, &config.music_dir, &config.cache_dir) {
+                Ok(copy) if copy.is_file() => {
+                    transcoded = copy;
+                    transcoded.as_path()
+                }
+                _ => {
+                    debug!("Leaving out {:?} (not transcoded yet)", path);
+                    counts.uncached += 1;
+                    continue;
+                }
+            }
+        } else if transcode {
+            let (source, music_dir, cache_dir) = (path.to_path_buf(), config.music_dir.clone(), config.cache_dir.clone());
+            let copy = tokio::task::spawn_blocking(move || ensure_transcoded(&source, &music_dir, &cache_dir))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+            match copy {
+                Ok(copy) => {
+                    transcoded = copy;
+                    transcoded.as_path()
+                }
+                Err(e) => {
+                    warn!("Failed to transcode {:?}: {:#}", path, e);
+                    counts.skipped += 1;
+                    continue;
+                }
+            }
+        } else {
+            path
+        };
+
+        // Open once: the bytes hashed for the cache lookup are reused by
+        // the decoder on a miss
+        let source = match HashedSource::open(path) {
+            Ok(source) => source,
             Err(e) => {
                 warn!("Failed to hash {:?}: {}", path, e);
+                counts.skipped += 1;
                 continue;
             }
         };
+        let file_hash = source.hash();
+        let cached = cache.get(file_hash, settings);
+        if cached.is_none() && options.cached_only {
+            debug!("Leaving out {:?} (not analyzed yet)", path);
+            counts.uncached += 1;
+            continue;
+        }
+        if track_ids.claimed(file_hash) {
+            warn!("{:?} has the same sampled hash as another file, giving it a new track id", path);
+        }
+        let track_id = track_ids.assign(file_hash, &path.to_string_lossy(), first_id);
+        let file_path = usb_file_path(path, &[&config.music_dir, &transcode_dir(&config.cache_dir)]);
 
         // Check cache first
-        if let Some(mut cached) = cache.get(file_hash) {
+        let mut analysis = if let Some(cached) = cached {
             debug!("Cache hit for {:?}", path);
-            cached.id = track_id;
+            counts.cache_hits += 1;
+            cached
+        } else {
+            info!("Analyzing: {:?}", path);
 
-            if let Some(ref name) = playlist_name {
-                playlists.entry(name.clone()).or_default().push(track_id);
+            match analyze_track(path, source, track_id, file_path.clone(), &track_options) {
+                Ok(analysis) => {
+                    // Cache the result (metadata-only results would shadow a
+                    // later full analysis, so they are never stored)
+                    if !config.metadata_only {
+                        if let Err(e) = cache.put(&analysis, settings) {
+                            warn!("Failed to cache analysis: {}", e);
+                        }
+                    }
+                    counts.analyzed += 1;
+                    analysis
+                }
+                Err(e) => {
+                    warn!("Failed to analyze {:?}: {}", path, e);
+                    counts.skipped += 1;
+                    continue;
+                }
             }
-            results.push(cached);
-            track_id += 1;
-            continue;
+        };
+        analysis.id = track_id;
+        analysis.file_path = file_path;
+
+        // A collection's cues replace those found in the file. Like
+        // sidecars (which still win), they are applied on every run rather
+        // than cached, so editing one takes effect without re-analyzing.
+        if let Some(cues) = xml_cues.as_ref().and_then(|xml| xml.cues_for(&walked_path)) {
+            debug!("Taking {} cues for {:?} from the rekordbox XML", cues.len(), walked_path);
+            analysis.cue_points = cues.to_vec();
         }
 
-        info!("Analyzing: {:?}", path);
+        // Sidecars are applied on every run rather than cached, so editing
+        // or deleting one takes effect without re-analyzing the file
+        if let Some(sidecar) = load_sidecar(&walked_path) {
+            debug!("Applying sidecar overrides for {:?}", walked_path);
+            sidecar.apply(&mut analysis);
+        }
 
-        // Analyze track
-        match analyze_track(path, track_id, file_hash) {
-            Ok(analysis) => {
-                // Cache the result
-                if let Err(e) = cache.put(&analysis) {
-                    warn!("Failed to cache analysis: {}", e);
-                }
+        if let Some(ref playlist) = playlist_path {
+            playlists.entry(playlist.clone()).or_default().push(track_id);
+        }
+        track_paths.insert(walked_path, track_id);
+        results.push(analysis);
+    }
 
-                if let Some(ref name) = playlist_name {
-                    playlists.entry(name.clone()).or_default().push(track_id);
-                }
-                results.push(analysis);
-                track_id += 1;
-            }
-            Err(e) => {
-                warn!("Failed to analyze {:?}: {}", path, e);
-            }
+    if !options.cached_only {
+        if let Err(e) = cache.save_track_ids(&track_ids) {
+            warn!("Failed to save track ids: {}", e);
         }
     }
 
+    // M3U files in the music directory list playlists explicitly, so they
+    // replace a folder or Navidrome playlist of the same name
+    let imported = import_playlists(&config.music_dir, &track_paths);
+    if !imported.is_empty() {
+        info!("Loaded {} playlists from M3U files", imported.len());
+    }
+    playlists.extend(imported);
+
     info!(
         "Analyzed {} tracks in {} playlists",
         results.len(),
@@ -135,34 +314,98 @@ pub async fn analyze_directory(
     Ok(AnalysisResult {
         tracks: results,
         playlists,
+        counts,
     })
 }
 
+/// Analyze one audio file into the cache unless it is already there
+///
+/// Returns the file's cache hash and whether it was analyzed (false on a
+/// cache hit). Sources the players can't read are analyzed as their AIFF
+/// copy, like in a directory walk.
+pub fn cache_file(config: &Config, cache: &AnalysisCache, path: &Path) -> anyhow::Result<(u64, bool)> {
+    let transcoded;
+    let path = if config.transcode_incompatible && needs_transcode(path) {
+        transcoded = ensure_transcoded(path, &config.music_dir, &config.cache_dir)?;
+        transcoded.as_path()
+    } else {
+        path
+    };
+
+    let source = HashedSource::open(path)?;
+    let file_hash = source.hash();
+    let settings = cache_settings(config);
+    if cache.get(file_hash, settings).is_some() {
+        debug!("Cache hit for {:?}", path);
+        return Ok((file_hash, false));
+    }
+
+    info!("Analyzing: {:?}", path);
+    // Ids are handed out again whenever cached tracks are listed or exported
+    let file_path = usb_file_path(path, &[&config.music_dir, &transcode_dir(&config.cache_dir)]);
+    let analysis = analyze_track(path, source, config.starting_track_id.max(1), file_path, &TrackOptions::from_config(config))?;
+    if !config.metadata_only {
+        cache.put(&analysis, settings)?;
+    }
+    Ok((file_hash, true))
+}
+
+/// Attempts at reaching Navidrome before giving up on network errors
+const NAVIDROME_ATTEMPTS: u32 = 3;
+
 /// Fetch playlists from Navidrome
+///
+/// Transport failures (and 5xx responses) are retried with a short backoff;
+/// auth and API errors fail immediately since retrying cannot help.
 async fn fetch_navidrome_playlists(
     config: &crate::config::NavidromeConfig,
-) -> anyhow::Result<HashMap<String, Vec<crate::navidrome::PlaylistTrack>>> {
+) -> Result<HashMap<String, Vec<crate::navidrome::PlaylistTrack>>, NavidromeError> {
     let client = NavidromeClient::new(&config.url, &config.user, &config.pass);
 
-    // Test connection first
-    if !client.ping().await? {
-        anyhow::bail!("Failed to connect to Navidrome");
+    let mut attempt = 1;
+    loop {
+        // Test connection first
+        let result = match client.ping().await {
+            Ok(()) => client.get_all_playlist_tracks().await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Err(e) if e.is_transient() && attempt < NAVIDROME_ATTEMPTS => {
+                warn!("Navidrome request failed ({}), retrying ({}/{})", e, attempt, NAVIDROME_ATTEMPTS);
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                attempt += 1;
+            }
+            other => return other,
+        }
     }
+}
 
-    client.get_all_playlist_tracks().await
+/// USB path of an analyzed file: `/Contents/` plus its path below the
+/// first of `roots` (the music folder, then the transcode directory) that
+/// holds it, so files sharing a name in different folders stay apart
+///
+/// Files under none of them go by their file name.
+fn usb_file_path(path: &Path, roots: &[&Path]) -> String {
+    let relative = match roots.iter().find_map(|root| path.strip_prefix(root).ok()) {
+        Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+        None => path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+    };
+    format!("/Contents/{}", relative)
 }
 
-/// Determine playlist name for a track
+/// Determine the playlist path (folders, then playlist name) for a track
 ///
 /// Priority:
-/// 1. Navidrome playlist (if path matches)
-/// 2. Folder name (if not in music_dir root)
+/// 1. Navidrome playlist (if path matches), at the root whatever its name
+/// 2. Folder path below music_dir (`House/Deep` -> a "Deep" playlist in a
+///    "House" folder), which the export nests into playlist folders
 /// 3. None (standalone track)
-fn determine_playlist_name(
+fn determine_playlist_path(
     path: &Path,
     music_dir: &Path,
     path_to_playlist: &HashMap<String, String>,
-) -> Option<String> {
+) -> Option<Vec<String>> {
     // Try to get relative path from music_dir
     let relative_path = path.strip_prefix(music_dir).ok()?;
     let relative_str = relative_path.to_str()?;
@@ -171,33 +414,166 @@ fn determine_playlist_name(
     let normalized = relative_str.replace('\\', "/");
 
     // Check Navidrome playlist first
-    if let Some(playlist_name) = path_to_playlist.get(&normalized) {
-        return Some(playlist_name.clone());
+    if let Some(playlist_name) = find_playlist_for_path(path_to_playlist, &normalized) {
+        return Some(vec![playlist_name.clone()]);
     }
 
     // Fall back to folder-based detection
     // If track is directly in music_dir, it's a standalone track (no playlist)
-    let parent = path.parent()?;
-    if parent == music_dir {
-        return None; // Standalone track
+    let folder = normalized.rsplit_once('/')?.0;
+    if folder.is_empty() {
+        return None;
+    }
+    Some(folder.split('/').filter(|part| !part.is_empty()).map(String::from).collect())
+}
+
+/// Per-track analysis settings taken from the config
+#[derive(Debug, Clone, Copy)]
+struct TrackOptions {
+    bpm_precision: f64,
+    auto_cues: bool,
+    /// Read tags and headers only: no decoding, BPM from the tag, no waveform
+    metadata_only: bool,
+    /// Steer half/double tempo choices toward the genre's usual tempo
+    genre_tempo_hints: bool,
+    /// dBFS below which leading audio counts as silence for audio_start_ms
+    silence_threshold_db: Option<f64>,
+    waveform: WaveformConfig,
+}
+
+impl TrackOptions {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            bpm_precision: config.bpm_precision,
+            auto_cues: config.auto_cues,
+            metadata_only: config.metadata_only,
+            genre_tempo_hints: config.genre_tempo_hints,
+            silence_threshold_db: config.silence_threshold_db,
+            waveform: config.waveform,
+        }
     }
 
-    // Use immediate parent folder as playlist name
-    parent
-        .file_name()
-        .and_then(|n| n.to_str())
-        .map(|s| s.to_string())
+    /// Fingerprint of the options that shape an analysis, stored with each
+    /// cache entry (metadata-only results are never cached)
+    fn cache_settings(&self) -> u64 {
+        let shaping = (self.bpm_precision, self.auto_cues, self.genre_tempo_hints, self.silence_threshold_db, self.waveform);
+        xxh3_64(&serde_json::to_vec(&shaping).unwrap_or_default())
+    }
 }
 
+/// Settings fingerprint of cache entries analyzed under `config`: entries
+/// made with other BPM, cue or waveform settings are analyzed again
+pub fn cache_settings(config: &Config) -> u64 {
+    TrackOptions::from_config(config).cache_settings()
+}
+
+impl Default for TrackOptions {
+    fn default() -> Self {
+        Self {
+            bpm_precision: 0.5,
+            auto_cues: false,
+            metadata_only: false,
+            genre_tempo_hints: false,
+            silence_threshold_db: None,
+            waveform: WaveformConfig::default(),
+        }
+    }
+}
+
+/// An audio file opened once for both the cache hash and decoding
+///
+/// The first `HASH_SAMPLE_SIZE` bytes are read up front for the hash and
+/// served from memory when the decoder probes the file, so the start of
+/// the file is only read from disk once.
+struct HashedSource {
+    file: File,
+    head: Vec<u8>,
+    len: u64,
+    /// Read position as seen by the decoder
+    pos: u64,
+    /// Position of the underlying file handle
+    file_pos: u64,
+}
+
+impl HashedSource {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut head = vec![0u8; (len as usize).min(HASH_SAMPLE_SIZE)];
+        file.read_exact(&mut head)?;
+        let file_pos = head.len() as u64;
+        Ok(Self { file, head, len, pos: 0, file_pos })
+    }
+
+    /// Same value as `compute_file_hash` on the path
+    fn hash(&self) -> u64 {
+        file_hash_from_prefix(&self.head, self.len)
+    }
+}
+
+impl Read for HashedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if let Some(rest) = self.head.get(self.pos as usize..).filter(|rest| !rest.is_empty()) {
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            n
+        } else {
+            if self.file_pos != self.pos {
+                self.file_pos = self.file.seek(SeekFrom::Start(self.pos))?;
+            }
+            let n = self.file.read(buf)?;
+            self.file_pos += n as u64;
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HashedSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // The file handle follows lazily, on the next read past the head
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"))?;
+        Ok(self.pos)
+    }
+}
+
+impl MediaSource for HashedSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
+
+/// Mono samples kept in memory for the passes that don't stream: about
+/// 50 MB, or 4.7 minutes at 44.1 kHz (2.2 at 96 kHz)
+///
+/// Waveforms and tempo see the whole file. Key, first beat, the quality
+/// checks, fingerprint, energy, audio start and automatic cues only see
+/// this much of its start; `analyze_track` notes what that means for each.
+const HEAD_SAMPLES: usize = 12_500_000;
+
 /// Analyze a single audio track
+///
+/// `file_path` is the track's USB path (see `usb_file_path`).
 fn analyze_track(
     path: &Path,
+    source: HashedSource,
     track_id: u32,
-    file_hash: u64,
+    file_path: String,
+    options: &TrackOptions,
 ) -> anyhow::Result<TrackAnalysis> {
-    // Open audio file
-    let file = File::open(path)?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let file_hash = source.hash();
+    let file_size = source.len;
+    let mss = MediaSourceStream::new(Box::new(source), Default::default());
     
     // Probe format
     let mut hint = Hint::new();
@@ -215,25 +591,28 @@ fn analyze_track(
     let mut format = probed.format;
     
     // Get track info - extract what we need before mutable borrows
-    let (codec_track_id, sample_rate, bit_depth, bitrate, codec_params) = {
+    let (codec_track_id, sample_rate, bit_depth, mut channels, bitrate, codec_params) = {
         let track = format.default_track()
             .ok_or_else(|| anyhow::anyhow!("No default track"))?;
         let sample_rate = track.codec_params.sample_rate
             .ok_or_else(|| anyhow::anyhow!("Unknown sample rate"))?;
         let bit_depth = track.codec_params.bits_per_sample.unwrap_or(16) as u16;
-        // Extract bitrate in kbps, default to 320 if not available
+        // Some demuxers only know the layout once a packet is decoded
+        let channels = track.codec_params.channels.map(|c| c.count() as u8);
+        // Extract bitrate in kbps, default to 320 if not available. Sample
+        // sizes are per channel; assume stereo unless the layout is known
+        let channel_count = channels.unwrap_or(2) as u32;
         let bitrate = track.codec_params.bits_per_coded_sample
-            .map(|bps| (bps * sample_rate / 1000) as u32)
+            .map(|bps| bps * sample_rate * channel_count / 1000)
             .or_else(|| {
                 // For lossless formats, estimate from sample rate and bit depth
                 match bit_depth {
-                    16 => Some(sample_rate * 16 * 2 / 1000), // stereo 16-bit
-                    24 => Some(sample_rate * 24 * 2 / 1000), // stereo 24-bit
+                    16 | 24 => Some(sample_rate * bit_depth as u32 * channel_count / 1000),
                     _ => None,
                 }
             })
             .unwrap_or(320);
-        (track.id, sample_rate, bit_depth, bitrate, track.codec_params.clone())
+        (track.id, sample_rate, bit_depth, channels, bitrate, track.codec_params.clone())
     };
 
     // Create decoder
@@ -243,78 +622,145 @@ fn analyze_track(
     )?;
 
     // Extract metadata
-    let (title, artist, album, genre, year, track_number) = extract_metadata(&mut format, path);
+    let tags = extract_metadata(&mut format, path);
     
-    // Get file type
-    let file_type = path.extension()
-        .and_then(|e| e.to_str())
-        .map(FileType::from_extension)
-        .unwrap_or_default();
+    // Get file type (the codec decides, e.g. ALAC vs AAC inside .m4a)
+    let file_type = detect_file_type(codec_params.codec, path);
     
-    // Collect samples for analysis (downsample to mono float)
+    // The waveform and tempo passes consume each packet as it is decoded,
+    // so the whole file is analyzed without holding it in memory
+    let mut generator = WaveformGenerator::new(sample_rate, options.waveform);
+    let mut onsets = OnsetEnvelope::new(sample_rate);
+
+    // The rest (key, first beat, energy, quality, fingerprint, auto cues)
+    // analyzes the first HEAD_SAMPLES of the mono downmix
     let mut samples: Vec<f32> = Vec::new();
     let mut total_samples = 0u64;
+
+    // Per-packet buffers; stereo sources keep both channels for the
+    // waveform's L/R heights
+    let mut mono: Vec<f32> = Vec::new();
+    let mut left: Vec<f32> = Vec::new();
+    let mut right: Vec<f32> = Vec::new();
     
-    // Memory limit: ~50MB of samples
-    const MAX_SAMPLES: usize = 12_500_000;
-    
-    loop {
-        let packet = match format.next_packet() {
-            Ok(p) => p,
-            Err(symphonia::core::errors::Error::IoError(ref e)) 
-                if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e.into()),
-        };
+    // Metadata-only mode never decodes
+    if !options.metadata_only {
+        loop {
+            let packet = match format.next_packet() {
+                Ok(p) => p,
+                Err(symphonia::core::errors::Error::IoError(ref e)) 
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
         
-        if packet.track_id() != codec_track_id {
-            continue;
-        }
+            if packet.track_id() != codec_track_id {
+                continue;
+            }
         
-        let decoded = decoder.decode(&packet)?;
-        total_samples += decoded.frames() as u64;
+            let decoded = decoder.decode(&packet)?;
+            total_samples += decoded.frames() as u64;
+            channels.get_or_insert(decoded.spec().channels.count() as u8);
         
-        if samples.len() < MAX_SAMPLES {
-            append_as_mono_f32(&decoded, &mut samples);
+            mono.clear();
+            if decoded.spec().channels.count() == 2 {
+                left.clear();
+                right.clear();
+                append_stereo_f32(&decoded, &mut left, &mut right);
+                generator.push_stereo(&left, &right);
+                mono.extend(left.iter().zip(&right).map(|(l, r)| (l + r) * 0.5));
+            } else {
+                append_as_mono_f32(&decoded, &mut mono);
+                generator.push_samples(&mono);
+            }
+            onsets.push(&mono);
+
+            let room = HEAD_SAMPLES.saturating_sub(samples.len());
+            samples.extend_from_slice(&mono[..mono.len().min(room)]);
         }
     }
+    drop((mono, left, right));
     
-    let duration_secs = total_samples as f64 / sample_rate as f64;
+    let duration_secs = resolve_duration(
+        total_samples,
+        codec_params.n_frames,
+        sample_rate,
+        file_size,
+        bitrate,
+    );
     debug!("Decoded {} samples, duration: {:.1}s", total_samples, duration_secs);
     
-    // BPM detection
-    let bpm = detect_bpm(&samples, sample_rate)?;
-    info!("Detected BPM: {:.1}", bpm);
+    // BPM detection (metadata-only mode trusts the tag instead)
+    let BpmResult { bpm, confidence: bpm_confidence } = match tags.bpm {
+        Some(bpm) if options.metadata_only => BpmResult {
+            bpm: round_bpm(bpm, options.bpm_precision),
+            confidence: 1.0,
+        },
+        None if options.metadata_only => BpmResult::guess(),
+        _ => {
+            let tempo_hint = tags.genre.as_deref()
+                .filter(|_| options.genre_tempo_hints)
+                .and_then(genre_tempo_hint);
+            detect_bpm(onsets, options.bpm_precision, tempo_hint)?
+        }
+    };
+    info!("Detected BPM: {:.2} (confidence {:.2})", bpm, bpm_confidence);
     
-    // Key detection (TODO: implement properly)
-    let key = None;
+    // Key detection, over the head: a long mix that modulates is keyed by
+    // its opening
+    let key = if options.metadata_only { None } else { detect_key(&samples, sample_rate) };
+    if let Some(key) = key {
+        info!("Detected key: {} ({})", key.name(), key.to_camelot());
+    }
     
-    // Generate beat grid
+    // Generate beat grid (the first beat is found well inside the head)
     let first_beat_ms = detect_first_beat(&samples, sample_rate, bpm);
     let beat_grid = BeatGrid::constant_tempo(bpm, first_beat_ms, duration_secs * 1000.0);
     
-    // Generate waveforms
-    let waveform_gen = WaveformGenerator::new(sample_rate);
-    let waveform = waveform_gen.generate(&samples, duration_secs);
-    
-    // Build relative file path for database
-    let file_name = path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown");
-    let file_path = format!("/Contents/{}", file_name);
-    
-    let file_size = std::fs::metadata(path)?.len();
+    // Finish the waveforms
+    let waveform = if options.metadata_only {
+        Waveform::default()
+    } else {
+        generator.finalize(duration_secs)
+    };
+
+    // Also over the head: clipping and the cutoff are judged on the opening
+    // minutes, and the energy rating describes them. Fingerprints still
+    // pair up copies, which share their opening.
+    let (clip_ratio, spectral_cutoff_hz, acoustic_id, energy) = if options.metadata_only {
+        (0.0, None, None, None)
+    } else {
+        (
+            clip_ratio(&samples),
+            spectral_cutoff_hz(&samples, sample_rate),
+            acoustic_id(&samples, sample_rate),
+            energy_rating(&samples, sample_rate),
+        )
+    };
+    // Leading silence longer than the head reads as no music at all
+    let audio_start_ms = options.silence_threshold_db
+        .filter(|_| !options.metadata_only)
+        .and_then(|threshold_db| audio_start_ms(&samples, sample_rate, threshold_db));
+    if clip_ratio > 0.001 {
+        warn!("{:?} looks clipped ({:.2}% of samples at full scale)", path, clip_ratio * 100.0);
+    }
     
-    Ok(TrackAnalysis {
+    let artist_sort = tags.artist_sort
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| derive_sort_name(&tags.artist));
+
+    let mut analysis = TrackAnalysis {
         id: track_id,
         file_path,
-        title,
-        artist,
-        album,
-        genre,
+        title: tags.title,
+        artist: tags.artist,
+        artist_sort: Some(artist_sort),
+        album: tags.album,
+        genre: tags.genre,
         label: None, // Could be extracted from metadata if available
         duration_secs,
         sample_rate,
         bit_depth,
+        channels: channels.unwrap_or(0),
         bitrate,
         bpm,
         key,
@@ -323,11 +769,98 @@ fn analyze_track(
         cue_points: Vec::new(), // No cue points detected yet (can be added from Navidrome)
         file_size,
         file_hash,
-        year,
+        year: tags.year,
+        release_date: tags.release_date,
         comment: None,
-        track_number,
+        track_number: tags.track_number,
         file_type,
-    })
+        clip_ratio,
+        spectral_cutoff_hz,
+        bpm_confidence,
+        acoustic_id,
+        energy,
+        album_artist: tags.album_artist.filter(|a| !a.trim().is_empty()),
+        compilation: tags.compilation,
+        audio_start_ms,
+    };
+
+    // Cues set in Serato carry over. Serato counts from the priming-free
+    // start like the analysis does, so they go in before the delay shift
+    // (see cue_import).
+    if let Some(cues) = serato_cues(path, tags.serato_cues) {
+        debug!("Importing {} Serato cues for {:?}", cues.len(), path);
+        add_cues(&mut analysis, cues);
+    }
+
+    // Priming samples make the decoded audio start late; move the grid to
+    // match what the player hears
+    let encoder_delay = match analysis.file_type {
+        FileType::Mp3 => lame_encoder_delay(path).or(tags.encoder_delay),
+        _ => tags.encoder_delay,
+    };
+    if let Some(delay) = encoder_delay {
+        debug!("Compensating {} samples of encoder delay for {:?}", delay, path);
+        apply_encoder_delay(&mut analysis, delay);
+    }
+
+    // Tracks nobody has cued yet get memory cues at the start and the drops
+    // (drops past the head of a long mix go uncued)
+    if options.auto_cues && !options.metadata_only && analysis.cue_points.is_empty() {
+        analysis.cue_points = detect_auto_cues(&samples, sample_rate, &analysis.beat_grid);
+        debug!("Placed {} automatic cues for {:?}", analysis.cue_points.len(), path);
+    }
+
+    Ok(analysis)
+}
+
+/// Work out a track's file type from its codec
+///
+/// The extension alone can't tell ALAC from AAC (both `.m4a`), so the
+/// decoder's codec wins; PCM and unknown codecs fall back to the extension
+/// since WAV and AIFF only differ by container.
+fn detect_file_type(codec: CodecType, path: &Path) -> FileType {
+    match codec {
+        CODEC_TYPE_ALAC => FileType::Alac,
+        CODEC_TYPE_AAC => FileType::M4a,
+        CODEC_TYPE_FLAC => FileType::Flac,
+        CODEC_TYPE_MP3 => FileType::Mp3,
+        _ => path.extension()
+            .and_then(|e| e.to_str())
+            .map(FileType::from_extension)
+            .unwrap_or_default(),
+    }
+}
+
+/// Work out a track's duration in seconds
+///
+/// Prefers the number of frames actually decoded, then the container's frame
+/// count, and finally an estimate from file size and bitrate for streams that
+/// report neither. Never returns 0 for a non-empty file, so the deck always
+/// gets a scrubbable length and a beat grid.
+fn resolve_duration(
+    decoded_frames: u64,
+    header_frames: Option<u64>,
+    sample_rate: u32,
+    file_size: u64,
+    bitrate_kbps: u32,
+) -> f64 {
+    if sample_rate > 0 {
+        if decoded_frames > 0 {
+            return decoded_frames as f64 / sample_rate as f64;
+        }
+        if let Some(frames) = header_frames.filter(|&n| n > 0) {
+            return frames as f64 / sample_rate as f64;
+        }
+    }
+
+    if file_size == 0 {
+        return 0.0;
+    }
+    let bitrate_kbps = if bitrate_kbps > 0 { bitrate_kbps } else { 320 };
+    let estimate = file_size as f64 * 8.0 / (bitrate_kbps as f64 * 1000.0);
+    warn!("Unknown track length, estimated {:.1}s from file size", estimate);
+    // At least one beat's worth, so the grid is never empty
+    estimate.max(1.0)
 }
 
 /// Convert decoded audio to mono f32
@@ -369,27 +902,137 @@ fn append_as_mono_f32(buffer: &AudioBufferRef, output: &mut Vec<f32>) {
     }
 }
 
-/// Detect BPM using autocorrelation
-fn detect_bpm(samples: &[f32], sample_rate: u32) -> anyhow::Result<f64> {
-    if samples.is_empty() {
-        return Ok(120.0); // Default
+/// Append a two-channel buffer as separate left/right f32 samples
+fn append_stereo_f32(buffer: &AudioBufferRef, left: &mut Vec<f32>, right: &mut Vec<f32>) {
+    match buffer {
+        AudioBufferRef::F32(buf) => {
+            left.extend_from_slice(buf.chan(0));
+            right.extend_from_slice(buf.chan(1));
+        }
+        AudioBufferRef::S16(buf) => {
+            left.extend(buf.chan(0).iter().map(|&s| s as f32 / 32768.0));
+            right.extend(buf.chan(1).iter().map(|&s| s as f32 / 32768.0));
+        }
+        AudioBufferRef::S32(buf) => {
+            left.extend(buf.chan(0).iter().map(|&s| s as f32 / 2147483648.0));
+            right.extend(buf.chan(1).iter().map(|&s| s as f32 / 2147483648.0));
+        }
+        _ => {
+            debug!("Unsupported sample format, skipping");
+        }
     }
-    
-    // Use first ~30 seconds for BPM detection
-    let analysis_samples = std::cmp::min(samples.len(), (sample_rate * 30) as usize);
-    let samples = &samples[..analysis_samples];
-    
-    // Onset detection via envelope following
-    let hop_size = sample_rate as usize / 100; // 10ms hops
-    let mut envelope = Vec::new();
-    
-    for chunk in samples.chunks(hop_size) {
-        let rms: f32 = (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt();
-        envelope.push(rms);
+}
+
+/// Tempo assumed when neither detection nor tags provide one
+const DEFAULT_BPM: f64 = 120.0;
+
+/// Detected tempo and how sharply it stood out
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BpmResult {
+    bpm: f64,
+    /// Autocorrelation peak height above the mean over the searched tempo
+    /// range, relative to the peak (0 = flat, 1 = single sharp peak)
+    confidence: f32,
+}
+
+impl BpmResult {
+    /// Fallback tempo when there is nothing to measure
+    fn guess() -> Self {
+        Self { bpm: DEFAULT_BPM, confidence: 0.0 }
     }
-    
+}
+
+/// Half or double tempo correlating at least this well (relative to the
+/// best lag) is an equally plausible reading of the beat
+const OCTAVE_AMBIGUITY: f32 = 0.5;
+
+/// Usual tempos of genres that often detect at half or double speed,
+/// matched as substrings of the lowercased genre tag (first match wins)
+const GENRE_TEMPOS: &[(&str, f64)] = &[
+    ("drum & bass", 174.0),
+    ("drum and bass", 174.0),
+    ("drum n bass", 174.0),
+    ("dnb", 174.0),
+    ("jungle", 170.0),
+    ("footwork", 160.0),
+    ("dubstep", 140.0),
+    ("grime", 140.0),
+    ("trance", 138.0),
+    ("techno", 130.0),
+    ("house", 124.0),
+    ("hip hop", 90.0),
+    ("hip-hop", 90.0),
+    ("downtempo", 95.0),
+];
+
+/// Expected tempo for a genre tag, if it's one with a characteristic tempo
+fn genre_tempo_hint(genre: &str) -> Option<f64> {
+    let genre = genre.to_lowercase();
+    GENRE_TEMPOS.iter()
+        .find(|(name, _)| genre.contains(name))
+        .map(|&(_, bpm)| bpm)
+}
+
+/// Onset envelope (10 ms RMS hops) of the audio, built as it is decoded
+///
+/// At 100 values per second it stays small for any track length.
+struct OnsetEnvelope {
+    hop_size: usize,
+    /// Sum of squares and length of the hop still being filled
+    hop_sum_sq: f32,
+    hop_len: usize,
+    values: Vec<f32>,
+}
+
+impl OnsetEnvelope {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            hop_size: (sample_rate as usize / 100).max(1), // 10ms hops
+            hop_sum_sq: 0.0,
+            hop_len: 0,
+            values: Vec::new(),
+        }
+    }
+
+    /// Feed the next mono samples
+    fn push(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.hop_sum_sq += sample * sample;
+            self.hop_len += 1;
+            if self.hop_len == self.hop_size {
+                self.end_hop();
+            }
+        }
+    }
+
+    fn end_hop(&mut self) {
+        self.values.push((self.hop_sum_sq / self.hop_len as f32).sqrt());
+        self.hop_sum_sq = 0.0;
+        self.hop_len = 0;
+    }
+
+    /// The envelope, including a trailing partial hop
+    fn finish(mut self) -> Vec<f32> {
+        if self.hop_len > 0 {
+            self.end_hop();
+        }
+        self.values
+    }
+}
+
+/// Detect BPM using autocorrelation of the whole track's onset envelope,
+/// rounded to multiples of `precision`
+///
+/// With a `tempo_hint`, half and double the detected tempo are considered
+/// too, and whichever plausible one lies closest to the hint wins.
+fn detect_bpm(
+    onsets: OnsetEnvelope,
+    precision: f64,
+    tempo_hint: Option<f64>,
+) -> anyhow::Result<BpmResult> {
+    let mut envelope = onsets.finish();
     if envelope.is_empty() {
-        return Ok(120.0);
+        return Ok(BpmResult::guess());
     }
     
     // Normalize envelope
@@ -406,32 +1049,77 @@ fn detect_bpm(samples: &[f32], sample_rate: u32) -> anyhow::Result<f64> {
     let min_lag = (env_rate * 60.0 / 200.0) as usize; // 200 BPM
     let max_lag = (env_rate * 60.0 / 60.0) as usize;  // 60 BPM
     
-    let mut best_bpm = 120.0;
+    let correlation_at = |lag: usize| -> f32 {
+        let count = envelope.len() - lag;
+        let sum: f32 = (0..count).map(|i| envelope[i] * envelope[i + lag]).sum();
+        sum / count as f32
+    };
+
+    let mut best_lag = None;
     let mut best_correlation = 0.0f32;
+    let mut correlation_sum = 0.0f32;
+    let mut lags = 0usize;
+    let search = min_lag..=max_lag.min(envelope.len() - 1);
     
-    for lag in min_lag..=max_lag.min(envelope.len() - 1) {
-        let mut correlation = 0.0f32;
-        let count = envelope.len() - lag;
-        
-        for i in 0..count {
-            correlation += envelope[i] * envelope[i + lag];
-        }
-        correlation /= count as f32;
-        
+    for lag in search.clone() {
+        let correlation = correlation_at(lag);
+        correlation_sum += correlation;
+        lags += 1;
         if correlation > best_correlation {
             best_correlation = correlation;
-            best_bpm = env_rate * 60.0 / lag as f64;
+            best_lag = Some(lag);
         }
     }
-    
-    // Round to 0.5 BPM precision
-    let rounded = (best_bpm * 2.0).round() / 2.0;
-    
-    Ok(rounded)
+
+    let Some(lag) = best_lag else {
+        return Ok(BpmResult::guess());
+    };
+
+    // A steady beat gives one tall peak over a low floor; noise or beatless
+    // audio correlates about equally at every lag
+    let mean_correlation = correlation_sum / lags as f32;
+    let confidence = ((best_correlation - mean_correlation) / best_correlation).clamp(0.0, 1.0);
+
+    // Octave errors: half or double tempo can correlate nearly as well, so
+    // let the hint choose among the plausible ones (each at its local peak,
+    // and still inside the 60-200 BPM search range)
+    let (lag, peak_correlation) = match tempo_hint.filter(|hint| *hint > 0.0) {
+        Some(hint) => {
+            let distance = |lag: usize| (env_rate * 60.0 / lag as f64 / hint).ln().abs();
+            [0.5, 1.0, 2.0].iter()
+                .filter_map(|&tempo_factor| {
+                    let center = (lag as f64 / tempo_factor).round() as usize;
+                    (center.saturating_sub(1)..=center + 1)
+                        .filter(|l| search.contains(l))
+                        .map(|l| (l, correlation_at(l)))
+                        .max_by(|a, b| a.1.total_cmp(&b.1))
+                })
+                .filter(|&(_, correlation)| correlation >= best_correlation * OCTAVE_AMBIGUITY)
+                .min_by(|a, b| distance(a.0).total_cmp(&distance(b.0)))
+                .unwrap_or((lag, best_correlation))
+        }
+        None => (lag, best_correlation),
+    };
+
+    // Whole-lag steps are ~2.5 BPM apart at 128 BPM; fit a parabola through
+    // the peak and its neighbours to estimate the fractional lag
+    let mut peak_lag = lag as f64;
+    if lag > min_lag && lag + 1 < envelope.len() {
+        let (left, right) = (correlation_at(lag - 1), correlation_at(lag + 1));
+        let curvature = left - 2.0 * peak_correlation + right;
+        if curvature < 0.0 {
+            peak_lag += (0.5 * (left - right) / curvature) as f64;
+        }
+    }
+
+    Ok(BpmResult {
+        bpm: round_bpm(env_rate * 60.0 / peak_lag, precision),
+        confidence,
+    })
 }
 
 /// Find first beat position in milliseconds
-fn detect_first_beat(samples: &[f32], sample_rate: u32, bpm: f64) -> f64 {
+fn detect_first_beat(samples: &[f32], sample_rate: u32, _bpm: f64) -> f64 {
     if samples.is_empty() {
         return 0.0;
     }
@@ -467,46 +1155,105 @@ fn detect_first_beat(samples: &[f32], sample_rate: u32, bpm: f64) -> f64 {
     0.0
 }
 
+/// Tag values read from an audio file
+struct TagMetadata {
+    title: String,
+    artist: String,
+    /// Explicit artist sort name (TSOP / ARTISTSORT)
+    artist_sort: Option<String>,
+    album: Option<String>,
+    /// Album artist (TPE2 / ALBUMARTIST / aART)
+    album_artist: Option<String>,
+    /// Compilation flag (TCMP / COMPILATION / cpil)
+    compilation: bool,
+    genre: Option<String>,
+    year: Option<u16>,
+    /// Full ISO release date (YYYY-MM-DD) when the tag has one
+    release_date: Option<String>,
+    track_number: Option<u32>,
+    /// Encoder delay in samples from an iTunSMPB tag
+    encoder_delay: Option<u32>,
+    /// Tempo from a TBPM / BPM tag
+    bpm: Option<f64>,
+    /// Serato cues from a SERATO_MARKERS_V2 comment (FLAC/Ogg)
+    serato_cues: Option<Vec<CuePoint>>,
+}
+
 /// Extract metadata from audio file
 fn extract_metadata(
     format: &mut Box<dyn symphonia::core::formats::FormatReader>,
     path: &Path,
-) -> (String, String, Option<String>, Option<String>, Option<u16>, Option<u32>) {
-    let mut title = path.file_stem()
-        .and_then(|n| n.to_str())
-        .unwrap_or("Unknown")
-        .to_string();
-    let mut artist = "Unknown Artist".to_string();
-    let mut album = None;
-    let mut genre = None;
-    let mut year = None;
-    let mut track_number = None;
+) -> TagMetadata {
+    let mut tags = TagMetadata {
+        title: path.file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Unknown")
+            .to_string(),
+        artist: "Unknown Artist".to_string(),
+        artist_sort: None,
+        album: None,
+        album_artist: None,
+        compilation: false,
+        genre: None,
+        year: None,
+        release_date: None,
+        track_number: None,
+        encoder_delay: None,
+        bpm: None,
+        serato_cues: None,
+    };
     
     // Try to get metadata from format
     if let Some(metadata) = format.metadata().current() {
         for tag in metadata.tags() {
             match tag.std_key {
                 Some(symphonia::core::meta::StandardTagKey::TrackTitle) => {
-                    title = tag.value.to_string();
+                    tags.title = tag.value.to_string();
                 }
                 Some(symphonia::core::meta::StandardTagKey::Artist) => {
-                    artist = tag.value.to_string();
+                    tags.artist = tag.value.to_string();
+                }
+                Some(symphonia::core::meta::StandardTagKey::SortArtist) => {
+                    tags.artist_sort = Some(tag.value.to_string());
                 }
                 Some(symphonia::core::meta::StandardTagKey::Album) => {
-                    album = Some(tag.value.to_string());
+                    tags.album = Some(tag.value.to_string());
+                }
+                Some(symphonia::core::meta::StandardTagKey::AlbumArtist) => {
+                    tags.album_artist = Some(tag.value.to_string());
+                }
+                Some(symphonia::core::meta::StandardTagKey::Compilation) => {
+                    tags.compilation = parse_flag(&tag.value.to_string());
                 }
                 Some(symphonia::core::meta::StandardTagKey::Genre) => {
-                    genre = Some(tag.value.to_string());
+                    tags.genre = Some(tag.value.to_string());
                 }
-                Some(symphonia::core::meta::StandardTagKey::Date) => {
-                    // Try to parse year
-                    if let Ok(y) = tag.value.to_string().get(..4).unwrap_or("").parse::<u16>() {
-                        year = Some(y);
+                Some(symphonia::core::meta::StandardTagKey::Date)
+                | Some(symphonia::core::meta::StandardTagKey::ReleaseDate) => {
+                    let value = tag.value.to_string();
+                    if let Some(y) = parse_year(&value) {
+                        tags.year = Some(y);
+                    }
+                    if let Some(date) = parse_release_date(&value) {
+                        tags.release_date = Some(date);
                     }
                 }
                 Some(symphonia::core::meta::StandardTagKey::TrackNumber) => {
                     if let Ok(n) = tag.value.to_string().parse::<u32>() {
-                        track_number = Some(n);
+                        tags.track_number = Some(n);
+                    }
+                }
+                Some(symphonia::core::meta::StandardTagKey::Bpm) => {
+                    tags.bpm = tag.value.to_string().trim().parse::<f64>().ok()
+                        .filter(|bpm| *bpm > 0.0);
+                }
+                _ if tag.key.contains("iTunSMPB") => {
+                    tags.encoder_delay = parse_itunsmpb(&tag.value.to_string());
+                }
+                _ if tag.key.eq_ignore_ascii_case(VORBIS_MARKERS2_KEY) => {
+                    tags.serato_cues = parse_vorbis_markers2(&tag.value.to_string());
+                    if tags.serato_cues.is_none() {
+                        warn!("Ignoring unreadable Serato markers in {:?}", path);
                     }
                 }
                 _ => {}
@@ -514,11 +1261,160 @@ fn extract_metadata(
         }
     }
     
-    (title, artist, album, genre, year, track_number)
+    tags
+}
+
+/// Boolean tag value: "1", "true" or "yes" (any case) is set
+fn parse_flag(value: &str) -> bool {
+    matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+/// Extract the year from a date tag
+///
+/// Takes the first four-digit run that looks like a year, wherever it sits
+/// ("2024-06-15", "15/06/2024", "June 2024", "20240615"). Without one, a
+/// lone two-digit year ("'99", "99") or the last field of a DD/MM/YY date is
+/// expanded: below 50 is 20xx, the rest 19xx.
+fn parse_year(value: &str) -> Option<u16> {
+    let runs: Vec<&str> = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|run| !run.is_empty())
+        .collect();
+
+    let full = runs.iter()
+        .filter(|run| run.len() >= 4)
+        .filter_map(|run| run[..4].parse::<u16>().ok())
+        .find(|y| (1900..=2099).contains(y));
+    if full.is_some() {
+        return full;
+    }
+
+    let short = match runs.as_slice() {
+        [yy] | [_, _, yy] if yy.len() == 2 => yy.parse::<u16>().ok()?,
+        _ => return None,
+    };
+    Some(if short < 50 { 2000 + short } else { 1900 + short })
+}
+
+/// Extract a full YYYY-MM-DD date from a date tag (e.g. "2024-06-15T00:00:00Z")
+///
+/// Year-only tags return None; the PDB writer falls back to January 1st.
+fn parse_release_date(value: &str) -> Option<String> {
+    let date = value.trim().get(..10)?;
+    let bytes = date.as_bytes();
+    let digits_ok = bytes.iter().enumerate()
+        .all(|(i, b)| if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() });
+    if !digits_ok {
+        return None;
+    }
+
+    let month: u8 = date[5..7].parse().ok()?;
+    let day: u8 = date[8..10].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    Some(date.to_string())
+}
+
+/// Parse an RFC 3339 timestamp (e.g. "2024-06-15T02:00:00Z" or with a
+/// "+02:00" offset; fractional seconds are ignored)
+pub fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let (date, rest) = value.split_once(['T', 't', ' '])?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Split the clock from the UTC offset
+    let (time, offset_secs) = if let Some(time) = rest.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let sign_pos = rest.rfind(['+', '-'])?;
+        let (time, offset) = rest.split_at(sign_pos);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let offset_secs = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+        (time, sign * offset_secs)
+    };
+
+    let time = time.split('.').next()?;
+    let mut time_parts = time.splitn(3, ':');
+    let hour: i64 = time_parts.next()?.parse().ok()?;
+    let minute: i64 = time_parts.next()?.parse().ok()?;
+    let second: i64 = time_parts.next()?.parse().ok()?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400
+        + hour * 3600 + minute * 60 + second - offset_secs;
+    if secs >= 0 {
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    }
+}
+
+/// Cheap summary of a directory's contents: every file's path, size and
+/// modification time, hashed without reading any audio
+///
+/// Equal values mean a new `analyze_directory` run would see the same
+/// files, so a result taken at that fingerprint can be reused.
+pub fn directory_fingerprint(dir: &Path) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for entry in WalkDir::new(dir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let Ok(metadata) = entry.metadata() else { continue };
+        if metadata.is_file() {
+            entry.path().hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Summary of the Navidrome state `analyze_directory` reads: the server
+/// settings plus every playlist's id, name, size and change time
+///
+/// One listing request, no tracks fetched. 0 without Navidrome; a server
+/// that can't be reached is summarized by its settings alone.
+pub async fn navidrome_fingerprint(config: &Config) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let Some(nav_config) = &config.navidrome else {
+        return 0;
+    };
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (&nav_config.url, &nav_config.user, &nav_config.path_prefix).hash(&mut hasher);
+    let client = NavidromeClient::new(&nav_config.url, &nav_config.user, &nav_config.pass);
+    match client.get_playlists().await {
+        Ok(mut playlists) => {
+            playlists.sort_by(|a, b| a.id.cmp(&b.id));
+            for playlist in playlists {
+                (playlist.id, playlist.name, playlist.song_count, playlist.duration_secs, playlist.changed)
+                    .hash(&mut hasher);
+            }
+        }
+        Err(e) => debug!("Navidrome unreachable for the library fingerprint: {}", e),
+    }
+    hasher.finish()
 }
 
 /// Check if path is a supported audio file
-fn is_audio_file(path: &Path) -> bool {
+pub fn is_audio_file(path: &Path) -> bool {
     if !path.is_file() {
         return false;
     }
@@ -530,11 +1426,261 @@ fn is_audio_file(path: &Path) -> bool {
     matches!(ext.as_deref(), Some("mp3" | "flac" | "wav" | "aiff" | "aif" | "m4a" | "aac"))
 }
 
+/// 16-bit PCM WAV with a 440 Hz tone on every channel (test fixture)
+#[cfg(test)]
+pub(crate) fn tone_wav(channels: u16, seconds: u32) -> Vec<u8> {
+    let sample_rate = 44100u32;
+    let frames = sample_rate * seconds;
+    let block_align = channels * 2;
+    let data_len = frames * block_align as u32;
+
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for i in 0..frames {
+        let t = i as f64 / sample_rate as f64;
+        let sample = ((2.0 * std::f64::consts::PI * 440.0 * t).sin() * 8000.0) as i16;
+        for _ in 0..channels {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+    wav
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempfile::TempDir;
-    use std::fs::File;
+    use rekordbox_core::compute_file_hash;
+
+    fn cached_analysis(file_hash: u64) -> TrackAnalysis {
+        TrackAnalysis {
+            id: 1,
+            file_path: "/Contents/track.mp3".into(),
+            title: "Track".into(),
+            artist: "Artist".into(),
+            duration_secs: 60.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 320,
+            bpm: 128.0,
+            beat_grid: BeatGrid::constant_tempo(128.0, 0.0, 60_000.0),
+            file_hash,
+            file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    fn onsets(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+        let mut onsets = OnsetEnvelope::new(sample_rate);
+        onsets.push(samples);
+        onsets
+    }
+
+    #[test]
+    fn test_serato_cues_move_to_pioneer_timeline() {
+        let mut analysis = cached_analysis(1);
+        analysis.cue_points.push(CuePoint { hot_cue: 2, time_ms: 500.0, ..Default::default() });
+        add_cues(&mut analysis, vec![
+            CuePoint { hot_cue: 1, time_ms: 1_234.0, ..Default::default() },
+            CuePoint { hot_cue: 2, time_ms: 9_000.0, ..Default::default() },
+            CuePoint { hot_cue: 0, time_ms: 30_000.0, loop_ms: 8_000.0, ..Default::default() },
+        ]);
+        // Slot B was already set
+        let times: Vec<f64> = analysis.cue_points.iter().map(|c| c.time_ms).collect();
+        assert_eq!(times, [500.0, 1_234.0, 30_000.0]);
+
+        // LAME's usual 576 samples plus the 529-sample decoder delay, at
+        // 44.1 kHz: Serato's 1234 ms is 1259.06 ms on the player
+        apply_encoder_delay(&mut analysis, 576 + 529);
+        let delay_ms = 1105.0 / 44.1;
+        assert!((analysis.cue_points[1].time_ms - (1_234.0 + delay_ms)).abs() < 1e-9);
+        assert!((analysis.cue_points[1].time_ms - 1_259.06).abs() < 0.01);
+        assert_eq!(analysis.cue_points[2].loop_ms, 8_000.0);
+    }
+
+    #[test]
+    fn test_serato_cues_shifted_on_lame_mp3() {
+        // Serato markers, a LAME Info frame (576 priming samples) and a
+        // second of silent MPEG-1 Layer III frames
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("cued.mp3");
+        let mut data = crate::serato::id3_with_markers();
+        data.extend(crate::gapless::lame_frame(576, 960));
+        for _ in 0..40 {
+            let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+            frame.resize(417, 0);
+            data.extend(frame);
+        }
+        std::fs::write(&path, data).unwrap();
+
+        let analysis = analyze_track(&path, HashedSource::open(&path).unwrap(), 1, "/Contents/cued.mp3".into(), &TrackOptions::default()).unwrap();
+        assert_eq!(analysis.file_type, FileType::Mp3);
+        let cues: Vec<(u8, f64)> = analysis.cue_points.iter().map(|c| (c.hot_cue, c.time_ms)).collect();
+        assert_eq!(cues.len(), 3);
+        // Serato's 1234 ms is 1259.06 ms on the player: 576 + 529 samples later
+        assert_eq!(cues[0].0, 1);
+        assert!((cues[0].1 - 1_259.06).abs() < 0.01, "{:?}", cues);
+        assert!((cues[1].1 - (60_000.0 + 1105.0 / 44.1)).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_rekordbox_xml_cues_replace_file_cues() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let path = music_dir.join("a.mp3");
+        std::fs::write(&path, [7u8; 64]).unwrap();
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+        let mut cached = cached_analysis(compute_file_hash(&path).unwrap());
+        cached.cue_points.push(CuePoint { hot_cue: 1, time_ms: 1_000.0, ..Default::default() });
+        cache.put(&cached, TrackOptions::default().cache_settings()).unwrap();
+
+        let xml = tmp.path().join("rekordbox.xml");
+        std::fs::write(&xml, format!(r#"<DJ_PLAYLISTS><COLLECTION>
+  <TRACK TrackID="1" Location="file://localhost{}">
+    <POSITION_MARK Type="0" Start="32.500" Num="2"/>
+  </TRACK>
+</COLLECTION></DJ_PLAYLISTS>"#, path.display())).unwrap();
+        let config = Config { rekordbox_xml: Some(xml), ..Config::for_test(tmp.path()) };
+
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        let cues: Vec<(u8, f64)> = result.tracks[0].cue_points.iter().map(|c| (c.hot_cue, c.time_ms)).collect();
+        assert_eq!(cues, [(3, 32_500.0)]);
+        // Applied on top of the cache, which keeps the file's own cues
+        assert_eq!(cache.get(cached.file_hash, cache_settings(&config)).unwrap().cue_points.len(), 1);
+    }
+
+    #[test]
+    fn test_bpm_confidence_click_vs_noise() {
+        let rate = 8000u32;
+        // 10 ms clicks every 0.5 s (120 BPM)
+        let clicks: Vec<f32> = (0..rate * 20)
+            .map(|i| if i % (rate / 2) < rate / 100 { 0.9 } else { 0.0 })
+            .collect();
+        // Deterministic white noise (xorshift)
+        let mut state = 0x2545_F491u32;
+        let noise: Vec<f32> = (0..rate * 20)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect();
+
+        let click = detect_bpm(onsets(&clicks, rate), 0.5, None).unwrap();
+        let hiss = detect_bpm(onsets(&noise, rate), 0.5, None).unwrap();
+        assert_eq!(click.bpm, 120.0);
+        assert!(click.confidence > 0.6, "click confidence {}", click.confidence);
+        assert!(hiss.confidence < 0.2, "noise confidence {}", hiss.confidence);
+        assert_eq!(detect_bpm(onsets(&[], rate), 0.5, None).unwrap().confidence, 0.0);
+    }
+
+    #[test]
+    fn test_tempo_hint_resolves_octave() {
+        let rate = 44100;
+        // Clicks at 174 BPM with every other one at half level: the accents
+        // alone make a steady 87 BPM pulse
+        let period = rate as f64 * 60.0 / 174.0;
+        let clicks: Vec<f32> = (0..rate * 20)
+            .map(|i| {
+                let beat = (i as f64 / period) as usize;
+                let in_click = i as f64 - beat as f64 * period < rate as f64 / 100.0;
+                match (in_click, beat % 2) {
+                    (false, _) => 0.0,
+                    (true, 0) => 0.9,
+                    (true, _) => 0.45,
+                }
+            })
+            .collect();
+
+        assert_eq!(detect_bpm(onsets(&clicks, rate), 0.5, None).unwrap().bpm, 87.0);
+        assert_eq!(detect_bpm(onsets(&clicks, rate), 0.5, Some(174.0)).unwrap().bpm, 174.0);
+        assert_eq!(detect_bpm(onsets(&clicks, rate), 0.5, Some(90.0)).unwrap().bpm, 87.0);
+        // Half of 87 lies below the search range, so a slow hint can't pick it
+        assert_eq!(detect_bpm(onsets(&clicks, rate), 0.5, Some(40.0)).unwrap().bpm, 87.0);
+
+        assert_eq!(genre_tempo_hint("Drum & Bass"), Some(174.0));
+        assert_eq!(genre_tempo_hint("Deep House"), Some(124.0));
+        assert_eq!(genre_tempo_hint("Classical"), None);
+    }
+
+    #[test]
+    fn test_metadata_only_skips_dsp() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("tone.wav");
+        std::fs::write(&path, tone_wav(2, 3)).unwrap();
+
+        let options = TrackOptions { metadata_only: true, ..Default::default() };
+        let analysis = analyze_track(&path, HashedSource::open(&path).unwrap(), 1, "/Contents/tone.wav".into(), &options).unwrap();
+        assert!(analysis.waveform.preview.columns.is_empty());
+        assert!(analysis.waveform.detail.entries.is_empty());
+        assert!(analysis.waveform.color_preview.columns.is_empty());
+        // No BPM tag on a bare WAV, so the default tempo
+        assert_eq!(analysis.bpm, DEFAULT_BPM);
+        assert_eq!(analysis.title, "tone");
+        assert!((analysis.duration_secs - 3.0).abs() < 0.01, "{}", analysis.duration_secs);
+        assert!(!analysis.beat_grid.beats.is_empty());
+    }
+
+    #[test]
+    fn test_channel_count_recorded() {
+        let tmp = TempDir::new().unwrap();
+        let stereo = tmp.path().join("stereo.wav");
+        let mono = tmp.path().join("mono.wav");
+        std::fs::write(&stereo, tone_wav(2, 1)).unwrap();
+        std::fs::write(&mono, tone_wav(1, 1)).unwrap();
+
+        let analysis = analyze_track(&stereo, HashedSource::open(&stereo).unwrap(), 1, "/Contents/stereo.wav".into(), &TrackOptions::default()).unwrap();
+        assert_eq!(analysis.channels, 2);
+        assert_eq!(analysis.bitrate, 1411);
+
+        let analysis = analyze_track(&mono, HashedSource::open(&mono).unwrap(), 2, "/Contents/mono.wav".into(), &TrackOptions::default()).unwrap();
+        assert_eq!(analysis.channels, 1);
+        assert_eq!(analysis.bitrate, 705);
+    }
+
+    #[test]
+    fn test_hashed_source_matches_compute_file_hash() {
+        let tmp = TempDir::new().unwrap();
+        let small = tmp.path().join("small.wav");
+        let large = tmp.path().join("large.bin");
+        std::fs::write(&small, tone_wav(2, 1)).unwrap();
+        let data: Vec<u8> = (0..HASH_SAMPLE_SIZE + 5000).map(|i| (i * 7 % 251) as u8).collect();
+        std::fs::write(&large, &data).unwrap();
+
+        for path in [&small, &large] {
+            let source = HashedSource::open(path).unwrap();
+            assert_eq!(source.hash(), compute_file_hash(path).unwrap());
+        }
+
+        // Reads see the whole file, across the in-memory head and after seeks
+        let mut source = HashedSource::open(&large).unwrap();
+        let mut all = Vec::new();
+        source.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        let mut buf = [0u8; 16];
+        source.seek(SeekFrom::Start(HASH_SAMPLE_SIZE as u64 - 8)).unwrap();
+        source.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[HASH_SAMPLE_SIZE - 8..HASH_SAMPLE_SIZE + 8]);
+        source.seek(SeekFrom::End(-4)).unwrap();
+        source.seek(SeekFrom::Current(-12)).unwrap();
+        source.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[data.len() - 16..]);
+        assert!(source.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+    }
 
     #[test]
     fn test_is_audio_file() {
@@ -557,4 +1703,297 @@ mod tests {
         // Non-existent file should return false
         assert!(!is_audio_file(Path::new("nonexistent.mp3")));
     }
+
+    #[test]
+    fn test_parse_release_date() {
+        assert_eq!(parse_release_date("2024-06-15"), Some("2024-06-15".to_string()));
+        assert_eq!(parse_release_date("2024-06-15T00:00:00Z"), Some("2024-06-15".to_string()));
+        assert_eq!(parse_release_date("2024"), None);
+        assert_eq!(parse_release_date("2024-13-01"), None);
+        assert_eq!(parse_release_date("15/06/2024"), None);
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2024"), Some(2024));
+        assert_eq!(parse_year("2024-06-15T00:00:00Z"), Some(2024));
+        assert_eq!(parse_year("2024/06"), Some(2024));
+        assert_eq!(parse_year("15/06/2024"), Some(2024));
+        assert_eq!(parse_year("June 2024"), Some(2024));
+        assert_eq!(parse_year("20240615"), Some(2024));
+        assert_eq!(parse_year("'99"), Some(1999));
+        assert_eq!(parse_year("07"), Some(2007));
+        assert_eq!(parse_year("15/06/99"), Some(1999));
+        assert_eq!(parse_year("0000"), None);
+        assert_eq!(parse_year("06/15"), None);
+        assert_eq!(parse_year("unknown"), None);
+        assert_eq!(parse_year(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_starting_track_id_offsets_tracks_and_playlists() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        let set_dir = music_dir.join("Friday");
+        std::fs::create_dir_all(&set_dir).unwrap();
+
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+        for (i, name) in ["a.mp3", "b.mp3", "c.mp3"].iter().enumerate() {
+            let path = set_dir.join(name);
+            std::fs::write(&path, vec![i as u8; 64]).unwrap();
+            // Pre-populate the cache so no real decoding is needed
+            cache.put(&cached_analysis(compute_file_hash(&path).unwrap()), TrackOptions::default().cache_settings()).unwrap();
+        }
+
+        let config = Config {
+            starting_track_id: 1000,
+            ..Config::for_test(tmp.path())
+        };
+
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+
+        let ids: Vec<u32> = result.tracks.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![1000, 1001, 1002]);
+        assert_eq!(result.playlists[&vec!["Friday".to_string()]], vec![1000, 1001, 1002]);
+
+        // Files keep their ids when more are added, and a file whose
+        // sampled hash collides with another's gets one of its own
+        let ids_by_hash = |tracks: &[TrackAnalysis]| {
+            let mut ids: Vec<(u64, u32)> = tracks.iter().map(|t| (t.file_hash, t.id)).collect();
+            ids.sort();
+            ids
+        };
+        let before = ids_by_hash(&result.tracks);
+        std::fs::write(set_dir.join("0.mp3"), vec![9u8; 64]).unwrap();
+        cache.put(&cached_analysis(compute_file_hash(set_dir.join("0.mp3")).unwrap()), TrackOptions::default().cache_settings()).unwrap();
+        std::fs::copy(set_dir.join("a.mp3"), set_dir.join("a copy.mp3")).unwrap();
+
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        let after = ids_by_hash(&result.tracks);
+        assert!(before.iter().all(|pair| after.contains(pair)), "{:?} -> {:?}", before, after);
+        let mut ids: Vec<u32> = after.iter().map(|&(_, id)| id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1000, 1001, 1002, 1003, 1004]);
+
+        let again = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(ids_by_hash(&again.tracks), after);
+
+        // A damaged id file stops the run instead of renumbering
+        std::fs::write(tmp.path().join("cache/track_ids.json"), b"{\"assigned\":").unwrap();
+        assert!(analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sidecar_applied_on_cache_hits() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let path = music_dir.join("a.mp3");
+        std::fs::write(&path, [7u8; 64]).unwrap();
+
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+        cache.put(&cached_analysis(compute_file_hash(&path).unwrap()), TrackOptions::default().cache_settings()).unwrap();
+        let config = Config::for_test(tmp.path());
+        let sidecar = crate::sidecar::sidecar_path(&path);
+
+        std::fs::write(&sidecar, r#"{"bpm": 124.0}"#).unwrap();
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(result.counts.cache_hits, 1);
+        assert_eq!(result.tracks[0].bpm, 124.0);
+
+        // Removing the sidecar restores the analyzed value
+        std::fs::remove_file(&sidecar).unwrap();
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(result.tracks[0].bpm, 128.0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_hits_need_matching_settings() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let path = music_dir.join("a.mp3");
+        std::fs::write(&path, [7u8; 64]).unwrap();
+
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+        let config = Config::for_test(tmp.path());
+        cache.put(&cached_analysis(compute_file_hash(&path).unwrap()), cache_settings(&config)).unwrap();
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(result.counts.cache_hits, 1);
+
+        // Other settings re-analyze (which fails here: the file isn't audio)
+        for config in [
+            Config { bpm_precision: 0.01, ..config.clone() },
+            Config { silence_threshold_db: Some(-60.0), ..config.clone() },
+            Config { waveform: WaveformConfig { normalize: true, ..config.waveform }, ..config.clone() },
+        ] {
+            let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+            assert_eq!((result.counts.cache_hits, result.counts.skipped), (0, 1));
+        }
+    }
+
+    #[test]
+    fn test_usb_file_path_keeps_folders() {
+        let music_dir = Path::new("/srv/music");
+        let transcoded = Path::new("/var/cache/transcoded");
+        let roots = [music_dir, transcoded];
+        assert_eq!(usb_file_path(&music_dir.join("House/Deep/a.mp3"), &roots), "/Contents/House/Deep/a.mp3");
+        assert_eq!(usb_file_path(&music_dir.join("a.mp3"), &roots), "/Contents/a.mp3");
+        assert_eq!(usb_file_path(&transcoded.join("House/b.opus.aiff"), &roots), "/Contents/House/b.opus.aiff");
+        assert_eq!(usb_file_path(Path::new("/elsewhere/c.mp3"), &roots), "/Contents/c.mp3");
+    }
+
+    #[test]
+    fn test_cached_analysis_keeps_folders() {
+        let tmp = TempDir::new().unwrap();
+        let config = Config::for_test(tmp.path());
+        let path = config.music_dir.join("House/tone.wav");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, tone_wav(2, 1)).unwrap();
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+
+        let (hash, analyzed) = cache_file(&config, &cache, &path).unwrap();
+        assert!(analyzed);
+        let cached = cache.get(hash, cache_settings(&config)).unwrap();
+        assert_eq!(cached.file_path, "/Contents/House/tone.wav");
+    }
+
+    #[tokio::test]
+    async fn test_m3u_playlists_imported() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        let set_dir = music_dir.join("Friday");
+        std::fs::create_dir_all(&set_dir).unwrap();
+
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+        for (i, name) in ["a.mp3", "b.mp3"].iter().enumerate() {
+            let path = set_dir.join(name);
+            std::fs::write(&path, vec![i as u8; 64]).unwrap();
+            cache.put(&cached_analysis(compute_file_hash(&path).unwrap()), TrackOptions::default().cache_settings()).unwrap();
+        }
+        // Reversed order, and a second list named like the folder
+        std::fs::write(music_dir.join("Warmup.m3u8"), "#EXTM3U\nFriday\\b.mp3\nFriday/a.mp3\n").unwrap();
+        std::fs::write(music_dir.join("Friday.m3u"), "Friday/b.mp3\n").unwrap();
+
+        let config = Config::for_test(tmp.path());
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+
+        let id = |name: &str| result.tracks.iter()
+            .find(|t| t.file_hash == compute_file_hash(set_dir.join(name)).unwrap())
+            .unwrap().id;
+        assert_eq!(result.playlists[&vec!["Warmup".to_string()]], vec![id("b.mp3"), id("a.mp3")]);
+        assert_eq!(result.playlists[&vec!["Friday".to_string()]], vec![id("b.mp3")]);
+    }
+
+    #[test]
+    fn test_playlist_path_from_folders_or_navidrome() {
+        let music_dir = Path::new("/srv/music");
+        let navidrome = HashMap::from([("Rock/back_in_black.mp3".to_string(), "AC/DC".to_string())]);
+        let path = |relative: &str| determine_playlist_path(&music_dir.join(relative), music_dir, &navidrome);
+
+        assert_eq!(path("House/Deep/a.mp3"), Some(vec!["House".to_string(), "Deep".to_string()]));
+        // A Navidrome playlist name is one name, slashes and all
+        assert_eq!(path("Rock/back_in_black.mp3"), Some(vec!["AC/DC".to_string()]));
+        assert_eq!(path("a.mp3"), None);
+    }
+
+    #[test]
+    fn test_resolve_duration_unknown_length() {
+        // Decoded frames win when available
+        assert_eq!(resolve_duration(441_000, None, 44100, 1_000, 320), 10.0);
+        // Container frame count when nothing decoded
+        assert_eq!(resolve_duration(0, Some(88_200), 44100, 1_000, 320), 2.0);
+
+        // Neither: 4.8 MB at 320 kbps is two minutes
+        let estimated = resolve_duration(0, None, 44100, 4_800_000, 320);
+        assert!((estimated - 120.0).abs() < 0.01, "got {}", estimated);
+        let grid = BeatGrid::constant_tempo(128.0, 0.0, estimated * 1000.0);
+        assert!(!grid.beats.is_empty());
+
+        // Unknown bitrate still gives a non-zero length
+        assert!(resolve_duration(0, None, 0, 4_800_000, 0) > 0.0);
+        assert_eq!(resolve_duration(0, None, 44100, 0, 320), 0.0);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let secs = |s: &str| parse_rfc3339(s).map(|t| t.duration_since(UNIX_EPOCH).unwrap().as_secs());
+
+        assert_eq!(secs("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(secs("2024-06-15T02:00:00Z"), Some(1_718_416_800));
+        assert_eq!(secs("2024-06-15T04:00:00.250+02:00"), Some(1_718_416_800));
+        assert_eq!(secs("2024-06-14T21:00:00-05:00"), Some(1_718_416_800));
+        assert_eq!(secs("2024-06-15"), None);
+        assert_eq!(secs("2024-13-15T00:00:00Z"), None);
+    }
+
+    #[tokio::test]
+    async fn test_since_skips_older_files() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+
+        let cutoff = parse_rfc3339("2024-06-15T00:00:00Z").unwrap();
+        for (name, modified) in [
+            ("old.mp3", cutoff - Duration::from_secs(86_400)),
+            ("new.mp3", cutoff + Duration::from_secs(86_400)),
+        ] {
+            let path = music_dir.join(name);
+            std::fs::write(&path, name).unwrap();
+            File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+
+            let mut analysis = cached_analysis(compute_file_hash(&path).unwrap());
+            analysis.title = name.to_string();
+            cache.put(&analysis, TrackOptions::default().cache_settings()).unwrap();
+        }
+
+        let config = Config::for_test(tmp.path());
+
+        let result = analyze_directory(&config, &cache, &AnalyzeOptions { since: Some(cutoff), ..Default::default() }).await.unwrap();
+        let titles: Vec<&str> = result.tracks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, vec!["new.mp3"]);
+
+        let all = analyze_directory(&config, &cache, &AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(all.tracks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cached_only_leaves_out_new_files() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let cache = AnalysisCache::new(tmp.path().join("cache")).unwrap();
+
+        let cached = music_dir.join("cached.mp3");
+        std::fs::write(&cached, b"cached").unwrap();
+        cache.put(&cached_analysis(compute_file_hash(&cached).unwrap()), TrackOptions::default().cache_settings()).unwrap();
+        // Not real audio: analyzing it would fail and count it as skipped
+        std::fs::write(music_dir.join("new.mp3"), b"new").unwrap();
+
+        let config = Config::for_test(tmp.path());
+        let options = AnalyzeOptions { cached_only: true, ..Default::default() };
+        let result = analyze_directory(&config, &cache, &options).await.unwrap();
+
+        assert_eq!(result.tracks.len(), 1);
+        assert_eq!(result.tracks[0].file_hash, compute_file_hash(&cached).unwrap());
+        assert_eq!(result.counts, AnalyzeCounts { cache_hits: 1, uncached: 1, ..Default::default() });
+        assert!(!tmp.path().join("cache/track_ids.json").exists());
+    }
+
+    #[test]
+    fn test_file_type_from_codec() {
+        use symphonia::core::codecs::{CODEC_TYPE_NULL, CODEC_TYPE_PCM_S16LE};
+
+        // ALAC and AAC share the .m4a extension; only the codec tells them apart
+        let m4a = Path::new("/music/track.m4a");
+        let alac = detect_file_type(CODEC_TYPE_ALAC, m4a);
+        assert_eq!(alac, FileType::Alac);
+        assert!(alac.is_lossless());
+        assert_eq!(detect_file_type(CODEC_TYPE_AAC, m4a), FileType::M4a);
+
+        // PCM and unknown codecs keep the extension's container type
+        assert_eq!(detect_file_type(CODEC_TYPE_PCM_S16LE, Path::new("a.aiff")), FileType::Aiff);
+        assert_eq!(detect_file_type(CODEC_TYPE_NULL, Path::new("a.mp3")), FileType::Mp3);
+    }
 }
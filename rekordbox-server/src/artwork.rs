@@ -0,0 +1,126 @@
+//! Album artwork extraction
+//!
+//! Pulls embedded cover art out of audio files (ID3 APIC, FLAC PICTURE, MP4
+//! covr) via Symphonia's visual metadata and writes the two JPEG sizes the
+//! players expect:
+//! - PIONEER/Artwork/xxxxx/a{id}.jpg (80x80 thumbnail)
+//! - PIONEER/Artwork/xxxxx/a{id}_m.jpg (240x240)
+
+use std::fs::{self, File};
+use std::path::Path;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardVisualKey};
+use symphonia::core::probe::Hint;
+
+use rekordbox_core::{
+    artwork_folder_path, artwork_full_name, artwork_thumbnail_name,
+    ARTWORK_FULL_SIZE, ARTWORK_THUMBNAIL_SIZE,
+};
+
+/// JPEG quality for written artwork
+const JPEG_QUALITY: u8 = 90;
+
+/// Read the embedded cover image from an audio file
+///
+/// Prefers the front cover when a file carries several pictures. Returns
+/// Ok(None) when the file has no artwork at all.
+pub fn extract_artwork(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    // Tags found while probing (e.g. a leading ID3v2) come first, then
+    // metadata embedded in the container itself
+    if let Some(image) = probed.metadata.get()
+        .and_then(|m| m.current().and_then(cover_image))
+    {
+        return Ok(Some(image));
+    }
+
+    Ok(probed.format.metadata().current().and_then(cover_image))
+}
+
+/// Pick the front cover (or else the first picture) from a metadata revision
+fn cover_image(revision: &MetadataRevision) -> Option<Vec<u8>> {
+    let visuals = revision.visuals();
+    visuals.iter()
+        .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .or_else(|| visuals.first())
+        .map(|v| v.data.to_vec())
+}
+
+/// Decode an embedded image and write its thumbnail and full-size JPEGs
+///
+/// Returns the artwork path to store in the Artwork table.
+pub fn write_artwork(image: &[u8], artwork_id: u32, output_dir: &Path) -> anyhow::Result<String> {
+    let decoded = image::load_from_memory(image)?;
+
+    let folder = artwork_folder_path(artwork_id);
+    let folder_dir = output_dir.join(&folder);
+    fs::create_dir_all(&folder_dir)?;
+
+    let thumbnail = resize_jpeg(&decoded, ARTWORK_THUMBNAIL_SIZE)?;
+    let full = resize_jpeg(&decoded, ARTWORK_FULL_SIZE)?;
+    fs::write(folder_dir.join(artwork_thumbnail_name(artwork_id)), thumbnail)?;
+    fs::write(folder_dir.join(artwork_full_name(artwork_id)), full)?;
+
+    Ok(format!("/{}/{}", folder, artwork_thumbnail_name(artwork_id)))
+}
+
+/// Crop to a centered square of `size` pixels and encode as baseline JPEG
+fn resize_jpeg(image: &DynamicImage, size: u32) -> anyhow::Result<Vec<u8>> {
+    let resized = image.resize_to_fill(size, size, FilterType::Triangle);
+    let rgb = DynamicImage::ImageRgb8(resized.to_rgb8());
+
+    let mut out = Vec::new();
+    rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_artwork_sizes() {
+        let tmp = TempDir::new().unwrap();
+        let mut png = Vec::new();
+        RgbImage::from_pixel(500, 300, Rgb([200, 40, 40]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let path = write_artwork(&png, 1, tmp.path()).unwrap();
+        assert_eq!(path, "/PIONEER/Artwork/00001/a1.jpg");
+
+        let thumb = image::open(tmp.path().join("PIONEER/Artwork/00001/a1.jpg")).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (80, 80));
+        let full = image::open(tmp.path().join("PIONEER/Artwork/00001/a1_m.jpg")).unwrap();
+        assert_eq!((full.width(), full.height()), (240, 240));
+    }
+
+    #[test]
+    fn test_write_artwork_rejects_garbage() {
+        let tmp = TempDir::new().unwrap();
+        assert!(write_artwork(b"definitely not an image", 1, tmp.path()).is_err());
+        assert!(!tmp.path().join("PIONEER/Artwork/00001/a1.jpg").exists());
+    }
+}
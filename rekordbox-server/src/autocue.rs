@@ -0,0 +1,147 @@
+//! Automatic memory cues
+//!
+//! For tracks that arrive without any cues, places memory cues at the first
+//! audible transient and at large sustained energy jumps (drops), snapped to
+//! the beat grid. Opt-in via `--auto-cues`.
+
+use rekordbox_core::{BeatGrid, CuePoint, CueType};
+
+/// RMS window hop in seconds
+const HOP_SECS: f64 = 0.5;
+
+/// Windows averaged on each side of a candidate boundary (4 s)
+const SPAN: usize = 8;
+
+/// Loudness ratio (after / before, RMS) that counts as a drop: about +6 dB
+const DROP_RATIO: f32 = 2.0;
+
+/// Minimum distance between two automatic cues
+const MIN_GAP_MS: f64 = 8_000.0;
+
+/// Drop cues placed per track at most
+const MAX_DROPS: usize = 3;
+
+/// Memory cues at the track start and the strongest drops
+///
+/// Drops are only looked for within `samples`, which the analyzer cuts
+/// off after the first few minutes of a long track.
+pub fn auto_cues(samples: &[f32], sample_rate: u32, beat_grid: &BeatGrid) -> Vec<CuePoint> {
+    let mut cues = vec![memory_cue(beat_grid.first_beat_ms.max(0.0), "Start")];
+
+    for time_ms in detect_drops(samples, sample_rate) {
+        let time_ms = snap_to_beat(time_ms, beat_grid);
+        if cues.iter().all(|c| (c.time_ms - time_ms).abs() >= MIN_GAP_MS) {
+            cues.push(memory_cue(time_ms, "Drop"));
+        }
+    }
+
+    cues.sort_by(|a, b| a.time_ms.total_cmp(&b.time_ms));
+    cues
+}
+
+/// Boundaries where the following seconds are much louder than the preceding
+/// ones, strongest first
+fn detect_drops(samples: &[f32], sample_rate: u32) -> Vec<f64> {
+    let hop = (sample_rate as f64 * HOP_SECS) as usize;
+    if hop == 0 {
+        return Vec::new();
+    }
+
+    let rms: Vec<f32> = samples
+        .chunks(hop)
+        .map(|chunk| (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt())
+        .collect();
+    if rms.len() < 2 * SPAN {
+        return Vec::new();
+    }
+
+    let mean = |window: &[f32]| window.iter().sum::<f32>() / window.len() as f32;
+    let mut candidates: Vec<(usize, f32)> = (SPAN..=rms.len() - SPAN)
+        .filter_map(|i| {
+            let before = mean(&rms[i - SPAN..i]);
+            let after = mean(&rms[i..i + SPAN]);
+            let ratio = after / before.max(1e-4);
+            (ratio >= DROP_RATIO).then_some((i, ratio))
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut drops: Vec<f64> = Vec::new();
+    for (i, _) in candidates {
+        let time_ms = i as f64 * HOP_SECS * 1000.0;
+        if drops.iter().all(|d| (d - time_ms).abs() >= MIN_GAP_MS) {
+            drops.push(time_ms);
+            if drops.len() == MAX_DROPS {
+                break;
+            }
+        }
+    }
+    drops
+}
+
+/// Move a time to the nearest beat of the grid (unchanged without beats)
+fn snap_to_beat(time_ms: f64, beat_grid: &BeatGrid) -> f64 {
+    beat_grid.beats.iter()
+        .map(|b| b.time_ms)
+        .min_by(|a, b| (a - time_ms).abs().total_cmp(&(b - time_ms).abs()))
+        .unwrap_or(time_ms)
+}
+
+fn memory_cue(time_ms: f64, comment: &str) -> CuePoint {
+    CuePoint {
+        hot_cue: 0,
+        cue_type: CueType::Cue,
+        time_ms,
+        loop_ms: 0.0,
+        comment: Some(comment.to_string()),
+        color: None,
+        memory_color_id: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Silence, a quiet tone from 1 s, then the same tone 8x louder from 15 s
+    fn quiet_then_loud(sample_rate: u32) -> Vec<f32> {
+        (0..sample_rate * 30)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let gain = match t {
+                    t if t < 1.0 => 0.0,
+                    t if t < 15.0 => 0.1,
+                    _ => 0.8,
+                };
+                (gain * (2.0 * std::f64::consts::PI * 220.0 * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_drop_cue_near_energy_jump() {
+        let samples = quiet_then_loud(8000);
+        let grid = BeatGrid::constant_tempo(120.0, 1000.0, 30_000.0);
+
+        let cues = auto_cues(&samples, 8000, &grid);
+        assert_eq!(cues.len(), 2, "{:?}", cues);
+
+        assert_eq!(cues[0].time_ms, 1000.0);
+        assert_eq!(cues[0].comment.as_deref(), Some("Start"));
+
+        assert_eq!(cues[1].hot_cue, 0);
+        assert_eq!(cues[1].comment.as_deref(), Some("Drop"));
+        assert!((cues[1].time_ms - 15_000.0).abs() <= 500.0, "drop at {}", cues[1].time_ms);
+        // Snapped onto the grid
+        assert!(grid.beats.iter().any(|b| b.time_ms == cues[1].time_ms));
+    }
+
+    #[test]
+    fn test_steady_signal_only_gets_start_cue() {
+        let samples = vec![0.5f32; 8000 * 30];
+        let grid = BeatGrid::constant_tempo(120.0, 0.0, 30_000.0);
+        let cues = auto_cues(&samples, 8000, &grid);
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].time_ms, 0.0);
+    }
+}
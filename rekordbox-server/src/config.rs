@@ -1,8 +1,17 @@
 //! Server configuration
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use rekordbox_core::{AnlzLayout, ExportClock, DEFAULT_TOOL_MARKER};
+
+use crate::export::{DirCasing, EnergyTag, MissingFilePolicy, TargetHardware};
+use crate::waveform::WaveformConfig;
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct Config {
     /// Root music directory (pre-export folder)
     pub music_dir: PathBuf,
@@ -10,16 +19,58 @@ pub struct Config {
     pub cache_dir: PathBuf,
     /// Output directory for USB export
     pub output_dir: Option<PathBuf>,
-    /// TCP bind address (host:port)
+    /// TCP bind address (host:port) or Unix socket path
     pub bind_addr: String,
     /// Max concurrent analysis tasks
     pub max_concurrent: usize,
     /// Navidrome configuration (optional)
     pub navidrome: Option<NavidromeConfig>,
+    /// Player model exports are targeted at
+    pub target: TargetHardware,
+    /// First id for newly seen tracks (offset when appending to a library);
+    /// files analyzed before keep the ids stored in the cache
+    pub starting_track_id: u32,
+    /// BPM rounding step for detected tempos (0.5, or 0.01 for precise sync)
+    pub bpm_precision: f64,
+    /// Place memory cues at the detected start and drops of uncued tracks
+    pub auto_cues: bool,
+    /// rekordbox collection XML whose cues replace those found in the files
+    pub rekordbox_xml: Option<PathBuf>,
+    /// Exported cues become hot cues up to this many, memory cues after
+    /// (None keeps imported slots)
+    pub hot_cue_slots: Option<u8>,
+    /// Exported cues within this many ms of a beat are snapped onto it
+    pub quantize_cues_ms: Option<f64>,
+    /// What exports do with tracks whose audio file has gone missing
+    pub missing_files: MissingFilePolicy,
+    /// Casing of the exported audio folder (`Contents` or `CONTENTS`)
+    pub dir_casing: DirCasing,
+    /// Cut playlist names longer than players are thought to display
+    pub truncate_playlist_names: bool,
+    /// Track string exports write the energy rating into
+    pub energy_tag: EnergyTag,
+    /// Section order of exported .EXT/.2EX files
+    pub anlz_layout: AnlzLayout,
+    /// Read tags only: skip decoding, BPM detection and waveforms
+    pub metadata_only: bool,
+    /// Resolve half/double tempo ambiguity toward the genre's usual tempo
+    pub genre_tempo_hints: bool,
+    /// Audio quieter than this many dBFS before the music counts as leading
+    /// silence, recorded as the track's audio_start_ms (None skips it)
+    pub silence_threshold_db: Option<f64>,
+    /// Waveform height and color scaling
+    pub waveform: WaveformConfig,
+    /// Analyze and export Opus/Ogg/WMA files as AIFF re-encoded by ffmpeg
+    pub transcode_incompatible: bool,
+    /// Pin export dates and the device UUID so repeated exports match byte for byte
+    pub deterministic: bool,
+    /// Write the rekord-export marker into each exported track row
+    pub tool_marker: bool,
 }
 
 /// Navidrome/Subsonic API configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NavidromeConfig {
     /// Server URL (e.g., http://192.168.1.100:4533)
     pub url: String,
@@ -27,10 +78,232 @@ pub struct NavidromeConfig {
     pub user: String,
     /// Password
     pub pass: String,
+    /// Leading path Navidrome puts before library-relative paths (stripped before matching)
+    #[serde(default)]
+    pub path_prefix: Option<String>,
 }
 
 impl NavidromeConfig {
     pub fn new(url: String, user: String, pass: String) -> Self {
-        Self { url, user, pass }
+        Self { url, user, pass, path_prefix: None }
+    }
+}
+
+/// Settings read from a `--config` TOML file
+///
+/// Every key is optional; keys are named after the CLI flags (`bind`,
+/// `hot_cues`, ...) and a `[navidrome]` table takes url/user/pass/path_prefix.
+/// A `[waveform]` table sets the `WaveformConfig` fields; its `normalize`
+/// key is overridden by `--normalize-waveforms`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub music_dir: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    pub bind: Option<String>,
+    pub target: Option<TargetHardware>,
+    pub starting_track_id: Option<u32>,
+    pub bpm_precision: Option<f64>,
+    pub auto_cues: Option<bool>,
+    pub rekordbox_xml: Option<PathBuf>,
+    pub hot_cues: Option<u8>,
+    pub quantize_cues: Option<f64>,
+    pub missing_files: Option<MissingFilePolicy>,
+    pub dir_casing: Option<DirCasing>,
+    pub truncate_playlist_names: Option<bool>,
+    pub energy_tag: Option<EnergyTag>,
+    pub anlz_layout: Option<AnlzLayout>,
+    pub metadata_only: Option<bool>,
+    pub genre_tempo_hints: Option<bool>,
+    pub silence_threshold: Option<f64>,
+    pub transcode_incompatible: Option<bool>,
+    pub deterministic: Option<bool>,
+    pub tool_marker: Option<bool>,
+    pub navidrome: Option<NavidromeConfig>,
+    pub waveform: Option<WaveformConfig>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {:?}", path))
+    }
+}
+
+impl Config {
+    /// Take values from a config file, except for settings given explicitly
+    /// on the command line (or via env), which `explicit` reports by CLI
+    /// argument id
+    pub fn with_file(mut self, file: ConfigFile, explicit: impl Fn(&str) -> bool) -> Self {
+        fn merge<T>(field: &mut T, value: Option<T>, explicit: bool) {
+            if let Some(value) = value.filter(|_| !explicit) {
+                *field = value;
+            }
+        }
+
+        merge(&mut self.music_dir, file.music_dir, explicit("music_dir"));
+        merge(&mut self.cache_dir, file.cache_dir, explicit("cache_dir"));
+        merge(&mut self.bind_addr, file.bind, explicit("bind"));
+        merge(&mut self.target, file.target, explicit("target"));
+        merge(&mut self.starting_track_id, file.starting_track_id.map(|id| id.max(1)), explicit("starting_track_id"));
+        merge(&mut self.bpm_precision, file.bpm_precision, explicit("bpm_precision"));
+        merge(&mut self.auto_cues, file.auto_cues, explicit("auto_cues"));
+        merge(&mut self.rekordbox_xml, file.rekordbox_xml.map(Some), explicit("rekordbox_xml"));
+        merge(&mut self.hot_cue_slots, file.hot_cues.map(|n| Some(n.min(8))), explicit("hot_cues"));
+        merge(&mut self.quantize_cues_ms, file.quantize_cues.map(Some), explicit("quantize_cues"));
+        merge(&mut self.missing_files, file.missing_files, explicit("missing_files"));
+        merge(&mut self.dir_casing, file.dir_casing, explicit("dir_casing"));
+        merge(&mut self.truncate_playlist_names, file.truncate_playlist_names, explicit("truncate_playlist_names"));
+        merge(&mut self.energy_tag, file.energy_tag, explicit("energy_tag"));
+        merge(&mut self.anlz_layout, file.anlz_layout, explicit("anlz_layout"));
+        merge(&mut self.metadata_only, file.metadata_only, explicit("metadata_only"));
+        merge(&mut self.genre_tempo_hints, file.genre_tempo_hints, explicit("genre_tempo_hints"));
+        merge(&mut self.silence_threshold_db, file.silence_threshold.map(Some), explicit("silence_threshold"));
+        merge(&mut self.transcode_incompatible, file.transcode_incompatible, explicit("transcode_incompatible"));
+        merge(&mut self.deterministic, file.deterministic, explicit("deterministic"));
+        merge(&mut self.tool_marker, file.tool_marker, explicit("no_tool_marker"));
+        merge(&mut self.navidrome, file.navidrome.map(Some), explicit("navidrome_url"));
+        if let Some(mut waveform) = file.waveform {
+            if explicit("normalize_waveforms") {
+                waveform.normalize = self.waveform.normalize;
+            }
+            self.waveform = waveform;
+        }
+        self
+    }
+
+    /// Where exports look for transcoded copies (None when transcoding is off)
+    pub fn transcode_dir(&self) -> Option<PathBuf> {
+        self.transcode_incompatible.then(|| crate::transcode::transcode_dir(&self.cache_dir))
+    }
+
+    /// Clock exports take their dates and device UUID from
+    pub fn export_clock(&self) -> ExportClock {
+        if self.deterministic { ExportClock::EPOCH } else { ExportClock::System }
+    }
+
+    /// Marker exports write into each track row (None when turned off)
+    pub fn tool_marker(&self) -> Option<String> {
+        self.tool_marker.then(|| DEFAULT_TOOL_MARKER.to_string())
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// CLI defaults with the music folder at `dir/music` and the cache at
+    /// `dir/cache`; tests override the fields they exercise
+    pub fn for_test(dir: &Path) -> Self {
+        Config {
+            music_dir: dir.join("music"),
+            cache_dir: dir.join("cache"),
+            output_dir: None,
+            bind_addr: "127.0.0.1:0".into(),
+            max_concurrent: 1,
+            navidrome: None,
+            target: TargetHardware::default(),
+            starting_track_id: 1,
+            bpm_precision: 0.5,
+            auto_cues: false,
+            rekordbox_xml: None,
+            hot_cue_slots: None,
+            quantize_cues_ms: None,
+            missing_files: MissingFilePolicy::default(),
+            dir_casing: DirCasing::default(),
+            truncate_playlist_names: false,
+            energy_tag: EnergyTag::default(),
+            anlz_layout: AnlzLayout::default(),
+            metadata_only: false,
+            genre_tempo_hints: false,
+            silence_threshold_db: None,
+            waveform: WaveformConfig::default(),
+            transcode_incompatible: false,
+            deterministic: false,
+            tool_marker: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+music_dir = "/srv/music"
+cache_dir = "/var/cache/rekordbox"
+bind = "/run/rekordbox.sock"
+target = "xdj-xz"
+bpm_precision = 0.01
+hot_cues = 3
+rekordbox_xml = "/srv/rekordbox.xml"
+missing_files = "skip"
+truncate_playlist_names = true
+anlz_layout = ["PPTH", "PCO2", "PWV5"]
+silence_threshold = -60.0
+tool_marker = false
+
+[waveform]
+normalize = true
+bass_crossover_hz = 150.0
+
+[navidrome]
+url = "http://192.168.1.100:4533"
+user = "dj"
+pass = "secret"
+"#;
+
+    fn cli_defaults() -> Config {
+        Config {
+            music_dir: "/mnt/ssd/pre-export".into(),
+            cache_dir: "/tmp/cache".into(),
+            bind_addr: "0.0.0.0:6969".into(),
+            ..Config::for_test(Path::new("/tmp"))
+        }
+    }
+
+    #[test]
+    fn test_config_file_values() {
+        let file: ConfigFile = toml::from_str(SAMPLE).unwrap();
+        let config = cli_defaults().with_file(file, |_| false);
+
+        assert_eq!(config.music_dir, PathBuf::from("/srv/music"));
+        assert_eq!(config.cache_dir, PathBuf::from("/var/cache/rekordbox"));
+        assert_eq!(config.bind_addr, "/run/rekordbox.sock");
+        assert_eq!(config.target, TargetHardware::XdjXz);
+        assert_eq!(config.bpm_precision, 0.01);
+        assert_eq!(config.hot_cue_slots, Some(3));
+        assert_eq!(config.rekordbox_xml, Some(PathBuf::from("/srv/rekordbox.xml")));
+        assert_eq!(config.missing_files, MissingFilePolicy::Skip);
+        assert!(config.truncate_playlist_names);
+        assert_eq!(config.anlz_layout, AnlzLayout::new(&["PPTH", "PCO2", "PWV5"]).unwrap());
+        assert_eq!(config.silence_threshold_db, Some(-60.0));
+        assert_eq!(config.tool_marker(), None);
+        assert!(config.waveform.normalize);
+        assert_eq!(config.waveform.bass_crossover_hz, 150.0);
+        assert_eq!(config.waveform.mid_crossover_hz, 4000.0);
+        // Keys the file leaves out keep the CLI defaults
+        assert_eq!(config.starting_track_id, 1);
+        assert!(!config.auto_cues);
+
+        let navidrome = config.navidrome.unwrap();
+        assert_eq!(navidrome.url, "http://192.168.1.100:4533");
+        assert_eq!(navidrome.user, "dj");
+        assert_eq!(navidrome.path_prefix, None);
+    }
+
+    #[test]
+    fn test_cli_flags_override_file() {
+        let file: ConfigFile = toml::from_str(SAMPLE).unwrap();
+        let config = cli_defaults().with_file(file, |id| {
+            id == "bind" || id == "target" || id == "normalize_waveforms"
+        });
+
+        assert_eq!(config.bind_addr, "0.0.0.0:6969");
+        assert_eq!(config.target, TargetHardware::Cdj3000);
+        assert_eq!(config.music_dir, PathBuf::from("/srv/music"));
+        assert!(!config.waveform.normalize);
+        assert_eq!(config.waveform.bass_crossover_hz, 150.0);
+
+        assert!(toml::from_str::<ConfigFile>("musik_dir = \"/typo\"").is_err());
     }
 }
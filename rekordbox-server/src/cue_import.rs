@@ -0,0 +1,131 @@
+//! Cue import from other DJ software
+//!
+//! A track's cue points can come from:
+//! - Serato markers stored in the audio file (the ID3 `GEOB` frame or the
+//!   FLAC/Ogg comment, parsed by `serato`), read when the track is analyzed
+//! - a rekordbox collection XML (`--rekordbox-xml`), matched to files by
+//!   location and applied on every run like a sidecar, so editing the
+//!   collection takes effect without re-analyzing anything
+//!
+//! The two count time differently. Serato plays MP3/AAC gaplessly and
+//! counts from the first sample after the encoder's priming, while Pioneer
+//! players (and rekordbox with them) count from the first decoded sample,
+//! priming included. A Serato cue on an MP3 therefore belongs later on the
+//! player by the encoder delay: LAME's 576 priming samples plus the
+//! decoder's 529, about 25 ms at 44.1 kHz. The analyzer adds Serato cues
+//! before its encoder delay compensation, which moves them along with the
+//! beat grid. rekordbox XML positions are already on the players' timeline
+//! and go in as they are.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::debug;
+
+use rekordbox_core::{import_collection_xml, CuePoint, TrackAnalysis};
+use crate::serato::load_serato_cues;
+
+/// Serato cues of the file at `path`: those already found among the tags
+/// the decoder read (FLAC/Ogg comments), else the ones in its ID3 tag
+pub fn serato_cues(path: &Path, from_tags: Option<Vec<CuePoint>>) -> Option<Vec<CuePoint>> {
+    from_tags.or_else(|| load_serato_cues(path))
+}
+
+/// Add imported cues, skipping hot cues whose slot is already taken
+pub fn add_cues(analysis: &mut TrackAnalysis, cues: Vec<CuePoint>) {
+    for cue in cues {
+        let slot_taken = cue.hot_cue > 0
+            && analysis.cue_points.iter().any(|c| c.hot_cue == cue.hot_cue);
+        if !slot_taken {
+            analysis.cue_points.push(cue);
+        }
+    }
+}
+
+/// Cues of the tracks in a rekordbox collection XML, by file location
+#[derive(Debug, Default)]
+pub struct XmlCues {
+    by_path: HashMap<PathBuf, Vec<CuePoint>>,
+    /// File name -> collection paths, for collections exported on another
+    /// machine whose music folder sits elsewhere
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl XmlCues {
+    /// Read the cued tracks of the collection XML at `path`
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let xml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rekordbox XML {:?}", path))?;
+        let (tracks, _) = import_collection_xml(&xml)
+            .with_context(|| format!("Invalid rekordbox XML {:?}", path))?;
+
+        let mut cues = Self::default();
+        for track in tracks.into_iter().filter(|t| !t.cue_points.is_empty()) {
+            let location = PathBuf::from(track.file_path);
+            if let Some(name) = location.file_name().and_then(|n| n.to_str()) {
+                cues.by_name.entry(name.to_string()).or_default().push(location.clone());
+            }
+            cues.by_path.insert(location, track.cue_points);
+        }
+        debug!("Loaded cues for {} tracks from {:?}", cues.by_path.len(), path);
+        Ok(cues)
+    }
+
+    /// Cues the collection holds for the file at `path`: by its full path,
+    /// else by its file name when only one cued track has that name
+    pub fn cues_for(&self, path: &Path) -> Option<&[CuePoint]> {
+        if let Some(cues) = self.by_path.get(path) {
+            return Some(cues);
+        }
+        let name = path.file_name()?.to_str()?;
+        match self.by_name.get(name)?.as_slice() {
+            [location] => self.by_path.get(location).map(Vec::as_slice),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rekordbox_core::CueType;
+    use tempfile::TempDir;
+
+    const COLLECTION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DJ_PLAYLISTS Version="1.0.0">
+  <COLLECTION Entries="3">
+    <TRACK TrackID="1" Name="Night Drive" Location="file://localhost/Users/dj/Music/Night%20Drive.mp3">
+      <POSITION_MARK Name="Drop" Type="0" Start="60.000" Num="0"/>
+      <POSITION_MARK Name="" Type="4" Start="90.000" End="91.875" Num="-1"/>
+    </TRACK>
+    <TRACK TrackID="2" Name="Intro" Location="file://localhost/Users/dj/Music/A/intro.mp3">
+      <POSITION_MARK Name="" Type="0" Start="1.000" Num="0"/>
+    </TRACK>
+    <TRACK TrackID="3" Name="Intro" Location="file://localhost/Users/dj/Music/B/intro.mp3">
+      <POSITION_MARK Name="" Type="0" Start="2.000" Num="0"/>
+    </TRACK>
+  </COLLECTION>
+</DJ_PLAYLISTS>"#;
+
+    #[test]
+    fn test_xml_cues_by_location() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("rekordbox.xml");
+        std::fs::write(&path, COLLECTION).unwrap();
+        let cues = XmlCues::load(&path).unwrap();
+
+        let drive = cues.cues_for(Path::new("/Users/dj/Music/Night Drive.mp3")).unwrap();
+        assert_eq!(drive.len(), 2);
+        assert_eq!((drive[0].hot_cue, drive[0].time_ms), (1, 60_000.0));
+        assert_eq!((drive[1].cue_type, drive[1].loop_ms), (CueType::Loop, 1875.0));
+
+        // Same file in another music folder
+        assert_eq!(cues.cues_for(Path::new("/srv/music/Night Drive.mp3")).unwrap().len(), 2);
+        // Two collection tracks share this name: only the full path tells
+        assert_eq!(cues.cues_for(Path::new("/Users/dj/Music/B/intro.mp3")).unwrap()[0].time_ms, 2000.0);
+        assert!(cues.cues_for(Path::new("/srv/music/intro.mp3")).is_none());
+
+        assert!(XmlCues::load(&tmp.path().join("missing.xml")).is_err());
+    }
+}
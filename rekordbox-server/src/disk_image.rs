@@ -0,0 +1,233 @@
+//! FAT32 disk image export
+//!
+//! Produces a filesystem image holding the same PIONEER/Contents tree as
+//! `export_usb`, ready to be `dd`'d onto a stick later, so the USB never has
+//! to be mounted on the NAS. The database and analysis files are staged in a
+//! scratch directory next to the image and copied in with a pure-Rust FAT
+//! writer; audio goes straight from the source files into the image.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fatfs::{Dir, FatType, FileSystem, FormatVolumeOptions, FsOptions};
+use tracing::{info, warn};
+
+use rekordbox_core::TrackAnalysis;
+
+use crate::export::{export_library, AudioCopies, ExportOptions, ExportReport};
+
+/// Smallest image that still has enough clusters to be formatted as FAT32
+pub const MIN_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Volume label shown by the players and desktop OSes (11 bytes, space padded)
+const VOLUME_LABEL: &[u8; 11] = b"REKORDBOX  ";
+
+/// Export analyzed tracks into a FAT32 image of `size_bytes` at `image_path`
+///
+/// Any existing file at `image_path` is overwritten. Fails if the export
+/// does not fit in the requested size.
+pub fn export_to_image(
+    tracks: &[TrackAnalysis],
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
+    source_dir: &Path,
+    image_path: &Path,
+    size_bytes: u64,
+    options: &ExportOptions,
+) -> anyhow::Result<ExportReport> {
+    if size_bytes < MIN_IMAGE_SIZE {
+        anyhow::bail!(
+            "Image size {} bytes is below the FAT32 minimum of {} bytes",
+            size_bytes, MIN_IMAGE_SIZE
+        );
+    }
+
+    let staging = staging_dir(image_path);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let result = export_library(tracks, playlists, source_dir, &staging, options)
+        .and_then(|(mut report, audio)| {
+            write_image(&staging, &audio, image_path, size_bytes, &mut report)?;
+            info!("Wrote {} byte FAT32 image to {:?}", size_bytes, image_path);
+            Ok(report)
+        });
+
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        warn!("Failed to remove staging directory {:?}: {}", staging, e);
+    }
+
+    result
+}
+
+/// Scratch directory used while building `image_path`
+fn staging_dir(image_path: &Path) -> PathBuf {
+    let mut name = image_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".staging");
+    image_path.with_file_name(name)
+}
+
+/// Format a fresh FAT32 image, copy the staged tree into it and stream the
+/// audio copies (whose destinations lie under `tree`) in after it
+fn write_image(
+    tree: &Path,
+    audio: &AudioCopies,
+    image_path: &Path,
+    size_bytes: u64,
+    report: &mut ExportReport,
+) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image_path)
+        .with_context(|| format!("Failed to create image {:?}", image_path))?;
+    file.set_len(size_bytes)?;
+
+    fatfs::format_volume(
+        &mut file,
+        FormatVolumeOptions::new()
+            .fat_type(FatType::Fat32)
+            .volume_label(*VOLUME_LABEL),
+    )?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let fat = FileSystem::new(&mut file, FsOptions::new())?;
+    let root = fat.root_dir();
+    copy_tree(tree, &root).context("Export does not fit in the image")?;
+    for (source, dest) in &audio.copies {
+        let relative = dest.strip_prefix(tree)?;
+        let mut out = create_file(&root, relative)?;
+        report.bytes_copied += io::copy(&mut File::open(source)?, &mut out)
+            .context("Export does not fit in the image")?;
+    }
+    drop(root);
+    fat.unmount()?;
+    Ok(())
+}
+
+/// Recursively copy a host directory into a FAT directory
+fn copy_tree<T: fatfs::ReadWriteSeek>(source: &Path, dest: &Dir<T>) -> io::Result<()> {
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Non UTF-8 file name {:?}", name))
+        })?;
+
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &dest.create_dir(name)?)?;
+        } else {
+            let mut out = dest.create_file(name)?;
+            out.truncate()?;
+            io::copy(&mut File::open(entry.path())?, &mut out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create (or truncate) the file at `relative` below `root`, creating the
+/// folders on the way
+fn create_file<'a, T: fatfs::ReadWriteSeek>(root: &Dir<'a, T>, relative: &Path) -> io::Result<fatfs::File<'a, T>> {
+    let mut parts = Vec::new();
+    for part in relative.components() {
+        let part = part.as_os_str();
+        parts.push(part.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Non UTF-8 file name {:?}", part))
+        })?);
+    }
+    let (name, folders) = parts.split_last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty file path"))?;
+
+    let mut dir = root.clone();
+    for folder in folders {
+        dir = dir.create_dir(folder)?;
+    }
+    let mut file = dir.create_file(name)?;
+    file.truncate()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use rekordbox_core::{BeatGrid, FileType};
+    use tempfile::TempDir;
+
+    fn make_track() -> TrackAnalysis {
+        TrackAnalysis {
+            id: 1,
+            file_path: "/Contents/track.mp3".into(),
+            title: "Track".into(),
+            artist: "Artist".into(),
+            duration_secs: 60.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 128,
+            bpm: 120.0,
+            beat_grid: BeatGrid::constant_tempo(120.0, 0.0, 60_000.0),
+            file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    #[test]
+    fn test_export_to_image_reads_back() {
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        fs::write(source.path().join("track.mp3"), [0xFFu8; 4096]).unwrap();
+        let image_path = output.path().join("usb.img");
+
+        let report = export_to_image(
+            &[make_track()],
+            &HashMap::from([(vec!["Friday".to_string()], vec![1])]),
+            source.path(),
+            &image_path,
+            MIN_IMAGE_SIZE,
+            &ExportOptions::default(),
+        ).unwrap();
+
+        assert_eq!(fs::metadata(&image_path).unwrap().len(), MIN_IMAGE_SIZE);
+        assert!(!staging_dir(&image_path).exists());
+
+        let image = File::open(&image_path).unwrap();
+        let fat = FileSystem::new(image, FsOptions::new()).unwrap();
+        assert_eq!(fat.fat_type(), FatType::Fat32);
+        assert_eq!(fat.volume_label(), "REKORDBOX");
+
+        let root = fat.root_dir();
+        let mut pdb = Vec::new();
+        root.open_file("PIONEER/rekordbox/export.pdb").unwrap().read_to_end(&mut pdb).unwrap();
+        assert!(rekordbox_core::validate_pdb(&pdb).valid);
+
+        let mut audio = Vec::new();
+        root.open_file("Contents/track.mp3").unwrap().read_to_end(&mut audio).unwrap();
+        assert_eq!(audio.len(), 4096);
+        // The Artist/Album copy is streamed in along with its folders
+        audio.clear();
+        root.open_file("Contents/Artist/Unknown Album/track.mp3").unwrap().read_to_end(&mut audio).unwrap();
+        assert_eq!(audio.len(), 4096);
+        assert_eq!(report.bytes_copied, 2 * 4096);
+    }
+
+    #[test]
+    fn test_image_too_small() {
+        let output = TempDir::new().unwrap();
+        let result = export_to_image(
+            &[],
+            &HashMap::new(),
+            output.path(),
+            &output.path().join("usb.img"),
+            MIN_IMAGE_SIZE - 1,
+            &ExportOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Energy rating
+//!
+//! A coarse 1-10 score in the spirit of Mixed In Key's energy levels, built
+//! from three measurements of the decoded mono samples:
+//! - loudness: RMS level in dBFS
+//! - brightness: spectral centroid of the averaged spectrum
+//! - density: onsets per second (jumps in short-frame energy)
+//!
+//! Each is scaled to 0..1 between fixed anchors and the weighted sum is
+//! spread over 1..10. The anchors are rough: the score is for sorting a
+//! collection, not for comparing with other tools.
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+const FFT_SIZE: usize = 2048;
+
+/// Upper bound on windows averaged for the centroid, spread over the track
+const MAX_WINDOWS: usize = 64;
+
+/// Onset frames per second (20 ms frames)
+const ONSET_FRAMES_PER_SECOND: u32 = 50;
+
+/// A frame this many times louder than the previous one is an onset (3 dB)
+const ONSET_RISE: f32 = 2.0;
+
+/// Mean square below this is silence (-60 dBFS)
+const SILENCE: f32 = 1e-6;
+
+/// Scaling anchors: (score 0, score 1)
+const LOUDNESS_DBFS: (f32, f32) = (-30.0, -8.0);
+const CENTROID_HZ: (f32, f32) = (300.0, 4000.0);
+const ONSETS_PER_SECOND: (f32, f32) = (0.5, 8.0);
+
+/// Weights of loudness, brightness and density in the score
+const WEIGHTS: (f32, f32, f32) = (0.4, 0.3, 0.3);
+
+/// Rate mono samples from 1 (calm) to 10 (peak time)
+///
+/// Returns None for audio too short or too quiet to rate. The rating is
+/// of `samples` as given, which for a long mix is only its opening.
+pub fn energy_rating(samples: &[f32], sample_rate: u32) -> Option<u8> {
+    if samples.len() < FFT_SIZE || sample_rate == 0 {
+        return None;
+    }
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    if mean_square < SILENCE {
+        return None;
+    }
+
+    let loudness = scale(10.0 * mean_square.log10(), LOUDNESS_DBFS);
+    let brightness = spectral_centroid_hz(samples, sample_rate)
+        .map(|hz| scale(hz.max(1.0).log2(), (CENTROID_HZ.0.log2(), CENTROID_HZ.1.log2())))
+        .unwrap_or(0.0);
+    let density = scale(onsets_per_second(samples, sample_rate), ONSETS_PER_SECOND);
+
+    let score = WEIGHTS.0 * loudness + WEIGHTS.1 * brightness + WEIGHTS.2 * density;
+    Some(1 + (score * 9.0).round() as u8)
+}
+
+/// Position of `value` between `lo` and `hi`, clamped to 0..1
+fn scale(value: f32, (lo, hi): (f32, f32)) -> f32 {
+    ((value - lo) / (hi - lo)).clamp(0.0, 1.0)
+}
+
+/// Power-weighted mean frequency of the averaged spectrum
+fn spectral_centroid_hz(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos()))
+        .collect();
+
+    let windows = (samples.len() / FFT_SIZE).min(MAX_WINDOWS);
+    let stride = if windows > 1 { (samples.len() - FFT_SIZE) / (windows - 1) } else { 0 };
+
+    let mut power = vec![0.0f32; FFT_SIZE / 2];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); FFT_SIZE];
+    for w in 0..windows {
+        let start = w * stride;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for (bin, value) in power.iter_mut().zip(&buffer) {
+            *bin += value.norm_sqr();
+        }
+    }
+
+    let total: f32 = power.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    let weighted: f32 = power.iter().enumerate().map(|(bin, p)| bin as f32 * bin_hz * p).sum();
+    Some(weighted / total)
+}
+
+/// Onsets per second: frames jumping well above the one before
+///
+/// A rise spread over consecutive frames counts once.
+fn onsets_per_second(samples: &[f32], sample_rate: u32) -> f32 {
+    let frame_len = (sample_rate / ONSET_FRAMES_PER_SECOND).max(1) as usize;
+    let mut previous = 0.0f32;
+    let mut previous_onset = false;
+    let mut onsets = 0usize;
+    for frame in samples.chunks_exact(frame_len) {
+        let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame_len as f32;
+        let onset = energy > SILENCE && energy > previous * ONSET_RISE && !previous_onset;
+        onsets += onset as usize;
+        previous_onset = onset;
+        previous = energy;
+    }
+    onsets as f32 * sample_rate as f32 / samples.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    /// 10 s of 50 ms noise bursts, 8 per second, near full scale
+    fn dense_bright_loud() -> Vec<f32> {
+        let mut state = 1u32;
+        let period = SAMPLE_RATE as usize / 8;
+        let burst = SAMPLE_RATE as usize / 20;
+        (0..SAMPLE_RATE as usize * 10)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (state >> 16) as f32 / 32768.0 - 1.0;
+                if i % period < burst { 0.8 * noise } else { 0.0 }
+            })
+            .collect()
+    }
+
+    /// 10 s of a soft 110 Hz note struck every 2 s
+    fn sparse_quiet() -> Vec<f32> {
+        let period = SAMPLE_RATE as usize * 2;
+        let note = SAMPLE_RATE as usize / 4;
+        (0..SAMPLE_RATE as usize * 10)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let tone = 0.05 * (2.0 * std::f32::consts::PI * 110.0 * t).sin();
+                if i % period < note { tone } else { 0.0 }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dense_loud_rates_higher_than_sparse_quiet() {
+        let high = energy_rating(&dense_bright_loud(), SAMPLE_RATE).unwrap();
+        let low = energy_rating(&sparse_quiet(), SAMPLE_RATE).unwrap();
+        assert!((1..=10).contains(&high) && (1..=10).contains(&low));
+        assert!(high >= 8, "dense/loud rated {}", high);
+        assert!(low <= 3, "sparse/quiet rated {}", low);
+
+        assert_eq!(energy_rating(&[0.5; 100], SAMPLE_RATE), None);
+        assert_eq!(energy_rating(&vec![0.0; SAMPLE_RATE as usize], SAMPLE_RATE), None);
+    }
+}
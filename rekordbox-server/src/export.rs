@@ -2,55 +2,299 @@
 //!
 //! Creates the complete Pioneer-compatible USB directory structure:
 //! - PIONEER/rekordbox/export.pdb
+//! - PIONEER/rekordbox/masterPlaylists6.xml (playlist order for rekordbox 6)
 //! - PIONEER/USBANLZ/Pxxx/[hex]/ANLZ0000.DAT
 //! - PIONEER/DEVSETTING.DAT
 //! - PIONEER/djprofile.nxs
-//! - Contents/[audio files]
+//! - Contents/[audio files] (or CONTENTS, see `DirCasing`)
+//! - export-manifest.json (tracks and playlists, for verification)
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tracing::{info, debug, warn};
 use walkdir::WalkDir;
+use xxhash_rust::xxh3::xxh3_64;
 
 use rekordbox_core::{
-    PdbBuilder, TrackAnalysis,
-    generate_dat_file, generate_ext_file, generate_2ex_file, generate_anlz_path,
-    generate_devsetting, generate_djprofile,
+    artwork_folder_path, artwork_thumbnail_name, stable_artwork_id, MAX_ARTWORK_ID,
+    assign_cue_slots, quantize_cues, EnergyField, read_sequence, PdbBuilder, TrackAnalysis, Waveform, DEFAULT_TOOL_MARKER,
+    generate_dat_file, generate_ext_file_with_layout, generate_2ex_file_with_layout, generate_anlz_path, AnlzLayout,
+    generate_devsetting, generate_djprofile, generate_master_playlists_xml,
+    device_backup_info_name, generate_device_backup_info, DeviceBackupInfo, ExportClock,
 };
+use crate::artwork::{extract_artwork, write_artwork};
+
+/// Manifest written next to (not inside) the PIONEER tree
+pub const MANIFEST_FILE_NAME: &str = "export-manifest.json";
+
+/// Default DJ profile name written to djprofile.nxs
+const DEFAULT_PROFILE_NAME: &str = "rekord-export";
+
+/// Device name and backup PC id in the DeviceLibBackup info
+const BACKUP_DEVICE_NAME: &str = "USB";
+const BACKUP_PC_ID: u32 = 1;
+
+/// Player model an export is targeted at
+///
+/// A target only picks which analysis files (.DAT/.EXT/.2EX) and whether
+/// color waveforms are written. Waveform resolution is the same for every
+/// model, and the output filesystem is not checked (all of them read FAT32).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum TargetHardware {
+    /// CDJ-2000NXS2 (reads .DAT and .EXT)
+    #[serde(rename = "cdj2000nxs2")]
+    #[value(name = "cdj2000nxs2")]
+    Cdj2000Nxs2,
+    /// CDJ-3000 (reads .DAT, .EXT and .2EX)
+    #[default]
+    #[serde(rename = "cdj3000")]
+    #[value(name = "cdj3000")]
+    Cdj3000,
+    /// XDJ-XZ (reads .DAT and .EXT)
+    #[serde(rename = "xdj-xz")]
+    #[value(name = "xdj-xz")]
+    XdjXz,
+    /// CDJ-900 (pre-Nexus, monochrome waveforms): the .DAT-only profile
+    #[serde(rename = "cdj900")]
+    #[value(name = "cdj900")]
+    Cdj900,
+}
+
+/// Hardware-dependent export settings
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// DJ profile name written to djprofile.nxs
+    pub profile_name: String,
+    /// Write ANLZ0000.EXT (color waveforms, extended cues)
+    pub write_ext: bool,
+    /// Write ANLZ0000.2EX (CDJ-3000 3-band waveforms)
+    pub write_2ex: bool,
+    /// Include color waveform data (PWV4/PWV5)
+    pub color_preview: bool,
+    /// Truncate playlist names too long for the player display
+    pub truncate_playlist_names: bool,
+    /// Tool/version marker written into each track row (None to omit)
+    pub tool_marker: Option<String>,
+    /// What to do with tracks whose audio file is not in the source directory
+    pub missing_files: MissingFilePolicy,
+    /// Re-slot cues so the first N become hot cues and the rest memory cues
+    /// (None keeps the slots the cues were imported with)
+    pub hot_cue_slots: Option<u8>,
+    /// Snap cues within this many ms of a beat onto the beat grid (None
+    /// keeps imported positions)
+    pub quantize_cues_ms: Option<f64>,
+    /// Casing of the audio folder (and the track paths pointing into it)
+    pub dir_casing: DirCasing,
+    /// Where the energy rating goes in each track row
+    pub energy_tag: EnergyTag,
+    /// Section order of the .EXT/.2EX files
+    pub anlz_layout: AnlzLayout,
+    /// Extra directory searched for audio files: re-encoded copies of
+    /// sources the players can't read (see `transcode`)
+    pub transcode_dir: Option<PathBuf>,
+    /// Source of the backup date, device UUID and default history date
+    pub clock: ExportClock,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self::for_target(TargetHardware::default())
+    }
+}
+
+impl ExportOptions {
+    /// Analysis file and color waveform defaults for a player model
+    pub fn for_target(target: TargetHardware) -> Self {
+        let (write_ext, write_2ex, color_preview) = match target {
+            TargetHardware::Cdj3000 => (true, true, true),
+            TargetHardware::Cdj2000Nxs2 | TargetHardware::XdjXz => (true, false, true),
+            TargetHardware::Cdj900 => (false, false, false),
+        };
+
+        Self {
+            profile_name: DEFAULT_PROFILE_NAME.to_string(),
+            write_ext,
+            write_2ex,
+            color_preview,
+            truncate_playlist_names: false,
+            tool_marker: Some(DEFAULT_TOOL_MARKER.to_string()),
+            missing_files: MissingFilePolicy::default(),
+            hot_cue_slots: None,
+            quantize_cues_ms: None,
+            dir_casing: DirCasing::default(),
+            energy_tag: EnergyTag::default(),
+            anlz_layout: AnlzLayout::default(),
+            transcode_dir: None,
+            clock: ExportClock::System,
+        }
+    }
+}
+
+/// Handling of tracks whose source audio can't be found at export time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingFilePolicy {
+    /// Leave the track (and its playlist entries) out of the export
+    Skip,
+    /// Export the track anyway; the player lists it but can't play it
+    #[default]
+    Warn,
+    /// Abort the export before anything is written
+    Fail,
+}
+
+/// Casing of the audio folder at the USB root
+///
+/// `PIONEER` is always upper case. Some firmwares and FAT drivers compare
+/// paths case-sensitively, so the folder on disk and the paths in the PDB
+/// and ANLZ files must agree exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DirCasing {
+    /// `Contents`, as rekordbox writes it
+    #[default]
+    Mixed,
+    /// `CONTENTS`, matching `PIONEER`
+    Upper,
+}
+
+impl DirCasing {
+    /// Name of the audio folder at the USB root
+    pub fn contents_dir(self) -> &'static str {
+        match self {
+            DirCasing::Mixed => "Contents",
+            DirCasing::Upper => "CONTENTS",
+        }
+    }
+}
+
+/// Track string carrying the energy rating ("Energy 7")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnergyTag {
+    /// Not written
+    #[default]
+    Off,
+    /// In front of the comment, where Mixed In Key puts it
+    Comment,
+    /// In the mix name, which exports otherwise leave empty
+    MixName,
+}
+
+impl EnergyTag {
+    /// PDB string the rating is written to
+    pub fn field(self) -> Option<EnergyField> {
+        match self {
+            EnergyTag::Off => None,
+            EnergyTag::Comment => Some(EnergyField::Comment),
+            EnergyTag::MixName => Some(EnergyField::MixName),
+        }
+    }
+}
+
+/// Summary of a finished export
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    /// Non-fatal problems the user should know about
+    pub warnings: Vec<String>,
+    /// Per-track steps that failed and were skipped (e.g. unreadable artwork)
+    pub skipped: Vec<String>,
+    /// Ids of tracks whose ANLZ .DAT is not on disk after the export
+    pub missing_anlz: Vec<u32>,
+    /// Tracks that made it into export.pdb
+    pub tracks_written: usize,
+    /// Audio bytes this export wrote under Contents/
+    pub bytes_copied: u64,
+    /// Audio bytes not written: destinations already on the stick, or that
+    /// an earlier track copies the same file to
+    pub bytes_skipped: u64,
+}
 
 /// Export analyzed tracks to Pioneer USB format
 pub fn export_usb(
     tracks: &[TrackAnalysis],
-    playlists: &HashMap<String, Vec<u32>>,
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
     source_dir: &Path,
     output_dir: &Path,
-) -> anyhow::Result<()> {
-    export_usb_with_profile(tracks, playlists, source_dir, output_dir, "rekord-export")
+    options: &ExportOptions,
+) -> anyhow::Result<ExportReport> {
+    let (mut report, audio) = export_library(tracks, playlists, source_dir, output_dir, options)?;
+    copy_audio_files(audio, &mut report)?;
+    info!("Export complete: {} tracks, {} playlists", report.tracks_written, playlists.len());
+    Ok(report)
 }
 
-/// Export analyzed tracks with custom DJ profile name
-pub fn export_usb_with_profile(
+/// Write everything `export_usb` does except the audio, and return the
+/// audio copies still to be made
+///
+/// The copies' destinations lie in `output_dir`'s audio folder; disk image
+/// exports stream them into the image instead of onto the host.
+pub fn export_library(
     tracks: &[TrackAnalysis],
-    playlists: &HashMap<String, Vec<u32>>,
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
     source_dir: &Path,
     output_dir: &Path,
-    profile_name: &str,
-) -> anyhow::Result<()> {
+    options: &ExportOptions,
+) -> anyhow::Result<(ExportReport, AudioCopies)> {
     info!("Exporting {} tracks in {} playlists to {:?}",
           tracks.len(), playlists.len(), output_dir);
 
     // Validate output directory
     validate_usb_target(output_dir)?;
 
+    let mut report = ExportReport::default();
+
+    // Transcoded copies live outside the music folder
+    let mut source_dirs = vec![source_dir];
+    source_dirs.extend(options.transcode_dir.as_deref());
+    let sources = SourceIndex::new(&source_dirs);
+
+    // Tracks without audio: drop, keep with a warning, or stop here
+    let missing: HashSet<u32> = find_missing_sources(tracks, &sources).into_iter().collect();
+    let kept_tracks;
+    let kept_playlists;
+    let (tracks, playlists) = match options.missing_files {
+        _ if missing.is_empty() => (tracks, playlists),
+        MissingFilePolicy::Fail => {
+            let mut ids: Vec<_> = missing.into_iter().collect();
+            ids.sort_unstable();
+            anyhow::bail!("Source audio missing for {} track(s): {:?}", ids.len(), ids);
+        }
+        MissingFilePolicy::Warn => {
+            for track in tracks.iter().filter(|t| missing.contains(&t.id)) {
+                let warning = format!("Source file not found for track {}: {}", track.id, track.file_path);
+                warn!("{}", warning);
+                report.warnings.push(warning);
+            }
+            (tracks, playlists)
+        }
+        MissingFilePolicy::Skip => {
+            for track in tracks.iter().filter(|t| missing.contains(&t.id)) {
+                let message = format!("Track {} ({}) skipped: source file not found", track.id, track.title);
+                warn!("{}", message);
+                report.skipped.push(message);
+            }
+            (kept_tracks, kept_playlists) = without_tracks(tracks, playlists, &missing);
+            (kept_tracks.as_slice(), &kept_playlists)
+        }
+    };
+
+    let contents_name = options.dir_casing.contents_dir();
+    let tracks = rebase_tracks(tracks, contents_name);
+    let tracks = &*tracks;
+
     // Create directory structure
     
     let pioneer_dir = output_dir.join("PIONEER");
     let rekordbox_dir = pioneer_dir.join("rekordbox");
     let anlz_dir = pioneer_dir.join("USBANLZ");
-    let contents_dir = output_dir.join("Contents");
+    let contents_dir = output_dir.join(contents_name);
     let artwork_dir = pioneer_dir.join("Artwork");
     let backup_dir = pioneer_dir.join("DeviceLibBackup");
 
@@ -60,29 +304,45 @@ pub fn export_usb_with_profile(
     fs::create_dir_all(&artwork_dir)?;
     fs::create_dir_all(&backup_dir)?;
 
-    // Build PDB database
-    let mut pdb_builder = PdbBuilder::new();
+    let artwork_paths = export_artwork(tracks, &sources, Some(output_dir), &mut report);
 
-    for track in tracks {
-        let anlz_path = generate_anlz_path(track.id);
-        pdb_builder.add_track(track, &anlz_path);
+    // Build PDB database
+    let mut pdb_builder = pdb_builder(tracks, playlists, &artwork_paths, options);
+    for warning in pdb_builder.warnings() {
+        warn!("{}", warning);
+        report.warnings.push(warning.clone());
     }
 
-    // Add playlists
-    let mut playlist_id = 1u32;
-    for (name, track_ids) in playlists {
-        if !name.is_empty() {
-            pdb_builder.add_playlist(playlist_id, 0, name, track_ids.clone());
-            playlist_id += 1;
-        }
+    // Re-exports continue the stick's transaction sequence so players see
+    // the library changed
+    let pdb_path = rekordbox_dir.join("export.pdb");
+    if let Some(previous) = fs::read(&pdb_path).ok().and_then(|data| read_sequence(&data).ok()) {
+        pdb_builder.set_sequence(previous.wrapping_add(1));
     }
-    
+
     // Write export.pdb
-    let pdb_data = pdb_builder.build()?;
-    let pdb_path = rekordbox_dir.join("export.pdb");
+    let (pdb_data, unbuildable) = pdb_builder.build_with_skipped()?;
+    for (id, reason) in &unbuildable {
+        let message = format!("Track {} left out of export.pdb: {}", id, reason);
+        warn!("{}", message);
+        report.skipped.push(message);
+    }
+    // Tracks without a row get no ANLZ, audio copy or manifest entry either
+    let built;
+    let (tracks, playlists) = if unbuildable.is_empty() {
+        (tracks, playlists)
+    } else {
+        built = without_tracks(tracks, playlists, &unbuildable.iter().map(|(id, _)| *id).collect());
+        (built.0.as_slice(), &built.1)
+    };
+    report.tracks_written = tracks.len();
     let mut pdb_file = File::create(&pdb_path)?;
     pdb_file.write_all(&pdb_data)?;
     info!("Wrote export.pdb ({} bytes, {} pages)", pdb_data.len(), pdb_data.len() / 4096);
+
+    // Playlist order and UUIDs for rekordbox 6 importing the stick
+    let master_playlists = generate_master_playlists_xml(pdb_builder.playlists());
+    fs::write(rekordbox_dir.join("masterPlaylists6.xml"), master_playlists)?;
     
     // Write DEVSETTING.DAT
     let devsetting_data = generate_devsetting();
@@ -92,11 +352,23 @@ pub fn export_usb_with_profile(
     debug!("Wrote DEVSETTING.DAT ({} bytes)", devsetting_data.len());
     
     // Write djprofile.nxs
-    let djprofile_data = generate_djprofile(profile_name);
+    let djprofile_data = generate_djprofile(&options.profile_name);
     let djprofile_path = pioneer_dir.join("djprofile.nxs");
     let mut djprofile_file = File::create(&djprofile_path)?;
     djprofile_file.write_all(&djprofile_data)?;
     debug!("Wrote djprofile.nxs ({} bytes)", djprofile_data.len());
+
+    // Device id and backup date rekordbox reads when the stick is plugged in
+    let backup_info = DeviceBackupInfo {
+        uuid: options.clock.uuid(),
+        device_name: BACKUP_DEVICE_NAME.to_string(),
+        filesystem: "FAT32".to_string(),
+        backup_pc_name: DEFAULT_PROFILE_NAME.to_string(),
+    };
+    fs::write(
+        backup_dir.join(device_backup_info_name(BACKUP_PC_ID)),
+        generate_device_backup_info(&backup_info, BACKUP_PC_ID, &options.clock),
+    )?;
     
     // Generate ANLZ files for each track
     for track in tracks {
@@ -111,10 +383,40 @@ pub fn export_usb_with_profile(
         // The file path stored in ANLZ should be the USB-relative path
         let usb_file_path = track.file_path.clone();
         
+        // Pre-Nexus players only understand the monochrome waveform
+        let monochrome;
+        let waveform = if options.color_preview {
+            &track.waveform
+        } else {
+            monochrome = Waveform {
+                preview: track.waveform.preview.clone(),
+                ..Waveform::default()
+            };
+            &monochrome
+        };
+
+        let quantized;
+        let cue_points = match options.quantize_cues_ms {
+            Some(tolerance_ms) => {
+                quantized = quantize_cues(&track.cue_points, &track.beat_grid, tolerance_ms);
+                &quantized
+            }
+            None => &track.cue_points,
+        };
+
+        let slotted;
+        let cue_points = match options.hot_cue_slots {
+            Some(hot_cues) => {
+                slotted = assign_cue_slots(cue_points, hot_cues);
+                &slotted
+            }
+            None => cue_points,
+        };
+
         // Generate .DAT file
         let dat_data = generate_dat_file(
             &track.beat_grid,
-            &track.waveform,
+            waveform,
             &usb_file_path,
         )?;
         
@@ -123,33 +425,300 @@ pub fn export_usb_with_profile(
         debug!("Wrote ANLZ for track {}: {} bytes", track.id, dat_data.len());
         
         // Also generate .EXT file for Nexus+ compatibility
-        let ext_path = anlz_full_path.with_extension("EXT");
-        let ext_data = generate_ext_file(
-            &track.beat_grid,
-            &track.waveform,
-            &usb_file_path,
-            &track.cue_points,
-        )?;
-        let mut ext_file = File::create(&ext_path)?;
-        ext_file.write_all(&ext_data)?;
+        if options.write_ext {
+            let ext_path = anlz_full_path.with_extension("EXT");
+            let ext_data = generate_ext_file_with_layout(
+                &track.beat_grid,
+                waveform,
+                &usb_file_path,
+                cue_points,
+                &options.anlz_layout,
+            )?;
+            let mut ext_file = File::create(&ext_path)?;
+            ext_file.write_all(&ext_data)?;
+        }
 
         // Also generate .2EX file for CDJ-3000 and newer hardware
-        let two_ex_path = anlz_full_path.with_extension("2EX");
-        let two_ex_data = generate_2ex_file(
-            &track.beat_grid,
-            &track.waveform,
-            &usb_file_path,
-            &track.cue_points,
-        )?;
-        let mut two_ex_file = File::create(&two_ex_path)?;
-        two_ex_file.write_all(&two_ex_data)?;
+        if options.write_2ex {
+            let two_ex_path = anlz_full_path.with_extension("2EX");
+            let two_ex_data = generate_2ex_file_with_layout(
+                &track.beat_grid,
+                waveform,
+                &usb_file_path,
+                cue_points,
+                &options.anlz_layout,
+            )?;
+            let mut two_ex_file = File::create(&two_ex_path)?;
+            two_ex_file.write_all(&two_ex_data)?;
+        }
     }
     
-    // Copy audio files to Contents directory
-    copy_audio_files(tracks, source_dir, &contents_dir)?;
-    
-    info!("Export complete: {} tracks, {} playlists", tracks.len(), playlists.len());
-    
+    // Audio files for the Contents directory
+    let audio = plan_audio_copies(tracks, &sources, &contents_dir)?;
+    report.bytes_skipped += audio.bytes_skipped;
+
+    // Every track row points at an ANLZ file; make sure it really exists
+    report.missing_anlz = find_missing_anlz(tracks, output_dir);
+    for id in &report.missing_anlz {
+        let warning = format!("Track {} has no ANLZ file at {}", id, generate_anlz_path(*id));
+        warn!("{}", warning);
+        report.warnings.push(warning);
+    }
+
+    write_manifest(&report, tracks, playlists, &output_dir.join(MANIFEST_FILE_NAME))?;
+
+    Ok((report, audio))
+}
+
+/// What an export would write, worked out without touching the target
+#[derive(Debug, Default)]
+pub struct ExportPlan {
+    /// Tracks that would make it into export.pdb
+    pub tracks: usize,
+    /// Size of export.pdb
+    pub pdb_bytes: usize,
+    /// ANLZ files: a .DAT per track, plus .EXT/.2EX when the target uses them
+    pub anlz_files: usize,
+    /// Audio bytes to copy under Contents/ (files already there excluded)
+    pub bytes_to_copy: u64,
+    /// Ids of tracks whose source audio can't be found
+    pub missing_sources: Vec<u32>,
+    /// Problems the export would warn about (long names, deep nesting,
+    /// unreadable cover art)
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for ExportPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Export plan: {} tracks, export.pdb {:.1} KiB, {} ANLZ files, {:.1} MiB of audio to copy",
+            self.tracks,
+            self.pdb_bytes as f64 / 1024.0,
+            self.anlz_files,
+            self.bytes_to_copy as f64 / (1024.0 * 1024.0),
+        )?;
+        if !self.missing_sources.is_empty() {
+            write!(f, "\n  source audio missing for tracks {:?}", self.missing_sources)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Work out what `export_usb` would write to `output_dir`, without writing
+///
+/// Source lookup, the missing file policy's track selection, the PDB and
+/// the copy list are the ones a real run uses; files are only read (tags,
+/// cover art, what's already on the stick). Cover art that turns out not to
+/// decode still counts towards the PDB here. Under
+/// `MissingFilePolicy::Fail` the real export stops if `missing_sources`
+/// isn't empty.
+pub fn export_usb_plan(
+    tracks: &[TrackAnalysis],
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
+    source_dir: &Path,
+    output_dir: &Path,
+    options: &ExportOptions,
+) -> anyhow::Result<ExportPlan> {
+    let mut source_dirs = vec![source_dir];
+    source_dirs.extend(options.transcode_dir.as_deref());
+    let sources = SourceIndex::new(&source_dirs);
+
+    let missing_sources = find_missing_sources(tracks, &sources);
+    let kept;
+    let (tracks, playlists) = match options.missing_files {
+        MissingFilePolicy::Skip if !missing_sources.is_empty() => {
+            kept = without_tracks(tracks, playlists, &missing_sources.iter().copied().collect());
+            (kept.0.as_slice(), &kept.1)
+        }
+        _ => (tracks, playlists),
+    };
+
+    let contents_name = options.dir_casing.contents_dir();
+    let tracks = rebase_tracks(tracks, contents_name);
+
+    let mut report = ExportReport::default();
+    let artwork_paths = export_artwork(&tracks, &sources, None, &mut report);
+    let builder = pdb_builder(&tracks, playlists, &artwork_paths, options);
+    let mut warnings = builder.warnings().to_vec();
+    let (pdb_data, unbuildable) = builder.build_with_skipped()?;
+    for (id, reason) in &unbuildable {
+        warnings.push(format!("Track {} left out of export.pdb: {}", id, reason));
+    }
+    warnings.extend(report.skipped);
+
+    let built;
+    let tracks = if unbuildable.is_empty() {
+        &*tracks
+    } else {
+        built = without_tracks(&tracks, playlists, &unbuildable.iter().map(|(id, _)| *id).collect()).0;
+        built.as_slice()
+    };
+    let copies = plan_audio_copies(tracks, &sources, &output_dir.join(contents_name))?;
+    let files_per_track = 1 + options.write_ext as usize + options.write_2ex as usize;
+
+    Ok(ExportPlan {
+        tracks: tracks.len(),
+        pdb_bytes: pdb_data.len(),
+        anlz_files: tracks.len() * files_per_track,
+        bytes_to_copy: copies.bytes,
+        missing_sources,
+        warnings,
+    })
+}
+
+/// Ids of tracks whose source audio isn't in `sources`
+fn find_missing_sources(tracks: &[TrackAnalysis], sources: &SourceIndex) -> Vec<u32> {
+    tracks.iter()
+        .filter(|track| sources.find(&track.file_path).is_none())
+        .map(|track| track.id)
+        .collect()
+}
+
+/// `tracks` and `playlists` with the tracks in `ids` left out
+///
+/// Playlist entry numbers are assigned when the PDB is built, so filtering
+/// keeps the remaining entries contiguous.
+fn without_tracks(
+    tracks: &[TrackAnalysis],
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
+    ids: &HashSet<u32>,
+) -> (Vec<TrackAnalysis>, HashMap<Vec<String>, Vec<u32>>) {
+    let tracks = tracks.iter()
+        .filter(|t| !ids.contains(&t.id))
+        .cloned()
+        .collect();
+    let playlists = playlists.iter()
+        .map(|(name, track_ids)| {
+            (name.clone(), track_ids.iter().copied().filter(|id| !ids.contains(id)).collect())
+        })
+        .collect();
+    (tracks, playlists)
+}
+
+/// Tracks with their paths naming the audio folder exactly as it is cased
+/// on disk, so the PDB rows and ANLZ PPTH tags agree with the copied files
+///
+/// Tracks are only copied when a path actually changes, since the analysis
+/// (waveforms in particular) is large.
+fn rebase_tracks<'a>(tracks: &'a [TrackAnalysis], contents_name: &str) -> Cow<'a, [TrackAnalysis]> {
+    if tracks.iter().all(|track| rebase_contents_path(&track.file_path, contents_name) == track.file_path) {
+        return Cow::Borrowed(tracks);
+    }
+    tracks.iter()
+        .map(|track| TrackAnalysis {
+            file_path: rebase_contents_path(&track.file_path, contents_name),
+            ..track.clone()
+        })
+        .collect()
+}
+
+/// PDB builder holding the tracks and playlist tree of an export
+fn pdb_builder(
+    tracks: &[TrackAnalysis],
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
+    artwork_paths: &HashMap<u32, String>,
+    options: &ExportOptions,
+) -> PdbBuilder {
+    let mut builder = PdbBuilder::new();
+    builder.set_truncate_playlist_names(options.truncate_playlist_names);
+    builder.set_tool_marker(options.tool_marker.as_deref());
+    builder.set_energy_field(options.energy_tag.field());
+    builder.set_clock(options.clock);
+
+    for track in tracks {
+        let anlz_path = generate_anlz_path(track.id);
+        let artwork_path = artwork_paths.get(&track.id).map(String::as_str);
+        builder.add_track_with_artwork(track, &anlz_path, artwork_path);
+    }
+
+    // Playlists nest in the folders of their paths; ids are handed out in
+    // tree order with siblings sorted by name, so they're stable across runs
+    let mut playlist_id = 1u32;
+    add_playlist_tree(&mut builder, &PlaylistFolder::from_paths(playlists), 0, &mut playlist_id);
+    builder
+}
+
+/// Ids of tracks whose ANLZ0000.DAT (the analyze_path in their PDB row) is
+/// missing under `output_dir`
+pub fn find_missing_anlz(tracks: &[TrackAnalysis], output_dir: &Path) -> Vec<u32> {
+    tracks.iter()
+        .filter(|track| !output_dir.join(generate_anlz_path(track.id)).is_file())
+        .map(|track| track.id)
+        .collect()
+}
+
+/// Machine-readable description of an export, for downstream verification
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ExportManifest {
+    pub tracks: Vec<ManifestTrack>,
+    /// Playlist path, its folders and name joined by `/` -> track ids, in
+    /// playlist order
+    pub playlists: BTreeMap<String, Vec<u32>>,
+    pub warnings: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// One exported track as listed in the manifest
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ManifestTrack {
+    pub id: u32,
+    pub title: String,
+    pub artist: String,
+    /// Artist name for sorting, for tools building a library from the
+    /// stick (export.pdb has nowhere to put it)
+    #[serde(default)]
+    pub artist_sort: Option<String>,
+    /// USB-relative audio path stored in the PDB
+    pub file_path: String,
+    /// USB-relative path of the track's ANLZ0000.DAT
+    pub anlz_path: String,
+    pub file_hash: u64,
+    /// Where the music starts after leading silence (--silence-threshold)
+    #[serde(default)]
+    pub audio_start_ms: Option<f64>,
+    /// Paths (as in `ExportManifest::playlists`) of the playlists containing
+    /// this track
+    pub playlists: Vec<String>,
+}
+
+/// Write the export manifest JSON to `path`
+pub fn write_manifest(
+    report: &ExportReport,
+    tracks: &[TrackAnalysis],
+    playlists: &HashMap<Vec<String>, Vec<u32>>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let playlists: BTreeMap<String, Vec<u32>> = playlists
+        .iter()
+        .map(|(path, ids)| (path.join("/"), ids.clone()))
+        .collect();
+
+    let manifest = ExportManifest {
+        tracks: tracks.iter().map(|track| ManifestTrack {
+            id: track.id,
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            artist_sort: track.artist_sort.clone(),
+            file_path: track.file_path.clone(),
+            anlz_path: generate_anlz_path(track.id),
+            file_hash: track.file_hash,
+            audio_start_ms: track.audio_start_ms,
+            playlists: playlists.iter()
+                .filter(|(_, ids)| ids.contains(&track.id))
+                .map(|(name, _)| name.clone())
+                .collect(),
+        }).collect(),
+        playlists,
+        warnings: report.warnings.clone(),
+        skipped: report.skipped.clone(),
+    };
+
+    fs::write(path, serde_json::to_vec_pretty(&manifest)?)?;
+    debug!("Wrote manifest for {} tracks to {:?}", manifest.tracks.len(), path);
     Ok(())
 }
 
@@ -177,23 +746,225 @@ pub fn validate_usb_target(path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Copy audio files to Contents directory with hierarchical structure
-/// Creates both:
-/// - Contents/filename.ext (flat, at root)
+/// Write cover art for every track that has some embedded (only work out
+/// the paths when `output_dir` is None)
+///
+/// Returns track id -> Artwork table path. Artwork ids come from the image
+/// bytes, so the same art keeps its `a{id}.jpg` name across re-exports and
+/// tracks sharing a cover share one file; `PdbBuilder` reads the id back
+/// from the path. A corrupt or unsupported image only costs that track its
+/// artwork (artwork_id 0); the failure is logged and recorded in the report.
+fn export_artwork(
+    tracks: &[TrackAnalysis],
+    sources: &SourceIndex,
+    output_dir: Option<&Path>,
+    report: &mut ExportReport,
+) -> HashMap<u32, String> {
+    let mut paths = HashMap::new();
+    // artwork id -> hash of the image written under it
+    let mut written: HashMap<u32, u64> = HashMap::new();
+
+    for track in tracks {
+        let Some(source) = sources.find(&track.file_path) else {
+            continue;
+        };
+
+        let image = match extract_artwork(source) {
+            Ok(Some(image)) => image,
+            Ok(None) => continue,
+            Err(e) => {
+                skip_artwork(track, &e, report);
+                continue;
+            }
+        };
+
+        let hash = xxh3_64(&image);
+        let mut artwork_id = stable_artwork_id(&image);
+        while written.get(&artwork_id).is_some_and(|&h| h != hash) {
+            artwork_id = artwork_id % MAX_ARTWORK_ID + 1;
+        }
+        let Some(output_dir) = output_dir.filter(|_| !written.contains_key(&artwork_id)) else {
+            let folder = artwork_folder_path(artwork_id);
+            paths.insert(track.id, format!("/{}/{}", folder, artwork_thumbnail_name(artwork_id)));
+            written.insert(artwork_id, hash);
+            continue;
+        };
+
+        match write_artwork(&image, artwork_id, output_dir) {
+            Ok(path) => {
+                paths.insert(track.id, path);
+                written.insert(artwork_id, hash);
+            }
+            Err(e) => skip_artwork(track, &e, report),
+        }
+    }
+
+    paths
+}
+
+fn skip_artwork(track: &TrackAnalysis, error: &anyhow::Error, report: &mut ExportReport) {
+    let message = format!("Artwork for track {} ({}) skipped: {}", track.id, track.title, error);
+    warn!("{}", message);
+    report.skipped.push(message);
+}
+
+/// Playlists grouped into the folders of their paths
+///
+/// Folders and playlists are kept apart, so a folder and a playlist sharing
+/// a name (["House"] and ["House", "Deep"]) are two entries with their own
+/// ids.
+#[derive(Default)]
+struct PlaylistFolder<'a> {
+    folders: BTreeMap<&'a str, PlaylistFolder<'a>>,
+    playlists: BTreeMap<&'a str, &'a [u32]>,
+}
+
+impl<'a> PlaylistFolder<'a> {
+    /// An empty path is skipped
+    fn from_paths(playlists: &'a HashMap<Vec<String>, Vec<u32>>) -> Self {
+        let mut root = Self::default();
+        for (path, track_ids) in playlists {
+            let Some((leaf, folders)) = path.split_last() else {
+                continue;
+            };
+            let folder = folders.iter()
+                .fold(&mut root, |folder, part| folder.folders.entry(part.as_str()).or_default());
+            folder.playlists.insert(leaf.as_str(), track_ids);
+        }
+        root
+    }
+}
+
+/// Add a folder's contents under `parent_id`, depth first
+///
+/// Siblings are sorted by name, a folder ahead of a playlist with the same
+/// name; `next_id` is the id the next folder or playlist gets.
+fn add_playlist_tree(builder: &mut PdbBuilder, folder: &PlaylistFolder, parent_id: u32, next_id: &mut u32) {
+    let mut children: Vec<(&str, Option<&PlaylistFolder>)> = folder.folders.iter()
+        .map(|(&name, sub)| (name, Some(sub)))
+        .chain(folder.playlists.keys().map(|&name| (name, None)))
+        .collect();
+    children.sort_by_key(|&(name, sub)| (name, sub.is_none()));
+
+    for (name, sub) in children {
+        let id = *next_id;
+        *next_id += 1;
+        match sub {
+            Some(sub) => {
+                builder.add_folder(id, parent_id, name);
+                add_playlist_tree(builder, sub, id, next_id);
+            }
+            None => builder.add_playlist(id, parent_id, name, folder.playlists[name].to_vec()),
+        }
+    }
+}
+
+/// Point a `/Contents/...` USB path at the audio folder named `contents_dir`
+///
+/// Paths outside the audio folder are returned unchanged.
+fn rebase_contents_path(file_path: &str, contents_dir: &str) -> String {
+    let relative = file_path.strip_prefix('/').unwrap_or(file_path);
+    match relative.split_once('/') {
+        Some((dir, rest)) if dir.eq_ignore_ascii_case("contents") => format!("/{}/{}", contents_dir, rest),
+        _ => file_path.to_string(),
+    }
+}
+
+/// Path of a `/Contents/...` USB path inside the audio folder (the whole
+/// path, less its leading `/`, for paths elsewhere)
+fn contents_relative_path(file_path: &str) -> &str {
+    let relative = file_path.strip_prefix('/').unwrap_or(file_path);
+    match relative.split_once('/') {
+        Some((dir, rest)) if dir.eq_ignore_ascii_case("contents") => rest,
+        _ => relative,
+    }
+}
+
+/// Files under the source directories, walked once per export
+///
+/// Track paths hold the file's path below the music (or transcode)
+/// directory, so tracks sharing a file name in different folders find
+/// their own file.
+struct SourceIndex {
+    /// `/`-separated path below its source directory -> file (the first
+    /// directory holding a path wins)
+    by_path: HashMap<String, PathBuf>,
+    /// File name -> files, in walk order
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl SourceIndex {
+    fn new(source_dirs: &[&Path]) -> Self {
+        let mut index = SourceIndex { by_path: HashMap::new(), by_name: HashMap::new() };
+        for source_dir in source_dirs {
+            for entry in WalkDir::new(source_dir)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| !e.file_type().is_dir())
+            {
+                let path = entry.path();
+                let Some(relative) = path.strip_prefix(source_dir).ok().and_then(Path::to_str) else {
+                    continue;
+                };
+                index.by_path.entry(relative.replace('\\', "/")).or_insert_with(|| path.to_path_buf());
+                if let Some(name) = entry.file_name().to_str() {
+                    index.by_name.entry(name.to_string()).or_default().push(path.to_path_buf());
+                }
+            }
+        }
+        index
+    }
+
+    /// Source audio file for a track's USB path
+    ///
+    /// A path without folders that isn't at the top of a source directory
+    /// is looked up by file name (the first match): tracks analyzed before
+    /// paths kept their folders only stored the file name.
+    fn find(&self, usb_path: &str) -> Option<&Path> {
+        let relative = contents_relative_path(usb_path);
+        if let Some(path) = self.by_path.get(relative) {
+            return Some(path);
+        }
+        if relative.contains('/') {
+            return None;
+        }
+        self.by_name.get(relative)?.first().map(PathBuf::as_path)
+    }
+}
+
+/// Audio files an export copies, and what they add up to
+#[derive(Debug, Default)]
+pub struct AudioCopies {
+    /// Source file -> destination under the audio folder
+    pub copies: Vec<(PathBuf, PathBuf)>,
+    /// Size of the files in `copies`
+    pub bytes: u64,
+    /// Size of the destinations left out (see `ExportReport::bytes_skipped`)
+    pub bytes_skipped: u64,
+}
+
+/// Work out the copies into the audio folder with hierarchical structure
+/// Plans both:
+/// - Contents/path/filename.ext (the track's own path, at the root for
+///   files at the top of the music directory)
 /// - Contents/Artist/Album/filename.ext (hierarchical by metadata)
-fn copy_audio_files(
+///
+/// Destinations that already exist, or that an earlier track copies to, are
+/// left out. Nothing is written.
+fn plan_audio_copies(
     tracks: &[TrackAnalysis],
-    source_dir: &Path,
+    sources: &SourceIndex,
     contents_dir: &Path,
-) -> anyhow::Result<()> {
-    use std::collections::HashSet;
-    
-    // Track which files we've already copied to avoid duplicates
-    let mut copied_files: HashSet<String> = HashSet::new();
+) -> anyhow::Result<AudioCopies> {
+    let mut plan = AudioCopies::default();
+    // Destinations already planned, to avoid duplicates
+    let mut planned: HashSet<PathBuf> = HashSet::new();
     
     for track in tracks {
-        // Extract filename from USB path
-        let filename = Path::new(&track.file_path)
+        // Extract path and filename from USB path
+        let relative = contents_relative_path(&track.file_path);
+        let filename = Path::new(relative)
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("");
@@ -203,60 +974,53 @@ fn copy_audio_files(
             continue;
         }
         
-        // Find source file
-        let mut source_path = None;
-        for entry in WalkDir::new(source_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.file_name().to_str() == Some(filename) {
-                source_path = Some(entry.path().to_path_buf());
-                break;
-            }
-        }
-        
-        let source = match source_path {
+        let source = match sources.find(relative) {
             Some(p) => p,
             None => {
-                warn!("Source file not found for track {}: {}", track.id, filename);
+                // Already reported by the missing file policy
+                debug!("Source file not found for track {}: {}", track.id, relative);
                 continue;
             }
         };
         
-        // 1. Copy to flat Contents/ directory (root level)
-        let flat_dest = contents_dir.join(filename);
-        if !flat_dest.exists() {
-            fs::copy(&source, &flat_dest)?;
-            debug!("Copied to flat: {:?} -> {:?}", source, flat_dest);
-        }
-        
-        // 2. Copy to hierarchical Artist/Album/ structure
+        let size = fs::metadata(source)?.len();
+
+        // 1. The track's own path, which the PDB row points at
+        let mut destinations = vec![contents_dir.join(relative)];
+
+        // 2. Hierarchical Artist/Album/ structure
         let artist = sanitize_path_component(&track.artist);
         let album = track.album.as_ref()
             .map(|a| sanitize_path_component(a))
             .unwrap_or_else(|| "Unknown Album".to_string());
-        
         if !artist.is_empty() {
-            // Create artist directory
-            let artist_dir = contents_dir.join(&artist);
-            fs::create_dir_all(&artist_dir)?;
-            
-            // Create album directory inside artist
-            let album_dir = artist_dir.join(&album);
-            fs::create_dir_all(&album_dir)?;
-            
-            // Copy file to album directory
-            let hier_dest = album_dir.join(filename);
-            let hier_key = format!("{}/{}/{}", artist, album, filename);
-            
-            if !copied_files.contains(&hier_key) && !hier_dest.exists() {
-                fs::copy(&source, &hier_dest)?;
-                copied_files.insert(hier_key);
-                debug!("Copied to hierarchy: {:?} -> {:?}", source, hier_dest);
+            destinations.push(contents_dir.join(&artist).join(&album).join(filename));
+        }
+
+        for dest in destinations {
+            if !dest.exists() && planned.insert(dest.clone()) {
+                plan.bytes += size;
+                plan.copies.push((source.to_path_buf(), dest));
+            } else {
+                plan.bytes_skipped += size;
             }
         }
     }
     
+    Ok(plan)
+}
+
+/// Make the copies planned by `plan_audio_copies`, adding the bytes written
+/// to the report
+fn copy_audio_files(audio: AudioCopies, report: &mut ExportReport) -> anyhow::Result<()> {
+    for (source, dest) in audio.copies {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        report.bytes_copied += fs::copy(&source, &dest)?;
+        debug!("Copied {:?} -> {:?}", source, dest);
+    }
+
     Ok(())
 }
 
@@ -292,17 +1056,521 @@ fn sanitize_path_component(name: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rekordbox_core::{BeatGrid, FileType};
     use tempfile::TempDir;
-    
+
+    fn make_track(id: u32, file_name: &str) -> TrackAnalysis {
+        TrackAnalysis {
+            id,
+            file_path: format!("/Contents/{}", file_name),
+            title: "Track".into(),
+            artist: "Artist".into(),
+            duration_secs: 60.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 128,
+            bpm: 120.0,
+            beat_grid: BeatGrid::constant_tempo(120.0, 0.0, 60_000.0),
+            file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    /// MP3 with an ID3v2.3 APIC frame holding `picture`, followed by a few
+    /// silent MPEG-1 Layer III frames
+    fn mp3_with_picture(picture: &[u8]) -> Vec<u8> {
+        let mut apic = vec![0u8]; // ISO-8859-1 text
+        apic.extend_from_slice(b"image/jpeg\0");
+        apic.push(3); // front cover
+        apic.push(0); // empty description
+        apic.extend_from_slice(picture);
+
+        let mut frame = b"APIC".to_vec();
+        frame.extend_from_slice(&(apic.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&apic);
+
+        let size = frame.len() as u32;
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        data.extend_from_slice(&[
+            (size >> 21 & 0x7F) as u8,
+            (size >> 14 & 0x7F) as u8,
+            (size >> 7 & 0x7F) as u8,
+            (size & 0x7F) as u8,
+        ]);
+        data.extend_from_slice(&frame);
+
+        // 128 kbps, 44.1 kHz: 417-byte frames
+        for _ in 0..4 {
+            data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+            data.extend_from_slice(&[0u8; 413]);
+        }
+        data
+    }
+
     #[test]
     fn test_validate_writable() {
         let tmp = TempDir::new().unwrap();
         assert!(validate_usb_target(tmp.path()).is_ok());
     }
     
+    #[test]
+    fn test_target_hardware_options() {
+        let cdj3000 = ExportOptions::for_target(TargetHardware::Cdj3000);
+        assert!(cdj3000.write_2ex);
+        assert!(cdj3000.color_preview);
+
+        let cdj900 = ExportOptions::for_target(TargetHardware::Cdj900);
+        assert!(!cdj900.write_2ex);
+        assert!(!cdj900.color_preview);
+        assert!(!cdj900.write_ext);
+
+        assert_eq!(
+            TargetHardware::from_str("xdj-xz", true).unwrap(),
+            TargetHardware::XdjXz
+        );
+    }
+
     #[test]
     fn test_validate_nonexistent() {
         let result = validate_usb_target(Path::new("/nonexistent/path"));
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_corrupt_artwork_does_not_abort_export() {
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        fs::write(
+            source.path().join("track.mp3"),
+            mp3_with_picture(b"\xFF\xD8 not really a jpeg"),
+        ).unwrap();
+        let tracks = vec![make_track(1, "track.mp3")];
+
+        let report = export_usb(
+            &tracks,
+            &HashMap::new(),
+            source.path(),
+            output.path(),
+            &ExportOptions::default(),
+        ).unwrap();
+
+        assert_eq!(report.skipped.len(), 1);
+        assert!(report.skipped[0].contains("track 1"));
+        assert!(output.path().join("PIONEER/rekordbox/export.pdb").exists());
+        assert!(!output.path().join("PIONEER/Artwork/00001").exists());
+
+        // No artwork path means the track row gets artwork_id 0
+        let mut report = ExportReport::default();
+        let paths = export_artwork(&tracks, &SourceIndex::new(&[source.path()]), Some(output.path()), &mut report);
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn test_cover_art_exported_and_shared() {
+        let png = |rgb: [u8; 3]| {
+            let mut png = Vec::new();
+            image::RgbImage::from_pixel(300, 300, image::Rgb(rgb))
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            png
+        };
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        let (red, blue) = (png([200, 40, 40]), png([40, 40, 200]));
+        fs::write(source.path().join("a.mp3"), mp3_with_picture(&red)).unwrap();
+        fs::write(source.path().join("b.mp3"), mp3_with_picture(&red)).unwrap();
+        fs::write(source.path().join("c.mp3"), mp3_with_picture(&blue)).unwrap();
+        let tracks = vec![make_track(1, "a.mp3"), make_track(2, "b.mp3"), make_track(3, "c.mp3")];
+
+        let report = export_usb(&tracks, &HashMap::new(), source.path(), output.path(), &ExportOptions::default())
+            .unwrap();
+        assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+
+        let pdb = fs::read(output.path().join("PIONEER/rekordbox/export.pdb")).unwrap();
+        let mut reader = rekordbox_core::PdbReader::new(std::io::Cursor::new(pdb)).unwrap();
+        let artworks: HashMap<u32, String> = reader.artworks()
+            .map(|row| row.map(|row| (row.id, row.name)))
+            .collect::<rekordbox_core::Result<_>>()
+            .unwrap();
+        let mut rows: Vec<_> = reader.tracks().collect::<rekordbox_core::Result<_>>().unwrap();
+        rows.sort_by_key(|row| row.id);
+
+        // Identical covers share one Artwork row and one pair of files
+        assert_eq!(artworks.len(), 2);
+        assert_eq!(rows[0].artwork_id, rows[1].artwork_id);
+        assert_ne!(rows[0].artwork_id, rows[2].artwork_id);
+        for row in &rows {
+            let path = &artworks[&row.artwork_id];
+            assert_eq!(path, &format!("/{}/{}", artwork_folder_path(row.artwork_id), artwork_thumbnail_name(row.artwork_id)));
+            let thumbnail = image::open(output.path().join(&path[1..])).unwrap();
+            assert_eq!((thumbnail.width(), thumbnail.height()), (80, 80));
+            let full = image::open(output.path().join(path[1..].replace(".jpg", "_m.jpg"))).unwrap();
+            assert_eq!((full.width(), full.height()), (240, 240));
+        }
+    }
+
+    #[test]
+    fn test_nested_playlists_become_folders() {
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        let path = |parts: &[&str]| parts.iter().map(|part| part.to_string()).collect::<Vec<_>>();
+        let playlists = HashMap::from([
+            (path(&["Openers"]), vec![]),
+            (path(&["House"]), vec![]),
+            (path(&["House", "Deep"]), vec![]),
+            (path(&["House", "Acid"]), vec![]),
+            (path(&["Sets", "2024", "Friday"]), vec![]),
+            (path(&["Sets", "Saturday"]), vec![]),
+            (path(&["AC/DC"]), vec![]),
+            (path(&[]), vec![]),
+        ]);
+        export_usb(&[], &playlists, source.path(), output.path(), &ExportOptions::default()).unwrap();
+
+        let pdb = fs::read(output.path().join("PIONEER/rekordbox/export.pdb")).unwrap();
+        let mut reader = rekordbox_core::PdbReader::new(std::io::Cursor::new(pdb)).unwrap();
+        let mut rows: Vec<_> = reader.playlists()
+            .map(|row| row.map(|row| (row.id, row.parent_id, row.name, row.is_folder, row.sort_order)))
+            .collect::<rekordbox_core::Result<_>>()
+            .unwrap();
+        rows.sort();
+
+        // The "House" folder and playlist get their own ids, and a slash in
+        // a name doesn't make a folder
+        let row = |id, parent_id, name: &str, is_folder, sort_order| (id, parent_id, name.to_string(), is_folder, sort_order);
+        assert_eq!(rows, vec![
+            row(1, 0, "AC/DC", false, 0),
+            row(2, 0, "House", true, 1),
+            row(3, 2, "Acid", false, 0),
+            row(4, 2, "Deep", false, 1),
+            row(5, 0, "House", false, 2),
+            row(6, 0, "Openers", false, 3),
+            row(7, 0, "Sets", true, 4),
+            row(8, 7, "2024", true, 0),
+            row(9, 8, "Friday", false, 0),
+            row(10, 7, "Saturday", false, 1),
+        ]);
+
+        // The folder and playlist named "House" are distinct nodes in
+        // masterPlaylists6.xml too
+        let xml = fs::read_to_string(output.path().join("PIONEER/rekordbox/masterPlaylists6.xml")).unwrap();
+        let ids: HashSet<&str> = xml.lines()
+            .filter_map(|line| line.split("Id=\"").nth(1)?.split('"').next())
+            .collect();
+        assert_eq!(ids.len(), rows.len());
+    }
+
+    #[test]
+    fn test_reexport_bumps_sequence() {
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        let pdb_path = output.path().join("PIONEER/rekordbox/export.pdb");
+        let export = || export_usb(
+            &[],
+            &HashMap::new(),
+            source.path(),
+            output.path(),
+            &ExportOptions::default(),
+        ).unwrap();
+
+        export();
+        let first = read_sequence(&fs::read(&pdb_path).unwrap()).unwrap();
+        export();
+        let second = read_sequence(&fs::read(&pdb_path).unwrap()).unwrap();
+        assert_eq!(second, first + 1);
+    }
+
+    /// Source dir with only a.mp3, tracks a.mp3 (1) and b.mp3 (2, missing),
+    /// both in one playlist
+    fn missing_file_setup() -> (TempDir, Vec<TrackAnalysis>, HashMap<Vec<String>, Vec<u32>>) {
+        let source = TempDir::new().unwrap();
+        fs::write(source.path().join("a.mp3"), b"audio").unwrap();
+        let tracks = vec![make_track(1, "a.mp3"), make_track(2, "b.mp3")];
+        let playlists = HashMap::from([(vec!["Set".to_string()], vec![2, 1])]);
+        (source, tracks, playlists)
+    }
+
+    fn export_with_policy(
+        source: &TempDir,
+        output: &TempDir,
+        tracks: &[TrackAnalysis],
+        playlists: &HashMap<Vec<String>, Vec<u32>>,
+        policy: MissingFilePolicy,
+    ) -> anyhow::Result<ExportReport> {
+        let options = ExportOptions { missing_files: policy, ..ExportOptions::default() };
+        export_usb(tracks, playlists, source.path(), output.path(), &options)
+    }
+
+    fn read_manifest(output: &TempDir) -> ExportManifest {
+        serde_json::from_slice(&fs::read(output.path().join(MANIFEST_FILE_NAME)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_missing_file_skip_drops_track() {
+        let (source, tracks, playlists) = missing_file_setup();
+        let output = TempDir::new().unwrap();
+
+        let report = export_with_policy(&source, &output, &tracks, &playlists, MissingFilePolicy::Skip).unwrap();
+        // (a.mp3 isn't real audio, so its artwork is skipped too)
+        let dropped: Vec<_> = report.skipped.iter().filter(|s| s.contains("source file")).collect();
+        assert_eq!(dropped.len(), 1);
+        assert!(dropped[0].contains("Track 2"));
+
+        let manifest = read_manifest(&output);
+        assert_eq!(manifest.tracks.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(manifest.playlists["Set"], vec![1]);
+        assert!(!output.path().join(generate_anlz_path(2)).exists());
+        assert!(output.path().join("Contents/a.mp3").exists());
+    }
+
+    #[test]
+    fn test_unbuildable_track_left_out_everywhere() {
+        let source = TempDir::new().unwrap();
+        fs::write(source.path().join("a.mp3"), b"audio").unwrap();
+        fs::write(source.path().join("b.mp3"), b"audio").unwrap();
+        let mut huge = make_track(2, "b.mp3");
+        huge.comment = Some("x".repeat(5000));
+        let tracks = vec![make_track(1, "a.mp3"), huge];
+        let playlists = HashMap::from([(vec!["Set".to_string()], vec![2, 1])]);
+        let output = TempDir::new().unwrap();
+        let options = ExportOptions::default();
+
+        let report = export_usb(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+        assert_eq!(report.tracks_written, 1);
+        assert!(report.skipped.iter().any(|s| s.contains("Track 2 left out")));
+
+        let manifest = read_manifest(&output);
+        assert_eq!(manifest.tracks.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(manifest.playlists["Set"], vec![1]);
+        assert!(!output.path().join(generate_anlz_path(2)).exists());
+        assert!(!output.path().join("Contents/b.mp3").exists());
+
+        let plan = export_usb_plan(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+        assert_eq!(plan.tracks, 1);
+        assert_eq!(plan.anlz_files, 1 + options.write_ext as usize + options.write_2ex as usize);
+    }
+
+    #[test]
+    fn test_missing_file_warn_keeps_track() {
+        let (source, tracks, playlists) = missing_file_setup();
+        let output = TempDir::new().unwrap();
+
+        let report = export_with_policy(&source, &output, &tracks, &playlists, MissingFilePolicy::Warn).unwrap();
+        assert!(!report.skipped.iter().any(|s| s.contains("source file")));
+        assert!(report.warnings.iter().any(|w| w.contains("track 2")));
+
+        let manifest = read_manifest(&output);
+        assert_eq!(manifest.tracks.len(), 2);
+        assert_eq!(manifest.playlists["Set"], vec![2, 1]);
+
+        let master = fs::read_to_string(output.path().join("PIONEER/rekordbox/masterPlaylists6.xml")).unwrap();
+        assert!(master.contains("Name=\"Set\""));
+    }
+
+    #[test]
+    fn test_missing_file_fail_aborts() {
+        let (source, tracks, playlists) = missing_file_setup();
+        let output = TempDir::new().unwrap();
+
+        let err = export_with_policy(&source, &output, &tracks, &playlists, MissingFilePolicy::Fail).unwrap_err();
+        assert!(err.to_string().contains("[2]"), "{}", err);
+        assert!(!output.path().join("PIONEER").exists());
+    }
+
+    #[test]
+    fn test_uppercase_contents_dir() {
+        let (source, tracks, playlists) = missing_file_setup();
+        let output = TempDir::new().unwrap();
+        let options = ExportOptions { dir_casing: DirCasing::Upper, ..ExportOptions::default() };
+        export_usb(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+
+        let dirs: Vec<_> = fs::read_dir(output.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(dirs.contains(&"CONTENTS".to_string()));
+        assert!(!dirs.contains(&"Contents".to_string()));
+        assert!(output.path().join("CONTENTS/a.mp3").exists());
+
+        let contains = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        let pdb = fs::read(output.path().join("PIONEER/rekordbox/export.pdb")).unwrap();
+        assert!(contains(&pdb, b"/CONTENTS/a.mp3"));
+        assert!(!contains(&pdb, b"/Contents/"));
+
+        // PPTH is UTF-16BE
+        let dat = fs::read(output.path().join(generate_anlz_path(1))).unwrap();
+        let ppth: Vec<u8> = "/CONTENTS/a.mp3".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert!(contains(&dat, &ppth));
+
+        assert_eq!(read_manifest(&output).tracks[0].file_path, "/CONTENTS/a.mp3");
+        assert_eq!(rebase_contents_path("/Other/a.mp3", "CONTENTS"), "/Other/a.mp3");
+
+        // Tracks are only copied when the casing changes a path
+        let tracks = [make_track(1, "a.mp3")];
+        assert!(matches!(rebase_tracks(&tracks, "Contents"), Cow::Borrowed(_)));
+        assert!(matches!(rebase_tracks(&tracks, "CONTENTS"), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_deterministic_exports_identical() {
+        let (source, tracks, playlists) = missing_file_setup();
+        let options = ExportOptions { clock: ExportClock::EPOCH, ..ExportOptions::default() };
+        let export = || {
+            let output = TempDir::new().unwrap();
+            export_usb(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+            output
+        };
+        let (first, second) = (export(), export());
+
+        let backup_info = |output: &TempDir| {
+            fs::read(output.path().join("PIONEER/DeviceLibBackup").join(device_backup_info_name(BACKUP_PC_ID))).unwrap()
+        };
+        assert_eq!(backup_info(&first), backup_info(&second));
+        let json: serde_json::Value = serde_json::from_slice(&backup_info(&first)).unwrap();
+        assert_eq!(json["info"][0]["backup_date"], "1970/01/01 00:00:00");
+        assert_eq!(json["uuid"], ExportClock::EPOCH.uuid());
+
+        // The default history session is dated by the same clock
+        let pdb = |output: &TempDir| fs::read(output.path().join("PIONEER/rekordbox/export.pdb")).unwrap();
+        assert_eq!(pdb(&first), pdb(&second));
+    }
+
+    #[test]
+    fn test_report_counts_copied_and_skipped_bytes() {
+        let source = TempDir::new().unwrap();
+        fs::write(source.path().join("a.mp3"), [1u8; 1000]).unwrap();
+        fs::write(source.path().join("b.mp3"), [2u8; 500]).unwrap();
+        // Tracks 1 and 3 are the same file, shared by both playlists
+        let tracks = vec![make_track(1, "a.mp3"), make_track(2, "b.mp3"), make_track(3, "a.mp3")];
+        let playlists = HashMap::from([
+            (vec!["Friday".to_string()], vec![1, 2]),
+            (vec!["Saturday".to_string()], vec![3]),
+        ]);
+        let output = TempDir::new().unwrap();
+        let export = || export_usb(&tracks, &playlists, source.path(), output.path(), &ExportOptions::default()).unwrap();
+
+        // Each file once flat and once under Artist/Album; track 3's copies
+        // are track 1's
+        let report = export();
+        assert_eq!(report.bytes_copied, 2 * (1000 + 500));
+        assert_eq!(report.bytes_skipped, 2 * 1000);
+
+        // Nothing new to copy the second time
+        let report = export();
+        assert_eq!(report.bytes_copied, 0);
+        assert_eq!(report.bytes_skipped, 2 * (2 * 1000 + 500));
+    }
+
+    #[test]
+    fn test_plan_matches_export() {
+        let (source, mut tracks, playlists) = missing_file_setup();
+        fs::write(source.path().join("c.mp3"), [3u8; 700]).unwrap();
+        tracks.push(make_track(3, "c.mp3"));
+        let output = TempDir::new().unwrap();
+        let options = ExportOptions { missing_files: MissingFilePolicy::Skip, ..ExportOptions::default() };
+
+        let plan = export_usb_plan(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 0);
+        assert_eq!(plan.missing_sources, vec![2]);
+        assert_eq!(plan.tracks, 2);
+        // .DAT, .EXT and .2EX for the CDJ-3000
+        assert_eq!(plan.anlz_files, 2 * 3);
+
+        let report = export_usb(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+        assert_eq!(plan.bytes_to_copy, report.bytes_copied);
+        assert_eq!(plan.tracks, report.tracks_written);
+        let pdb = fs::metadata(output.path().join("PIONEER/rekordbox/export.pdb")).unwrap();
+        assert_eq!(plan.pdb_bytes as u64, pdb.len());
+        let anlz_files = WalkDir::new(output.path().join("PIONEER/USBANLZ"))
+            .into_iter()
+            .filter(|e| e.as_ref().unwrap().file_type().is_file())
+            .count();
+        assert_eq!(plan.anlz_files, anlz_files);
+
+        // Audio already on the stick isn't copied again
+        let plan = export_usb_plan(&tracks, &playlists, source.path(), output.path(), &options).unwrap();
+        assert_eq!(plan.bytes_to_copy, 0);
+    }
+
+    #[test]
+    fn test_same_file_name_in_different_folders() {
+        let source = TempDir::new().unwrap();
+        for (dir, byte) in [("House", 1u8), ("Techno", 2), ("Deep", 3)] {
+            fs::create_dir_all(source.path().join(dir)).unwrap();
+            let name = if dir == "Deep" { "b.mp3" } else { "a.mp3" };
+            fs::write(source.path().join(dir).join(name), [byte; 100]).unwrap();
+        }
+        // Track 3 was analyzed before paths kept their folders
+        let tracks = vec![make_track(1, "House/a.mp3"), make_track(2, "Techno/a.mp3"), make_track(3, "b.mp3")];
+        let output = TempDir::new().unwrap();
+
+        let report = export_usb(&tracks, &HashMap::new(), source.path(), output.path(), &ExportOptions::default())
+            .unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let contents = output.path().join("Contents");
+        assert_eq!(fs::read(contents.join("House/a.mp3")).unwrap(), [1; 100]);
+        assert_eq!(fs::read(contents.join("Techno/a.mp3")).unwrap(), [2; 100]);
+        assert_eq!(fs::read(contents.join("b.mp3")).unwrap(), [3; 100]);
+
+        let missing = make_track(4, "Ambient/a.mp3");
+        let sources = SourceIndex::new(&[source.path()]);
+        assert_eq!(find_missing_sources(&[missing], &sources), vec![4]);
+    }
+
+    #[test]
+    fn test_missing_anlz_flagged() {
+        let source = TempDir::new().unwrap();
+        let output = TempDir::new().unwrap();
+        let tracks = vec![make_track(1, "a.mp3"), make_track(2, "b.mp3")];
+
+        let report = export_usb(
+            &tracks,
+            &HashMap::new(),
+            source.path(),
+            output.path(),
+            &ExportOptions::default(),
+        ).unwrap();
+        assert!(report.missing_anlz.is_empty());
+
+        fs::remove_file(output.path().join(generate_anlz_path(2))).unwrap();
+        assert_eq!(find_missing_anlz(&tracks, output.path()), vec![2]);
+    }
+
+    #[test]
+    fn test_manifest_lists_all_tracks() {
+        let tmp = TempDir::new().unwrap();
+        let mut tracks = vec![make_track(1, "a.mp3"), make_track(2, "b.mp3"), make_track(3, "c.mp3")];
+        tracks[1].file_hash = 0xDEAD_BEEF_CAFE_F00D;
+        tracks[1].artist = "The Chemical Brothers".into();
+        tracks[1].artist_sort = Some("Chemical Brothers, The".into());
+        tracks[1].audio_start_ms = Some(2000.0);
+        let playlists = HashMap::from([
+            (vec!["Friday".to_string()], vec![2, 1]),
+            (vec!["Sets".to_string(), "Saturday".to_string()], vec![2]),
+        ]);
+        let report = ExportReport {
+            warnings: vec!["a warning".into()],
+            ..Default::default()
+        };
+
+        let path = tmp.path().join(MANIFEST_FILE_NAME);
+        write_manifest(&report, &tracks, &playlists, &path).unwrap();
+        let manifest: ExportManifest = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(manifest.tracks.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(manifest.tracks[1].file_path, "/Contents/b.mp3");
+        assert_eq!(manifest.tracks[1].anlz_path, generate_anlz_path(2));
+        assert_eq!(manifest.tracks[1].file_hash, 0xDEAD_BEEF_CAFE_F00D);
+        assert_eq!(manifest.tracks[1].artist, "The Chemical Brothers");
+        assert_eq!(manifest.tracks[1].artist_sort.as_deref(), Some("Chemical Brothers, The"));
+        assert_eq!(manifest.tracks[1].audio_start_ms, Some(2000.0));
+        assert_eq!(manifest.tracks[1].playlists, vec!["Friday", "Sets/Saturday"]);
+        assert!(manifest.tracks[2].playlists.is_empty());
+        assert_eq!(manifest.playlists["Friday"], vec![2, 1]);
+        assert_eq!(manifest.warnings, vec!["a warning"]);
+
+        let round_trip: ExportManifest =
+            serde_json::from_str(&serde_json::to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(round_trip, manifest);
+    }
 }
@@ -0,0 +1,197 @@
+//! Acoustic fingerprints for duplicate detection
+//!
+//! A deliberately coarse, chromaprint-like hash: the first two minutes are
+//! cut into 9 segments, each summarized by its energy in 9 log-spaced bands
+//! (100 Hz - 5 kHz). Each of the 64 bits says whether the level difference
+//! between two neighbouring bands grew from one segment to the next. Gain,
+//! EQ tilt and codec noise shift band levels the same way in every segment,
+//! so re-encodes of a recording flip only a few bits while unrelated tracks
+//! differ in about half of them.
+
+use std::collections::HashSet;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use rekordbox_core::TrackAnalysis;
+
+/// Audio considered for the fingerprint (intros and edits differ later on)
+const MAX_SECONDS: f64 = 120.0;
+
+/// Time segments; consecutive pairs give 8 rows of bits
+const SEGMENTS: usize = 9;
+
+/// Band edges in Hz (9 bands, 8 neighbouring pairs per segment)
+const BAND_EDGES_HZ: [f32; 10] = [100.0, 155.0, 240.0, 370.0, 575.0, 890.0, 1380.0, 2140.0, 3300.0, 5000.0];
+
+const FFT_SIZE: usize = 2048;
+
+/// FFT frames averaged per segment
+const FRAMES_PER_SEGMENT: usize = 16;
+
+/// Fingerprints differing in at most this many bits are the same recording
+pub const NEAR_MATCH_BITS: u32 = 10;
+
+/// Compute the fingerprint of mono samples
+///
+/// Returns None for audio too short (or too quiet) to say anything about.
+/// Segments are spread over `samples`, so fingerprints only compare when
+/// taken over the same stretch (the analyzer always uses the start).
+pub fn acoustic_id(samples: &[f32], sample_rate: u32) -> Option<u64> {
+    if sample_rate == 0 {
+        return None;
+    }
+    let usable = samples.len().min((MAX_SECONDS * sample_rate as f64) as usize);
+    let segment_len = usable / SEGMENTS;
+    if segment_len < FFT_SIZE {
+        return None;
+    }
+
+    let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos()))
+        .collect();
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    let band_bins: Vec<(usize, usize)> = BAND_EDGES_HZ.windows(2)
+        .map(|edge| {
+            let start = ((edge[0] / bin_hz) as usize).max(1);
+            let end = ((edge[1] / bin_hz) as usize).clamp(start + 1, FFT_SIZE / 2);
+            (start, end)
+        })
+        .collect();
+
+    // Log band energy per segment, averaged over evenly spaced frames
+    let mut levels = [[0.0f32; 9]; SEGMENTS];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); FFT_SIZE];
+    let hop = (segment_len - FFT_SIZE) / FRAMES_PER_SEGMENT.max(1);
+    let mut total = 0.0f32;
+    for (segment, level) in levels.iter_mut().enumerate() {
+        let mut energy = [0.0f32; 9];
+        for frame in 0..FRAMES_PER_SEGMENT {
+            let start = segment * segment_len + frame * hop;
+            for (i, value) in buffer.iter_mut().enumerate() {
+                *value = Complex::new(samples[start + i] * window[i], 0.0);
+            }
+            fft.process(&mut buffer);
+            for (band, &(lo, hi)) in band_bins.iter().enumerate() {
+                energy[band] += buffer[lo..hi].iter().map(|c| c.norm_sqr()).sum::<f32>() / (hi - lo) as f32;
+            }
+        }
+        total += energy.iter().sum::<f32>();
+        for (band, e) in energy.iter().enumerate() {
+            level[band] = (e + 1e-9).ln();
+        }
+    }
+    if total < 1e-6 {
+        return None;
+    }
+
+    let mut id = 0u64;
+    for segment in 1..SEGMENTS {
+        for band in 0..8 {
+            let before = levels[segment - 1][band] - levels[segment - 1][band + 1];
+            let now = levels[segment][band] - levels[segment][band + 1];
+            if now > before {
+                id |= 1 << ((segment - 1) * 8 + band);
+            }
+        }
+    }
+    Some(id)
+}
+
+/// Whether two fingerprints are close enough to be the same recording
+pub fn is_near_match(a: u64, b: u64) -> bool {
+    (a ^ b).count_ones() <= NEAR_MATCH_BITS
+}
+
+/// Group track ids whose fingerprints nearly match (groups of two or more)
+///
+/// Each group is seeded by its lowest-indexed track and collects every later
+/// track within `NEAR_MATCH_BITS` of it. Tracks without a fingerprint are
+/// never grouped.
+pub fn find_duplicates(tracks: &[TrackAnalysis]) -> Vec<Vec<u32>> {
+    let mut grouped = HashSet::new();
+    let mut groups = Vec::new();
+
+    for (i, track) in tracks.iter().enumerate() {
+        let Some(seed) = track.acoustic_id else { continue };
+        if grouped.contains(&track.id) {
+            continue;
+        }
+
+        let mut group = vec![track.id];
+        for other in &tracks[i + 1..] {
+            if grouped.contains(&other.id) {
+                continue;
+            }
+            if other.acoustic_id.is_some_and(|id| is_near_match(seed, id)) {
+                group.push(other.id);
+            }
+        }
+
+        if group.len() > 1 {
+            grouped.extend(group.iter().copied());
+            groups.push(group);
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    /// 18 s of two-note chords changing every second, picked by `seed`
+    fn chords(seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1u32 << 24) as f32
+        };
+
+        let mut samples = Vec::new();
+        for _ in 0..18 {
+            let f1 = 110.0 * 2f32.powf(next() * 4.0);
+            let f2 = 110.0 * 2f32.powf(next() * 5.0);
+            for i in 0..SAMPLE_RATE {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let tone = |f: f32| (2.0 * std::f32::consts::PI * f * t).sin();
+                samples.push(0.3 * tone(f1) + 0.2 * tone(f2) + 0.05 * tone(f1 * 3.0));
+            }
+        }
+        samples
+    }
+
+    /// Stand-in for a lossy re-encode: quieter, darker, coarser and noisy
+    fn reencode(samples: &[f32]) -> Vec<f32> {
+        let mut state = 12345u32;
+        let mut lowpassed = 0.0f32;
+        samples.iter()
+            .map(|&s| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = ((state >> 16) as f32 / 32768.0 - 1.0) * 0.005;
+                lowpassed += 0.6 * (s - lowpassed);
+                ((0.7 * lowpassed + noise) * 127.0).round() / 127.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reencode_matches_different_track_does_not() {
+        let original = chords(1);
+        let a = acoustic_id(&original, SAMPLE_RATE).unwrap();
+        let b = acoustic_id(&reencode(&original), SAMPLE_RATE).unwrap();
+        let other = acoustic_id(&chords(2), SAMPLE_RATE).unwrap();
+
+        assert!(is_near_match(a, b), "re-encode differs in {} bits", (a ^ b).count_ones());
+        assert!(!is_near_match(a, other), "different track differs in only {} bits", (a ^ other).count_ones());
+    }
+
+    #[test]
+    fn test_too_short_or_silent() {
+        assert_eq!(acoustic_id(&[0.1; 1000], SAMPLE_RATE), None);
+        assert_eq!(acoustic_id(&vec![0.0; SAMPLE_RATE as usize * 10], SAMPLE_RATE), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Encoder delay (gapless) compensation
+//!
+//! MP3 and AAC encoders prepend priming samples, so decoded audio starts a
+//! few dozen milliseconds late. The delay is read from:
+//! - the LAME tag inside the first MP3 frame (Xing/Info header), plus the
+//!   standard 529-sample MP3 decoder delay
+//! - an `iTunSMPB` tag (" 00000000 <delay hex> <padding hex> <length hex> ...")
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use rekordbox_core::TrackAnalysis;
+
+/// Decoder delay added on top of the LAME encoder delay (MP3 synthesis filterbank)
+const MP3_DECODER_DELAY: u32 = 529;
+
+/// How much of the file start to scan for the first MP3 frame
+const SCAN_LIMIT: usize = 256 * 1024;
+
+/// Read the encoder delay in samples from an MP3's LAME tag
+pub fn lame_encoder_delay(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    let mut head = vec![0u8; 10];
+    file.read_exact(&mut head).ok()?;
+
+    // Skip a leading ID3v2 tag
+    let mut skip = 0usize;
+    if &head[..3] == b"ID3" {
+        skip = 10 + head[6..10].iter().fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    }
+
+    let mut data = head;
+    file.take((skip + SCAN_LIMIT) as u64).read_to_end(&mut data).ok()?;
+    parse_lame_delay(data.get(skip..)?)
+}
+
+/// Find the first frame and read delay from its Xing/Info + LAME header
+fn parse_lame_delay(data: &[u8]) -> Option<u32> {
+    let start = data.windows(2).position(|w| w[0] == 0xFF && w[1] & 0xE0 == 0xE0)?;
+    let frame = &data[start..];
+    let header = frame.get(..4)?;
+
+    // Side info length depends on MPEG version and channel mode
+    let mpeg1 = header[1] & 0x18 == 0x18;
+    let mono = header[3] >> 6 == 0b11;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) => 17,
+        (false, false) => 17,
+        (false, true) => 9,
+    };
+
+    let xing = 4 + side_info;
+    let tag = frame.get(xing..xing + 4)?;
+    if tag != b"Xing" && tag != b"Info" {
+        return None;
+    }
+
+    // Optional fields: frames, bytes, TOC, quality
+    let flags = u32::from_be_bytes(frame.get(xing + 4..xing + 8)?.try_into().ok()?);
+    let mut pos = xing + 8;
+    if flags & 0x1 != 0 { pos += 4; }
+    if flags & 0x2 != 0 { pos += 4; }
+    if flags & 0x4 != 0 { pos += 100; }
+    if flags & 0x8 != 0 { pos += 4; }
+
+    // LAME extension: 9-byte version string, then 12 bytes before the
+    // 12-bit delay / 12-bit padding pair
+    let lame = frame.get(pos..pos + 24)?;
+    if &lame[..4] != b"LAME" && &lame[..4] != b"Lavf" && &lame[..4] != b"Lavc" {
+        return None;
+    }
+    let delay = ((lame[21] as u32) << 4) | (lame[22] as u32 >> 4);
+    Some(delay + MP3_DECODER_DELAY)
+}
+
+/// Parse the delay (in samples) from an iTunSMPB tag value
+pub fn parse_itunsmpb(value: &str) -> Option<u32> {
+    let delay = value.split_whitespace().nth(1)?;
+    u32::from_str_radix(delay, 16).ok()
+}
+
+/// Shift the beat grid and cue points later by the encoder delay
+pub fn apply_encoder_delay(analysis: &mut TrackAnalysis, delay_samples: u32) {
+    if delay_samples == 0 || analysis.sample_rate == 0 {
+        return;
+    }
+
+    let delay_ms = delay_samples as f64 / analysis.sample_rate as f64 * 1000.0;
+    analysis.beat_grid.shift_ms(delay_ms);
+    if let Some(start) = &mut analysis.audio_start_ms {
+        *start += delay_ms;
+    }
+    for cue in &mut analysis.cue_points {
+        cue.time_ms += delay_ms;
+    }
+}
+
+/// First frame of a LAME-encoded file: MPEG-1 Layer III joint stereo,
+/// Info header without optional fields, LAME tag with the given delay
+/// (test fixture)
+#[cfg(test)]
+pub(crate) fn lame_frame(delay: u32, padding: u32) -> Vec<u8> {
+    let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+    frame.extend_from_slice(&[0u8; 32]);
+    frame.extend_from_slice(b"Info");
+    frame.extend_from_slice(&0u32.to_be_bytes());
+    frame.extend_from_slice(b"LAME3.100");
+    frame.extend_from_slice(&[0u8; 12]);
+    frame.push((delay >> 4) as u8);
+    frame.push((((delay & 0xF) << 4) | (padding >> 8)) as u8);
+    frame.push((padding & 0xFF) as u8);
+    frame.resize(417, 0);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rekordbox_core::{BeatGrid, CuePoint, FileType};
+    use tempfile::TempDir;
+
+    fn make_analysis() -> TrackAnalysis {
+        TrackAnalysis {
+            id: 1,
+            file_path: "/Contents/track.mp3".into(),
+            title: "Track".into(),
+            artist: "Artist".into(),
+            duration_secs: 60.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 128,
+            bpm: 120.0,
+            beat_grid: BeatGrid::constant_tempo(120.0, 100.0, 60_000.0),
+            cue_points: vec![CuePoint { hot_cue: 1, time_ms: 1000.0, ..Default::default() }],
+            file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    #[test]
+    fn test_lame_delay_shifts_grid() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("track.mp3");
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x00\x04\0\0\0\0".to_vec();
+        data.extend(lame_frame(576, 960));
+        std::fs::write(&path, data).unwrap();
+
+        let delay = lame_encoder_delay(&path).unwrap();
+        assert_eq!(delay, 576 + MP3_DECODER_DELAY);
+
+        let mut analysis = make_analysis();
+        analysis.audio_start_ms = Some(50.0);
+        apply_encoder_delay(&mut analysis, delay);
+
+        let expected_ms = 1105.0 / 44100.0 * 1000.0;
+        assert!((analysis.audio_start_ms.unwrap() - (50.0 + expected_ms)).abs() < 1e-9);
+        assert!((analysis.beat_grid.first_beat_ms - (100.0 + expected_ms)).abs() < 1e-9);
+        assert!((analysis.beat_grid.beats[0].time_ms - (100.0 + expected_ms)).abs() < 1e-9);
+        assert!((analysis.cue_points[0].time_ms - (1000.0 + expected_ms)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_lame_tag() {
+        let mut frame = lame_frame(576, 0);
+        frame[36..40].copy_from_slice(b"\0\0\0\0");
+        assert_eq!(parse_lame_delay(&frame), None);
+    }
+
+    #[test]
+    fn test_parse_itunsmpb() {
+        assert_eq!(
+            parse_itunsmpb(" 00000000 00000840 000001C0 0000000000A5A7C0 00000000 00000000"),
+            Some(0x840)
+        );
+        assert_eq!(parse_itunsmpb("garbage"), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! Musical key detection
+//!
+//! Krumhansl-Schmuckler key finding on a chromagram of the decoded mono
+//! samples: the spectrum of windows spread over the track is folded into
+//! 12 pitch classes, and the key is the rotation of the major or minor
+//! probe-tone profile that correlates best with the result.
+//!
+//! Only the tonic and mode are estimated. Tracks that modulate get the key
+//! that dominates overall.
+
+use rekordbox_core::Key;
+use rustfft::{num_complex::Complex, FftPlanner};
+
+/// Long enough to tell semitones apart in the bass (2.7 Hz bins at 44.1 kHz)
+const FFT_SIZE: usize = 16384;
+
+/// Upper bound on windows folded into the chromagram, spread over the track
+const MAX_WINDOWS: usize = 64;
+
+/// Frequencies outside this range carry little pitch information (kick
+/// drums below, cymbals and upper harmonics above)
+const MIN_HZ: f32 = 100.0;
+const MAX_HZ: f32 = 5000.0;
+
+/// Mean square below this is silence (-60 dBFS)
+const SILENCE: f32 = 1e-6;
+
+/// Krumhansl-Kessler probe-tone ratings, from the tonic upwards
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+/// Estimate the key of mono samples
+///
+/// Returns None for audio too short, too quiet or too atonal to call.
+/// Chroma is averaged over all of `samples`; the analyzer passes only the
+/// start of long tracks.
+pub fn detect_key(samples: &[f32], sample_rate: u32) -> Option<Key> {
+    if samples.len() < FFT_SIZE || sample_rate == 0 {
+        return None;
+    }
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    if mean_square < SILENCE {
+        return None;
+    }
+
+    let chroma = chromagram(samples, sample_rate);
+    let mut best: Option<(f32, Key)> = None;
+    for tonic in 0..12 {
+        for (profile, is_major) in [(&MAJOR_PROFILE, true), (&MINOR_PROFILE, false)] {
+            let rotated: Vec<f32> = (0..12).map(|pc| profile[(pc + 12 - tonic) % 12]).collect();
+            let Some(r) = correlation(&chroma, &rotated) else {
+                continue;
+            };
+            if best.is_none_or(|(best_r, _)| r > best_r) {
+                best = Some((r, Key::new(tonic as u8, is_major)));
+            }
+        }
+    }
+    best.filter(|&(r, _)| r > 0.0).map(|(_, key)| key)
+}
+
+/// Spectral magnitude per pitch class (0 = C), summed over the windows
+fn chromagram(samples: &[f32], sample_rate: u32) -> [f32; 12] {
+    let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos()))
+        .collect();
+
+    // Pitch class of every bin in range, A4 = 440 Hz
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    let bins: Vec<(usize, usize)> = (1..FFT_SIZE / 2)
+        .filter(|&bin| (MIN_HZ..=MAX_HZ).contains(&(bin as f32 * bin_hz)))
+        .map(|bin| {
+            let midi = 69.0 + 12.0 * (bin as f32 * bin_hz / 440.0).log2();
+            (bin, (midi.round() as i32).rem_euclid(12) as usize)
+        })
+        .collect();
+
+    let windows = (samples.len() / FFT_SIZE).min(MAX_WINDOWS);
+    let stride = if windows > 1 { (samples.len() - FFT_SIZE) / (windows - 1) } else { 0 };
+
+    let mut chroma = [0.0f32; 12];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); FFT_SIZE];
+    for w in 0..windows {
+        let start = w * stride;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for &(bin, pitch_class) in &bins {
+            chroma[pitch_class] += buffer[bin].norm();
+        }
+    }
+    chroma
+}
+
+/// Pearson correlation, None when either side is flat
+fn correlation(a: &[f32], b: &[f32]) -> Option<f32> {
+    let mean = |v: &[f32]| v.iter().sum::<f32>() / v.len() as f32;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let mut covariance = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    let denominator = (var_a * var_b).sqrt();
+    (denominator > f32::EPSILON).then(|| covariance / denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// 4 s of equal-level sines at the given MIDI notes, with a few
+    /// harmonics like a real instrument
+    fn notes(midi: &[u8]) -> Vec<f32> {
+        let hz: Vec<f32> = midi.iter().map(|&n| 440.0 * 2f32.powf((n as f32 - 69.0) / 12.0)).collect();
+        (0..SAMPLE_RATE as usize * 4)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let tone = |f: f32| (1..=3)
+                    .map(|h| (2.0 * std::f32::consts::PI * f * h as f32 * t).sin() / (h * h) as f32)
+                    .sum::<f32>();
+                0.2 * hz.iter().map(|&f| tone(f)).sum::<f32>() / hz.len() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_note_gives_its_pitch_class() {
+        for midi in [57, 60, 66] {
+            let key = detect_key(&notes(&[midi]), SAMPLE_RATE).unwrap();
+            assert_eq!(key.pitch_class, midi % 12, "MIDI note {}", midi);
+        }
+    }
+
+    #[test]
+    fn test_chords_give_key_and_mode() {
+        // C major with the root in the bass: C3 C4 E4 G4
+        assert_eq!(detect_key(&notes(&[48, 60, 64, 67]), SAMPLE_RATE), Some(Key::new(0, true)));
+        // A minor: A2 A3 C4 E4
+        let a_minor = detect_key(&notes(&[45, 57, 60, 64]), SAMPLE_RATE).unwrap();
+        assert_eq!(a_minor, Key::new(9, false));
+        assert_eq!(a_minor.to_rekordbox_id(), 4);
+        // F# major: F#3 F#4 A#4 C#5
+        assert_eq!(detect_key(&notes(&[54, 66, 70, 73]), SAMPLE_RATE), Some(Key::new(6, true)));
+    }
+
+    #[test]
+    fn test_no_key_for_silence_or_short_audio() {
+        assert_eq!(detect_key(&vec![0.0; SAMPLE_RATE as usize], SAMPLE_RATE), None);
+        assert_eq!(detect_key(&notes(&[60])[..1000], SAMPLE_RATE), None);
+    }
+}
@@ -7,26 +7,48 @@
 //! - Communication with CLI client via TCP socket
 
 mod analyzer;
+mod artwork;
+mod autocue;
 mod config;
+mod cue_import;
+mod disk_image;
+mod energy;
 mod export;
+mod fingerprint;
+mod gapless;
+mod key;
 mod navidrome;
+mod playlist_import;
+mod quality;
+mod selftest;
+mod serato;
 mod server;
+mod sidecar;
+mod transcode;
 mod waveform;
+mod watch;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
-use tracing::{info, Level};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use tracing::{info, warn, Level};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use rekordbox_core::AnalysisCache;
-use config::{Config, NavidromeConfig};
+use rekordbox_core::{AnalysisCache, AnlzLayout};
+use analyzer::AnalyzeCounts;
+use config::{Config, ConfigFile, NavidromeConfig};
+use waveform::WaveformConfig;
 
 #[derive(Parser, Debug)]
 #[command(name = "rekordbox-server")]
 #[command(about = "Pioneer DJ export server for NAS deployment")]
 struct Args {
+    /// TOML config file (keys named like the flags; flags given here win)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
     /// Music directory to analyze
     #[arg(short, long, default_value = "/mnt/ssd/pre-export")]
     music_dir: PathBuf,
@@ -35,7 +57,7 @@ struct Args {
     #[arg(short, long, default_value = "/var/cache/rekordbox")]
     cache_dir: PathBuf,
     
-    /// TCP bind address (host:port) - use 0.0.0.0 for network access
+    /// Bind address: host:port for TCP (0.0.0.0 for network access) or a Unix socket path
     #[arg(short, long, default_value = "0.0.0.0:6969")]
     bind: String,
     
@@ -46,7 +68,95 @@ struct Args {
     /// Export directly to path without running server
     #[arg(short, long)]
     export: Option<PathBuf>,
+
+    /// Keep the cache current while serving: analyze audio files as they are added or changed under --music-dir, forget removed ones
+    #[arg(long, conflicts_with = "export")]
+    watch: bool,
+
+    /// Write --export as a FAT32 disk image of this many MiB instead of a directory
+    #[arg(long, requires = "export")]
+    image_size: Option<u64>,
+
+    /// Print what --export would write (PDB size, ANLZ files, audio to copy, missing sources) and exit without writing it; plans from the analysis cache only, leaving out files not analyzed yet
+    #[arg(long, requires = "export", conflicts_with = "image_size")]
+    plan: bool,
     
+    /// Target player model (picks which ANLZ files and color waveforms are written)
+    #[arg(long, value_enum, default_value = "cdj3000")]
+    target: export::TargetHardware,
+
+    /// First id for tracks not seen before; known files keep their ids (use an offset to avoid clashing with an existing library)
+    #[arg(long, default_value_t = 1)]
+    starting_track_id: u32,
+
+    /// Round detected BPM to multiples of this (0.01 keeps full PDB precision)
+    #[arg(long, default_value_t = 0.5)]
+    bpm_precision: f64,
+
+    /// Add memory cues at the track start and energy drops when a track has no cues
+    #[arg(long)]
+    auto_cues: bool,
+
+    /// Export the first N cues of each track as hot cues A.. and the rest as memory cues
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=8))]
+    hot_cues: Option<u8>,
+
+    /// rekordbox collection XML (File > Export Collection in xml format) whose hot cues, memory cues and loops replace those found in the audio files
+    #[arg(long, value_name = "FILE")]
+    rekordbox_xml: Option<PathBuf>,
+
+    /// Snap exported cues lying within this many ms of a beat onto the beat grid
+    #[arg(long, value_name = "MS")]
+    quantize_cues: Option<f64>,
+
+    /// Tracks whose audio file is missing at export: skip, warn (export anyway) or fail
+    #[arg(long, value_enum, default_value = "warn")]
+    missing_files: export::MissingFilePolicy,
+
+    /// Casing of the exported audio folder: mixed (Contents) or upper (CONTENTS)
+    #[arg(long, value_enum, default_value = "mixed")]
+    dir_casing: export::DirCasing,
+
+    /// Cut playlist and folder names longer than 64 characters instead of only warning about them
+    #[arg(long)]
+    truncate_playlist_names: bool,
+
+    /// Write each track's 1-10 energy rating into the comment, the mix name, or nowhere (off)
+    #[arg(long, value_enum, default_value = "off")]
+    energy_tag: export::EnergyTag,
+
+    /// Comma-separated .EXT/.2EX section order, e.g. PPTH,PQTZ,PCO2,PWV5 (unlisted sections follow in the default order)
+    #[arg(long, value_name = "TAGS")]
+    anlz_layout: Option<AnlzLayout>,
+
+    /// Fast metadata pass: tags and duration only (BPM from tags, no waveforms); not cached
+    #[arg(long)]
+    metadata_only: bool,
+
+    /// When BPM detection is torn between half and double tempo, pick the one nearer the genre's usual tempo (e.g. 174 for drum & bass)
+    #[arg(long)]
+    genre_tempo_hints: bool,
+
+    /// Record where each track's audio first rises above this level (dBFS, e.g. -60) as its audio_start_ms, in the manifest and track listings
+    #[arg(long, value_name = "DB", allow_hyphen_values = true)]
+    silence_threshold: Option<f64>,
+
+    /// Scale each track's waveforms so its loudest moment fills the display (for quiet material such as classical)
+    #[arg(long)]
+    normalize_waveforms: bool,
+
+    /// Re-encode sources the players can't read (Opus, Ogg, WMA) to AIFF with ffmpeg
+    #[arg(long)]
+    transcode_incompatible: bool,
+
+    /// Date exports 1970-01-01 and fix the device UUID, so exporting the same library twice gives identical files (for CI)
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Leave the rekord-export marker out of the track rows' message field
+    #[arg(long)]
+    no_tool_marker: bool,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, default_value = "info")]
     log_level: String,
@@ -66,11 +176,47 @@ struct Args {
     /// Navidrome password
     #[arg(long, env = "NAVIDROME_PASS")]
     navidrome_pass: Option<String>,
+
+    /// Prefix to strip from Navidrome paths when its library root differs from --music-dir
+    #[arg(long, env = "NAVIDROME_PATH_PREFIX")]
+    navidrome_path_prefix: Option<String>,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
+
+    let mut config = Config {
+        music_dir: args.music_dir,
+        cache_dir: args.cache_dir,
+        output_dir: args.export.clone(),
+        bind_addr: args.bind,
+        max_concurrent: 1, // Single-threaded for memory efficiency
+        navidrome: None,
+        target: args.target,
+        starting_track_id: args.starting_track_id.max(1),
+        bpm_precision: args.bpm_precision,
+        auto_cues: args.auto_cues,
+        rekordbox_xml: args.rekordbox_xml,
+        hot_cue_slots: args.hot_cues,
+        quantize_cues_ms: args.quantize_cues,
+        missing_files: args.missing_files,
+        dir_casing: args.dir_casing,
+        truncate_playlist_names: args.truncate_playlist_names,
+        energy_tag: args.energy_tag,
+        anlz_layout: args.anlz_layout.unwrap_or_default(),
+        metadata_only: args.metadata_only,
+        genre_tempo_hints: args.genre_tempo_hints,
+        silence_threshold_db: args.silence_threshold,
+        waveform: WaveformConfig { normalize: args.normalize_waveforms, ..WaveformConfig::default() },
+        transcode_incompatible: args.transcode_incompatible,
+        deterministic: args.deterministic,
+        tool_marker: !args.no_tool_marker,
+    };
+    if let Some(path) = &args.config {
+        config = config.with_file(ConfigFile::load(path)?, |id| is_explicit(&matches, id));
+    }
     
     // Setup dual logging (terminal + file)
     let level = match args.log_level.to_lowercase().as_str() {
@@ -82,7 +228,7 @@ async fn main() -> anyhow::Result<()> {
         _ => Level::INFO,
     };
 
-    let log_dir = args.log_dir.as_ref().unwrap_or(&args.cache_dir);
+    let log_dir = args.log_dir.as_ref().unwrap_or(&config.cache_dir);
 
     // Ensure log directory exists
     std::fs::create_dir_all(log_dir)?;
@@ -119,18 +265,26 @@ async fn main() -> anyhow::Result<()> {
     let _log_guard = _guard;
     
     info!("rekordbox-server starting");
-    info!("Music directory: {:?}", args.music_dir);
-    info!("Cache directory: {:?}", args.cache_dir);
+    if let Some(path) = &args.config {
+        info!("Config file: {:?}", path);
+    }
+    info!("Music directory: {:?}", config.music_dir);
+    info!("Cache directory: {:?}", config.cache_dir);
     info!("Log directory: {:?}", log_dir);
+    if config.transcode_incompatible && !transcode::encoder_available() {
+        warn!("--transcode-incompatible needs ffmpeg on PATH; Opus/Ogg/WMA files will be skipped");
+    }
     
     // Initialize cache
-    let cache = AnalysisCache::new(&args.cache_dir)?;
+    let cache = AnalysisCache::new(&config.cache_dir)?;
 
-    // Build Navidrome config if all parameters provided
+    // Build Navidrome config if all parameters provided (overrides the file's)
     let navidrome = match (&args.navidrome_url, &args.navidrome_user, &args.navidrome_pass) {
         (Some(url), Some(user), Some(pass)) => {
             info!("Navidrome integration enabled: {}", url);
-            Some(NavidromeConfig::new(url.clone(), user.clone(), pass.clone()))
+            let mut nav_config = NavidromeConfig::new(url.clone(), user.clone(), pass.clone());
+            nav_config.path_prefix = args.navidrome_path_prefix.clone();
+            Some(nav_config)
         }
         (Some(_), _, _) | (_, Some(_), _) | (_, _, Some(_)) => {
             tracing::warn!("Navidrome config incomplete - need --navidrome-url, --navidrome-user, and --navidrome-pass");
@@ -139,26 +293,192 @@ async fn main() -> anyhow::Result<()> {
         _ => None,
     };
 
-    let config = Config {
-        music_dir: args.music_dir,
-        cache_dir: args.cache_dir,
-        output_dir: args.export.clone(),
-        bind_addr: args.bind,
-        max_concurrent: 1, // Single-threaded for memory efficiency
-        navidrome,
-    };
+    if navidrome.is_some() {
+        config.navidrome = navidrome;
+    }
     
     // If --export is specified, run export directly and exit
+    if let (Some(output_path), true) = (&args.export, args.plan) {
+        info!("Planning export to {:?}", output_path);
+        let plan = plan_export(&config, &cache, output_path).await?;
+        println!("{}", plan);
+        return Ok(());
+    }
+
     if let Some(output_path) = args.export {
         info!("Running direct export to {:?}", output_path);
 
-        let result = analyzer::analyze_directory(&config, &cache).await?;
-        export::export_usb(&result.tracks, &result.playlists, &config.music_dir, &output_path)?;
+        let summary = direct_export(&config, &cache, &output_path, args.image_size).await?;
+        println!("{}", summary);
 
-        info!("Export complete");
+        // Non-zero exit so cron jobs notice tracks that didn't make it
+        let failed = summary.failed();
+        if failed > 0 {
+            anyhow::bail!("{} track(s) failed to analyze or export", failed);
+        }
+        info!("Export complete ({} warnings, {} skipped)", summary.report.warnings.len(), summary.report.skipped.len());
         return Ok(());
     }
     
+    if args.watch {
+        let (config, cache) = (config.clone(), cache.clone());
+        tokio::spawn(async move {
+            if let Err(e) = watch::run(config, cache).await {
+                warn!("Watch mode stopped: {:#}", e);
+            }
+        });
+    }
+
     // Otherwise run as server
     server::run(config, cache).await
 }
+
+/// Outcome of a direct `--export` run
+struct ExportSummary {
+    counts: AnalyzeCounts,
+    /// Tracks handed to the export
+    tracks: usize,
+    report: export::ExportReport,
+}
+
+impl ExportSummary {
+    /// Audio files that couldn't be analyzed plus tracks left out of export.pdb
+    fn failed(&self) -> usize {
+        self.counts.skipped + self.tracks.saturating_sub(self.report.tracks_written)
+    }
+}
+
+impl std::fmt::Display for ExportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Export summary: {} analyzed, {} cached, {} failed, {} written ({} warnings, {} skipped)",
+            self.counts.analyzed,
+            self.counts.cache_hits,
+            self.failed(),
+            self.report.tracks_written,
+            self.report.warnings.len(),
+            self.report.skipped.len(),
+        )?;
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "\n  audio: {:.1} MiB copied, {:.1} MiB already on the stick or copied for another track",
+            mib(self.report.bytes_copied),
+            mib(self.report.bytes_skipped),
+        )?;
+        for warning in &self.report.warnings {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        for skipped in &self.report.skipped {
+            write!(f, "\n  skipped: {}", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Analyze the music directory and export it to a directory or disk image
+async fn direct_export(
+    config: &Config,
+    cache: &AnalysisCache,
+    output_path: &Path,
+    image_size_mb: Option<u64>,
+) -> anyhow::Result<ExportSummary> {
+    let result = analyzer::analyze_directory(config, cache, &Default::default()).await?;
+    let options = export_options(config);
+    let report = match image_size_mb {
+        Some(size_mb) => disk_image::export_to_image(
+            &result.tracks,
+            &result.playlists,
+            &config.music_dir,
+            output_path,
+            size_mb * 1024 * 1024,
+            &options,
+        )?,
+        None => export::export_usb(&result.tracks, &result.playlists, &config.music_dir, output_path, &options)?,
+    };
+
+    Ok(ExportSummary { counts: result.counts, tracks: result.tracks.len(), report })
+}
+
+/// Work out what exporting the music directory to `output_path` would
+/// write, from the tracks already in the cache
+///
+/// Nothing is analyzed, transcoded or saved, so a plan leaves the cache as
+/// it found it; files not analyzed yet are left out and warned about.
+async fn plan_export(
+    config: &Config,
+    cache: &AnalysisCache,
+    output_path: &Path,
+) -> anyhow::Result<export::ExportPlan> {
+    let options = analyzer::AnalyzeOptions { cached_only: true, ..Default::default() };
+    let result = analyzer::analyze_directory(config, cache, &options).await?;
+    if result.counts.uncached > 0 {
+        warn!(
+            "{} files aren't analyzed yet and are left out of the plan; export (or warm the cache) to include them",
+            result.counts.uncached
+        );
+    }
+    export::export_usb_plan(&result.tracks, &result.playlists, &config.music_dir, output_path, &export_options(config))
+}
+
+/// Export settings for the configured target
+fn export_options(config: &Config) -> export::ExportOptions {
+    export::ExportOptions {
+        hot_cue_slots: config.hot_cue_slots,
+        quantize_cues_ms: config.quantize_cues_ms,
+        missing_files: config.missing_files,
+        dir_casing: config.dir_casing,
+        truncate_playlist_names: config.truncate_playlist_names,
+        energy_tag: config.energy_tag,
+        anlz_layout: config.anlz_layout.clone(),
+        transcode_dir: config.transcode_dir(),
+        clock: config.export_clock(),
+        tool_marker: config.tool_marker(),
+        ..export::ExportOptions::for_target(config.target)
+    }
+}
+
+/// Whether a setting was given on the command line or via env rather than
+/// left at its default
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::tone_wav;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_direct_export_summary_counts() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        let output = tmp.path().join("usb");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(music_dir.join("a.wav"), tone_wav(1, 2)).unwrap();
+        std::fs::write(music_dir.join("b.wav"), tone_wav(1, 2)).unwrap();
+        std::fs::write(music_dir.join("broken.mp3"), [0u8; 16]).unwrap();
+
+        let config = Config {
+            output_dir: Some(output.clone()),
+            ..Config::for_test(tmp.path())
+        };
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+
+        let summary = direct_export(&config, &cache, &output, None).await.unwrap();
+        // a.wav and b.wav share their audio, so the second is a cache hit
+        assert_eq!(summary.counts.analyzed + summary.counts.cache_hits, 2);
+        assert_eq!(summary.report.tracks_written, 2);
+        assert_eq!(summary.failed(), 1);
+
+        let text = summary.to_string();
+        assert!(text.contains("1 failed, 2 written"), "{}", text);
+        assert!(output.join("PIONEER/rekordbox/export.pdb").exists());
+    }
+}
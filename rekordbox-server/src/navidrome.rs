@@ -8,9 +8,47 @@
 use std::collections::HashMap;
 use md5::{Md5, Digest};
 use rand::Rng;
+use reqwest::StatusCode;
 use serde::Deserialize;
+use thiserror::Error;
 use tracing::{debug, info, warn};
 
+/// Subsonic error codes meaning the credentials were rejected
+/// (40 = wrong username or password, 41 = token auth not supported)
+const AUTH_ERROR_CODES: [u32; 2] = [40, 41];
+
+/// Errors from the Navidrome/Subsonic client
+#[derive(Error, Debug)]
+pub enum NavidromeError {
+    /// Credentials rejected (HTTP 401 or Subsonic code 40/41)
+    #[error("authentication failed: {0}")]
+    Auth(String),
+
+    /// Non-success HTTP status
+    #[error("HTTP {0}")]
+    Http(StatusCode),
+
+    /// Subsonic-level error (e.g. 70 = playlist not found)
+    #[error("Subsonic error {code}: {message}")]
+    Api { code: u32, message: String },
+
+    /// Connection, timeout or other network failure
+    #[error("transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// Body was not a Subsonic JSON response
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+}
+
+impl NavidromeError {
+    /// Whether retrying the same request might succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, NavidromeError::Transport(_))
+            || matches!(self, NavidromeError::Http(status) if status.is_server_error())
+    }
+}
+
 /// Subsonic API client for Navidrome
 pub struct NavidromeClient {
     base_url: String,
@@ -21,16 +59,20 @@ pub struct NavidromeClient {
 
 /// Playlist metadata from Navidrome
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct Playlist {
     pub id: String,
     pub name: String,
     pub song_count: u32,
     pub duration_secs: u32,
     pub owner: String,
+    /// Last modification time as Navidrome reports it (ISO 8601)
+    pub changed: Option<String>,
 }
 
 /// Track info from a playlist
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct PlaylistTrack {
     pub id: String,
     pub title: String,
@@ -71,7 +113,7 @@ struct PlaylistsWrapper {
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum PlaylistOrList {
-    Single(PlaylistResponse),
+    Single(Box<PlaylistResponse>),
     List(Vec<PlaylistResponse>),
 }
 
@@ -85,6 +127,8 @@ struct PlaylistResponse {
     duration: u32,
     #[serde(default)]
     owner: String,
+    #[serde(default)]
+    changed: Option<String>,
     entry: Option<EntryOrList>,
 }
 
@@ -144,31 +188,19 @@ impl NavidromeClient {
         params
     }
 
-    /// Test connection to Navidrome
-    pub async fn ping(&self) -> anyhow::Result<bool> {
-        let url = format!("{}/rest/ping", self.base_url);
-        let params = self.auth_params();
-
-        let response = self.client
-            .get(&url)
-            .query(&params)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Ok(false);
+    /// Call a Subsonic endpoint and unwrap its response envelope
+    async fn call(
+        &self,
+        endpoint: &str,
+        extra: &[(&str, &str)],
+    ) -> Result<SubsonicResponseInner, NavidromeError> {
+        let url = format!("{}/rest/{}", self.base_url, endpoint);
+        let mut params = self.auth_params();
+        for (key, value) in extra {
+            params.insert(key.to_string(), value.to_string());
         }
 
-        let body: SubsonicResponse = response.json().await?;
-        Ok(body.response.status == "ok")
-    }
-
-    /// Get all playlists from Navidrome
-    pub async fn get_playlists(&self) -> anyhow::Result<Vec<Playlist>> {
-        let url = format!("{}/rest/getPlaylists", self.base_url);
-        let params = self.auth_params();
-
-        debug!("Fetching playlists from {}", url);
+        debug!("Calling {}", url);
 
         let response = self.client
             .get(&url)
@@ -176,22 +208,23 @@ impl NavidromeClient {
             .send()
             .await?;
 
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to fetch playlists: HTTP {}", response.status());
-        }
+        let status = response.status();
+        let body = response.text().await?;
+        parse_response(status, &body)
+    }
 
-        let body: SubsonicResponse = response.json().await?;
+    /// Test connection to Navidrome
+    pub async fn ping(&self) -> Result<(), NavidromeError> {
+        self.call("ping", &[]).await.map(|_| ())
+    }
 
-        if body.response.status != "ok" {
-            if let Some(err) = body.response.error {
-                anyhow::bail!("Subsonic error {}: {}", err.code, err.message);
-            }
-            anyhow::bail!("Unknown Subsonic error");
-        }
+    /// Get all playlists from Navidrome
+    pub async fn get_playlists(&self) -> Result<Vec<Playlist>, NavidromeError> {
+        let response = self.call("getPlaylists", &[]).await?;
 
-        let playlists = match body.response.playlists {
+        let playlists = match response.playlists {
             Some(wrapper) => match wrapper.playlist {
-                Some(PlaylistOrList::Single(p)) => vec![p],
+                Some(PlaylistOrList::Single(p)) => vec![*p],
                 Some(PlaylistOrList::List(list)) => list,
                 None => vec![],
             },
@@ -206,6 +239,7 @@ impl NavidromeClient {
                 song_count: p.song_count,
                 duration_secs: p.duration,
                 owner: p.owner,
+                changed: p.changed,
             })
             .collect();
 
@@ -214,34 +248,11 @@ impl NavidromeClient {
     }
 
     /// Get tracks from a specific playlist
-    pub async fn get_playlist_tracks(&self, playlist_id: &str) -> anyhow::Result<Vec<PlaylistTrack>> {
-        let url = format!("{}/rest/getPlaylist", self.base_url);
-        let mut params = self.auth_params();
-        params.insert("id".to_string(), playlist_id.to_string());
-
-        debug!("Fetching playlist {} from {}", playlist_id, url);
-
-        let response = self.client
-            .get(&url)
-            .query(&params)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to fetch playlist: HTTP {}", response.status());
-        }
-
-        let body: SubsonicResponse = response.json().await?;
-
-        if body.response.status != "ok" {
-            if let Some(err) = body.response.error {
-                anyhow::bail!("Subsonic error {}: {}", err.code, err.message);
-            }
-            anyhow::bail!("Unknown Subsonic error");
-        }
+    pub async fn get_playlist_tracks(&self, playlist_id: &str) -> Result<Vec<PlaylistTrack>, NavidromeError> {
+        let response = self.call("getPlaylist", &[("id", playlist_id)]).await?;
 
-        let playlist = body.response.playlist
-            .ok_or_else(|| anyhow::anyhow!("No playlist in response"))?;
+        let playlist = response.playlist
+            .ok_or_else(|| NavidromeError::InvalidResponse("no playlist in response".into()))?;
 
         let entries = match playlist.entry {
             Some(EntryOrList::Single(e)) => vec![e],
@@ -269,7 +280,7 @@ impl NavidromeClient {
     }
 
     /// Get all playlists with their tracks
-    pub async fn get_all_playlist_tracks(&self) -> anyhow::Result<HashMap<String, Vec<PlaylistTrack>>> {
+    pub async fn get_all_playlist_tracks(&self) -> Result<HashMap<String, Vec<PlaylistTrack>>, NavidromeError> {
         let playlists = self.get_playlists().await?;
         let mut result = HashMap::new();
 
@@ -289,19 +300,54 @@ impl NavidromeClient {
     }
 }
 
+/// Classify an HTTP status and Subsonic JSON body
+fn parse_response(status: StatusCode, body: &str) -> Result<SubsonicResponseInner, NavidromeError> {
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(NavidromeError::Auth(format!("HTTP {}", status)));
+    }
+    if !status.is_success() {
+        return Err(NavidromeError::Http(status));
+    }
+
+    let parsed: SubsonicResponse = serde_json::from_str(body)
+        .map_err(|e| NavidromeError::InvalidResponse(e.to_string()))?;
+    let response = parsed.response;
+    if response.status == "ok" {
+        return Ok(response);
+    }
+
+    match response.error {
+        Some(err) if AUTH_ERROR_CODES.contains(&err.code) => Err(NavidromeError::Auth(err.message)),
+        Some(err) => Err(NavidromeError::Api { code: err.code, message: err.message }),
+        None => Err(NavidromeError::InvalidResponse(format!("status {:?} without error", response.status))),
+    }
+}
+
 /// Build a mapping from file paths to playlist names
 ///
 /// This allows the analyzer to look up which playlist a track belongs to
-/// based on its file path.
+/// based on its file path. `path_prefix` is stripped from Navidrome's paths
+/// when its library root differs from the local music directory.
 pub fn build_path_to_playlist_map(
     playlists: &HashMap<String, Vec<PlaylistTrack>>,
+    path_prefix: Option<&str>,
 ) -> HashMap<String, String> {
+    let prefix = path_prefix
+        .map(|p| p.replace('\\', "/").trim_matches('/').to_string())
+        .filter(|p| !p.is_empty());
     let mut path_map = HashMap::new();
 
     for (playlist_name, tracks) in playlists {
         for track in tracks {
             // Normalize path separators
-            let normalized_path = track.path.replace('\\', "/");
+            let mut normalized_path = track.path.replace('\\', "/");
+            if let Some(ref prefix) = prefix {
+                if let Some(rest) = normalized_path.trim_start_matches('/').strip_prefix(prefix.as_str()) {
+                    if rest.is_empty() || rest.starts_with('/') {
+                        normalized_path = rest.trim_start_matches('/').to_string();
+                    }
+                }
+            }
             path_map.insert(normalized_path, playlist_name.clone());
         }
     }
@@ -309,6 +355,30 @@ pub fn build_path_to_playlist_map(
     path_map
 }
 
+/// Find the playlist for a music-dir-relative path
+///
+/// Tries an exact match first, then falls back to matching on whole trailing
+/// path components (Navidrome's `Library/House/x.mp3` matches a local
+/// `House/x.mp3` and vice versa). The shortest matching Navidrome path wins.
+pub fn find_playlist_for_path<'a>(
+    path_map: &'a HashMap<String, String>,
+    relative_path: &str,
+) -> Option<&'a String> {
+    if let Some(name) = path_map.get(relative_path) {
+        return Some(name);
+    }
+
+    let is_suffix = |long: &str, short: &str| {
+        long.strip_suffix(short).is_some_and(|head| head.ends_with('/'))
+    };
+
+    path_map
+        .iter()
+        .filter(|(path, _)| is_suffix(path, relative_path) || is_suffix(relative_path, path))
+        .min_by_key(|(path, _)| (path.len(), path.as_str()))
+        .map(|(_, name)| name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,9 +439,83 @@ mod tests {
             ],
         );
 
-        let path_map = build_path_to_playlist_map(&playlists);
+        let path_map = build_path_to_playlist_map(&playlists, None);
 
         assert_eq!(path_map.get("Music/House/track1.mp3"), Some(&"House".to_string()));
         assert_eq!(path_map.get("Music/Techno/track2.flac"), Some(&"Techno".to_string()));
     }
+
+    fn single_track_playlist(name: &str, path: &str) -> HashMap<String, Vec<PlaylistTrack>> {
+        let mut playlists = HashMap::new();
+        playlists.insert(
+            name.to_string(),
+            vec![PlaylistTrack {
+                id: "1".to_string(),
+                title: "x".to_string(),
+                artist: "Artist".to_string(),
+                album: None,
+                duration_secs: 300,
+                path: path.to_string(),
+            }],
+        );
+        playlists
+    }
+
+    #[test]
+    fn test_suffix_match_across_library_roots() {
+        let path_map = build_path_to_playlist_map(
+            &single_track_playlist("Warmup", "Library/House/x.mp3"),
+            None,
+        );
+
+        assert_eq!(find_playlist_for_path(&path_map, "House/x.mp3"), Some(&"Warmup".to_string()));
+        // Only whole components count
+        assert_eq!(find_playlist_for_path(&path_map, "ouse/x.mp3"), None);
+        assert_eq!(find_playlist_for_path(&path_map, "Techno/x.mp3"), None);
+    }
+
+    #[test]
+    fn test_path_prefix_stripped() {
+        let path_map = build_path_to_playlist_map(
+            &single_track_playlist("Warmup", "/Library/House/x.mp3"),
+            Some("Library/"),
+        );
+
+        assert_eq!(path_map.get("House/x.mp3"), Some(&"Warmup".to_string()));
+        // A local file one level deeper still matches by suffix
+        assert_eq!(
+            find_playlist_for_path(&path_map, "Sets/House/x.mp3"),
+            Some(&"Warmup".to_string())
+        );
+    }
+
+    #[test]
+    fn test_auth_failure_maps_to_auth_error() {
+        let body = r#"{"subsonic-response": {"status": "failed", "version": "1.16.1",
+            "error": {"code": 40, "message": "Wrong username or password"}}}"#;
+
+        let err = parse_response(StatusCode::OK, body).err().unwrap();
+        assert!(matches!(err, NavidromeError::Auth(ref m) if m.contains("Wrong username")));
+        assert!(!err.is_transient());
+
+        let err = parse_response(StatusCode::UNAUTHORIZED, "").err().unwrap();
+        assert!(matches!(err, NavidromeError::Auth(_)));
+    }
+
+    #[test]
+    fn test_api_and_http_errors() {
+        let body = r#"{"subsonic-response": {"status": "failed",
+            "error": {"code": 70, "message": "Playlist not found"}}}"#;
+        assert!(matches!(
+            parse_response(StatusCode::OK, body),
+            Err(NavidromeError::Api { code: 70, .. })
+        ));
+
+        let err = parse_response(StatusCode::BAD_GATEWAY, "").err().unwrap();
+        assert!(matches!(err, NavidromeError::Http(StatusCode::BAD_GATEWAY)));
+        assert!(err.is_transient());
+
+        let ok = parse_response(StatusCode::OK, r#"{"subsonic-response": {"status": "ok"}}"#);
+        assert!(ok.is_ok());
+    }
 }
@@ -0,0 +1,195 @@
+//! M3U/M3U8 playlist import
+//!
+//! Playlist files kept in the music directory become playlists named after
+//! the file, nested on export in the folders above it (`sets/Friday.m3u8`
+//! -> a "Friday" playlist in a "sets" folder), in the order they list tracks.
+//! Lines starting with `#` (`#EXTM3U`, `#EXTINF:...`) are skipped; entries
+//! may be relative to the playlist file or absolute, with `/` or `\`
+//! separators. Entries outside the music directory, or that weren't
+//! analyzed, are warned about and left out.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+/// Whether a file is an M3U/M3U8 playlist
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u") || ext.eq_ignore_ascii_case("m3u8"))
+}
+
+/// Read every playlist file under `music_dir`
+///
+/// `tracks` maps each analyzed file's path (as found in the directory walk)
+/// to its track id. Playlists are keyed by their folders below `music_dir`
+/// followed by the file stem; those without any resolvable entry are dropped.
+pub fn import_playlists(music_dir: &Path, tracks: &HashMap<PathBuf, u32>) -> HashMap<Vec<String>, Vec<u32>> {
+    let music_dir = absolute(music_dir);
+    let tracks: HashMap<PathBuf, u32> = tracks.iter()
+        .map(|(path, &id)| (absolute(path), id))
+        .collect();
+
+    let mut playlists = HashMap::new();
+    for entry in WalkDir::new(&music_dir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !path.is_file() || !is_playlist_file(path) {
+            continue;
+        }
+        let Some(name) = playlist_path(path, &music_dir) else {
+            continue;
+        };
+        if playlists.contains_key(&name) {
+            warn!("Ignoring {:?}: a playlist at {:?} was already read", path, name);
+            continue;
+        }
+        let contents = match fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                warn!("Failed to read playlist {:?}: {}", path, e);
+                continue;
+            }
+        };
+
+        let ids = resolve_playlist(&contents, path, &music_dir, &tracks);
+        debug!("Playlist {:?}: {} tracks from {:?}", name, ids.len(), path);
+        if !ids.is_empty() {
+            playlists.insert(name, ids);
+        }
+    }
+    playlists
+}
+
+/// Folders below `music_dir`, then the file stem
+fn playlist_path(path: &Path, music_dir: &Path) -> Option<Vec<String>> {
+    let stem = path.file_stem()?.to_str()?;
+    let folder = path.parent()?.strip_prefix(music_dir).ok()?;
+    let mut parts = folder.iter()
+        .map(|part| part.to_str().map(String::from))
+        .collect::<Option<Vec<_>>>()?;
+    parts.push(stem.to_string());
+    Some(parts)
+}
+
+/// Track ids of a playlist file's entries, in order
+fn resolve_playlist(
+    contents: &str,
+    playlist_path: &Path,
+    music_dir: &Path,
+    tracks: &HashMap<PathBuf, u32>,
+) -> Vec<u32> {
+    let playlist_dir = playlist_path.parent().unwrap_or(music_dir);
+    let mut ids = Vec::new();
+
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let path = match resolve_entry(line, playlist_dir) {
+            Some(path) if path.starts_with(music_dir) => path,
+            _ => {
+                warn!("Skipping {:?} in {:?}: outside {:?}", line, playlist_path, music_dir);
+                continue;
+            }
+        };
+        match tracks.get(&path) {
+            Some(&id) => ids.push(id),
+            None => warn!("Skipping {:?} in {:?}: not an analyzed track", line, playlist_path),
+        }
+    }
+    ids
+}
+
+/// Absolute, normalized path of a playlist entry
+///
+/// None for Windows drive paths (`C:\...`), which can't point into the
+/// music directory here.
+fn resolve_entry(entry: &str, playlist_dir: &Path) -> Option<PathBuf> {
+    let entry = entry.replace('\\', "/");
+    let bytes = entry.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return None;
+    }
+    Some(absolute(&playlist_dir.join(entry)))
+}
+
+/// `path` made absolute against the working directory, with `.` and `..`
+/// resolved lexically (entries need not exist)
+fn absolute(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_import_playlists() {
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        let sets = music_dir.join("sets");
+        fs::create_dir_all(&sets).unwrap();
+        let tracks = HashMap::from([
+            (music_dir.join("a.mp3"), 1),
+            (music_dir.join("House/b.flac"), 2),
+            (music_dir.join("House/c d.mp3"), 3),
+        ]);
+
+        // Relative (with .. and backslashes), absolute, #EXTINF lines, a BOM
+        let playlist = format!(
+            "\u{feff}#EXTM3U\r\n#EXTINF:215,Artist - C\r\n..\\House\\c d.mp3\r\n\r\n\
+             #EXTINF:180,Artist - A\r\n{}\r\n../House/./b.flac\r\n../a.mp3\r\n",
+            music_dir.join("a.mp3").display(),
+        );
+        fs::write(sets.join("Friday.m3u8"), playlist).unwrap();
+
+        // Outside the music directory, not analyzed, or on another drive
+        fs::write(tmp.path().join("elsewhere.mp3"), b"audio").unwrap();
+        let strays = format!(
+            "../../elsewhere.mp3\n{}\nC:\\Music\\a.mp3\nmissing.mp3\n../House/b.flac\n",
+            tmp.path().join("elsewhere.mp3").display(),
+        );
+        fs::write(sets.join("Strays.M3U"), strays).unwrap();
+        fs::write(music_dir.join("Empty.m3u"), "#EXTM3U\nnothing.mp3\n").unwrap();
+
+        let playlists = import_playlists(&music_dir, &tracks);
+        assert_eq!(playlists.len(), 2);
+        assert_eq!(playlists[&vec!["sets".to_string(), "Friday".to_string()]], vec![3, 1, 2, 1]);
+        assert_eq!(playlists[&vec!["sets".to_string(), "Strays".to_string()]], vec![2]);
+    }
+
+    #[test]
+    fn test_is_playlist_file() {
+        assert!(is_playlist_file(Path::new("set.m3u8")));
+        assert!(is_playlist_file(Path::new("Set.M3U")));
+        assert!(!is_playlist_file(Path::new("set.pls")));
+        assert!(!is_playlist_file(Path::new("m3u")));
+    }
+}
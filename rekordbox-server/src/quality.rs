@@ -0,0 +1,162 @@
+//! Audio quality checks
+//!
+//! Cheap heuristics run on the decoded mono samples so bad files can be
+//! culled before they reach a stick:
+//! - clip ratio: fraction of samples sitting at full scale
+//! - spectral cutoff: highest frequency with meaningful energy; MP3/AAC
+//!   encoders low-pass at 16-20 kHz, so a "FLAC" that stops there is usually
+//!   a transcode
+//! - audio start: where the music begins after leading silence
+//!
+//! The analyzer hands them the first few minutes of each track, so a long
+//! mix is judged by its opening.
+
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// Samples at or above this magnitude count as clipped
+const CLIP_THRESHOLD: f32 = 0.999;
+
+/// FFT window length for the averaged spectrum
+const FFT_SIZE: usize = 4096;
+
+/// Upper bound on windows averaged, spread evenly over the track
+const MAX_WINDOWS: usize = 64;
+
+/// Energy this far below the spectrum's peak counts as silence (-60 dB)
+const CUTOFF_FLOOR: f32 = 1e-6;
+
+/// Fraction of samples at full scale
+pub fn clip_ratio(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let clipped = samples.iter().filter(|s| s.abs() >= CLIP_THRESHOLD).count();
+    clipped as f32 / samples.len() as f32
+}
+
+/// Block length the audio start is measured over (10 ms)
+const START_BLOCKS_PER_SEC: u32 = 100;
+
+/// Start of the first 10 ms block louder than `threshold_db` (dBFS), in ms
+///
+/// Returns None when nothing is that loud.
+pub fn audio_start_ms(samples: &[f32], sample_rate: u32, threshold_db: f64) -> Option<f64> {
+    let threshold = 10f64.powf(threshold_db / 20.0);
+    let block = (sample_rate / START_BLOCKS_PER_SEC).max(1) as usize;
+    let first = samples.chunks(block).position(|chunk| {
+        let power = chunk.iter().map(|&s| s as f64 * s as f64).sum::<f64>() / chunk.len() as f64;
+        power.sqrt() > threshold
+    })?;
+    Some((first * block) as f64 * 1000.0 / sample_rate.max(1) as f64)
+}
+
+/// Estimate the frequency above which the averaged spectrum falls silent
+///
+/// Returns None when there is too little (or only silent) audio to tell.
+pub fn spectral_cutoff_hz(samples: &[f32], sample_rate: u32) -> Option<u32> {
+    if samples.len() < FFT_SIZE || sample_rate == 0 {
+        return None;
+    }
+
+    let fft: Arc<dyn Fft<f32>> = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+
+    let windows = (samples.len() / FFT_SIZE).min(MAX_WINDOWS);
+    let stride = if windows > 1 { (samples.len() - FFT_SIZE) / (windows - 1) } else { 0 };
+
+    let mut power = vec![0.0f32; FFT_SIZE / 2];
+    let mut buffer = vec![Complex::new(0.0, 0.0); FFT_SIZE];
+    for w in 0..windows {
+        let start = w * stride;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for (bin, value) in power.iter_mut().zip(&buffer) {
+            *bin += value.norm_sqr();
+        }
+    }
+
+    let peak = power.iter().copied().fold(0.0f32, f32::max);
+    if peak <= 0.0 {
+        return None;
+    }
+
+    let last = power.iter().rposition(|&p| p >= peak * CUTOFF_FLOOR)?;
+    let bin_hz = sample_rate as f64 / FFT_SIZE as f64;
+    Some(((last + 1) as f64 * bin_hz).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone_mix(freqs: &[f32], sample_rate: u32, seconds: f32, gain: f32) -> Vec<f32> {
+        let len = (sample_rate as f32 * seconds) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let sum: f64 = freqs.iter()
+                    .map(|&f| (2.0 * std::f64::consts::PI * f as f64 * t).sin())
+                    .sum();
+                gain * (sum / freqs.len() as f64) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clipped_signal() {
+        // A sine driven 4x past full scale and hard-clipped
+        let clipped: Vec<f32> = tone_mix(&[440.0], 44100, 1.0, 4.0)
+            .into_iter()
+            .map(|s| s.clamp(-1.0, 1.0))
+            .collect();
+        assert!(clip_ratio(&clipped) > 0.5);
+
+        let clean = tone_mix(&[440.0], 44100, 1.0, 0.5);
+        assert_eq!(clip_ratio(&clean), 0.0);
+        assert_eq!(clip_ratio(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_band_limited_signal() {
+        // Partials up to 16 kHz only, like a 128 kbps MP3 transcode
+        let freqs: Vec<f32> = (1..=32).map(|k| k as f32 * 500.0).collect();
+        let limited = tone_mix(&freqs, 44100, 2.0, 0.5);
+        let cutoff = spectral_cutoff_hz(&limited, 44100).unwrap();
+        assert!((15_500..=17_000).contains(&cutoff), "cutoff {}", cutoff);
+
+        // Extending the partials to 21 kHz moves the cutoff up with them
+        let freqs: Vec<f32> = (1..=42).map(|k| k as f32 * 500.0).collect();
+        let full = tone_mix(&freqs, 44100, 2.0, 0.5);
+        assert!(spectral_cutoff_hz(&full, 44100).unwrap() > 20_500);
+
+        assert_eq!(spectral_cutoff_hz(&[0.0; 100], 44100), None);
+        assert_eq!(spectral_cutoff_hz(&vec![0.0; 10_000], 44100), None);
+    }
+
+    #[test]
+    fn test_audio_start_after_leading_silence() {
+        // 2 s of silence, then a 440 Hz tone
+        let samples: Vec<f32> = (0..6 * 44100)
+            .map(|i| if i < 2 * 44100 {
+                0.0
+            } else {
+                0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin()
+            })
+            .collect();
+        assert_eq!(audio_start_ms(&samples, 44100, -60.0), Some(2000.0));
+        assert_eq!(audio_start_ms(&samples[2 * 44100..], 44100, -60.0), Some(0.0));
+        // Too strict a threshold finds no music at all
+        assert_eq!(audio_start_ms(&samples, 44100, 0.0), None);
+        assert_eq!(audio_start_ms(&[0.0; 1000], 44100, -60.0), None);
+    }
+}
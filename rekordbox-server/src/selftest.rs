@@ -0,0 +1,217 @@
+//! Built-in self-test
+//!
+//! Runs the export pipeline on a few synthetic tracks, with no music files
+//! or server state involved: waveforms are generated from test tones, the
+//! PDB is built in memory and read back, and the ANLZ files are written to
+//! a scratch directory and validated. Lets a user check a fresh build (or
+//! a cross-compiled binary on the NAS) before trusting it with a stick.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use rekordbox_core::{
+    generate_2ex_file, generate_anlz_path, generate_dat_file, generate_ext_file, validate_anlz,
+    validate_pdb, BeatGrid, CuePoint, CueType, FileType, PdbBuilder, PdbReader, TrackAnalysis,
+};
+
+use crate::waveform::{WaveformConfig, WaveformGenerator};
+
+/// Synthetic tracks: (title, BPM, tone Hz)
+const TRACKS: [(&str, f64, f32); 3] = [
+    ("Self-test 124", 124.0, 220.0),
+    ("Self-test 128", 128.0, 330.0),
+    ("Self-test 174", 174.0, 440.0),
+];
+
+const SAMPLE_RATE: u32 = 44100;
+const SECONDS: u32 = 8;
+
+/// One step of the self-test
+#[derive(Debug, Serialize)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of `run_selftest`
+#[derive(Debug, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    fn check(&mut self, name: impl Into<String>, outcome: Result<String, String>) {
+        let (passed, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.passed &= passed;
+        self.checks.push(SelfTestCheck { name: name.into(), passed, detail });
+    }
+}
+
+/// Build, write and validate a synthetic library
+pub fn run_selftest() -> SelfTestReport {
+    let mut report = SelfTestReport { passed: true, checks: Vec::new() };
+    let tracks: Vec<TrackAnalysis> = TRACKS.iter()
+        .enumerate()
+        .map(|(i, &(title, bpm, tone_hz))| synthetic_track(i as u32 + 1, title, bpm, tone_hz))
+        .collect();
+
+    // export.pdb, built in memory and streamed back
+    let mut builder = PdbBuilder::new();
+    for track in &tracks {
+        builder.add_track(track, &generate_anlz_path(track.id));
+    }
+    builder.add_playlist(1, 0, "Self-test", tracks.iter().map(|t| t.id).collect());
+    match builder.build() {
+        Ok(pdb) => {
+            let result = validate_pdb(&pdb);
+            report.check("validate export.pdb", if result.valid {
+                Ok(format!("{} pages, {} tracks", result.stats.total_pages, result.stats.track_count))
+            } else {
+                Err(result.errors.join("; "))
+            });
+            report.check("read back tracks", read_back(&pdb, &tracks));
+        }
+        Err(e) => report.check("build export.pdb", Err(e.to_string())),
+    }
+
+    // ANLZ files, through the filesystem like a real export
+    let scratch = scratch_dir();
+    for track in &tracks {
+        report.check(format!("ANLZ files for track {}", track.id), write_and_validate_anlz(track, &scratch));
+    }
+    let _ = fs::remove_dir_all(&scratch);
+
+    report
+}
+
+/// A track with a click-and-tone signal at `bpm`, analyzed like a real one
+fn synthetic_track(id: u32, title: &str, bpm: f64, tone_hz: f32) -> TrackAnalysis {
+    let beat_len = (SAMPLE_RATE as f64 * 60.0 / bpm) as usize;
+    let samples: Vec<f32> = (0..(SAMPLE_RATE * SECONDS) as usize)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let click = if i % beat_len < SAMPLE_RATE as usize / 100 { 0.8 } else { 0.0 };
+            click + 0.2 * (2.0 * std::f32::consts::PI * tone_hz * t).sin()
+        })
+        .collect();
+    let duration_secs = SECONDS as f64;
+    let beat_grid = BeatGrid::constant_tempo(bpm, 0.0, duration_secs * 1000.0);
+    let first_beats: Vec<f64> = beat_grid.beats.iter().take(2).map(|b| b.time_ms).collect();
+
+    TrackAnalysis {
+        id,
+        file_path: format!("/Contents/{}.wav", title),
+        title: title.to_string(),
+        artist: "rekord-export".to_string(),
+        artist_sort: None,
+        album: Some("Self-test".to_string()),
+        genre: None,
+        label: None,
+        duration_secs,
+        sample_rate: SAMPLE_RATE,
+        bit_depth: 16,
+        channels: 1,
+        bitrate: SAMPLE_RATE * 16 / 1000,
+        bpm,
+        key: None,
+        waveform: WaveformGenerator::new(SAMPLE_RATE, WaveformConfig::default()).generate(&samples, duration_secs),
+        cue_points: first_beats.iter()
+            .enumerate()
+            .map(|(slot, &time_ms)| CuePoint {
+                hot_cue: slot as u8,
+                cue_type: CueType::Cue,
+                time_ms,
+                loop_ms: 0.0,
+                comment: None,
+                color: None,
+                memory_color_id: None,
+            })
+            .collect(),
+        beat_grid,
+        file_size: samples.len() as u64 * 2,
+        file_hash: id as u64,
+        year: None,
+        release_date: None,
+        comment: None,
+        track_number: Some(id),
+        file_type: FileType::Wav,
+        clip_ratio: 0.0,
+        spectral_cutoff_hz: None,
+        bpm_confidence: 1.0,
+        acoustic_id: None,
+        energy: None,
+        album_artist: None,
+        compilation: false,
+        audio_start_ms: None,
+    }
+}
+
+/// Every track comes back from the built file with its id, title and tempo
+fn read_back(pdb: &[u8], tracks: &[TrackAnalysis]) -> Result<String, String> {
+    let mut reader = PdbReader::new(std::io::Cursor::new(pdb)).map_err(|e| e.to_string())?;
+    let rows = reader.tracks().collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+    if rows.len() != tracks.len() {
+        return Err(format!("{} rows for {} tracks", rows.len(), tracks.len()));
+    }
+    for (row, track) in rows.iter().zip(tracks) {
+        if row.id != track.id || row.title != track.title || row.bpm != track.bpm {
+            return Err(format!("track {} read back as {} {:?} at {} BPM", track.id, row.id, row.title, row.bpm));
+        }
+    }
+    Ok(format!("{} tracks", rows.len()))
+}
+
+fn write_and_validate_anlz(track: &TrackAnalysis, scratch: &std::path::Path) -> Result<String, String> {
+    let dat_path = scratch.join(generate_anlz_path(track.id));
+    let files = [
+        (dat_path.clone(), generate_dat_file(&track.beat_grid, &track.waveform, &track.file_path)),
+        (dat_path.with_extension("EXT"), generate_ext_file(&track.beat_grid, &track.waveform, &track.file_path, &track.cue_points)),
+        (dat_path.with_extension("2EX"), generate_2ex_file(&track.beat_grid, &track.waveform, &track.file_path, &track.cue_points)),
+    ];
+
+    for (path, data) in files.iter() {
+        let data = data.as_ref().map_err(|e| e.to_string())?;
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        fs::create_dir_all(path.parent().unwrap_or(scratch)).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| format!("{}: {}", name, e))?;
+        let written = fs::read(path).map_err(|e| format!("{}: {}", name, e))?;
+        let result = validate_anlz(&written);
+        if !result.valid {
+            return Err(format!("{}: {}", name, result.errors.join("; ")));
+        }
+        if result.path.as_deref() != Some(track.file_path.as_str()) {
+            return Err(format!("{}: path {:?}", name, result.path));
+        }
+    }
+    Ok("DAT, EXT and 2EX valid".to_string())
+}
+
+/// Fresh directory under the system temp dir
+fn scratch_dir() -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    std::env::temp_dir().join(format!("rekordbox-selftest-{}-{}", std::process::id(), nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        let report = run_selftest();
+        let failed: Vec<_> = report.checks.iter().filter(|c| !c.passed).collect();
+        assert!(report.passed, "{:?}", failed);
+        assert_eq!(report.checks.len(), 2 + TRACKS.len());
+    }
+}
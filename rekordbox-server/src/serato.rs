@@ -0,0 +1,345 @@
+//! Serato cue import
+//!
+//! Serato DJ stores cues and saved loops in an ID3v2 `GEOB` frame described
+//! as "Serato Markers2". The object is two version bytes (0x01 0x01)
+//! followed by base64 text (wrapped at 72 columns) that decodes to:
+//! ```text
+//! 01 01                      version
+//! <name>\0 <u32 BE len> <data>   repeated, ends at an empty name
+//!   CUE:  00, index, position_ms (u32), 00, R G B, 00 00, name\0
+//!   LOOP: 00, index, start_ms (u32), end_ms (u32), FF FF FF FF,
+//!         00 27 AA E1, locked, name\0
+//! ```
+//! Other entries (COLOR, BPMLOCK, FLIP) are ignored.
+//!
+//! FLAC and Ogg files carry the same object in a `SERATO_MARKERS_V2`
+//! Vorbis comment, base64 encoded once more behind the GEOB header
+//! (`application/octet-stream\0\0Serato Markers2\0`).
+//!
+//! Positions are on Serato's gapless timeline; `cue_import` describes how
+//! they are moved onto the players'.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use tracing::{debug, warn};
+
+use rekordbox_core::{CuePoint, CueType, HotCueColor};
+
+/// GEOB description of the cue/loop payload
+const MARKERS2_DESCRIPTION: &str = "Serato Markers2";
+
+/// Upper bound on the ID3 tag we are willing to read (tags carry artwork)
+const MAX_TAG_SIZE: usize = 16 * 1024 * 1024;
+
+/// Serato pads and wraps its base64 loosely, so accept any padding and
+/// non-canonical trailing bits
+const SERATO_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+/// Read Serato cues and loops from an audio file's ID3v2 tag
+///
+/// Returns None when the file has no (readable) Serato markers.
+pub fn load_serato_cues(path: &Path) -> Option<Vec<CuePoint>> {
+    let tag = match read_id3_tag(path) {
+        Ok(tag) => tag?,
+        Err(e) => {
+            warn!("Failed to read ID3 tag from {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let object = find_geob(&tag, MARKERS2_DESCRIPTION)?;
+    let cues = parse_markers2(object);
+    if cues.is_none() {
+        warn!("Ignoring unreadable Serato markers in {:?}", path);
+    }
+    cues
+}
+
+/// Vorbis comment holding the markers in FLAC/Ogg files
+pub const VORBIS_MARKERS2_KEY: &str = "SERATO_MARKERS_V2";
+
+/// Read Serato cues and loops from a `SERATO_MARKERS_V2` comment value
+pub fn parse_vorbis_markers2(value: &str) -> Option<Vec<CuePoint>> {
+    let text: Vec<u8> = value.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let data = SERATO_BASE64.decode(&text).ok()?;
+    let (_mime, rest) = split_terminated(&data, false)?;
+    let (_filename, rest) = split_terminated(rest, false)?;
+    let (desc, object) = split_terminated(rest, false)?;
+    if desc != MARKERS2_DESCRIPTION.as_bytes() {
+        return None;
+    }
+    parse_markers2(object)
+}
+
+/// Read the raw ID3v2 tag (header included) from the start of a file
+fn read_id3_tag(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(None);
+    }
+
+    let size = syncsafe(&header[6..10]) as usize;
+    if size > MAX_TAG_SIZE {
+        return Ok(None);
+    }
+
+    let mut tag = header.to_vec();
+    tag.resize(10 + size, 0);
+    file.read_exact(&mut tag[10..])?;
+    Ok(Some(tag))
+}
+
+/// Find the object data of the GEOB frame with the given description
+fn find_geob<'a>(tag: &'a [u8], description: &str) -> Option<&'a [u8]> {
+    let version = *tag.get(3)?;
+    if !(3..=4).contains(&version) {
+        debug!("Unsupported ID3v2.{} tag", version);
+        return None;
+    }
+
+    let size = syncsafe(tag.get(6..10)?) as usize;
+    let body = tag.get(10..10 + size)?;
+
+    // Skip the extended header if present
+    let mut pos = 0;
+    if tag[5] & 0x40 != 0 {
+        pos = if version == 4 {
+            syncsafe(body.get(..4)?) as usize
+        } else {
+            read_u32_be(body, 0)? as usize + 4
+        };
+    }
+
+    while pos + 10 <= body.len() && body[pos] != 0 {
+        let id = &body[pos..pos + 4];
+        let frame_size = if version == 4 {
+            syncsafe(&body[pos + 4..pos + 8])
+        } else {
+            read_u32_be(body, pos + 4)?
+        } as usize;
+        let frame = body.get(pos + 10..pos + 10 + frame_size)?;
+        pos += 10 + frame_size;
+
+        if id != b"GEOB" {
+            continue;
+        }
+
+        // encoding, mime\0, filename\0, description\0, object
+        let (&encoding, rest) = frame.split_first()?;
+        let wide = encoding == 1 || encoding == 2;
+        let (_mime, rest) = split_terminated(rest, false)?;
+        let (_filename, rest) = split_terminated(rest, wide)?;
+        let (desc, object) = split_terminated(rest, wide)?;
+        if desc == description.as_bytes() {
+            return Some(object);
+        }
+    }
+
+    None
+}
+
+/// Decode a Markers2 GEOB object into cue points
+fn parse_markers2(object: &[u8]) -> Option<Vec<CuePoint>> {
+    let text = object.strip_prefix(&[0x01, 0x01])?;
+    let text: Vec<u8> = text.iter()
+        .take_while(|&&b| b != 0)
+        .filter(|b| !b.is_ascii_whitespace())
+        .copied()
+        .collect();
+    let payload = SERATO_BASE64.decode(&text).ok()?;
+    let mut data = payload.strip_prefix(&[0x01, 0x01])?;
+
+    let mut cues = Vec::new();
+    loop {
+        let (name, rest) = split_terminated(data, false)?;
+        if name.is_empty() {
+            break;
+        }
+        let len = read_u32_be(rest, 0)? as usize;
+        let entry = rest.get(4..4 + len)?;
+        data = &rest[4 + len..];
+
+        match name {
+            b"CUE" => cues.extend(parse_cue(entry)),
+            b"LOOP" => cues.extend(parse_loop(entry)),
+            _ => {}
+        }
+    }
+
+    Some(cues)
+}
+
+/// CUE entry -> hot cue (Serato index 0 = hot cue A)
+fn parse_cue(entry: &[u8]) -> Option<CuePoint> {
+    let index = *entry.get(1)?;
+    let position_ms = read_u32_be(entry, 2)?;
+    let rgb = entry.get(7..10)?;
+
+    Some(CuePoint {
+        hot_cue: index + 1,
+        cue_type: CueType::Cue,
+        time_ms: position_ms as f64,
+        loop_ms: 0.0,
+        comment: entry_name(entry.get(12..)?),
+        color: Some(HotCueColor::nearest(rgb[0], rgb[1], rgb[2])),
+        memory_color_id: None,
+    })
+}
+
+/// LOOP entry -> memory loop (Serato saved loops have their own slots)
+fn parse_loop(entry: &[u8]) -> Option<CuePoint> {
+    let start_ms = read_u32_be(entry, 2)?;
+    let end_ms = read_u32_be(entry, 6)?;
+
+    Some(CuePoint {
+        hot_cue: 0,
+        cue_type: CueType::Loop,
+        time_ms: start_ms as f64,
+        loop_ms: end_ms.saturating_sub(start_ms) as f64,
+        comment: entry_name(entry.get(19..)?),
+        color: None,
+        memory_color_id: None,
+    })
+}
+
+/// NUL-terminated UTF-8 name, None when empty
+fn entry_name(data: &[u8]) -> Option<String> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let name = String::from_utf8_lossy(&data[..end]).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Split at the first NUL (or aligned double NUL for UTF-16 text)
+fn split_terminated(data: &[u8], wide: bool) -> Option<(&[u8], &[u8])> {
+    if wide {
+        let end = data.chunks_exact(2).position(|c| c == [0, 0])? * 2;
+        Some((&data[..end], &data[end + 2..]))
+    } else {
+        let end = data.iter().position(|&b| b == 0)?;
+        Some((&data[..end], &data[end + 1..]))
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as u32)
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Markers2 payload as written by Serato DJ: track color, hot cue A
+/// "Drop" (red) at 1.234s, hot cue C (cyan) at 60s, saved loop "Break"
+/// 30s-38s and a BPM lock
+#[cfg(test)]
+const MARKERS2_BASE64: &str = "\
+AQFDT0xPUgAAAAAEAP///0NVRQAAAAARAAAAAATSAMwAAAAARHJvcABDVUUAAAAADQACAADq
+YAAAzMwAAABMT09QAAAAABkAAAAAdTAAAJRw/////wAnquEAQnJlYWsAQlBNTE9DSwAAAAAB
+AAA";
+
+/// ID3v2.3 tag holding a title and `MARKERS2_BASE64` (test fixture)
+#[cfg(test)]
+pub(crate) fn id3_with_markers() -> Vec<u8> {
+    let mut geob = vec![0u8];
+    geob.extend_from_slice(b"application/octet-stream\0\0");
+    geob.extend_from_slice(MARKERS2_DESCRIPTION.as_bytes());
+    geob.push(0);
+    geob.extend_from_slice(&[0x01, 0x01]);
+    geob.extend_from_slice(MARKERS2_BASE64.as_bytes());
+    geob.push(0);
+
+    let mut frames = b"TIT2".to_vec();
+    frames.extend_from_slice(&6u32.to_be_bytes());
+    frames.extend_from_slice(&[0, 0, 0]);
+    frames.extend_from_slice(b"Title");
+    frames.extend_from_slice(b"GEOB");
+    frames.extend_from_slice(&(geob.len() as u32).to_be_bytes());
+    frames.extend_from_slice(&[0, 0]);
+    frames.extend_from_slice(&geob);
+    frames.extend_from_slice(&[0u8; 32]); // padding
+
+    let size = frames.len() as u32;
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend_from_slice(&[
+        (size >> 21 & 0x7F) as u8,
+        (size >> 14 & 0x7F) as u8,
+        (size >> 7 & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ]);
+    tag.extend_from_slice(&frames);
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_serato_cues() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("track.mp3");
+        let mut data = id3_with_markers();
+        data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+        std::fs::write(&path, data).unwrap();
+
+        let cues = load_serato_cues(&path).expect("markers should decode");
+        assert_eq!(cues.len(), 3);
+
+        assert_eq!(cues[0].hot_cue, 1);
+        assert_eq!(cues[0].time_ms, 1234.0);
+        assert_eq!(cues[0].comment.as_deref(), Some("Drop"));
+        assert_eq!(cues[0].color, Some(HotCueColor::RED));
+
+        assert_eq!(cues[1].hot_cue, 3);
+        assert_eq!(cues[1].time_ms, 60_000.0);
+        assert_eq!(cues[1].comment, None);
+        assert_eq!(cues[1].color, Some(HotCueColor::CYAN));
+
+        assert_eq!(cues[2].hot_cue, 0);
+        assert_eq!(cues[2].cue_type, CueType::Loop);
+        assert_eq!(cues[2].time_ms, 30_000.0);
+        assert_eq!(cues[2].loop_ms, 8_000.0);
+        assert_eq!(cues[2].comment.as_deref(), Some("Break"));
+    }
+
+    #[test]
+    fn test_vorbis_markers2() {
+        let mut object = b"application/octet-stream\0\0Serato Markers2\0\x01\x01".to_vec();
+        object.extend_from_slice(MARKERS2_BASE64.as_bytes());
+        object.push(0);
+        let value = SERATO_BASE64.encode(&object);
+
+        let cues = parse_vorbis_markers2(&value).expect("markers should decode");
+        assert_eq!(cues.len(), 3);
+        assert_eq!((cues[0].hot_cue, cues[0].time_ms), (1, 1234.0));
+        assert_eq!(cues[0].color, Some(HotCueColor::RED));
+        assert_eq!((cues[2].cue_type, cues[2].time_ms, cues[2].loop_ms), (CueType::Loop, 30_000.0, 8_000.0));
+
+        // Some other GEOB-style object
+        let other = SERATO_BASE64.encode(b"application/octet-stream\0\0Serato Overview\0\x01\x05");
+        assert!(parse_vorbis_markers2(&other).is_none());
+    }
+
+    #[test]
+    fn test_no_serato_markers() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("plain.mp3");
+        std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+        assert!(load_serato_cues(&path).is_none());
+
+        assert!(parse_markers2(b"\x01\x01!!!not base64").is_none());
+    }
+}
@@ -1,24 +1,130 @@
-//! Unix socket server for CLI communication
+//! TCP / Unix socket server for CLI communication
 //!
 //! Provides a simple JSON-RPC style interface for the lightweight CLI client.
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
+use clap::ValueEnum;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn, error, debug};
 
-use rekordbox_core::AnalysisCache;
+use rekordbox_core::{validate_to_json, validate_verbose, AnalysisCache};
 use crate::config::Config;
-use crate::analyzer;
-use crate::export;
+use crate::analyzer::{self, AnalysisResult, AnalyzeOptions};
+use crate::export::{self, ExportOptions, TargetHardware};
+use crate::fingerprint::find_duplicates;
+use crate::selftest::run_selftest;
+
+/// Longest request line accepted; requests are small JSON objects, so
+/// anything bigger is a broken or hostile client
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// How long a held analysis stays around for an Export to pick up; it
+/// holds every track's waveforms, so it isn't kept indefinitely
+const HOLD_FOR: Duration = Duration::from_secs(10 * 60);
 
 /// Server state
 struct ServerState {
     config: Config,
     cache: AnalysisCache,
+    /// Parent of every request's token; replaced after a Cancel
+    cancel: CancellationToken,
+    /// Audio files visited by the current (or last) directory walk
+    progress: Arc<AtomicUsize>,
+    /// Bumped when cached analyses are dropped, retiring `held`
+    generation: u64,
+    /// Last full analysis, so an Export right after an Analyze doesn't
+    /// walk the library again
+    held: Option<HeldAnalysis>,
+}
+
+impl ServerState {
+    fn new(config: Config, cache: AnalysisCache) -> Self {
+        Self {
+            config,
+            cache,
+            cancel: CancellationToken::new(),
+            progress: Arc::new(AtomicUsize::new(0)),
+            generation: 0,
+            held: None,
+        }
+    }
+}
+
+/// A full analysis result and what the library looked like when it started
+struct HeldAnalysis {
+    music_dir: PathBuf,
+    generation: u64,
+    /// `directory_fingerprint` of `music_dir` before the walk
+    fingerprint: u64,
+    /// `navidrome_fingerprint` before the walk, so playlists edited in
+    /// Navidrome retire the result too
+    navidrome: u64,
+    /// When the result was held; it is dropped `HOLD_FOR` later
+    taken: Instant,
+    result: Arc<AnalysisResult>,
+}
+
+/// Analyze `config.music_dir` and hold the result for later requests
+async fn analyze_and_hold(
+    state: &Arc<Mutex<ServerState>>,
+    config: &Config,
+    cache: &AnalysisCache,
+    options: &AnalyzeOptions,
+) -> anyhow::Result<Arc<AnalysisResult>> {
+    let generation = state.lock().await.generation;
+    let fingerprint = analyzer::directory_fingerprint(&config.music_dir);
+    let navidrome = analyzer::navidrome_fingerprint(config).await;
+    let result = Arc::new(analyzer::analyze_directory(config, cache, options).await?);
+
+    let mut state_guard = state.lock().await;
+    // A CacheClear while walking leaves nothing worth keeping
+    if state_guard.generation == generation {
+        let taken = Instant::now();
+        state_guard.held = Some(HeldAnalysis {
+            music_dir: config.music_dir.clone(),
+            generation,
+            fingerprint,
+            navidrome,
+            taken,
+            result: Arc::clone(&result),
+        });
+
+        let state = Arc::clone(state);
+        tokio::spawn(async move {
+            tokio::time::sleep(HOLD_FOR).await;
+            let mut state_guard = state.lock().await;
+            if state_guard.held.as_ref().is_some_and(|held| held.taken == taken) {
+                debug!("Dropping the held analysis after {:?}", HOLD_FOR);
+                state_guard.held = None;
+            }
+        });
+    }
+    Ok(result)
+}
+
+/// The held analysis of `config.music_dir`, if it is recent, no file or
+/// Navidrome playlist changed and the cache wasn't cleared since it was taken
+async fn held_analysis(state: &Arc<Mutex<ServerState>>, config: &Config) -> Option<Arc<AnalysisResult>> {
+    let (fingerprint, navidrome, result) = {
+        let state_guard = state.lock().await;
+        let held = state_guard.held.as_ref()
+            .filter(|held| held.music_dir == config.music_dir && held.generation == state_guard.generation)
+            .filter(|held| held.taken.elapsed() < HOLD_FOR)?;
+        (held.fingerprint, held.navidrome, Arc::clone(&held.result))
+    };
+    let unchanged = analyzer::directory_fingerprint(&config.music_dir) == fingerprint
+        && analyzer::navidrome_fingerprint(config).await == navidrome;
+    unchanged.then_some(result)
 }
 
 /// Request from CLI client
@@ -26,12 +132,45 @@ struct ServerState {
 #[serde(tag = "method")]
 #[serde(rename_all = "snake_case")]
 enum Request {
-    Analyze { path: Option<String> },
-    Export { output: String },
+    Analyze {
+        path: Option<String>,
+        /// RFC 3339 cutoff; only files modified after it are analyzed
+        #[serde(default)]
+        since: Option<String>,
+    },
+    /// Analyze and cache without returning per-track data
+    Warm {
+        path: Option<String>,
+    },
+    Export {
+        output: String,
+        #[serde(default)]
+        target: Option<TargetHardware>,
+        /// Overrides the configured `truncate_playlist_names`
+        #[serde(default)]
+        truncate_playlist_names: Option<bool>,
+    },
     Status,
+    /// Version, export targets and optional features, for clients to adapt to
+    Info,
     CacheStats,
     CacheClear,
+    /// Every cached analysis with its title, artist and age
+    CacheList,
     ListTracks,
+    /// Groups of tracks whose acoustic fingerprints nearly match
+    FindDuplicates,
+    /// Validate an export.pdb (or the USB root containing one)
+    Validate {
+        path: String,
+        /// Include every page's header fields
+        #[serde(default)]
+        verbose: bool,
+    },
+    /// Build and validate a synthetic library to check this build
+    SelfTest,
+    /// Abort every running analyze/export/list operation
+    Cancel,
 }
 
 /// Response to CLI client
@@ -71,25 +210,25 @@ impl Response {
 }
 
 /// Run the server
+///
+/// `bind_addr` is either host:port (TCP) or a filesystem path (Unix socket).
 pub async fn run(config: Config, cache: AnalysisCache) -> anyhow::Result<()> {
-    let bind_addr = &config.bind_addr;
+    let bind_addr = config.bind_addr.clone();
+    let state = Arc::new(Mutex::new(ServerState::new(config, cache)));
+
+    if is_socket_path(&bind_addr) {
+        return run_unix(Path::new(&bind_addr), state).await;
+    }
 
     // Create TCP listener
-    let listener = TcpListener::bind(bind_addr).await?;
+    let listener = TcpListener::bind(&bind_addr).await?;
     info!("Server listening on {}", bind_addr);
 
-    let state = Arc::new(Mutex::new(ServerState { config, cache }));
-
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
                 debug!("Client connected from {}", addr);
-                let state = Arc::clone(&state);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(stream, state).await {
-                        error!("Client error: {}", e);
-                    }
-                });
+                spawn_client(stream, &state);
             }
             Err(e) => {
                 warn!("Accept error: {}", e);
@@ -98,20 +237,103 @@ pub async fn run(config: Config, cache: AnalysisCache) -> anyhow::Result<()> {
     }
 }
 
+/// Accept clients on a Unix socket
+#[cfg(unix)]
+async fn run_unix(path: &Path, state: Arc<Mutex<ServerState>>) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by a previous run makes bind fail
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    info!("Server listening on {:?}", path);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                debug!("Client connected on {:?}", path);
+                spawn_client(stream, &state);
+            }
+            Err(e) => {
+                warn!("Accept error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_unix(path: &Path, _state: Arc<Mutex<ServerState>>) -> anyhow::Result<()> {
+    anyhow::bail!("Unix sockets are not supported on this platform: {:?}", path)
+}
+
+/// Bind addresses containing a slash are socket paths; host:port never does
+fn is_socket_path(bind_addr: &str) -> bool {
+    bind_addr.contains('/')
+}
+
+fn spawn_client<S>(stream: S, state: &Arc<Mutex<ServerState>>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        if let Err(e) = handle_client(stream, state).await {
+            error!("Client error: {}", e);
+        }
+    });
+}
+
 /// Handle a single client connection
-async fn handle_client(
-    stream: TcpStream,
+async fn handle_client<S>(
+    stream: S,
     state: Arc<Mutex<ServerState>>,
-) -> anyhow::Result<()> {
-    let (reader, mut writer) = stream.into_split();
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
     
-    while reader.read_line(&mut line).await? > 0 {
+    // Read at most one byte past the limit, so an endless line without a
+    // newline can't grow the buffer without bound
+    while (&mut reader).take(MAX_REQUEST_BYTES as u64 + 1).read_line(&mut line).await? > 0 {
+        if line.len() > MAX_REQUEST_BYTES && !line.ends_with('\n') {
+            warn!("Request longer than {} bytes, closing connection", MAX_REQUEST_BYTES);
+            let response = Response::error(format!("Request too long (limit {} bytes)", MAX_REQUEST_BYTES));
+            let response_json = serde_json::to_string(&response)?;
+            writer.write_all(response_json.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
+            return Ok(());
+        }
+
         debug!("Received: {}", line.trim());
         
         let response = match serde_json::from_str::<Request>(&line) {
-            Ok(request) => handle_request(request, &state).await,
+            Ok(request) => {
+                let cancel = state.lock().await.cancel.child_token();
+                let work = handle_request(request, &state, cancel.clone());
+                tokio::pin!(work);
+
+                // Watch the socket while the request runs so a client that
+                // goes away stops the work instead of leaving it running
+                tokio::select! {
+                    response = &mut work => response,
+                    closed = client_closed(&mut reader) => {
+                        if closed {
+                            debug!("Client disconnected, cancelling request");
+                            cancel.cancel();
+                            work.await;
+                            return Ok(());
+                        }
+                        // Next request already pipelined; it stays buffered
+                        work.await
+                    }
+                }
+            }
             Err(e) => Response::error(format!("Invalid request: {}", e)),
         };
         
@@ -126,24 +348,68 @@ async fn handle_client(
     Ok(())
 }
 
+/// Wait until the client either sends more data (false) or closes the
+/// connection (true), without consuming anything from the buffer
+async fn client_closed<R: AsyncRead>(reader: &mut BufReader<ReadHalf<R>>) -> bool {
+    match reader.fill_buf().await {
+        Ok(buf) => buf.is_empty(),
+        Err(_) => true,
+    }
+}
+
+/// Clone what a long-running operation needs so the lock is released before it starts
+async fn snapshot(state: &Arc<Mutex<ServerState>>) -> (Config, AnalysisCache) {
+    let state_guard = state.lock().await;
+    (state_guard.config.clone(), state_guard.cache.clone())
+}
+
+/// Walk options for a request, sharing the server's progress counter
+async fn analyze_options(
+    state: &Arc<Mutex<ServerState>>,
+    cancel: CancellationToken,
+    since: Option<std::time::SystemTime>,
+) -> AnalyzeOptions {
+    let progress = Arc::clone(&state.lock().await.progress);
+    progress.store(0, Ordering::Relaxed);
+    AnalyzeOptions { since, cancel, progress, cached_only: false }
+}
+
 /// Process a request
 async fn handle_request(
     request: Request,
     state: &Arc<Mutex<ServerState>>,
+    cancel: CancellationToken,
 ) -> Response {
     match request {
-        Request::Analyze { path } => {
-            let state_guard = state.lock().await;
+        Request::Analyze { path, since } => {
+            let since = match since {
+                Some(ref value) => match analyzer::parse_rfc3339(value) {
+                    Some(time) => Some(time),
+                    None => return Response::error(format!(
+                        "Invalid since timestamp {:?} (expected RFC 3339)", value
+                    )),
+                },
+                None => None,
+            };
+
+            let (config, cache) = snapshot(state).await;
             let music_dir = path
                 .map(std::path::PathBuf::from)
-                .unwrap_or_else(|| state_guard.config.music_dir.clone());
+                .unwrap_or_else(|| config.music_dir.clone());
             
             let config = Config {
                 music_dir,
-                ..state_guard.config.clone()
+                ..config
             };
-            
-            match analyzer::analyze_directory(&config, &state_guard.cache).await {
+            let options = analyze_options(state, cancel, since).await;
+            // A `since` walk only sees part of the library
+            let analysis = if since.is_some() {
+                analyzer::analyze_directory(&config, &cache, &options).await.map(Arc::new)
+            } else {
+                analyze_and_hold(state, &config, &cache, &options).await
+            };
+
+            match analysis {
                 Ok(result) => {
                     Response::ok_with_data(
                         format!("Analyzed {} tracks in {} playlists",
@@ -167,20 +433,78 @@ async fn handle_request(
             }
         }
 
-        Request::Export { output } => {
-            let state_guard = state.lock().await;
+        Request::Warm { path } => {
+            let (config, cache) = snapshot(state).await;
+            let config = Config {
+                music_dir: path
+                    .map(std::path::PathBuf::from)
+                    .unwrap_or_else(|| config.music_dir.clone()),
+                ..config
+            };
+            let options = analyze_options(state, cancel, None).await;
+
+            match analyzer::analyze_directory(&config, &cache, &options).await {
+                Ok(result) => Response::ok_with_data(
+                    format!(
+                        "Cache warmed: {} analyzed, {} cached, {} skipped",
+                        result.counts.analyzed, result.counts.cache_hits, result.counts.skipped
+                    ),
+                    serde_json::json!(result.counts),
+                ),
+                Err(e) => Response::error(format!("Warm failed: {}", e)),
+            }
+        }
+
+        Request::Export { output, target, truncate_playlist_names } => {
+            let (config, cache) = snapshot(state).await;
             let output_path = std::path::Path::new(&output);
+            let options = ExportOptions {
+                hot_cue_slots: config.hot_cue_slots,
+                quantize_cues_ms: config.quantize_cues_ms,
+                missing_files: config.missing_files,
+                dir_casing: config.dir_casing,
+                truncate_playlist_names: truncate_playlist_names.unwrap_or(config.truncate_playlist_names),
+                energy_tag: config.energy_tag,
+                anlz_layout: config.anlz_layout.clone(),
+                transcode_dir: config.transcode_dir(),
+                clock: config.export_clock(),
+                tool_marker: config.tool_marker(),
+                ..ExportOptions::for_target(target.unwrap_or(config.target))
+            };
 
-            // First analyze
-            match analyzer::analyze_directory(&state_guard.config, &state_guard.cache).await {
+            // First analyze, unless the last Analyze still describes the library
+            let held = held_analysis(state, &config).await;
+            let reused = held.is_some();
+            let analysis = match held {
+                Some(result) => {
+                    debug!("Export reusing held analysis of {:?}", config.music_dir);
+                    Ok(result)
+                }
+                None => {
+                    let analyze = analyze_options(state, cancel, None).await;
+                    analyze_and_hold(state, &config, &cache, &analyze).await
+                }
+            };
+            match analysis {
                 Ok(result) => {
                     match export::export_usb(
                         &result.tracks,
                         &result.playlists,
-                        &state_guard.config.music_dir,
-                        output_path
+                        &config.music_dir,
+                        output_path,
+                        &options,
                     ) {
-                        Ok(()) => Response::ok(format!("Exported {} tracks to {}", result.tracks.len(), output)),
+                        Ok(report) => Response::ok_with_data(
+                            format!("Exported {} tracks to {}", report.tracks_written, output),
+                            serde_json::json!({
+                                "warnings": report.warnings,
+                                "skipped": report.skipped,
+                                "missing_anlz": report.missing_anlz,
+                                "bytes_copied": report.bytes_copied,
+                                "bytes_skipped": report.bytes_skipped,
+                                "analysis_reused": reused,
+                            }),
+                        ),
                         Err(e) => Response::error(format!("Export failed: {}", e)),
                     }
                 }
@@ -188,8 +512,57 @@ async fn handle_request(
             }
         }
         
+        Request::Validate { path, verbose } => {
+            let mut pdb_path = std::path::PathBuf::from(&path);
+            if pdb_path.is_dir() {
+                pdb_path = pdb_path.join("PIONEER").join("rekordbox").join("export.pdb");
+            }
+
+            match tokio::fs::read(&pdb_path).await {
+                Ok(data) => {
+                    let mut result = validate_to_json(&data);
+                    if verbose {
+                        result["pages"] = serde_json::json!(validate_verbose(&data));
+                    }
+                    let status = if result["valid"] == true { "VALID" } else { "INVALID" };
+                    Response::ok_with_data(format!("{:?} is {}", pdb_path, status), result)
+                }
+                Err(e) => Response::error(format!("Failed to read {:?}: {}", pdb_path, e)),
+            }
+        }
+
+        Request::SelfTest => {
+            let report = run_selftest();
+            let failed = report.checks.iter().filter(|c| !c.passed).count();
+            let message = if report.passed {
+                "Self-test passed".to_string()
+            } else {
+                format!("Self-test failed: {} of {} checks", failed, report.checks.len())
+            };
+            Response::ok_with_data(message, serde_json::json!(report))
+        }
+
         Request::Status => {
-            Response::ok("Server running")
+            let files_processed = state.lock().await.progress.load(Ordering::Relaxed);
+            Response::ok_with_data(
+                "Server running",
+                serde_json::json!({ "files_processed": files_processed }),
+            )
+        }
+
+        Request::Info => {
+            let config = state.lock().await.config.clone();
+            Response::ok_with_data(
+                format!("rekordbox-server {}", env!("CARGO_PKG_VERSION")),
+                server_info(&config),
+            )
+        }
+
+        Request::Cancel => {
+            let mut state_guard = state.lock().await;
+            state_guard.cancel.cancel();
+            state_guard.cancel = CancellationToken::new();
+            Response::ok("Cancelled running operations")
         }
         
         Request::CacheStats => {
@@ -208,16 +581,39 @@ async fn handle_request(
         }
         
         Request::CacheClear => {
-            let state_guard = state.lock().await;
+            let mut state_guard = state.lock().await;
+            state_guard.generation += 1;
+            state_guard.held = None;
             match state_guard.cache.clear() {
                 Ok(()) => Response::ok("Cache cleared"),
                 Err(e) => Response::error(format!("Failed to clear cache: {}", e)),
             }
         }
         
+        Request::CacheList => {
+            let cache = state.lock().await.cache.clone();
+            match cache.entries() {
+                Ok(entries) => Response::ok_with_data(
+                    format!("{} cached analyses", entries.len()),
+                    serde_json::json!(entries.iter().map(|(hash, meta)| serde_json::json!({
+                        "hash": format!("{:016x}", hash),
+                        "title": meta.title,
+                        "artist": meta.artist,
+                        "path": meta.file_path,
+                        "cached_at": meta.modified
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map(|d| d.as_secs())
+                            .unwrap_or(0),
+                    })).collect::<Vec<_>>()),
+                ),
+                Err(e) => Response::error(format!("Failed to list cache: {}", e)),
+            }
+        }
+
         Request::ListTracks => {
-            let state_guard = state.lock().await;
-            match analyzer::analyze_directory(&state_guard.config, &state_guard.cache).await {
+            let (config, cache) = snapshot(state).await;
+            let options = analyze_options(state, cancel, None).await;
+            match analyzer::analyze_directory(&config, &cache, &options).await {
                 Ok(result) => Response::ok_with_data(
                     format!("{} tracks found in {} playlists",
                             result.tracks.len(), result.playlists.len()),
@@ -229,8 +625,12 @@ async fn handle_request(
                             "artist": t.artist,
                             "album": t.album,
                             "bpm": t.bpm,
+                            "bpm_confidence": t.bpm_confidence,
                             "key": t.key.map(|k| k.to_camelot()),
                             "duration": t.duration_secs,
+                            "clip_ratio": t.clip_ratio,
+                            "spectral_cutoff_hz": t.spectral_cutoff_hz,
+                            "audio_start_ms": t.audio_start_ms,
                         })).collect::<Vec<_>>(),
                         "playlists": result.playlists.iter().map(|(name, ids)| {
                             serde_json::json!({
@@ -243,5 +643,358 @@ async fn handle_request(
                 Err(e) => Response::error(format!("Failed to list tracks: {}", e)),
             }
         }
+
+        Request::FindDuplicates => {
+            let (config, cache) = snapshot(state).await;
+            let options = analyze_options(state, cancel, None).await;
+            match analyzer::analyze_directory(&config, &cache, &options).await {
+                Ok(result) => {
+                    let groups = find_duplicates(&result.tracks);
+                    let by_id: std::collections::HashMap<u32, &rekordbox_core::TrackAnalysis> =
+                        result.tracks.iter().map(|t| (t.id, t)).collect();
+                    Response::ok_with_data(
+                        format!("{} groups of likely duplicates", groups.len()),
+                        serde_json::json!(groups.iter().map(|group| {
+                            group.iter().map(|id| {
+                                let t = by_id[id];
+                                serde_json::json!({
+                                    "id": t.id,
+                                    "path": t.file_path,
+                                    "title": t.title,
+                                    "artist": t.artist,
+                                    "bitrate": t.bitrate,
+                                    "file_type": t.file_type,
+                                })
+                            }).collect::<Vec<_>>()
+                        }).collect::<Vec<_>>()),
+                    )
+                }
+                Err(e) => Response::error(format!("Failed to find duplicates: {}", e)),
+            }
+        }
+    }
+}
+
+/// Build info and capabilities reported by `Request::Info`
+fn server_info(config: &Config) -> serde_json::Value {
+    let targets: Vec<_> = TargetHardware::value_variants()
+        .iter()
+        .map(|&target| {
+            let options = ExportOptions::for_target(target);
+            let mut anlz = vec!["DAT"];
+            if options.write_ext {
+                anlz.push("EXT");
+            }
+            if options.write_2ex {
+                anlz.push("2EX");
+            }
+            serde_json::json!({
+                "name": target.to_possible_value().map(|v| v.get_name().to_string()),
+                "anlz_files": anlz,
+                "color_waveforms": options.color_preview,
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        // FAT32 images are only written by a direct `--export --image-size`
+        "export_formats": ["directory"],
+        "targets": targets,
+        "default_target": config.target.to_possible_value().map(|v| v.get_name().to_string()),
+        "navidrome": config.navidrome.is_some(),
+        "transcode_incompatible": config.transcode_incompatible,
+        "unix_socket": cfg!(unix),
+        "progress_streaming": false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::tone_wav;
+    use tempfile::TempDir;
+    use tokio::net::TcpStream;
+
+    fn test_config(tmp: &TempDir) -> Config {
+        Config::for_test(tmp.path())
+    }
+
+    fn test_state(tmp: &TempDir) -> Arc<Mutex<ServerState>> {
+        let config = test_config(tmp);
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+        Arc::new(Mutex::new(ServerState::new(config, cache)))
+    }
+
+    #[tokio::test]
+    async fn test_status_responds_during_long_operation() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        for seconds in 1..=3 {
+            std::fs::write(music_dir.join(format!("{}.wav", seconds)), tone_wav(1, seconds)).unwrap();
+        }
+
+        let analyze = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                let request = Request::Analyze { path: None, since: None };
+                handle_request(request, &state, CancellationToken::new()).await
+            }
+        });
+
+        // The walk yields before each file; catch it after the first one
+        let files_processed = |response: Response| response.data.unwrap()["files_processed"].clone();
+        loop {
+            tokio::task::yield_now().await;
+            let status = handle_request(Request::Status, &state, CancellationToken::new()).await;
+            if files_processed(status) != 0 {
+                break;
+            }
+        }
+        assert!(!analyze.is_finished());
+        let status = handle_request(Request::Status, &state, CancellationToken::new()).await;
+        assert_eq!(files_processed(status), 1);
+        let stats = handle_request(Request::CacheStats, &state, CancellationToken::new()).await;
+        assert_eq!(stats.data.unwrap()["entries"], 1);
+
+        assert!(analyze.await.unwrap().success);
+        let status = handle_request(Request::Status, &state, CancellationToken::new()).await;
+        assert_eq!(files_processed(status), 3);
+        let stats = handle_request(Request::CacheStats, &state, CancellationToken::new()).await;
+        assert_eq!(stats.data.unwrap()["entries"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_info_reports_version_and_navidrome() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+
+        let response = handle_request(Request::Info, &state, CancellationToken::new()).await;
+        assert!(response.success);
+        let info = response.data.unwrap();
+        let version = info["version"].as_str().unwrap();
+        let parts: Vec<_> = version.split(['.', '-', '+']).take(3).collect();
+        assert_eq!(parts.len(), 3, "{}", version);
+        assert!(parts.iter().all(|p| p.parse::<u64>().is_ok()), "{}", version);
+        assert_eq!(info["navidrome"], false);
+        assert_eq!(info["default_target"], "cdj3000");
+        assert_eq!(info["export_formats"], serde_json::json!(["directory"]));
+        assert_eq!(info["targets"].as_array().unwrap().len(), TargetHardware::value_variants().len());
+
+        state.lock().await.config.navidrome = Some(crate::config::NavidromeConfig::new(
+            "http://localhost:4533".into(), "dj".into(), "secret".into(),
+        ));
+        let response = handle_request(Request::Info, &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["navidrome"], true);
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_cancels_analysis() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+
+        // Undecodable files still count as visited, and fail fast
+        const FILES: usize = 200;
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        for i in 0..FILES {
+            std::fs::write(music_dir.join(format!("{:03}.mp3", i)), [i as u8; 16]).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let server = tokio::spawn(handle_client(stream, Arc::clone(&state)));
+
+        client.write_all(b"{\"method\":\"analyze\"}\n").await.unwrap();
+        client.flush().await.unwrap();
+        drop(client);
+
+        server.await.unwrap().unwrap();
+        let visited = state.lock().await.progress.load(Ordering::Relaxed);
+        assert!(visited < FILES, "walk visited all {} files", visited);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_rejected() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let server = tokio::spawn(handle_client(stream, Arc::clone(&state)));
+
+        // One byte over the limit and no newline in sight
+        client.write_all(&vec![b'x'; MAX_REQUEST_BYTES + 1]).await.unwrap();
+
+        let mut line = String::new();
+        let mut reader = BufReader::new(&mut client);
+        let read = reader.read_line(&mut line);
+        tokio::time::timeout(std::time::Duration::from_secs(5), read).await
+            .expect("server should answer instead of waiting for a newline")
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["success"], false);
+        assert!(response["message"].as_str().unwrap().contains("too long"), "{}", line);
+
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_round_trip() {
+        use tokio::net::UnixStream;
+
+        let tmp = TempDir::new().unwrap();
+        let socket = tmp.path().join("rekordbox.sock");
+        let config = Config {
+            bind_addr: socket.to_string_lossy().into_owned(),
+            ..test_config(&tmp)
+        };
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+        let server = tokio::spawn(run(config, cache));
+
+        let connect = async {
+            loop {
+                match UnixStream::connect(&socket).await {
+                    Ok(client) => break client,
+                    Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let mut client = tokio::time::timeout(std::time::Duration::from_secs(5), connect)
+            .await
+            .expect("server never started listening on the socket");
+        client.write_all(b"{\"method\":\"status\"}\n").await.unwrap();
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["success"], true);
+        assert_eq!(response["data"]["files_processed"], 0);
+
+        server.abort();
+    }
+
+    #[test]
+    fn test_socket_path_detection() {
+        assert!(is_socket_path("/tmp/rekordbox.sock"));
+        assert!(is_socket_path("./rekordbox.sock"));
+        assert!(!is_socket_path("0.0.0.0:6969"));
+        assert!(!is_socket_path("[::1]:6969"));
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicates_groups_copies() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(music_dir.join("Crate")).unwrap();
+        std::fs::write(music_dir.join("a.wav"), tone_wav(1, 2)).unwrap();
+        std::fs::write(music_dir.join("Crate/a copy.wav"), tone_wav(1, 2)).unwrap();
+        std::fs::write(music_dir.join("short.wav"), tone_wav(1, 0)).unwrap();
+
+        let response = handle_request(Request::FindDuplicates, &state, CancellationToken::new()).await;
+        assert!(response.success, "{:?}", response.message);
+        let groups = response.data.unwrap();
+        assert_eq!(groups.as_array().unwrap().len(), 1);
+        assert_eq!(groups[0].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_reuses_analysis_until_library_changes() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+        let cache = state.lock().await.cache.clone();
+
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        std::fs::write(music_dir.join("a.wav"), tone_wav(1, 2)).unwrap();
+        let output = tmp.path().join("usb");
+        std::fs::create_dir_all(&output).unwrap();
+        let export = || Request::Export { output: output.to_string_lossy().into_owned(), target: None, truncate_playlist_names: None };
+
+        let response = handle_request(Request::Analyze { path: None, since: None }, &state, CancellationToken::new()).await;
+        assert!(response.success, "{:?}", response.message);
+        // Emptied behind the server's back: only a new analysis refills it
+        cache.clear().unwrap();
+
+        let response = handle_request(export(), &state, CancellationToken::new()).await;
+        assert!(response.success, "{:?}", response.message);
+        assert_eq!(response.data.unwrap()["analysis_reused"], true);
+        assert_eq!(cache.stats().unwrap().entry_count, 0);
+
+        // A new file invalidates the held result
+        std::fs::write(music_dir.join("b.wav"), tone_wav(1, 3)).unwrap();
+        let response = handle_request(export(), &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["analysis_reused"], false);
+        assert_eq!(cache.stats().unwrap().entry_count, 2);
+
+        // So does clearing the cache
+        let response = handle_request(export(), &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["analysis_reused"], true);
+        assert!(handle_request(Request::CacheClear, &state, CancellationToken::new()).await.success);
+        let response = handle_request(export(), &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["analysis_reused"], false);
+
+        // Or holding it for too long
+        let taken = state.lock().await.held.as_ref().unwrap().taken;
+        state.lock().await.held.as_mut().unwrap().taken = taken.checked_sub(HOLD_FOR).unwrap();
+        let response = handle_request(export(), &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["analysis_reused"], false);
+        assert!(state.lock().await.held.as_ref().unwrap().taken > taken);
+    }
+
+    #[tokio::test]
+    async fn test_held_analysis_follows_navidrome_settings() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        std::fs::write(music_dir.join("a.wav"), tone_wav(1, 2)).unwrap();
+
+        let response = handle_request(Request::Analyze { path: None, since: None }, &state, CancellationToken::new()).await;
+        assert!(response.success, "{:?}", response.message);
+        let config = state.lock().await.config.clone();
+        assert!(held_analysis(&state, &config).await.is_some());
+
+        // Playlists would now come from a server the held result never saw
+        state.lock().await.config.navidrome = Some(crate::config::NavidromeConfig::new(
+            "http://127.0.0.1:9".into(), "dj".into(), "secret".into(),
+        ));
+        let config = state.lock().await.config.clone();
+        assert!(held_analysis(&state, &config).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_warm_populates_cache_with_compact_response() {
+        let tmp = TempDir::new().unwrap();
+        let state = test_state(&tmp);
+
+        let music_dir = tmp.path().join("music");
+        std::fs::create_dir_all(&music_dir).unwrap();
+        std::fs::write(music_dir.join("a.wav"), tone_wav(1, 2)).unwrap();
+        std::fs::write(music_dir.join("b.wav"), tone_wav(1, 3)).unwrap();
+        std::fs::write(music_dir.join("broken.mp3"), [0u8; 16]).unwrap();
+
+        let warm = || Request::Warm { path: None };
+        let response = handle_request(warm(), &state, CancellationToken::new()).await;
+        assert!(response.success, "{:?}", response.message);
+        assert_eq!(
+            response.data,
+            Some(serde_json::json!({ "analyzed": 2, "cache_hits": 0, "skipped": 1 }))
+        );
+        assert!(serde_json::to_string(&response).unwrap().len() < 200);
+        assert_eq!(state.lock().await.cache.stats().unwrap().entry_count, 2);
+
+        let response = handle_request(warm(), &state, CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["cache_hits"], 2);
     }
 }
@@ -0,0 +1,204 @@
+//! Analysis sidecar files
+//!
+//! A sidecar is a `<name>.rbx.json` file next to an audio file holding
+//! hand-corrected analysis (BPM, key, first beat, cues). Any field present
+//! in the sidecar overrides the auto-detected value.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use rekordbox_core::{BeatGrid, CuePoint, Key, TrackAnalysis};
+
+/// Sidecar file extension (appended in place of the audio extension)
+const SIDECAR_EXTENSION: &str = "rbx.json";
+
+/// Partial analysis overrides read from a sidecar file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Sidecar {
+    /// BPM override
+    pub bpm: Option<f64>,
+    /// Key override
+    pub key: Option<Key>,
+    /// First beat position in milliseconds
+    pub first_beat_ms: Option<f64>,
+    /// Cue points (replace any detected cues)
+    pub cues: Option<Vec<CuePoint>>,
+}
+
+impl Sidecar {
+    /// Merge sidecar fields over an analysis result
+    ///
+    /// A BPM override scales the detected grid (every tempo of a changing
+    /// one by the same factor) and a first beat override moves it, so its
+    /// time signature and tempo changes stay. A grid with no beats is
+    /// regenerated at the BPM instead.
+    pub fn apply(&self, analysis: &mut TrackAnalysis) {
+        let detected_bpm = analysis.beat_grid.bpm;
+        let bpm = self.bpm.filter(|b| *b > 0.0);
+        if let Some(bpm) = bpm {
+            analysis.bpm = bpm;
+            analysis.bpm_confidence = 1.0;
+        }
+
+        if let Some(key) = self.key {
+            analysis.key = Some(key);
+        }
+
+        if bpm.is_some() || self.first_beat_ms.is_some() {
+            let grid = &mut analysis.beat_grid;
+            let first_beat_ms = self.first_beat_ms.unwrap_or(grid.first_beat_ms);
+            if grid.beats.is_empty() || detected_bpm <= 0.0 {
+                *grid = BeatGrid::constant_tempo_with_signature(
+                    analysis.bpm,
+                    first_beat_ms,
+                    analysis.duration_secs * 1000.0,
+                    grid.time_signature,
+                );
+            } else {
+                if let Some(bpm) = bpm {
+                    grid.scale_tempo(bpm / detected_bpm);
+                }
+                grid.set_first_beat(first_beat_ms);
+            }
+        }
+
+        if let Some(ref cues) = self.cues {
+            analysis.cue_points = cues.clone();
+        }
+    }
+}
+
+/// Get the sidecar path for an audio file (`track.mp3` -> `track.rbx.json`)
+pub fn sidecar_path(audio_path: &Path) -> PathBuf {
+    audio_path.with_extension(SIDECAR_EXTENSION)
+}
+
+/// Load the sidecar for an audio file, if one exists and parses
+pub fn load_sidecar(audio_path: &Path) -> Option<Sidecar> {
+    let path = sidecar_path(audio_path);
+    if !path.is_file() {
+        return None;
+    }
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Failed to read sidecar {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(sidecar) => {
+            debug!("Loaded sidecar {:?}", path);
+            Some(sidecar)
+        }
+        Err(e) => {
+            warn!("Ignoring invalid sidecar {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rekordbox_core::FileType;
+    use tempfile::TempDir;
+
+    fn make_analysis(bpm: f64) -> TrackAnalysis {
+        TrackAnalysis {
+            id: 1,
+            file_path: "/Contents/track.mp3".into(),
+            title: "Track".into(),
+            artist: "Artist".into(),
+            duration_secs: 60.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            bitrate: 320,
+            bpm,
+            beat_grid: BeatGrid::constant_tempo(bpm, 0.0, 60_000.0),
+            file_type: FileType::Mp3,
+            ..TrackAnalysis::default()
+        }
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            sidecar_path(Path::new("/music/track.mp3")),
+            PathBuf::from("/music/track.rbx.json")
+        );
+    }
+
+    #[test]
+    fn test_sidecar_overrides_detected_bpm() {
+        let tmp = TempDir::new().unwrap();
+        let audio = tmp.path().join("track.mp3");
+        fs::write(
+            sidecar_path(&audio),
+            r#"{"bpm": 140.0, "first_beat_ms": 250.0, "key": {"pitch_class": 9, "is_major": false}}"#,
+        ).unwrap();
+
+        let mut analysis = make_analysis(128.0);
+        let sidecar = load_sidecar(&audio).expect("sidecar should load");
+        sidecar.apply(&mut analysis);
+
+        assert_eq!(analysis.bpm, 140.0);
+        assert_eq!(analysis.key, Some(Key::new(9, false)));
+        assert_eq!(analysis.beat_grid.bpm, 140.0);
+        assert_eq!(analysis.beat_grid.first_beat_ms, 250.0);
+        assert_eq!(analysis.beat_grid.beats[0].tempo_100, 14000);
+    }
+
+    #[test]
+    fn test_sidecar_keeps_time_signature() {
+        let mut analysis = make_analysis(128.0);
+        analysis.beat_grid = BeatGrid::constant_tempo_with_signature(128.0, 0.0, 60_000.0, (3, 4));
+
+        let sidecar = Sidecar { bpm: Some(140.0), first_beat_ms: Some(250.0), ..Sidecar::default() };
+        sidecar.apply(&mut analysis);
+
+        let grid = &analysis.beat_grid;
+        assert_eq!(grid.time_signature, (3, 4));
+        assert_eq!(grid.bpm, 140.0);
+        assert_eq!(grid.first_beat_ms, 250.0);
+        let numbers: Vec<u8> = grid.beats[..4].iter().map(|b| b.beat_number).collect();
+        assert_eq!(numbers, [1, 2, 3, 1]);
+        assert_eq!(grid.beats[1].tempo_100, 14000);
+    }
+
+    #[test]
+    fn test_sidecar_keeps_tempo_changes() {
+        let mut analysis = make_analysis(120.0);
+        analysis.beat_grid = BeatGrid::variable_tempo(&[(0.0, 120.0), (30_000.0, 126.0)], 60_000.0, (4, 4));
+
+        // Only the first beat: the grid moves, tempos untouched
+        let sidecar = Sidecar { first_beat_ms: Some(100.0), ..Sidecar::default() };
+        sidecar.apply(&mut analysis);
+        assert_eq!(analysis.bpm, 120.0);
+        assert_eq!(analysis.beat_grid.tempo_changes(), [(100.0, 120.0), (30_100.0, 126.0)]);
+
+        // Half the BPM: both tempos halve, the first beat stays
+        let sidecar = Sidecar { bpm: Some(60.0), ..Sidecar::default() };
+        sidecar.apply(&mut analysis);
+        let changes = analysis.beat_grid.tempo_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0], (100.0, 60.0));
+        assert_eq!(changes[1].1, 63.0);
+    }
+
+    #[test]
+    fn test_invalid_sidecar_ignored() {
+        let tmp = TempDir::new().unwrap();
+        let audio = tmp.path().join("track.mp3");
+        fs::write(sidecar_path(&audio), "not json").unwrap();
+
+        assert!(load_sidecar(&audio).is_none());
+    }
+}
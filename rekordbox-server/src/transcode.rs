@@ -0,0 +1,186 @@
+//! Re-encoding of formats the players can't read
+//!
+//! CDJs play MP3, AAC/ALAC, FLAC, WAV and AIFF only. With transcoding
+//! enabled, Opus/Ogg/WMA sources are decoded by an external `ffmpeg` into
+//! 16-bit AIFF under `<cache_dir>/transcoded/`, laid out like the music
+//! folder. The AIFF is what gets analyzed and exported, so the track's file
+//! path, type and size describe the file that actually ends up in
+//! `Contents/`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+use tracing::{debug, info};
+
+/// Source extensions that need re-encoding before export
+const INCOMPATIBLE_EXTENSIONS: &[&str] = &["opus", "ogg", "oga", "wma"];
+
+/// External encoder binary (looked up on PATH)
+const FFMPEG: &str = "ffmpeg";
+
+/// Whether a file is in a format the players can't read
+pub fn needs_transcode(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| INCOMPATIBLE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Whether ffmpeg can be run
+pub fn encoder_available() -> bool {
+    Command::new(FFMPEG)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Directory holding the transcoded copies
+pub fn transcode_dir(cache_dir: &Path) -> PathBuf {
+    cache_dir.join("transcoded")
+}
+
+/// Where the AIFF copy of `source` goes
+///
+/// The copy keeps the source's path below `music_dir`, file name and
+/// extension included, plus `.aiff`: `House/a.opus` becomes
+/// `House/a.opus.aiff`, so neither `Techno/a.opus` nor `House/a.ogg` share
+/// it. Sources outside `music_dir` go by their file name.
+pub fn transcoded_path(source: &Path, music_dir: &Path, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let relative = match source.strip_prefix(music_dir) {
+        Ok(relative) => relative.as_os_str().to_owned(),
+        Err(_) => source.file_name()
+            .with_context(|| format!("No file name in {:?}", source))?
+            .to_owned(),
+    };
+    let mut name = relative;
+    name.push(".aiff");
+    Ok(transcode_dir(cache_dir).join(name))
+}
+
+/// AIFF copy of `source`, encoding it unless an up-to-date copy exists
+///
+/// The copy lies at `transcoded_path`, so it is exported (and found again
+/// at export time) under the source's folder in `Contents/`. Runs ffmpeg to
+/// completion; call it off the async runtime.
+pub fn ensure_transcoded(source: &Path, music_dir: &Path, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let dest = transcoded_path(source, music_dir, cache_dir)?;
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let (Some(copy), Some(original)) = (modified(&dest), modified(source)) {
+        if copy >= original {
+            debug!("Reusing transcoded {:?}", dest);
+            return Ok(dest);
+        }
+    }
+
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    info!("Transcoding {:?} to AIFF", source);
+    // Write under a temporary name so an interrupted run never leaves a
+    // truncated file that looks up to date
+    let partial = dest.with_extension("aiff.partial");
+    let output = Command::new(FFMPEG)
+        .args(["-nostdin", "-v", "error", "-y", "-i"])
+        .arg(source)
+        .args(["-map", "0:a:0", "-map_metadata", "0", "-write_id3v2", "1", "-c:a", "pcm_s16be", "-f", "aiff"])
+        .arg(&partial)
+        .output()
+        .with_context(|| format!("Failed to run {} (is it installed?)", FFMPEG))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial);
+        anyhow::bail!(
+            "{} failed on {:?}: {}",
+            FFMPEG,
+            source,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fs::rename(&partial, &dest)?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    use rekordbox_core::{AnalysisCache, FileType};
+    use crate::analyzer::analyze_directory;
+    use crate::config::Config;
+    use crate::export::{export_usb, ExportOptions};
+
+    /// Encode a 2 s tone to Opus with ffmpeg; None when ffmpeg (or its
+    /// Opus encoder) isn't available
+    fn opus_tone(path: &Path) -> Option<()> {
+        let status = Command::new(FFMPEG)
+            .args(["-nostdin", "-v", "error", "-y", "-f", "lavfi", "-i", "sine=frequency=440:duration=2"])
+            .args(["-metadata", "title=Opus Tone", "-c:a", "libopus"])
+            .arg(path)
+            .status()
+            .ok()?;
+        status.success().then_some(())
+    }
+
+    #[tokio::test]
+    async fn test_opus_source_exported_as_aiff() {
+        assert!(needs_transcode(Path::new("set.OPUS")));
+        assert!(!needs_transcode(Path::new("set.flac")));
+
+        let tmp = TempDir::new().unwrap();
+        let music_dir = tmp.path().join("music");
+        let output = tmp.path().join("usb");
+        fs::create_dir_all(&music_dir).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        if !encoder_available() || opus_tone(&music_dir.join("tone.opus")).is_none() {
+            eprintln!("skipping: ffmpeg with libopus not available");
+            return;
+        }
+
+        let config = Config {
+            music_dir: music_dir.clone(),
+            output_dir: Some(output.clone()),
+            transcode_incompatible: true,
+            ..Config::for_test(tmp.path())
+        };
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+        let result = analyze_directory(&config, &cache, &Default::default()).await.unwrap();
+
+        assert_eq!(result.tracks.len(), 1);
+        let track = &result.tracks[0];
+        let transcoded = transcode_dir(&config.cache_dir).join("tone.opus.aiff");
+        assert_eq!(track.file_path, "/Contents/tone.opus.aiff");
+        assert_eq!(track.file_type, FileType::Aiff);
+        assert_eq!(track.file_size, fs::metadata(&transcoded).unwrap().len());
+        assert!((track.duration_secs - 2.0).abs() < 0.1, "{}", track.duration_secs);
+
+        let options = ExportOptions {
+            transcode_dir: Some(transcode_dir(&config.cache_dir)),
+            ..ExportOptions::default()
+        };
+        let report = export_usb(&result.tracks, &HashMap::new(), &music_dir, &output, &options).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let exported = output.join("Contents/tone.opus.aiff");
+        assert_eq!(fs::read(&exported).unwrap(), fs::read(&transcoded).unwrap());
+        assert!(!output.join("Contents/tone.opus").exists());
+        let pdb = fs::read(output.join("PIONEER/rekordbox/export.pdb")).unwrap();
+        assert!(pdb.windows(24).any(|w| w == b"/Contents/tone.opus.aiff"));
+    }
+
+    #[test]
+    fn test_transcoded_paths_stay_apart() {
+        let music = Path::new("/music");
+        let cache = Path::new("/cache");
+        let copy = |source: &str| transcoded_path(Path::new(source), music, cache).unwrap();
+
+        assert_eq!(copy("/music/House/a.opus"), transcode_dir(cache).join("House/a.opus.aiff"));
+        assert_ne!(copy("/music/House/a.opus"), copy("/music/Techno/a.opus"));
+        assert_ne!(copy("/music/House/a.opus"), copy("/music/House/a.ogg"));
+        assert_eq!(copy("/elsewhere/b.wma"), transcode_dir(cache).join("b.wma.aiff"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Watch mode
+//!
+//! Keeps the analysis cache current while tracks are added to the music
+//! directory. Filesystem events from `notify` are collected until the
+//! directory has been quiet for a moment (a copy arrives as a burst of
+//! writes), then new or changed audio files are analyzed and removed ones
+//! have their cache entries dropped. Nothing else is rescanned.
+//!
+//! The cache is keyed by content hash, which can't be computed once a file
+//! is gone, so the watcher remembers the hash of every audio file it has
+//! seen. Copies of one file share an entry; it is only dropped once no
+//! known file has that hash.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use rekordbox_core::{compute_file_hash, AnalysisCache};
+use crate::analyzer::{cache_file, is_audio_file};
+use crate::config::Config;
+use crate::transcode::needs_transcode;
+
+/// Quiet period that ends a batch of events
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// A change to one path, reduced from the raw filesystem events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// Created, written to or renamed into place
+    Changed(PathBuf),
+    /// Deleted or renamed away (a directory stands for everything under it)
+    Removed(PathBuf),
+}
+
+impl WatchEvent {
+    fn path(&self) -> &Path {
+        match self {
+            WatchEvent::Changed(path) | WatchEvent::Removed(path) => path,
+        }
+    }
+}
+
+/// Order a batch's changes for handling: removals first, then by path
+///
+/// A removal may drop a cache entry; handling the changed files afterwards
+/// means an entry one of them needs (a file moved or copied within the
+/// batch) is rebuilt rather than left dropped.
+fn sort_changes(changes: &mut [WatchEvent]) {
+    changes.sort_by(|a, b| {
+        let key = |change: &WatchEvent| (matches!(change, WatchEvent::Changed(_)), change.path().to_path_buf());
+        key(a).cmp(&key(b))
+    });
+}
+
+/// Changes described by a `notify` event
+///
+/// Renames become a removal of the old path and a change of the new one.
+/// Access and metadata-only events are dropped.
+pub fn watch_events(event: &Event) -> Vec<WatchEvent> {
+    let changed = |path: &PathBuf| WatchEvent::Changed(path.clone());
+    let removed = |path: &PathBuf| WatchEvent::Removed(path.clone());
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => {
+            event.paths.iter().map(changed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+            event.paths.iter().map(removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => event.paths.iter().map(changed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match event.paths.as_slice() {
+            [from, to] => vec![removed(from), changed(to)],
+            _ => Vec::new(),
+        },
+        // Rename with no known side: whichever path still exists is the new one
+        EventKind::Modify(ModifyKind::Name(_)) => event.paths.iter()
+            .map(|path| if path.exists() { changed(path) } else { removed(path) })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Cache upkeep for one music directory
+pub struct LibraryWatcher {
+    config: Config,
+    cache: AnalysisCache,
+    /// Audio file -> cache hash of its contents
+    known: HashMap<PathBuf, u64>,
+}
+
+impl LibraryWatcher {
+    /// Start from the audio files already in the music directory
+    ///
+    /// Only hashes are taken (the first MiB of each file); analysis is left
+    /// to the regular analyze/export runs. Reads every file's start, so run
+    /// it off the async runtime.
+    pub fn new(config: Config, cache: AnalysisCache) -> Self {
+        let known = WalkDir::new(&config.music_dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|entry| is_audio_file(entry.path()))
+            .filter_map(|entry| {
+                let hash = compute_file_hash(entry.path()).ok()?;
+                Some((entry.into_path(), hash))
+            })
+            .collect();
+        Self { config, cache, known }
+    }
+
+    /// Bring the cache in line with one change
+    pub fn handle(&mut self, event: &WatchEvent) -> anyhow::Result<()> {
+        match event {
+            WatchEvent::Changed(path) => {
+                let transcode = self.config.transcode_incompatible && path.is_file() && needs_transcode(path);
+                if !is_audio_file(path) && !transcode {
+                    return Ok(());
+                }
+                let (hash, analyzed) = cache_file(&self.config, &self.cache, path)?;
+                if analyzed {
+                    info!("Watch: analyzed {:?}", path);
+                }
+                // Rewritten in place: the old contents' entry is stale
+                if let Some(old) = self.known.insert(path.clone(), hash).filter(|&old| old != hash) {
+                    self.release(old)?;
+                }
+            }
+            WatchEvent::Removed(path) => {
+                let gone: Vec<PathBuf> = self.known.keys()
+                    .filter(|known| known.starts_with(path))
+                    .cloned()
+                    .collect();
+                for file in gone {
+                    if let Some(hash) = self.known.remove(&file) {
+                        info!("Watch: {:?} removed", file);
+                        self.release(hash)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the cache entry for `hash` unless a known file still has it
+    fn release(&self, hash: u64) -> anyhow::Result<()> {
+        if self.known.values().any(|&known| known == hash) {
+            debug!("Watch: keeping entry {:016x}, another file has the same contents", hash);
+        } else {
+            self.cache.invalidate(hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Watch `config.music_dir` and keep the cache current until the watcher
+/// stops
+pub async fn run(config: Config, cache: AnalysisCache) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })?;
+    watcher.watch(&config.music_dir, RecursiveMode::Recursive)?;
+    info!("Watching {:?} for changes", config.music_dir);
+
+    let mut library = tokio::task::spawn_blocking(move || LibraryWatcher::new(config, cache)).await?;
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        while let Ok(Some(event)) = tokio::time::timeout(SETTLE, rx.recv()).await {
+            batch.push(event);
+        }
+
+        // Last change per path wins (a file written and then deleted is
+        // just removed)
+        let mut changes: HashMap<PathBuf, WatchEvent> = HashMap::new();
+        for event in batch {
+            match event {
+                Ok(event) => {
+                    for change in watch_events(&event) {
+                        changes.insert(change.path().to_path_buf(), change);
+                    }
+                }
+                Err(e) => warn!("Watch error: {}", e),
+            }
+        }
+        let mut changes: Vec<WatchEvent> = changes.into_values().collect();
+        sort_changes(&mut changes);
+        debug!("Watch: {} changed paths", changes.len());
+
+        for change in &changes {
+            if let Err(e) = library.handle(change) {
+                warn!("Watch: failed to update {:?}: {:#}", change.path(), e);
+            }
+            // Analysis is blocking; let clients have a turn between files
+            tokio::task::yield_now().await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, RemoveKind};
+    use crate::analyzer::tone_wav;
+    use tempfile::TempDir;
+
+    fn test_config(tmp: &TempDir) -> Config {
+        Config::for_test(tmp.path())
+    }
+
+    #[test]
+    fn test_created_file_analyzed_and_removed_file_invalidated() {
+        let tmp = TempDir::new().unwrap();
+        let config = test_config(&tmp);
+        std::fs::create_dir_all(&config.music_dir).unwrap();
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+        let mut library = LibraryWatcher::new(config.clone(), cache.clone());
+
+        // Created: analyzed into the cache
+        let path = config.music_dir.join("new.wav");
+        std::fs::write(&path, tone_wav(1, 2)).unwrap();
+        let created = Event::new(EventKind::Create(CreateKind::File)).add_path(path.clone());
+        for change in watch_events(&created) {
+            library.handle(&change).unwrap();
+        }
+        let hash = compute_file_hash(&path).unwrap();
+        let cached = cache.get(hash, crate::analyzer::cache_settings(&config)).expect("created file analyzed");
+        assert_eq!(cached.file_path, "/Contents/new.wav");
+
+        // Non-audio files are ignored
+        std::fs::write(config.music_dir.join("notes.txt"), "hello").unwrap();
+        library.handle(&WatchEvent::Changed(config.music_dir.join("notes.txt"))).unwrap();
+        assert_eq!(cache.stats().unwrap().entry_count, 1);
+
+        // Removed: its entry is dropped even though the file can't be hashed
+        std::fs::remove_file(&path).unwrap();
+        let removed = Event::new(EventKind::Remove(RemoveKind::File)).add_path(path.clone());
+        for change in watch_events(&removed) {
+            library.handle(&change).unwrap();
+        }
+        assert!(cache.get(hash, crate::analyzer::cache_settings(&config)).is_none());
+        assert_eq!(cache.stats().unwrap().entry_count, 0);
+    }
+
+    #[test]
+    fn test_copies_share_an_entry_until_the_last_goes() {
+        let tmp = TempDir::new().unwrap();
+        let config = test_config(&tmp);
+        std::fs::create_dir_all(&config.music_dir).unwrap();
+        let cache = AnalysisCache::new(&config.cache_dir).unwrap();
+        let original = config.music_dir.join("a.wav");
+        let copy = config.music_dir.join("b.wav");
+        std::fs::write(&original, tone_wav(1, 2)).unwrap();
+        std::fs::write(&copy, tone_wav(1, 2)).unwrap();
+        let mut library = LibraryWatcher::new(config.clone(), cache.clone());
+        library.handle(&WatchEvent::Changed(original.clone())).unwrap();
+        assert_eq!(cache.stats().unwrap().entry_count, 1);
+
+        std::fs::remove_file(&original).unwrap();
+        library.handle(&WatchEvent::Removed(original)).unwrap();
+        assert_eq!(cache.stats().unwrap().entry_count, 1);
+
+        std::fs::remove_file(&copy).unwrap();
+        library.handle(&WatchEvent::Removed(copy)).unwrap();
+        assert_eq!(cache.stats().unwrap().entry_count, 0);
+    }
+
+    #[test]
+    fn test_removals_handled_first() {
+        let mut changes = vec![
+            WatchEvent::Changed("/music/a.wav".into()),
+            WatchEvent::Removed("/music/b.wav".into()),
+            WatchEvent::Changed("/music/0.wav".into()),
+            WatchEvent::Removed("/music/a".into()),
+        ];
+        sort_changes(&mut changes);
+        assert_eq!(changes, [
+            WatchEvent::Removed("/music/a".into()),
+            WatchEvent::Removed("/music/b.wav".into()),
+            WatchEvent::Changed("/music/0.wav".into()),
+            WatchEvent::Changed("/music/a.wav".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_rename_events() {
+        let from = PathBuf::from("/music/a.wav");
+        let to = PathBuf::from("/music/b.wav");
+        let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(from.clone())
+            .add_path(to.clone());
+        assert_eq!(watch_events(&rename), [WatchEvent::Removed(from), WatchEvent::Changed(to)]);
+
+        let access = Event::new(EventKind::Access(notify::event::AccessKind::Any)).add_path("/music/a.wav".into());
+        assert!(watch_events(&access).is_empty());
+    }
+}
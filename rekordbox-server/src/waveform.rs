@@ -2,300 +2,537 @@
 //!
 //! Generates both preview (PWAV) and detail (PWV5) waveforms using FFT
 //! for frequency band separation (bass/mid/high → red/green/blue).
+//!
+//! Audio is consumed as it is decoded (`push_samples`/`push_stereo`, then
+//! `finalize`): each PWV5 entry is computed as soon as its FFT window has
+//! arrived, and the previews are drawn from per-block summaries that are
+//! merged pairwise whenever there are too many. Memory stays bounded by the
+//! detail waveform itself, whatever the track length.
+//!
+//! Heights, band gains and crossovers come from a `WaveformConfig`; its
+//! defaults reproduce the long-standing scaling, and `normalize` scales
+//! each track so quiet material still fills the display.
 
-use rustfft::{FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use serde::{Deserialize, Serialize};
 use rekordbox_core::{Waveform, WaveformPreview, WaveformDetail, WaveformColumn, WaveformColorEntry,
                      WaveformColorPreview, WaveformColorPreviewColumn};
 
+/// PWV5 entries per second
+const DETAIL_ENTRIES_PER_SEC: u32 = 150;
+
+/// FFT length for the band energies of each detail entry
+const FFT_SIZE: usize = 1024;
+
+/// Preview summaries kept before neighbours are merged (4 per PWV4 column)
+const MAX_BLOCKS: usize = 4 * 1200;
+
+/// How levels and spectra map onto waveform heights and colors
+///
+/// Set from the `[waveform]` table of the config file; missing keys keep
+/// their defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaveformConfig {
+    /// Share of full height per unit of RMS amplitude (4.0: an RMS of 0.25
+    /// fills the column)
+    pub height_gain: f32,
+    /// Color gains of the bass, mid and high bands, on top of each format's
+    /// own scaling
+    pub bass_gain: f32,
+    pub mid_gain: f32,
+    pub high_gain: f32,
+    /// Upper edges of the bass (from 20 Hz), mid and high bands
+    pub bass_crossover_hz: f32,
+    pub mid_crossover_hz: f32,
+    pub high_cutoff_hz: f32,
+    /// Scale each waveform so the track's loudest column reaches full
+    /// height, instead of applying `height_gain`
+    pub normalize: bool,
+}
+
+impl Default for WaveformConfig {
+    fn default() -> Self {
+        Self {
+            height_gain: 4.0,
+            bass_gain: 1.0,
+            mid_gain: 1.0,
+            high_gain: 1.0,
+            bass_crossover_hz: 200.0,
+            mid_crossover_hz: 4000.0,
+            high_cutoff_hz: 20000.0,
+            normalize: false,
+        }
+    }
+}
+
+/// Summary of consecutive detail entries, the unit the previews are drawn
+/// from
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+    /// Detail entries merged into this block
+    entries: u32,
+    samples: usize,
+    /// Sums of squares of the mono (mid) signal, each channel and the side
+    /// signal (channels equal the mid for mono sources)
+    sum_sq: f32,
+    left_sq: f32,
+    right_sq: f32,
+    side_sq: f32,
+    /// Peak and (sample-weighted sum of) median absolute mono amplitude
+    peak: f32,
+    median_sum: f32,
+    /// Band energies summed over the entries
+    bass: f32,
+    mid: f32,
+    high: f32,
+}
+
+impl Block {
+    fn merge(&mut self, other: &Block) {
+        self.entries += other.entries;
+        self.samples += other.samples;
+        self.sum_sq += other.sum_sq;
+        self.left_sq += other.left_sq;
+        self.right_sq += other.right_sq;
+        self.side_sq += other.side_sq;
+        self.peak = self.peak.max(other.peak);
+        self.median_sum += other.median_sum;
+        self.bass += other.bass;
+        self.mid += other.mid;
+        self.high += other.high;
+    }
+
+    /// Blocks `from` merged into one
+    fn merged(from: &[Block]) -> Block {
+        let mut block = Block::default();
+        for other in from {
+            block.merge(other);
+        }
+        block
+    }
+
+    fn rms(&self) -> f32 {
+        mean_sqrt(self.sum_sq, self.samples)
+    }
+
+    /// Louder channel's RMS
+    fn louder_rms(&self) -> f32 {
+        mean_sqrt(self.left_sq, self.samples).max(mean_sqrt(self.right_sq, self.samples))
+    }
+
+    /// Share of side (L-R) energy against mid (L+R): 0 for mono, 0.5 when
+    /// one channel is silent, 1 for fully out-of-phase channels
+    fn stereo_width(&self) -> f32 {
+        let (mid, side) = (self.sum_sq.sqrt(), self.side_sq.sqrt());
+        if mid + side > 0.0 { side / (mid + side) } else { 0.0 }
+    }
+
+    /// Mean band energies over the entries
+    fn bands(&self) -> (f32, f32, f32) {
+        let entries = self.entries.max(1) as f32;
+        (self.bass / entries, self.mid / entries, self.high / entries)
+    }
+}
+
 /// Waveform generator with FFT support
 pub struct WaveformGenerator {
     sample_rate: u32,
+    config: WaveformConfig,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// FFT input/output and scratch, reused across entries
+    fft_buffer: Vec<Complex<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
+    /// Previous frame magnitudes for spectral flux (onset strength)
+    prev_magnitudes: Vec<f32>,
+    /// Mono (mid) samples not yet behind every entry that needs them,
+    /// starting at absolute sample `pending_start`
+    pending: Vec<f32>,
+    /// Per-channel samples alongside `pending` (stereo sources only)
+    pending_left: Vec<f32>,
+    pending_right: Vec<f32>,
+    pending_start: usize,
+    /// Samples pushed so far
+    pushed: usize,
+    stereo: bool,
+    /// Detail entries, their heights set in `finalize` from `amplitudes`
+    entries: Vec<WaveformColorEntry>,
+    amplitudes: Vec<f32>,
+    blocks: Vec<Block>,
+    /// Detail entries per full block (doubles on every merge)
+    block_span: u32,
 }
 
 impl WaveformGenerator {
-    pub fn new(sample_rate: u32) -> Self {
-        Self { sample_rate }
+    pub fn new(sample_rate: u32, config: WaveformConfig) -> Self {
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos()))
+            .collect();
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        Self {
+            sample_rate,
+            config,
+            fft_buffer: vec![Complex::default(); FFT_SIZE],
+            fft_scratch: vec![Complex::default(); fft.get_inplace_scratch_len()],
+            fft,
+            window,
+            prev_magnitudes: vec![0.0; FFT_SIZE / 2 + 1],
+            pending: Vec::new(),
+            pending_left: Vec::new(),
+            pending_right: Vec::new(),
+            pending_start: 0,
+            pushed: 0,
+            stereo: false,
+            entries: Vec::new(),
+            amplitudes: Vec::new(),
+            blocks: Vec::new(),
+            block_span: 1,
+        }
     }
-    
+
+    /// A generator with the same settings and nothing pushed yet
+    fn fresh(&self) -> Self {
+        Self::new(self.sample_rate, self.config)
+    }
+
     /// Generate all waveform types (preview, color preview, and detail)
     pub fn generate(&self, samples: &[f32], duration_secs: f64) -> Waveform {
-        let preview = self.generate_preview(samples);
-        let color_preview = self.generate_color_preview(samples);
-        let detail = self.generate_detail(samples, duration_secs);
-        
-        Waveform { preview, color_preview, detail }
+        let mut generator = self.fresh();
+        generator.push_samples(samples);
+        generator.finalize(duration_secs)
     }
 
-    /// Generate 1200-column color preview waveform (PWV4 format)
-    fn generate_color_preview(&self, samples: &[f32]) -> WaveformColorPreview {
-        let mut columns = Vec::with_capacity(1200);
+    /// Feed the next mono samples
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        self.pushed += samples.len();
+        self.process_ready();
+    }
 
-        if samples.is_empty() {
-            return WaveformColorPreview {
-                columns: vec![WaveformColorPreviewColumn::default(); 1200],
-            };
+    /// Feed the next left/right samples (a source is either mono or stereo
+    /// throughout)
+    ///
+    /// Colors come from the mid (L+R) signal exactly as for mono samples,
+    /// but PWV4/PWV5 heights follow the louder channel so hard-panned
+    /// material isn't drawn at half size, and the PWV4 blue2 field carries
+    /// the stereo width (side vs mid energy, 0 = mono).
+    pub fn push_stereo(&mut self, left: &[f32], right: &[f32]) {
+        let len = left.len().min(right.len());
+        let (left, right) = (&left[..len], &right[..len]);
+        self.stereo = true;
+        self.pending.extend(left.iter().zip(right).map(|(l, r)| (l + r) * 0.5));
+        self.pending_left.extend_from_slice(left);
+        self.pending_right.extend_from_slice(right);
+        self.pushed += len;
+        self.process_ready();
+    }
+
+    /// Finish the waveforms for a track of `duration_secs`
+    pub fn finalize(mut self, duration_secs: f64) -> Waveform {
+        // 150 entries per second
+        let count = ((duration_secs * DETAIL_ENTRIES_PER_SEC as f64).ceil() as usize).max(1);
+        let per_entry = self.samples_per_entry();
+
+        if per_entry > 0 && self.entries.is_empty() && self.pushed > 0
+            && self.pushed < count * per_entry && self.pushed < self.sample_rate as usize
+        {
+            // A sub-second buffer that falls short of its entry count (the
+            // reported duration is longer than what decoded, or rounds up to
+            // whole entries) is stretched evenly over all of them, so the
+            // deck doesn't get a mostly blank waveform
+            let len = self.pushed;
+            for index in 0..count {
+                let start = index * len / count;
+                let end = ((index + 1) * len / count).clamp(start + 1, len);
+                self.process_entry(start..end);
+            }
+        } else if per_entry > 0 {
+            // The rest of the entries, their FFT windows zero-padded past
+            // the end
+            let mut start = self.entries.len() * per_entry;
+            while start < self.pushed {
+                self.process_entry(start..(start + per_entry).min(self.pushed));
+                start += per_entry;
+            }
         }
 
-        // FFT setup for frequency analysis
-        let fft_size = 1024;
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(fft_size);
+        let mut entries = std::mem::take(&mut self.entries);
+        let loudest = self.amplitudes.iter().copied().fold(0.0, f32::max);
+        for (entry, &amplitude) in entries.iter_mut().zip(&self.amplitudes) {
+            entry.height = self.height(amplitude, loudest, 31.0);
+        }
+        entries.resize(count, WaveformColorEntry::default());
 
-        // Hann window
-        let window: Vec<f32> = (0..fft_size)
-            .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / fft_size as f32).cos()))
-            .collect();
+        Waveform {
+            preview: self.generate_preview(),
+            color_preview: self.generate_color_preview(),
+            detail: WaveformDetail { entries },
+        }
+    }
+
+    fn samples_per_entry(&self) -> usize {
+        (self.sample_rate / DETAIL_ENTRIES_PER_SEC) as usize
+    }
+
+    /// Compute every entry whose FFT window has fully arrived
+    ///
+    /// Nothing is computed before a second of audio is in, so a shorter
+    /// track can still be stretched in `finalize`.
+    fn process_ready(&mut self) {
+        let per_entry = self.samples_per_entry();
+        if per_entry == 0 || self.pushed < self.sample_rate as usize {
+            return;
+        }
+        loop {
+            let start = self.entries.len() * per_entry;
+            if start + FFT_SIZE > self.pushed {
+                break;
+            }
+            self.process_entry(start..start + per_entry);
+        }
+
+        // Drop what no later entry reaches
+        let keep_from = self.entries.len() * per_entry;
+        let consumed = keep_from.saturating_sub(self.pending_start).min(self.pending.len());
+        self.pending.drain(..consumed);
+        if self.stereo {
+            self.pending_left.drain(..consumed);
+            self.pending_right.drain(..consumed);
+        }
+        self.pending_start += consumed;
+    }
+
+    /// Compute the next PWV5 entry from the samples in `range` (absolute),
+    /// and summarize them for the previews
+    fn process_entry(&mut self, range: std::ops::Range<usize>) {
+        let offset = range.start - self.pending_start;
+        let local = offset..offset + range.len();
+
+        // Get FFT window of samples
+        let mut silent = true;
+        for (i, value) in self.fft_buffer.iter_mut().enumerate() {
+            let sample = self.pending.get(offset + i).copied().unwrap_or(0.0);
+            silent &= sample == 0.0;
+            *value = Complex::new(sample * self.window[i], 0.0);
+        }
+
+        // Run FFT (digital silence, common in long intros and outros, has
+        // an all-zero spectrum already)
+        if !silent {
+            self.fft.process_with_scratch(&mut self.fft_buffer, &mut self.fft_scratch);
+        }
+
+        // Spectral flux: broadband energy increase since the previous entry.
+        // Kicks and snares light up every band at once, which rekordbox
+        // renders as near-white columns. The magnitudes are kept for the
+        // next entry and for the band energies below.
+        let mut flux = 0.0f32;
+        if silent {
+            self.prev_magnitudes.fill(0.0);
+        } else {
+            for (bin, prev) in self.prev_magnitudes.iter_mut().enumerate() {
+                let magnitude = self.fft_buffer[bin].norm();
+                flux += (magnitude - *prev).max(0.0);
+                *prev = magnitude;
+            }
+        }
+        let flux = flux / self.prev_magnitudes.len() as f32;
+
+        let (bass_energy, mid_energy, high_energy) = self.band_energies(&self.prev_magnitudes);
+
+        let samples = &self.pending[local.clone()];
+        let sum_sq: f32 = samples.iter().map(|s| s * s).sum();
+        let (left_sq, right_sq, side_sq) = if self.stereo {
+            let (left, right) = (&self.pending_left[local.clone()], &self.pending_right[local]);
+            (
+                left.iter().map(|s| s * s).sum(),
+                right.iter().map(|s| s * s).sum(),
+                left.iter().zip(right).map(|(l, r)| ((l - r) * 0.5).powi(2)).sum(),
+            )
+        } else {
+            (sum_sq, sum_sq, 0.0)
+        };
+        let mut magnitudes: Vec<f32> = samples.iter().map(|s| s.abs()).collect();
+        let peak = magnitudes.iter().cloned().fold(0.0f32, f32::max);
+        let middle = magnitudes.len() / 2;
+        let median = if magnitudes.is_empty() {
+            0.0
+        } else {
+            *magnitudes.select_nth_unstable_by(middle, f32::total_cmp).1
+        };
+
+        let block = Block {
+            entries: 1,
+            samples: samples.len(),
+            sum_sq,
+            left_sq,
+            right_sq,
+            side_sq,
+            peak,
+            median_sum: median * samples.len() as f32,
+            bass: bass_energy,
+            mid: mid_energy,
+            high: high_energy,
+        };
+
+        // Height follows the louder channel, so hard-panned material isn't
+        // drawn at half size
+        let amplitude = if self.stereo { block.louder_rms() } else { block.rms() };
+
+        // Scale to 0-7 range for colors (3 bits each); the transient term
+        // is added to all channels to push onsets towards white
+        let boost = 8.0;
+        let whiteness = flux * boost * 4.0;
+        let red = (bass_energy * boost + whiteness).clamp(0.0, 7.0) as u8;
+        let green = (mid_energy * boost * 2.0 + whiteness).clamp(0.0, 7.0) as u8;
+        let blue = (high_energy * boost * 4.0 + whiteness).clamp(0.0, 7.0) as u8;
+
+        // Height 0-31 once the track's loudest entry is known
+        self.entries.push(WaveformColorEntry { red, green, blue, height: 0 });
+        self.amplitudes.push(amplitude);
+        self.add_block(block);
+    }
+
+    /// Column height, 0 to `full`, for an RMS `amplitude` in a waveform whose
+    /// loudest column has RMS `loudest`
+    ///
+    /// A silent track (`loudest` 0) keeps the fixed gain even when
+    /// normalizing; a zero, negative or NaN gain draws every column empty.
+    fn height(&self, amplitude: f32, loudest: f32, full: f32) -> u8 {
+        let level = if self.config.normalize && loudest > 0.0 {
+            amplitude / loudest
+        } else {
+            amplitude * self.config.height_gain
+        };
+        (level * full).clamp(0.0, full) as u8
+    }
 
-        // Frequency bin ranges
-        let bin_hz = self.sample_rate as f32 / fft_size as f32;
+    /// Mean magnitude of the bass (20 Hz to the bass crossover), mid and
+    /// high bins of a magnitude spectrum, times the band gains
+    fn band_energies(&self, magnitudes: &[f32]) -> (f32, f32, f32) {
+        let config = &self.config;
+        let bin_hz = self.sample_rate as f32 / FFT_SIZE as f32;
         let bass_start = (20.0 / bin_hz).ceil() as usize;
-        let bass_end = (200.0 / bin_hz) as usize;
-        let mid_end = (4000.0 / bin_hz) as usize;
-        let high_end = std::cmp::min((20000.0 / bin_hz) as usize, fft_size / 2);
+        let bass_end = (config.bass_crossover_hz / bin_hz) as usize;
+        let mid_end = (config.mid_crossover_hz / bin_hz) as usize;
+        let high_end = std::cmp::min((config.high_cutoff_hz / bin_hz) as usize, FFT_SIZE / 2);
+
+        let band = |range: std::ops::RangeInclusive<usize>| -> f32 {
+            if range.is_empty() {
+                return 0.0;
+            }
+            magnitudes[range.clone()].iter().sum::<f32>()
+                / (range.end() - range.start() + 1) as f32
+        };
+        (
+            band(bass_start.max(1)..=bass_end.min(FFT_SIZE / 2)) * config.bass_gain,
+            band((bass_end + 1)..=mid_end.min(FFT_SIZE / 2)) * config.mid_gain,
+            band((mid_end + 1)..=high_end.min(FFT_SIZE / 2)) * config.high_gain,
+        )
+    }
+
+    /// Append one entry's summary, halving the block resolution when there
+    /// are too many
+    fn add_block(&mut self, block: Block) {
+        match self.blocks.last_mut() {
+            Some(last) if last.entries < self.block_span => last.merge(&block),
+            _ => self.blocks.push(block),
+        }
+        if self.blocks.len() > MAX_BLOCKS {
+            self.blocks = self.blocks.chunks(2).map(Block::merged).collect();
+            self.block_span *= 2;
+        }
+    }
+
+    /// Blocks behind preview column `index` of `columns`
+    fn column_blocks(blocks: &[Block], index: usize, columns: usize) -> Block {
+        let start = index * blocks.len() / columns;
+        let end = ((index + 1) * blocks.len() / columns).clamp(start + 1, blocks.len());
+        Block::merged(&blocks[start.min(end)..end])
+    }
 
-        // Divide samples into 1200 segments
-        let segment_size = samples.len() / 1200;
-        if segment_size == 0 {
+    /// Generate 1200-column color preview waveform (PWV4 format)
+    fn generate_color_preview(&self) -> WaveformColorPreview {
+        let blocks = &self.blocks;
+        let samples: usize = blocks.iter().map(|b| b.samples).sum();
+        if samples < 1200 {
             return WaveformColorPreview {
                 columns: vec![WaveformColorPreviewColumn::default(); 1200],
             };
         }
 
-        for i in 0..1200 {
-            let start = i * segment_size;
-            let end = std::cmp::min(start + segment_size, samples.len());
-            
-            if start >= samples.len() {
-                columns.push(WaveformColorPreviewColumn::default());
-                continue;
-            }
-
-            // Get FFT window centered on this segment
-            let mut fft_buffer: Vec<Complex<f32>> = (0..fft_size)
-                .map(|j| {
-                    let sample_idx = start + j;
-                    let sample = if sample_idx < samples.len() {
-                        samples[sample_idx]
-                    } else {
-                        0.0
-                    };
-                    Complex::new(sample * window[j], 0.0)
-                })
-                .collect();
-
-            fft.process(&mut fft_buffer);
-
-            // Calculate energy for each band
-            let bass_range = bass_start.max(1)..=bass_end.min(fft_size / 2);
-            let mid_range = (bass_end + 1)..=mid_end.min(fft_size / 2);
-            let high_range = (mid_end + 1)..=high_end.min(fft_size / 2);
-
-            let bass_energy: f32 = if bass_range.is_empty() { 0.0 } else {
-                fft_buffer[bass_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (bass_range.end() - bass_range.start() + 1) as f32
-            };
+        let amplitude = |column: &Block| if self.stereo { column.louder_rms() } else { column.rms() };
+        let columns: Vec<Block> = (0..1200).map(|i| Self::column_blocks(blocks, i, 1200)).collect();
+        let loudest = columns.iter().map(amplitude).fold(0.0, f32::max);
 
-            let mid_energy: f32 = if mid_range.is_empty() { 0.0 } else {
-                fft_buffer[mid_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (mid_range.end() - mid_range.start() + 1) as f32
-            };
+        let columns = columns.iter()
+            .map(|column| {
+                let (bass_energy, mid_energy, high_energy) = column.bands();
 
-            let high_energy: f32 = if high_range.is_empty() { 0.0 } else {
-                fft_buffer[high_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (high_range.end() - high_range.start() + 1) as f32
-            };
+                // Scale values for PWV4 format (7-bit values, 0-127)
+                let boost = 16.0;
+                let height = self.height(amplitude(column), loudest, 127.0);
+                let luminance = ((bass_energy + mid_energy + high_energy) * boost).clamp(0.0, 127.0) as u8;
+                let blue = (bass_energy * boost * 2.0).clamp(0.0, 127.0) as u8;
+                let red = (bass_energy * boost).clamp(0.0, 127.0) as u8;
+                let green = (mid_energy * boost * 1.5).clamp(0.0, 127.0) as u8;
+                // Stereo sources show their width instead of the highs
+                let blue2 = if self.stereo {
+                    (column.stereo_width() * 127.0).round().clamp(0.0, 127.0) as u8
+                } else {
+                    (high_energy * boost * 2.0).clamp(0.0, 127.0) as u8
+                };
 
-            // Calculate RMS for height
-            let segment = &samples[start..end];
-            let rms: f32 = (segment.iter().map(|s| s * s).sum::<f32>() / segment.len() as f32).sqrt();
-
-            // Scale values for PWV4 format (7-bit values, 0-127)
-            let boost = 16.0;
-            let height = (rms * 127.0 * 4.0).clamp(0.0, 127.0) as u8;
-            let luminance = ((bass_energy + mid_energy + high_energy) * boost).clamp(0.0, 127.0) as u8;
-            let blue = (bass_energy * boost * 2.0).clamp(0.0, 127.0) as u8;
-            let red = (bass_energy * boost).clamp(0.0, 127.0) as u8;
-            let green = (mid_energy * boost * 1.5).clamp(0.0, 127.0) as u8;
-            let blue2 = (high_energy * boost * 2.0).clamp(0.0, 127.0) as u8;
-
-            columns.push(WaveformColorPreviewColumn {
-                height,
-                luminance,
-                blue,
-                red,
-                green,
-                blue2,
-            });
-        }
+                WaveformColorPreviewColumn { height, luminance, blue, red, green, blue2 }
+            })
+            .collect();
 
         WaveformColorPreview { columns }
     }
-    
+
     /// Generate 400-column preview waveform (PWAV format)
-    fn generate_preview(&self, samples: &[f32]) -> WaveformPreview {
-        let mut columns = Vec::with_capacity(400);
-        
-        if samples.is_empty() {
+    fn generate_preview(&self) -> WaveformPreview {
+        let blocks = &self.blocks;
+        let samples: usize = blocks.iter().map(|b| b.samples).sum();
+        if samples < 400 {
             return WaveformPreview {
                 columns: vec![WaveformColumn { height: 0, whiteness: 0 }; 400],
             };
         }
-        
-        // Divide samples into 400 segments
-        let segment_size = samples.len() / 400;
-        if segment_size == 0 {
-            return WaveformPreview {
-                columns: vec![WaveformColumn { height: 0, whiteness: 0 }; 400],
-            };
-        }
-        
-        for i in 0..400 {
-            let start = i * segment_size;
-            let end = std::cmp::min(start + segment_size, samples.len());
-            let segment = &samples[start..end];
-            
-            if segment.is_empty() {
-                columns.push(WaveformColumn { height: 0, whiteness: 0 });
-                continue;
-            }
-            
-            // Calculate RMS amplitude
-            let rms: f32 = (segment.iter().map(|s| s * s).sum::<f32>() 
-                           / segment.len() as f32).sqrt();
-            
-            // Calculate peak for "whiteness" (loudness variation)
-            let peak: f32 = segment.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
-            
-            // Scale to 0-31 range for height (boost for visibility)
-            let height = (rms * 31.0 * 4.0).min(31.0) as u8;
-            
-            // Whiteness based on peak-to-RMS ratio (crest factor)
-            let crest = if rms > 0.001 { peak / rms } else { 1.0 };
-            let whiteness = ((crest - 1.0) / 2.0).clamp(0.0, 7.0) as u8;
-            
-            columns.push(WaveformColumn { height, whiteness });
-        }
-        
+
+        let columns: Vec<Block> = (0..400).map(|i| Self::column_blocks(blocks, i, 400)).collect();
+        let loudest = columns.iter().map(Block::rms).fold(0.0, f32::max);
+
+        let columns = columns.iter()
+            .map(|column| {
+                // Scale to 0-31 range for height (boost for visibility)
+                let height = self.height(column.rms(), loudest, 31.0);
+
+                // Whiteness is the quieter "body" of the column relative to
+                // its peak (median/peak amplitude): sustained material
+                // renders solid white, sparse transients over silence stay
+                // dark
+                let median = column.median_sum / column.samples.max(1) as f32;
+                let body = if column.peak > 0.001 { median / column.peak } else { 0.0 };
+                let whiteness = (body * 7.0).round().clamp(0.0, 7.0) as u8;
+
+                WaveformColumn { height, whiteness }
+            })
+            .collect();
+
         WaveformPreview { columns }
     }
-    
-    /// Generate detail color waveform (PWV5 format, 150 entries/second)
-    fn generate_detail(&self, samples: &[f32], duration_secs: f64) -> WaveformDetail {
-        // 150 entries per second
-        let num_entries = (duration_secs * 150.0).ceil() as usize;
-        let num_entries = num_entries.max(1);
-        let mut entries = Vec::with_capacity(num_entries);
-        
-        if samples.is_empty() {
-            return WaveformDetail {
-                entries: vec![WaveformColorEntry::default(); num_entries],
-            };
-        }
-        
-        // FFT setup
-        let fft_size = 1024;
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(fft_size);
-        
-        // Samples per waveform entry
-        let samples_per_entry = self.sample_rate as usize / 150;
-        if samples_per_entry == 0 {
-            return WaveformDetail {
-                entries: vec![WaveformColorEntry::default(); num_entries],
-            };
-        }
-        
-        // Hann window
-        let window: Vec<f32> = (0..fft_size)
-            .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / fft_size as f32).cos()))
-            .collect();
-        
-        // Frequency bin ranges for each color
-        let bin_hz = self.sample_rate as f32 / fft_size as f32;
-        let bass_start = (20.0 / bin_hz).ceil() as usize;
-        let bass_end = (200.0 / bin_hz) as usize;
-        let mid_end = (4000.0 / bin_hz) as usize;
-        let high_end = std::cmp::min((20000.0 / bin_hz) as usize, fft_size / 2);
-        
-        for entry_idx in 0..num_entries {
-            let sample_start = entry_idx * samples_per_entry;
-            
-            if sample_start >= samples.len() {
-                entries.push(WaveformColorEntry::default());
-                continue;
-            }
-            
-            // Get FFT window of samples
-            let mut fft_buffer: Vec<Complex<f32>> = (0..fft_size)
-                .map(|i| {
-                    let sample_idx = sample_start + i;
-                    let sample = if sample_idx < samples.len() {
-                        samples[sample_idx]
-                    } else {
-                        0.0
-                    };
-                    Complex::new(sample * window[i], 0.0)
-                })
-                .collect();
-            
-            // Run FFT
-            fft.process(&mut fft_buffer);
-            
-            // Calculate magnitude for each frequency band
-            let bass_range = bass_start.max(1)..=bass_end.min(fft_size / 2);
-            let mid_range = (bass_end + 1)..=mid_end.min(fft_size / 2);
-            let high_range = (mid_end + 1)..=high_end.min(fft_size / 2);
-            
-            let bass_energy: f32 = if bass_range.is_empty() { 0.0 } else {
-                fft_buffer[bass_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (bass_range.end() - bass_range.start() + 1) as f32
-            };
-            
-            let mid_energy: f32 = if mid_range.is_empty() { 0.0 } else {
-                fft_buffer[mid_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (mid_range.end() - mid_range.start() + 1) as f32
-            };
-            
-            let high_energy: f32 = if high_range.is_empty() { 0.0 } else {
-                fft_buffer[high_range.clone()]
-                    .iter()
-                    .map(|c| c.norm())
-                    .sum::<f32>() / (high_range.end() - high_range.start() + 1) as f32
-            };
-            
-            // Calculate overall amplitude for height
-            let segment_end = std::cmp::min(sample_start + samples_per_entry, samples.len());
-            let amplitude = if sample_start < segment_end {
-                let segment = &samples[sample_start..segment_end];
-                (segment.iter().map(|s| s * s).sum::<f32>() / segment.len() as f32).sqrt()
-            } else {
-                0.0
-            };
-            
-            // Scale to 0-7 range for colors (3 bits each)
-            let boost = 8.0;
-            let red = (bass_energy * boost).clamp(0.0, 7.0) as u8;
-            let green = (mid_energy * boost * 2.0).clamp(0.0, 7.0) as u8;
-            let blue = (high_energy * boost * 4.0).clamp(0.0, 7.0) as u8;
-            
-            // Height 0-31
-            let height = (amplitude * 31.0 * 4.0).clamp(0.0, 31.0) as u8;
-            
-            entries.push(WaveformColorEntry { red, green, blue, height });
-        }
-        
-        WaveformDetail { entries }
+}
+
+/// Square root of the mean of a sum of squares over `count` samples
+fn mean_sqrt(sum_sq: f32, count: usize) -> f32 {
+    if count == 0 {
+        return 0.0;
     }
+    (sum_sq / count as f32).sqrt()
 }
 
 #[cfg(test)]
@@ -304,41 +541,243 @@ mod tests {
     
     #[test]
     fn test_preview_generation() {
-        let gen = WaveformGenerator::new(44100);
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
         
         // Generate 1 second of sine wave
         let samples: Vec<f32> = (0..44100)
             .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
             .collect();
         
-        let preview = gen.generate_preview(&samples);
+        let preview = gen.generate(&samples, 1.0).preview;
         
         assert_eq!(preview.columns.len(), 400);
         // All columns should have some amplitude
         assert!(preview.columns.iter().any(|c| c.height > 0));
     }
     
+    #[test]
+    fn test_preview_whiteness_tracks_body() {
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
+        let segment = 441;
+
+        // First half: steady sine (median close to peak); second half: one
+        // click per column over near-silence (median far below peak)
+        let samples: Vec<f32> = (0..400 * segment)
+            .map(|i| {
+                if i < 200 * segment {
+                    0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin()
+                } else if i % segment == 0 {
+                    0.9
+                } else {
+                    0.001
+                }
+            })
+            .collect();
+
+        let preview = gen.generate(&samples, 4.0).preview;
+        let flat = preview.columns[100].whiteness;
+        let spiky = preview.columns[300].whiteness;
+
+        assert!(flat > spiky, "flat {} should be whiter than spiky {}", flat, spiky);
+        assert_eq!(spiky, 0);
+    }
+
     #[test]
     fn test_detail_generation() {
-        let gen = WaveformGenerator::new(44100);
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
         
         // Generate 1 second of sine wave
         let samples: Vec<f32> = (0..44100)
             .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
             .collect();
         
-        let detail = gen.generate_detail(&samples, 1.0);
+        let detail = gen.generate(&samples, 1.0).detail;
         
         // 1 second at 150 entries/sec = 150 entries
         assert_eq!(detail.entries.len(), 150);
     }
     
+    #[test]
+    fn test_transients_brighter_than_tone() {
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
+        let rms = 0.02f32;
+
+        // 440 Hz tone and a 4 Hz click train with the same RMS
+        let tone: Vec<f32> = (0..44100)
+            .map(|i| rms * 2f32.sqrt() * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
+            .collect();
+        let period = 44100 / 4;
+        let click = rms * (period as f32).sqrt();
+        let clicks: Vec<f32> = (0..44100)
+            .map(|i| if i % period == 0 { click } else { 0.0 })
+            .collect();
+
+        let brightest = |samples: &[f32]| {
+            gen.generate(samples, 1.0).detail.entries.iter()
+                .map(|e| e.red as u32 + e.green as u32 + e.blue as u32)
+                .max()
+                .unwrap_or(0)
+        };
+
+        assert!(brightest(&clicks) > brightest(&tone));
+    }
+    
+    fn generate_stereo(gen: &WaveformGenerator, left: &[f32], right: &[f32], duration_secs: f64) -> Waveform {
+        let mut gen = gen.fresh();
+        gen.push_stereo(left, right);
+        gen.finalize(duration_secs)
+    }
+
+    #[test]
+    fn test_stereo_hard_panned() {
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
+
+        // Tone in the left channel only; a mono downmix halves it
+        let left: Vec<f32> = (0..44100)
+            .map(|i| 0.1 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
+            .collect();
+        let right = vec![0.0f32; left.len()];
+        let downmix: Vec<f32> = left.iter().map(|l| l * 0.5).collect();
+
+        let mono = gen.generate(&downmix, 1.0);
+        let stereo = generate_stereo(&gen, &left, &right, 1.0);
+
+        let (m, s) = (&mono.color_preview.columns[600], &stereo.color_preview.columns[600]);
+        assert_ne!(s.blue2, m.blue2);
+        assert_eq!(s.blue2, 64, "one silent channel is half width");
+        assert!(s.height > m.height);
+        assert!(stereo.detail.entries[75].height > mono.detail.entries[75].height);
+
+        // Same colors: both are derived from the mid signal
+        assert_eq!(s.red, m.red);
+        assert_eq!(stereo.detail.entries[75].green, mono.detail.entries[75].green);
+
+        // A centered signal has no width
+        let centered = generate_stereo(&gen, &left, &left, 1.0);
+        assert_eq!(centered.color_preview.columns[600].blue2, 0);
+    }
+
+    #[test]
+    fn test_stereo_identical_channels_match_mono() {
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
+        let samples: Vec<f32> = (0..44100)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 44100.0).sin())
+            .collect();
+
+        let mono = gen.generate(&samples, 1.0);
+        let stereo = generate_stereo(&gen, &samples, &samples, 1.0);
+        let bytes = |w: &Waveform| w.detail.entries.iter().map(|e| e.to_bytes()).collect::<Vec<_>>();
+        assert_eq!(bytes(&stereo), bytes(&mono));
+    }
+
+    #[test]
+    fn test_short_track_detail_not_blank() {
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
+        let samples: Vec<f32> = (0..13230)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 44100.0).sin())
+            .collect();
+
+        // 0.3 s of audio, reported both exactly and rounded up to a second
+        for duration in [0.3, 1.0] {
+            let detail = gen.generate(&samples, duration).detail;
+            assert_eq!(detail.entries.len(), (duration * 150.0f64).ceil() as usize);
+            assert!(
+                detail.entries.iter().all(|e| e.height > 0),
+                "blank entries at duration {}", duration
+            );
+        }
+    }
+
+    #[test]
+    fn test_streamed_long_track_keeps_full_detail() {
+        // 30 minutes (longer than the analyzer keeps in memory) pushed in
+        // decoder-sized chunks: 29 of silence, then a minute of tone that
+        // must still land at the end of the detail. 7.5 kHz gives exactly
+        // 50 samples per entry.
+        let sample_rate = 7500u32;
+        let total = 30 * 60 * sample_rate as usize;
+        let tone_from = 29 * 60 * sample_rate as usize;
+        let mut gen = WaveformGenerator::new(sample_rate, WaveformConfig::default());
+        let mut chunk = Vec::with_capacity(4096);
+        let mut pushed = 0;
+        while pushed < total {
+            chunk.clear();
+            chunk.extend((pushed..total.min(pushed + 4096)).map(|i| if i < tone_from {
+                0.0
+            } else {
+                0.3 * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / sample_rate as f32).sin()
+            }));
+            pushed += chunk.len();
+            gen.push_samples(&chunk);
+            assert!(gen.pending.len() < 4096 + FFT_SIZE, "{} samples held", gen.pending.len());
+            assert!(gen.blocks.len() <= MAX_BLOCKS);
+        }
+
+        let waveform = gen.finalize(30.0 * 60.0);
+        let entries = &waveform.detail.entries;
+        assert_eq!(entries.len(), 30 * 60 * 150);
+        assert!(entries[..29 * 60 * 150].iter().all(|e| e.height == 0));
+        assert!(entries[29 * 60 * 150 + 10..].iter().all(|e| e.height > 0));
+        // The previews cover the whole track too: the tone is the last 30th
+        assert!(waveform.preview.columns[..385].iter().all(|c| c.height == 0));
+        assert!(waveform.preview.columns[390..].iter().all(|c| c.height > 0));
+    }
+
+    #[test]
+    fn test_normalize_fills_quiet_tracks() {
+        // A -40 dBFS tone with a brief swell to -34 dBFS in the middle
+        let samples: Vec<f32> = (0..2 * 44100)
+            .map(|i| {
+                let level = if (40000..48000).contains(&i) { 0.02 } else { 0.01 };
+                level * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin()
+            })
+            .collect();
+
+        let flat = WaveformGenerator::new(44100, WaveformConfig::default()).generate(&samples, 2.0);
+        assert!(flat.detail.entries.iter().all(|e| e.height <= 1));
+
+        let config = WaveformConfig { normalize: true, ..WaveformConfig::default() };
+        let waveform = WaveformGenerator::new(44100, config).generate(&samples, 2.0);
+        let detail: Vec<u8> = waveform.detail.entries.iter().map(|e| e.height).collect();
+        assert_eq!(detail.iter().max(), Some(&31));
+        assert!((14..=16).contains(&detail[20]), "{}", detail[20]);
+        assert_eq!(waveform.preview.columns.iter().map(|c| c.height).max(), Some(31));
+        assert_eq!(waveform.color_preview.columns.iter().map(|c| c.height).max(), Some(127));
+        // Colors don't depend on the heights
+        let colors = |w: &Waveform| w.detail.entries.iter().map(|e| (e.red, e.green, e.blue)).collect::<Vec<_>>();
+        assert_eq!(colors(&waveform), colors(&flat));
+    }
+
+    #[test]
+    fn test_zero_gains_and_silence() {
+        let samples: Vec<f32> = (0..44100)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 100.0 * i as f32 / 44100.0).sin())
+            .collect();
+        let config = WaveformConfig {
+            height_gain: 0.0,
+            bass_gain: 0.0,
+            mid_gain: 0.0,
+            high_gain: 0.0,
+            ..WaveformConfig::default()
+        };
+        let waveform = WaveformGenerator::new(44100, config).generate(&samples, 1.0);
+        // Only the transient whiteness is left, the same in every channel
+        assert!(waveform.detail.entries.iter().all(|e| e.height == 0 && e.red == e.green && e.green == e.blue));
+        assert!(waveform.preview.columns.iter().all(|c| c.height == 0));
+
+        // Normalizing silence leaves it silent rather than dividing by zero
+        let config = WaveformConfig { normalize: true, ..WaveformConfig::default() };
+        let waveform = WaveformGenerator::new(44100, config).generate(&vec![0.0; 44100], 1.0);
+        assert!(waveform.detail.entries.iter().all(|e| e.height == 0));
+        assert!(waveform.color_preview.columns.iter().all(|c| c.height == 0));
+    }
+
     #[test]
     fn test_empty_samples() {
-        let gen = WaveformGenerator::new(44100);
+        let gen = WaveformGenerator::new(44100, WaveformConfig::default());
         let waveform = gen.generate(&[], 0.0);
         
         assert_eq!(waveform.preview.columns.len(), 400);
-        assert!(waveform.detail.entries.len() >= 1);
+        assert!(!waveform.detail.entries.is_empty());
     }
 }